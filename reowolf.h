@@ -46,11 +46,35 @@ int connector_bind_passive(Connector *connector,
 /**
  * Configures the given Reowolf connector with a protocol description in PDL.
  * Returns:
+ * - 0 SUCCESS
+ * - -1 the connector could not be configured. If the description has errors,
+ *   the last error lists all of them.
  * # Safety
  * TODO
  */
 int connector_configure(Connector *connector, char *pdl, char *main);
 
+/**
+ * Configures the given Reowolf connector with a protocol description in PDL,
+ * passing arguments to the parameters of the main component. The arguments are
+ * a comma-separated list, with one argument per parameter:
+ * - `port` for an `in` or `out` parameter, bound to the next port index
+ * - `port[N]` for an `in[]` or `out[]` parameter, bound to the next N port indices
+ * - `true`, `false`, or an integer for a parameter of that type
+ * - `{...}` with a list of such arguments for an array parameter
+ *
+ * Returns:
+ * - 0 SUCCESS
+ * - 1 the arguments could not be parsed
+ * - -1 the connector could not be configured
+ * # Safety
+ * TODO
+ */
+int connector_configure_with_args(Connector *connector,
+                                  char *pdl,
+                                  char *main,
+                                  const char *args);
+
 /**
  * Provides a binding annotation for the port with the given index with "active":
  * (The port will conenct to a "passive" port at the given address during connect())
@@ -133,6 +157,17 @@ int connector_put(Connector *connector,
                   unsigned char *buf_ptr,
                   unsigned int msg_len);
 
+/**
+ * Limits the number of steps each protocol component may take in a synchronous round.
+ * A step budget of 0 removes the limit.
+ * Returns:
+ * - 0 SUCCESS
+ * - 1 the connector is not yet configured
+ * # Safety
+ * TODO
+ */
+int connector_set_step_budget(Connector *connector, uintptr_t step_budget);
+
 /**
  * # Safety
  * TODO
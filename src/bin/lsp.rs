@@ -0,0 +1,12 @@
+// The language server for protocol descriptions, which editors run and talk to over stdio
+fn main() {
+    let stdin = std::io::stdin();
+    let stdout = std::io::stdout();
+    match reowolf_rs::lsp::serve(stdin.lock(), stdout.lock()) {
+        Ok(code) => std::process::exit(code),
+        Err(err) => {
+            eprintln!("reowolf-lsp: {}", err);
+            std::process::exit(1)
+        }
+    }
+}
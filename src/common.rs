@@ -33,7 +33,7 @@ pub struct PortId {
     pub(crate) port_index: u32,
 }
 
-#[derive(Debug, Clone, Eq, PartialEq, Ord, PartialOrd)]
+#[derive(Debug, Clone, Eq, PartialEq, Ord, PartialOrd, Hash)]
 pub struct Payload(Arc<Vec<u8>>);
 
 /// This is a unique identifier for a channel (i.e., port).
@@ -62,13 +62,92 @@ pub enum MainComponentErr {
     UnknownPort(Port),
     WrongPortPolarity { param_index: usize, port: Port },
     DuplicateMovedPort(Port),
+    WrongArgumentType { param_index: usize },
+}
+/// Argument for a parameter of a main component. Ports are bound by index,
+/// in the order of the parameters they are passed to.
+#[derive(Eq, PartialEq, Clone, Debug)]
+pub enum ComponentArg {
+    Port,
+    Ports(usize),
+    Boolean(bool),
+    Integer(i64),
+    Message(Payload),
+    Array(Vec<ComponentArg>),
+}
+/// User modules that a protocol description may import. The import `a.b`
+/// resolves to the module source named `a.b`, or else to the file `a/b.pdl`
+/// in the first directory of the search path that contains it.
+#[derive(Eq, PartialEq, Clone, Debug, Default)]
+pub struct PdlModules {
+    pub sources: Vec<(Vec<u8>, Vec<u8>)>,
+    pub search_path: Vec<std::path::PathBuf>,
+}
+/// The pragmas of a protocol description: the language version and module
+/// name it declares, if any, and the language features it enables.
+#[derive(Eq, PartialEq, Clone, Debug, Default)]
+pub struct PdlPragmas {
+    pub version: Option<i64>,
+    pub module_name: Option<Vec<u8>>,
+    pub features: Vec<Vec<u8>>,
+}
+/// A problem found in a protocol description, at a line and column of the
+/// named source. The main description has an empty name. The end is just
+/// past the offending source, or the character after the position.
+#[derive(Eq, PartialEq, Clone, Debug)]
+pub struct PdlDiagnostic {
+    pub severity: Severity,
+    pub filename: String,
+    pub line: usize,
+    pub column: usize,
+    pub end_line: usize,
+    pub end_column: usize,
+    pub message: String,
+}
+#[derive(Eq, PartialEq, Copy, Clone, Debug)]
+pub enum Severity {
+    Error,
+    Warning,
+}
+/// A serialization of the constraint automaton of a primitive component:
+/// JSON for tools, or Graphviz DOT for drawing it.
+#[derive(Eq, PartialEq, Copy, Clone, Debug)]
+pub enum AutomatonFormat {
+    Json,
+    Dot,
 }
 pub trait ProtocolDescription: Sized {
     type S: ComponentState<D = Self>;
 
-    fn parse(pdl: &[u8]) -> Result<Self, String>;
-    fn component_polarities(&self, identifier: &[u8]) -> Result<Vec<Polarity>, MainComponentErr>;
-    fn new_main_component(&self, identifier: &[u8], ports: &[Port]) -> Self::S;
+    fn parse(pdl: &[u8]) -> Result<Self, String> {
+        Self::parse_with_modules(pdl, &PdlModules::default())
+    }
+    fn parse_with_modules(pdl: &[u8], modules: &PdlModules) -> Result<Self, String>;
+    // All problems found in the description, in order of their positions
+    fn diagnose(pdl: &[u8], modules: &PdlModules) -> Vec<PdlDiagnostic>;
+    // The canonical form of the description, if it is syntactically correct
+    fn format(pdl: &[u8]) -> Result<String, String>;
+    // The language version, module name and features declared by the pragmas
+    fn pragmas(&self) -> PdlPragmas;
+    // Without arguments, every parameter of the main component is a single port
+    fn component_polarities(
+        &self,
+        identifier: &[u8],
+        args: Option<&[ComponentArg]>,
+    ) -> Result<Vec<Polarity>, MainComponentErr>;
+    // Warnings about the main component and the components it creates, such as causal loops
+    // that keep their synchronous blocks from ever completing
+    fn analyze(&self, identifier: &[u8]) -> Vec<PdlDiagnostic>;
+    // The constraint automaton of a primitive component, or none if there is no such primitive
+    fn automaton(&self, identifier: &[u8], format: AutomatonFormat) -> Option<String>;
+    // The Graphviz DOT graph of the components and channels that a composite component creates
+    fn topology(&self, identifier: &[u8]) -> Option<String>;
+    fn new_main_component(
+        &self,
+        identifier: &[u8],
+        args: Option<&[ComponentArg]>,
+        ports: &[Port],
+    ) -> Self::S;
 }
 
 pub trait ComponentState: Sized + Clone {
@@ -91,6 +170,8 @@ pub enum MonoBlocker {
     Inconsistent,
     ComponentExit,
     SyncBlockStart,
+    StepBudgetExhausted,
+    Fault(String),
 }
 
 #[derive(Debug, Clone)]
@@ -99,7 +180,10 @@ pub enum PolyBlocker {
     SyncBlockEnd,
     CouldntReadMsg(Port),
     CouldntCheckFiring(Port),
+    CouldntChoose,
     PutMsg(Port, Payload),
+    StepBudgetExhausted,
+    Fault(String),
 }
 
 pub trait MonoContext {
@@ -109,12 +193,17 @@ pub trait MonoContext {
     fn new_component(&mut self, moved_ports: HashSet<Port>, init_state: Self::S);
     fn new_channel(&mut self) -> [Port; 2];
     fn new_random(&mut self) -> u64;
+    fn step_budget(&self) -> Option<usize>;
 }
 pub trait PolyContext {
     type D: ProtocolDescription;
 
     fn is_firing(&mut self, port: Port) -> Option<bool>;
     fn read_msg(&mut self, port: Port) -> Option<&Payload>;
+    // The outcome of the nondeterministic choice with the given index in the
+    // synchronous round, if the branch has made it
+    fn choose(&mut self, index: usize) -> Option<bool>;
+    fn step_budget(&self) -> Option<usize>;
 }
 
 ///////////////////// IMPL /////////////////////
@@ -2,13 +2,15 @@
 mod macros;
 
 mod common; // common to both
+pub mod lsp; // language server
 mod protocol; // hans' stuff
 mod runtime; // chris' stuff
 
 #[cfg(test)]
 mod test;
 
-pub use runtime::{errors, Connector, PortBinding};
+pub use common::{AutomatonFormat, ComponentArg, PdlDiagnostic, PdlModules, PdlPragmas, Severity};
+pub use runtime::{checker, errors, Connector, PortBinding};
 
 #[cfg(feature = "ffi")]
 pub use runtime::ffi;
@@ -0,0 +1,294 @@
+use std::fmt::{self, Display, Formatter};
+
+/* The JSON values of the language server protocol. Fields of objects keep their order, so that
+messages are written as they are built. */
+#[derive(Clone, Debug, PartialEq)]
+pub enum Value {
+    Null,
+    Bool(bool),
+    Number(f64),
+    String(String),
+    Array(Vec<Value>),
+    Object(Vec<(String, Value)>),
+}
+
+static NULL: Value = Value::Null;
+
+pub fn object(fields: Vec<(&str, Value)>) -> Value {
+    Value::Object(fields.into_iter().map(|(key, value)| (key.to_string(), value)).collect())
+}
+
+impl Value {
+    // The field of an object, or null if it has no such field or is no object
+    pub fn get(&self, key: &str) -> &Value {
+        match self {
+            Value::Object(fields) => {
+                fields.iter().find(|(field, _)| field == key).map_or(&NULL, |(_, value)| value)
+            }
+            _ => &NULL,
+        }
+    }
+    pub fn is_null(&self) -> bool {
+        matches!(self, Value::Null)
+    }
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Value::String(string) => Some(string),
+            _ => None,
+        }
+    }
+    pub fn as_usize(&self) -> Option<usize> {
+        match *self {
+            Value::Number(number) if number >= 0.0 && number.fract() == 0.0 => {
+                Some(number as usize)
+            }
+            _ => None,
+        }
+    }
+    pub fn as_array(&self) -> Option<&[Value]> {
+        match self {
+            Value::Array(values) => Some(values),
+            _ => None,
+        }
+    }
+    pub fn parse(text: &str) -> Result<Value, String> {
+        let mut parser = Parser { text: text.as_bytes(), pos: 0 };
+        let value = parser.value()?;
+        parser.whitespace();
+        if parser.pos < parser.text.len() {
+            return Err(parser.error("Expected the end of the text"));
+        }
+        Ok(value)
+    }
+}
+
+impl From<bool> for Value {
+    fn from(value: bool) -> Self {
+        Value::Bool(value)
+    }
+}
+impl From<usize> for Value {
+    fn from(value: usize) -> Self {
+        Value::Number(value as f64)
+    }
+}
+impl From<&str> for Value {
+    fn from(value: &str) -> Self {
+        Value::String(value.to_string())
+    }
+}
+impl From<String> for Value {
+    fn from(value: String) -> Self {
+        Value::String(value)
+    }
+}
+impl From<Vec<Value>> for Value {
+    fn from(values: Vec<Value>) -> Self {
+        Value::Array(values)
+    }
+}
+
+impl Display for Value {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Value::Null => write!(f, "null"),
+            Value::Bool(value) => write!(f, "{}", value),
+            Value::Number(number) => write!(f, "{}", number),
+            Value::String(string) => write_string(f, string),
+            Value::Array(values) => {
+                write!(f, "[")?;
+                for (index, value) in values.iter().enumerate() {
+                    if index > 0 {
+                        write!(f, ",")?;
+                    }
+                    write!(f, "{}", value)?;
+                }
+                write!(f, "]")
+            }
+            Value::Object(fields) => {
+                write!(f, "{{")?;
+                for (index, (key, value)) in fields.iter().enumerate() {
+                    if index > 0 {
+                        write!(f, ",")?;
+                    }
+                    write_string(f, key)?;
+                    write!(f, ":{}", value)?;
+                }
+                write!(f, "}}")
+            }
+        }
+    }
+}
+
+fn write_string(f: &mut Formatter<'_>, string: &str) -> fmt::Result {
+    write!(f, "\"")?;
+    for c in string.chars() {
+        match c {
+            '"' => write!(f, "\\\"")?,
+            '\\' => write!(f, "\\\\")?,
+            '\n' => write!(f, "\\n")?,
+            '\r' => write!(f, "\\r")?,
+            '\t' => write!(f, "\\t")?,
+            c if (c as u32) < 0x20 => write!(f, "\\u{:04x}", c as u32)?,
+            c => write!(f, "{}", c)?,
+        }
+    }
+    write!(f, "\"")
+}
+
+struct Parser<'a> {
+    text: &'a [u8],
+    pos: usize,
+}
+
+impl Parser<'_> {
+    fn error(&self, message: &str) -> String {
+        format!("{} at offset {}", message, self.pos)
+    }
+    fn whitespace(&mut self) {
+        while let Some(b' ') | Some(b'\t') | Some(b'\n') | Some(b'\r') = self.peek() {
+            self.pos += 1;
+        }
+    }
+    fn peek(&self) -> Option<u8> {
+        self.text.get(self.pos).copied()
+    }
+    fn expect(&mut self, expected: &str) -> Result<(), String> {
+        if self.text[self.pos..].starts_with(expected.as_bytes()) {
+            self.pos += expected.len();
+            Ok(())
+        } else {
+            Err(self.error(&format!("Expected `{}`", expected)))
+        }
+    }
+    fn value(&mut self) -> Result<Value, String> {
+        self.whitespace();
+        match self.peek() {
+            Some(b'n') => self.expect("null").map(|()| Value::Null),
+            Some(b't') => self.expect("true").map(|()| Value::Bool(true)),
+            Some(b'f') => self.expect("false").map(|()| Value::Bool(false)),
+            Some(b'"') => self.string().map(Value::String),
+            Some(b'[') => {
+                self.pos += 1;
+                let mut values = Vec::new();
+                self.whitespace();
+                if self.peek() == Some(b']') {
+                    self.pos += 1;
+                    return Ok(Value::Array(values));
+                }
+                loop {
+                    values.push(self.value()?);
+                    self.whitespace();
+                    match self.peek() {
+                        Some(b',') => self.pos += 1,
+                        Some(b']') => {
+                            self.pos += 1;
+                            return Ok(Value::Array(values));
+                        }
+                        _ => return Err(self.error("Expected `,` or `]`")),
+                    }
+                }
+            }
+            Some(b'{') => {
+                self.pos += 1;
+                let mut fields = Vec::new();
+                self.whitespace();
+                if self.peek() == Some(b'}') {
+                    self.pos += 1;
+                    return Ok(Value::Object(fields));
+                }
+                loop {
+                    self.whitespace();
+                    if self.peek() != Some(b'"') {
+                        return Err(self.error("Expected a string"));
+                    }
+                    let key = self.string()?;
+                    self.whitespace();
+                    self.expect(":")?;
+                    fields.push((key, self.value()?));
+                    self.whitespace();
+                    match self.peek() {
+                        Some(b',') => self.pos += 1,
+                        Some(b'}') => {
+                            self.pos += 1;
+                            return Ok(Value::Object(fields));
+                        }
+                        _ => return Err(self.error("Expected `,` or `}`")),
+                    }
+                }
+            }
+            Some(b'-') | Some(b'0'..=b'9') => {
+                let start = self.pos;
+                while let Some(b'-') | Some(b'+') | Some(b'.') | Some(b'e') | Some(b'E')
+                | Some(b'0'..=b'9') = self.peek()
+                {
+                    self.pos += 1;
+                }
+                let number = std::str::from_utf8(&self.text[start..self.pos]).unwrap();
+                number.parse().map(Value::Number).map_err(|_| self.error("Invalid number"))
+            }
+            _ => Err(self.error("Expected a value")),
+        }
+    }
+    // The opening quote is at the current position
+    fn string(&mut self) -> Result<String, String> {
+        self.pos += 1;
+        let mut bytes = Vec::new();
+        loop {
+            match self.peek() {
+                None => return Err(self.error("Unterminated string")),
+                Some(b'"') => {
+                    self.pos += 1;
+                    return String::from_utf8(bytes).map_err(|_| self.error("Invalid UTF-8"));
+                }
+                Some(b'\\') => {
+                    self.pos += 1;
+                    let c = match self.peek() {
+                        Some(b'"') => '"',
+                        Some(b'\\') => '\\',
+                        Some(b'/') => '/',
+                        Some(b'b') => '\u{8}',
+                        Some(b'f') => '\u{c}',
+                        Some(b'n') => '\n',
+                        Some(b'r') => '\r',
+                        Some(b't') => '\t',
+                        Some(b'u') => {
+                            let mut code = self.hex()?;
+                            // Characters outside the basic plane are written as surrogate pairs
+                            if (0xD800..0xDC00).contains(&code) {
+                                self.pos += 1;
+                                self.expect("\\")?;
+                                let low = self.hex()?;
+                                if !(0xDC00..0xE000).contains(&low) {
+                                    return Err(self.error("Invalid surrogate pair"));
+                                }
+                                code = 0x10000 + ((code - 0xD800) << 10) + (low - 0xDC00);
+                            }
+                            std::char::from_u32(code)
+                                .ok_or_else(|| self.error("Invalid character"))?
+                        }
+                        _ => return Err(self.error("Invalid escape")),
+                    };
+                    self.pos += 1;
+                    let mut buffer = [0; 4];
+                    bytes.extend_from_slice(c.encode_utf8(&mut buffer).as_bytes());
+                }
+                Some(b) => {
+                    self.pos += 1;
+                    bytes.push(b);
+                }
+            }
+        }
+    }
+    // The four digits after the `u` at the current position, which ends on the last digit
+    fn hex(&mut self) -> Result<u32, String> {
+        let digits = self
+            .text
+            .get(self.pos + 1..self.pos + 5)
+            .ok_or_else(|| self.error("Invalid escape"))?;
+        let digits = std::str::from_utf8(digits).map_err(|_| self.error("Invalid escape"))?;
+        let code = u32::from_str_radix(digits, 16).map_err(|_| self.error("Invalid escape"))?;
+        self.pos += 4;
+        Ok(code)
+    }
+}
@@ -0,0 +1,316 @@
+mod json;
+
+use crate::common::*;
+use crate::protocol::ide::*;
+use json::{object, Value};
+
+use std::io::{self, BufRead, Write};
+use std::path::PathBuf;
+
+/* A language server for protocol descriptions, speaking JSON-RPC over a pair of streams. Open
+documents are parsed again on every change, which publishes their diagnostics. Their
+definitions, variables and calls can be navigated and hovered, and their definitions are listed
+as symbols. Imports are searched for next to the document, if it is a file. Positions of the protocol count
+characters from 0 in UTF-16 code units, and are converted to and from the byte columns of the sources
+by the text of their lines. */
+
+/// Serves the language server protocol on the given streams until the
+/// client exits, and returns the exit code the server should exit with:
+/// 0 if the client asked it to shut down before, and 1 otherwise.
+pub fn serve<R: BufRead, W: Write>(mut input: R, mut output: W) -> io::Result<i32> {
+    let mut server = Server { documents: HashMap::new(), shutdown: false, output: &mut output };
+    while let Some(message) = read_message(&mut input)? {
+        let message = match Value::parse(&message) {
+            Ok(message) => message,
+            Err(err) => {
+                server.error(&Value::Null, PARSE_ERROR, &err)?;
+                continue;
+            }
+        };
+        if message.get("method").as_str() == Some("exit") {
+            return Ok(if server.shutdown { 0 } else { 1 });
+        }
+        server.handle(&message)?;
+    }
+    Ok(1)
+}
+
+const PARSE_ERROR: i32 = -32700;
+const INVALID_PARAMS: i32 = -32602;
+const METHOD_NOT_FOUND: i32 = -32601;
+
+struct Server<'a, W: Write> {
+    // The open documents by their URI
+    documents: HashMap<String, Document>,
+    shutdown: bool,
+    output: &'a mut W,
+}
+
+impl<W: Write> Server<'_, W> {
+    fn handle(&mut self, message: &Value) -> io::Result<()> {
+        let id = message.get("id");
+        let params = message.get("params");
+        let uri = params.get("textDocument").get("uri").as_str().unwrap_or("").to_string();
+        let result = match message.get("method").as_str().unwrap_or("") {
+            "initialize" => object(vec![
+                (
+                    "capabilities",
+                    object(vec![
+                        // The whole text of a document is sent on every change
+                        ("textDocumentSync", 1usize.into()),
+                        ("definitionProvider", true.into()),
+                        ("hoverProvider", true.into()),
+                        ("documentSymbolProvider", true.into()),
+                    ]),
+                ),
+                (
+                    "serverInfo",
+                    object(vec![
+                        ("name", "reowolf-lsp".into()),
+                        ("version", env!("CARGO_PKG_VERSION").into()),
+                    ]),
+                ),
+            ]),
+            "shutdown" => {
+                self.shutdown = true;
+                Value::Null
+            }
+            "textDocument/didOpen" => {
+                let text = params.get("textDocument").get("text").as_str().unwrap_or("");
+                return self.update(uri, text);
+            }
+            "textDocument/didChange" => {
+                let changes = params.get("contentChanges").as_array().unwrap_or(&[]);
+                return match changes.last().and_then(|change| change.get("text").as_str()) {
+                    Some(text) => self.update(uri, text),
+                    None => Ok(()),
+                };
+            }
+            "textDocument/didClose" => {
+                self.documents.remove(&uri);
+                return self.publish(&uri, Vec::new());
+            }
+            method @ "textDocument/definition"
+            | method @ "textDocument/hover"
+            | method @ "textDocument/documentSymbol" => {
+                let document = match self.documents.get(&uri) {
+                    Some(document) => document,
+                    None => return self.error(id, INVALID_PARAMS, "Unknown document"),
+                };
+                let position = params.get("position");
+                let line = position.get("line").as_usize().unwrap_or(0) + 1;
+                let character = position.get("character").as_usize().unwrap_or(0);
+                let column = column(document.line("", line), character);
+                match method {
+                    "textDocument/definition" => match document.definition(line, column) {
+                        Some(location) => match location_uri(&uri, &location.filename) {
+                            Some(uri) => {
+                                let range = range(document, &location);
+                                object(vec![("uri", uri.into()), ("range", range)])
+                            }
+                            None => Value::Null,
+                        },
+                        None => Value::Null,
+                    },
+                    "textDocument/hover" => match document.hover(line, column) {
+                        Some(hover) => {
+                            let mut value = format!("```pdl\n{}\n```", hover.declaration);
+                            if let Some(module) = hover.module {
+                                value.push_str(&format!("\n\nfrom `{}`", module));
+                            }
+                            let contents =
+                                object(vec![("kind", "markdown".into()), ("value", value.into())]);
+                            object(vec![("contents", contents)])
+                        }
+                        None => Value::Null,
+                    },
+                    _ => {
+                        let symbols = document.symbols();
+                        symbols.iter().map(|s| symbol(document, s)).collect::<Vec<_>>().into()
+                    }
+                }
+            }
+            _ if id.is_null() => return Ok(()),
+            _ => return self.error(id, METHOD_NOT_FOUND, "Unknown method"),
+        };
+        if id.is_null() {
+            return Ok(());
+        }
+        self.send(object(vec![("jsonrpc", "2.0".into()), ("id", id.clone()), ("result", result)]))
+    }
+    // Parses the new text of a document and publishes its diagnostics
+    fn update(&mut self, uri: String, text: &str) -> io::Result<()> {
+        let mut modules = PdlModules::default();
+        if let Some(dir) = file_path(&uri).as_ref().and_then(|path| path.parent()) {
+            modules.search_path.push(dir.to_path_buf());
+        }
+        let document = Document::new(text.as_bytes(), &modules);
+        let diagnostics = document.diagnostics().iter().map(|d| diagnostic(&document, d)).collect();
+        self.documents.insert(uri.clone(), document);
+        self.publish(&uri, diagnostics)
+    }
+    fn publish(&mut self, uri: &str, diagnostics: Vec<Value>) -> io::Result<()> {
+        self.send(object(vec![
+            ("jsonrpc", "2.0".into()),
+            ("method", "textDocument/publishDiagnostics".into()),
+            ("params", object(vec![("uri", uri.into()), ("diagnostics", diagnostics.into())])),
+        ]))
+    }
+    fn error(&mut self, id: &Value, code: i32, message: &str) -> io::Result<()> {
+        let error = object(vec![("code", Value::Number(code.into())), ("message", message.into())]);
+        self.send(object(vec![("jsonrpc", "2.0".into()), ("id", id.clone()), ("error", error)]))
+    }
+    fn send(&mut self, message: Value) -> io::Result<()> {
+        let body = message.to_string();
+        write!(self.output, "Content-Length: {}\r\n\r\n{}", body.len(), body)?;
+        self.output.flush()
+    }
+}
+
+// The body of the next message, or none at the end of the input
+fn read_message<R: BufRead>(input: &mut R) -> io::Result<Option<String>> {
+    let mut length = None;
+    loop {
+        let mut line = String::new();
+        if input.read_line(&mut line)? == 0 {
+            return Ok(None);
+        }
+        let line = line.trim_end();
+        if line.is_empty() {
+            break;
+        }
+        let mut parts = line.splitn(2, ':');
+        if parts.next().unwrap().eq_ignore_ascii_case("Content-Length") {
+            length = parts.next().and_then(|value| value.trim().parse().ok());
+        }
+    }
+    let length = length.ok_or_else(|| {
+        io::Error::new(io::ErrorKind::InvalidData, "Message without a content length")
+    })?;
+    let mut body = vec![0; length];
+    input.read_exact(&mut body)?;
+    String::from_utf8(body).map(Some).map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))
+}
+
+// Lines and characters of the protocol count from 0, and lines and columns of sources from 1
+fn range(document: &Document, location: &Location) -> Value {
+    let position = |line: usize, column: usize| {
+        let character = character(document.line(&location.filename, line), column);
+        object(vec![("line", (line - 1).into()), ("character", character.into())])
+    };
+    object(vec![
+        ("start", position(location.line, location.column)),
+        ("end", position(location.end_line, location.end_column)),
+    ])
+}
+
+// The character of the protocol at a byte column of a line, which counts the UTF-16 code units
+// before it. Columns past the end of the line count one character per byte.
+fn character(line: Option<&[u8]>, column: usize) -> usize {
+    let line = line.unwrap_or(&[]);
+    let end = (column - 1).min(line.len());
+    String::from_utf8_lossy(&line[..end]).encode_utf16().count() + (column - 1 - end)
+}
+
+// The byte column of a line at a character of the protocol, the inverse of the above
+fn column(line: Option<&[u8]>, character: usize) -> usize {
+    let text = String::from_utf8_lossy(line.unwrap_or(&[]));
+    let mut units = 0;
+    for (offset, c) in text.char_indices() {
+        if units >= character {
+            return offset + 1;
+        }
+        units += c.len_utf16();
+    }
+    text.len() + (character - units) + 1
+}
+
+// Problems in imported modules are shown at the start of the document, naming their source
+fn diagnostic(document: &Document, diagnostic: &PdlDiagnostic) -> Value {
+    let location = Location {
+        filename: diagnostic.filename.clone(),
+        line: diagnostic.line,
+        column: diagnostic.column,
+        end_line: diagnostic.end_line,
+        end_column: diagnostic.end_column,
+    };
+    let (range, message) = if location.filename.is_empty() {
+        (range(document, &location), diagnostic.message.clone())
+    } else {
+        let start = Location { line: 1, column: 1, end_line: 1, end_column: 1, ..location };
+        let message = format!(
+            "{}:{}:{}: {}",
+            start.filename, diagnostic.line, diagnostic.column, diagnostic.message
+        );
+        (range(document, &start), message)
+    };
+    let severity: usize = match diagnostic.severity {
+        Severity::Error => 1,
+        Severity::Warning => 2,
+    };
+    object(vec![
+        ("range", range),
+        ("severity", severity.into()),
+        ("source", "reowolf".into()),
+        ("message", message.into()),
+    ])
+}
+
+fn symbol(document: &Document, symbol: &Symbol) -> Value {
+    // The kinds of symbols of the protocol
+    let kind: usize = match symbol.kind {
+        SymbolKind::Primitive | SymbolKind::Composite => 5,
+        SymbolKind::Function => 12,
+        SymbolKind::Struct => 23,
+        SymbolKind::Enum => 10,
+    };
+    object(vec![
+        ("name", symbol.name.as_str().into()),
+        ("detail", symbol.detail.as_str().into()),
+        ("kind", kind.into()),
+        ("range", range(document, &symbol.location)),
+        ("selectionRange", range(document, &symbol.location)),
+    ])
+}
+
+// The main description is the document itself, and the modules next to it are files of their
+// name. The bundled std.reo has no URI.
+fn location_uri(document: &str, filename: &str) -> Option<String> {
+    if filename.is_empty() {
+        return Some(document.to_string());
+    }
+    let path = file_path(document)?.parent()?.join(filename);
+    if !path.is_file() {
+        return None;
+    }
+    let mut uri = "file://".to_string();
+    for &b in path.canonicalize().ok()?.to_str()?.as_bytes() {
+        match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'/' | b'-' | b'_' | b'.' | b'~' => {
+                uri.push(b as char)
+            }
+            _ => uri.push_str(&format!("%{:02X}", b)),
+        }
+    }
+    Some(uri)
+}
+
+fn file_path(uri: &str) -> Option<PathBuf> {
+    let path = uri.strip_prefix("file://")?.as_bytes();
+    let mut bytes = Vec::new();
+    let mut i = 0;
+    while i < path.len() {
+        let escaped = path.get(i + 1..i + 3).and_then(|hex| std::str::from_utf8(hex).ok());
+        match escaped.and_then(|hex| u8::from_str_radix(hex, 16).ok()) {
+            Some(b) if path[i] == b'%' => {
+                bytes.push(b);
+                i += 3;
+            }
+            _ => {
+                bytes.push(path[i]);
+                i += 1;
+            }
+        }
+    }
+    Some(PathBuf::from(String::from_utf8(bytes).ok()?))
+}
@@ -0,0 +1,383 @@
+use crate::protocol::ast::*;
+use crate::protocol::inputsource::*;
+
+use std::collections::{HashMap, HashSet};
+
+/* Static analysis of the components that a main component is made of, which finds synchronous
+blocks that can never complete. These are only warnings: the connector is configured anyway,
+but its synchronous rounds would time out.
+
+A primitive causally depends on an input port for an output port if, in some synchronous block,
+it may get from the input before it puts on the output. Such a dependency is summarized over
+the parameters of the component, and composites connect the summaries of the components they
+create through their channels. A cycle of dependencies is a causal loop: every port on it waits
+for a message on the one before it, so none of them can fire. Besides, a synchronous block of
+which every path ends in `assert(false)` or loops forever never completes. */
+pub fn analyze(h: &Heap, main: DefinitionId) -> Vec<Warning> {
+    let mut analysis = Analysis { h, summaries: HashMap::new(), warnings: Vec::new() };
+    analysis.summary(main);
+    let mut warnings = analysis.warnings;
+    let key = |w: &Warning| (w.position.file(), w.position.line(), w.position.column());
+    warnings.sort_by_key(key);
+    warnings
+}
+
+#[derive(Debug, Clone)]
+pub struct Warning {
+    position: InputPosition,
+    message: String,
+}
+
+impl Warning {
+    fn new<S: ToString>(position: InputPosition, message: S) -> Warning {
+        Warning { position, message: message.to_string() }
+    }
+    pub fn position(&self) -> InputPosition {
+        self.position
+    }
+    pub fn message(&self) -> &str {
+        &self.message
+    }
+}
+
+// Pairs of the indices of an input and an output parameter, where the output depends on the input
+type Summary = Vec<(usize, usize)>;
+
+// A dependency of the port `to` on the port `from`, caused at the given position by a channel,
+// or by the component created there
+struct Edge {
+    from: VariableId,
+    to: VariableId,
+    position: InputPosition,
+    channel: bool,
+}
+
+struct Analysis<'a> {
+    h: &'a Heap,
+    summaries: HashMap<DefinitionId, Summary>,
+    warnings: Vec<Warning>,
+}
+
+impl Analysis<'_> {
+    // Every definition is analyzed once, when it is first created
+    fn summary(&mut self, def: DefinitionId) -> Summary {
+        if let Some(summary) = self.summaries.get(&def) {
+            return summary.clone();
+        }
+        // Recursive creations are summarized without dependencies
+        self.summaries.insert(def, Vec::new());
+        let summary = match &self.h[def] {
+            Definition::Component(Component::Primitive(_)) => self.primitive(def),
+            Definition::Component(Component::Composite(_)) => self.composite(def),
+            Definition::Function(_) => Vec::new(),
+        };
+        self.summaries.insert(def, summary.clone());
+        summary
+    }
+    fn summarize(&self, def: DefinitionId, edges: &[Edge]) -> Summary {
+        let h = self.h;
+        let params: Vec<VariableId> = h[def].parameters().iter().map(|p| p.upcast()).collect();
+        let mut summary = Vec::new();
+        for (i, &input) in params.iter().enumerate() {
+            if h[input].the_type(h).primitive != PrimitiveType::Input {
+                continue;
+            }
+            let reached = reachable(edges, input);
+            for (o, output) in params.iter().enumerate() {
+                if h[*output].the_type(h).primitive == PrimitiveType::Output
+                    && reached.contains(output)
+                {
+                    summary.push((i, o));
+                }
+            }
+        }
+        summary
+    }
+    fn primitive(&mut self, def: DefinitionId) -> Summary {
+        let h = self.h;
+        let mut edges = Vec::new();
+        for stmt in reachable_statements(h, h[def].body()) {
+            if let Statement::Synchronous(sync) = &h[stmt] {
+                if !self.synchronous(sync, &mut edges) {
+                    let warning = Warning::new(sync.position, "Synchronous block never completes");
+                    self.warnings.push(warning);
+                }
+            }
+        }
+        self.summarize(def, &edges)
+    }
+    // Collects the dependencies of the puts in a synchronous block on the gets before them,
+    // resulting in whether the end of the block is reachable
+    fn synchronous(&mut self, sync: &SynchronousStatement, edges: &mut Vec<Edge>) -> bool {
+        let h = self.h;
+        let mut completes = false;
+        // The ports that may have been gotten from before each statement
+        let mut gotten: HashMap<StatementId, HashSet<VariableId>> = HashMap::new();
+        gotten.insert(sync.body, HashSet::new());
+        let mut pending = vec![sync.body];
+        while let Some(stmt) = pending.pop() {
+            let mut ports = gotten[&stmt].clone();
+            match &h[stmt] {
+                Statement::EndSynchronous(_) => {
+                    completes = true;
+                    continue;
+                }
+                Statement::Put(put) => {
+                    gets(h, put.port, &mut ports);
+                    gets(h, put.message, &mut ports);
+                    for to in port_variables(h, put.port) {
+                        for &from in ports.iter() {
+                            edges.push(Edge { from, to, position: put.position, channel: false });
+                        }
+                    }
+                }
+                _ => {
+                    for expr in expressions(h, stmt) {
+                        gets(h, expr, &mut ports);
+                    }
+                }
+            }
+            for next in successors(h, stmt) {
+                let before = gotten.get(&next).map(|ports| ports.len());
+                let after = gotten.entry(next).or_default();
+                after.extend(ports.iter().copied());
+                if before != Some(after.len()) {
+                    pending.push(next);
+                }
+            }
+        }
+        completes
+    }
+    fn composite(&mut self, def: DefinitionId) -> Summary {
+        let h = self.h;
+        let mut edges = Vec::new();
+        for stmt in reachable_statements(h, h[def].body()) {
+            match &h[stmt] {
+                Statement::Local(LocalStatement::Channel(channel)) => edges.push(Edge {
+                    from: channel.from.upcast(),
+                    to: channel.to.upcast(),
+                    position: channel.position,
+                    channel: true,
+                }),
+                Statement::New(new) => {
+                    let call = &h[new.expression];
+                    let callee = h[call.declaration.unwrap()].as_defined().definition;
+                    for (i, o) in self.summary(callee) {
+                        for from in port_variables(h, call.arguments[i]) {
+                            for to in port_variables(h, call.arguments[o]) {
+                                let position = new.position;
+                                edges.push(Edge { from, to, position, channel: false });
+                            }
+                        }
+                    }
+                }
+                _ => {}
+            }
+        }
+        // Every cycle passes through a channel, and is reported at the first one on it
+        let mut reported: HashSet<usize> = HashSet::new();
+        for (index, edge) in edges.iter().enumerate() {
+            if !edge.channel || reported.contains(&index) {
+                continue;
+            }
+            if let Some(path) = path(&edges, edge.to, edge.from) {
+                let name = |var: VariableId| h[h[var].identifier()].to_string();
+                let mut names = vec![name(edge.from), name(edge.to)];
+                names.extend(path.iter().map(|&index| name(edges[index].to)));
+                reported.extend(path);
+                let message = format!("Causal loop: {}", names.join(" -> "));
+                self.warnings.push(Warning::new(edge.position, message));
+            }
+        }
+        self.summarize(def, &edges)
+    }
+}
+
+fn reachable(edges: &[Edge], from: VariableId) -> HashSet<VariableId> {
+    let mut reached = HashSet::new();
+    let mut pending = vec![from];
+    while let Some(var) = pending.pop() {
+        for edge in edges.iter().filter(|edge| edge.from == var) {
+            if reached.insert(edge.to) {
+                pending.push(edge.to);
+            }
+        }
+    }
+    reached
+}
+
+// The indices of the edges on a shortest path between two ports, if there is one
+fn path(edges: &[Edge], from: VariableId, to: VariableId) -> Option<Vec<usize>> {
+    let mut parents: HashMap<VariableId, usize> = HashMap::new();
+    let mut pending = std::collections::VecDeque::new();
+    pending.push_back(from);
+    while let Some(var) = pending.pop_front() {
+        if var == to {
+            let mut path = Vec::new();
+            let mut at = to;
+            while at != from {
+                let index = parents[&at];
+                path.push(index);
+                at = edges[index].from;
+            }
+            path.reverse();
+            return Some(path);
+        }
+        for (index, edge) in edges.iter().enumerate().filter(|(_, edge)| edge.from == var) {
+            if edge.to != from && !parents.contains_key(&edge.to) {
+                parents.insert(edge.to, index);
+                pending.push_back(edge.to);
+            }
+        }
+    }
+    None
+}
+
+pub(crate) fn reachable_statements(h: &Heap, body: StatementId) -> Vec<StatementId> {
+    let mut reached = vec![body];
+    let mut seen: HashSet<StatementId> = reached.iter().copied().collect();
+    let mut index = 0;
+    while index < reached.len() {
+        for next in successors(h, reached[index]) {
+            if seen.insert(next) {
+                reached.push(next);
+            }
+        }
+        index += 1;
+    }
+    reached
+}
+
+// The statements that evaluation may continue at, like the compiled code of the statement does.
+// Conditions are not evaluated, except that a constant true loop never ends and a constant false
+// assertion always fails.
+pub(crate) fn successors(h: &Heap, stmt: StatementId) -> Vec<StatementId> {
+    let constant = |expr: ExpressionId, value: bool| match &h[expr] {
+        Expression::Constant(constant) => match constant.value {
+            Constant::True => value,
+            Constant::False => !value,
+            _ => false,
+        },
+        _ => false,
+    };
+    let mut result = Vec::new();
+    match &h[stmt] {
+        Statement::Block(stmt) => result.push(stmt.first()),
+        Statement::Local(stmt) => result.extend(stmt.next()),
+        Statement::Skip(stmt) => result.extend(stmt.next),
+        Statement::Labeled(stmt) => result.push(stmt.body),
+        Statement::If(stmt) => result.extend(&[stmt.true_body, stmt.false_body]),
+        Statement::EndIf(stmt) => result.extend(stmt.next),
+        Statement::Switch(stmt) => {
+            result.extend(stmt.cases.iter().map(|case| case.body));
+            result.extend(stmt.default);
+        }
+        Statement::While(stmt) => {
+            result.push(stmt.body);
+            if !constant(stmt.test, true) {
+                result.extend(stmt.next.map(EndWhileStatementId::upcast));
+            }
+        }
+        Statement::EndWhile(stmt) => result.extend(stmt.next),
+        Statement::For(stmt) => result.extend(stmt.next.map(EndForStatementId::upcast)),
+        Statement::EndFor(stmt) => {
+            result.push(h[stmt.start].body);
+            result.extend(stmt.next);
+        }
+        Statement::Break(stmt) => result.extend(stmt.target),
+        Statement::Continue(stmt) => result.extend(stmt.target),
+        Statement::Synchronous(stmt) => result.push(stmt.body),
+        Statement::EndSynchronous(stmt) => result.extend(stmt.next),
+        Statement::Return(_) => {}
+        Statement::Assert(stmt) => {
+            if !constant(stmt.expression, false) {
+                result.extend(stmt.next);
+            }
+        }
+        Statement::Goto(stmt) => result.extend(stmt.target.map(|x| x.upcast())),
+        Statement::New(stmt) => result.extend(stmt.next),
+        Statement::Put(stmt) => result.extend(stmt.next),
+        Statement::Expression(stmt) => result.extend(stmt.next),
+    }
+    result
+}
+
+// The expressions that a statement evaluates, in order
+fn expressions(h: &Heap, stmt: StatementId) -> Vec<ExpressionId> {
+    match &h[stmt] {
+        Statement::Local(LocalStatement::Memory(stmt)) => vec![stmt.initial],
+        Statement::If(stmt) => vec![stmt.test],
+        Statement::Switch(stmt) => vec![stmt.subject],
+        Statement::While(stmt) => vec![stmt.test],
+        Statement::For(stmt) => vec![stmt.subject],
+        Statement::Return(stmt) => vec![stmt.expression],
+        Statement::Assert(stmt) => vec![stmt.expression],
+        Statement::New(stmt) => h[stmt.expression].arguments.clone(),
+        Statement::Put(stmt) => vec![stmt.port, stmt.message],
+        Statement::Expression(stmt) => vec![stmt.expression],
+        _ => Vec::new(),
+    }
+}
+
+// Adds the ports that an expression gets from to the given ones
+fn gets(h: &Heap, expr: ExpressionId, ports: &mut HashSet<VariableId>) {
+    match &h[expr] {
+        Expression::Assignment(expr) => {
+            gets(h, expr.left, ports);
+            gets(h, expr.right, ports);
+        }
+        Expression::Conditional(expr) => {
+            gets(h, expr.test, ports);
+            gets(h, expr.true_expression, ports);
+            gets(h, expr.false_expression, ports);
+        }
+        Expression::Binary(expr) => {
+            gets(h, expr.left, ports);
+            gets(h, expr.right, ports);
+        }
+        Expression::Unary(expr) => gets(h, expr.expression, ports),
+        Expression::Cast(expr) => gets(h, expr.expression, ports),
+        Expression::Indexing(expr) => {
+            gets(h, expr.subject, ports);
+            gets(h, expr.index, ports);
+        }
+        Expression::Slicing(expr) => {
+            gets(h, expr.subject, ports);
+            gets(h, expr.from_index, ports);
+            gets(h, expr.to_index, ports);
+        }
+        Expression::Select(expr) => gets(h, expr.subject, ports),
+        Expression::Array(expr) => {
+            for &element in expr.elements.iter() {
+                gets(h, element, ports);
+            }
+        }
+        Expression::Struct(expr) => {
+            for &(_, field) in expr.fields.iter() {
+                gets(h, field, ports);
+            }
+        }
+        Expression::Call(expr) => {
+            for &arg in expr.arguments.iter() {
+                gets(h, arg, ports);
+            }
+            if let Method::Get = expr.method {
+                ports.extend(port_variables(h, expr.arguments[0]));
+            }
+        }
+        Expression::Enum(_) | Expression::Constant(_) | Expression::Variable(_) => {}
+    }
+}
+
+// The variables of the ports that an expression refers to, where an element of an array of
+// ports stands for all of them
+pub(crate) fn port_variables(h: &Heap, expr: ExpressionId) -> Vec<VariableId> {
+    match &h[expr] {
+        Expression::Variable(expr) => expr.declaration.into_iter().collect(),
+        Expression::Indexing(expr) => port_variables(h, expr.subject),
+        Expression::Array(expr) => {
+            expr.elements.iter().flat_map(|&element| port_variables(h, element)).collect()
+        }
+        _ => Vec::new(),
+    }
+}
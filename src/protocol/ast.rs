@@ -60,9 +60,15 @@ impl LocalId {
     }
 }
 
-#[derive(Debug, Clone, Copy, PartialEq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub struct DefinitionId(Id<Definition>);
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct StructId(Id<Struct>);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct EnumId(Id<Enum>);
+
 #[derive(Debug, Clone, Copy, PartialEq)]
 pub struct ComponentId(DefinitionId);
 
@@ -99,7 +105,7 @@ impl PrimitiveId {
     }
 }
 
-#[derive(Debug, Clone, Copy, PartialEq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub struct StatementId(Id<Statement>);
 
 #[derive(Debug, Clone, Copy, PartialEq)]
@@ -165,6 +171,15 @@ impl IfStatementId {
     }
 }
 
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SwitchStatementId(StatementId);
+
+impl SwitchStatementId {
+    pub fn upcast(self) -> StatementId {
+        self.0
+    }
+}
+
 #[derive(Debug, Clone, Copy, PartialEq)]
 pub struct EndIfStatementId(StatementId);
 
@@ -192,6 +207,24 @@ impl EndWhileStatementId {
     }
 }
 
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ForStatementId(StatementId);
+
+impl ForStatementId {
+    pub fn upcast(self) -> StatementId {
+        self.0
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct EndForStatementId(StatementId);
+
+impl EndForStatementId {
+    pub fn upcast(self) -> StatementId {
+        self.0
+    }
+}
+
 #[derive(Debug, Clone, Copy, PartialEq)]
 pub struct BreakStatementId(StatementId);
 
@@ -282,7 +315,7 @@ impl ExpressionStatementId {
     }
 }
 
-#[derive(Debug, Clone, Copy, PartialEq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub struct ExpressionId(Id<Expression>);
 
 #[derive(Debug, Clone, Copy)]
@@ -321,6 +354,15 @@ impl UnaryExpressionId {
     }
 }
 
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CastExpressionId(ExpressionId);
+
+impl CastExpressionId {
+    pub fn upcast(self) -> ExpressionId {
+        self.0
+    }
+}
+
 #[derive(Debug, Clone, Copy, PartialEq)]
 pub struct IndexingExpressionId(ExpressionId);
 
@@ -357,6 +399,24 @@ impl ArrayExpressionId {
     }
 }
 
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct StructExpressionId(ExpressionId);
+
+impl StructExpressionId {
+    pub fn upcast(self) -> ExpressionId {
+        self.0
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct EnumExpressionId(ExpressionId);
+
+impl EnumExpressionId {
+    pub fn upcast(self) -> ExpressionId {
+        self.0
+    }
+}
+
 #[derive(Debug, Clone, Copy, PartialEq)]
 pub struct ConstantExpressionId(ExpressionId);
 
@@ -414,6 +474,8 @@ pub struct Heap {
     type_annotations: Arena<TypeAnnotation>,
     variables: Arena<Variable>,
     definitions: Arena<Definition>,
+    structs: Arena<Struct>,
+    enums: Arena<Enum>,
     statements: Arena<Statement>,
     expressions: Arena<Expression>,
     declarations: Arena<Declaration>,
@@ -429,6 +491,8 @@ impl Heap {
             type_annotations: Arena::new(),
             variables: Arena::new(),
             definitions: Arena::new(),
+            structs: Arena::new(),
+            enums: Arena::new(),
             statements: Arena::new(),
             expressions: Arena::new(),
             declarations: Arena::new(),
@@ -512,6 +576,15 @@ impl Heap {
                 .alloc_with_id(|id| Expression::Slicing(f(SlicingExpressionId(ExpressionId(id))))),
         ))
     }
+    pub fn alloc_cast_expression(
+        &mut self,
+        f: impl FnOnce(CastExpressionId) -> CastExpression,
+    ) -> CastExpressionId {
+        CastExpressionId(ExpressionId(
+            self.expressions
+                .alloc_with_id(|id| Expression::Cast(f(CastExpressionId(ExpressionId(id))))),
+        ))
+    }
     pub fn alloc_indexing_expression(
         &mut self,
         f: impl FnOnce(IndexingExpressionId) -> IndexingExpression,
@@ -540,6 +613,24 @@ impl Heap {
                 .alloc_with_id(|id| Expression::Array(f(ArrayExpressionId(ExpressionId(id))))),
         ))
     }
+    pub fn alloc_struct_expression(
+        &mut self,
+        f: impl FnOnce(StructExpressionId) -> StructExpression,
+    ) -> StructExpressionId {
+        StructExpressionId(ExpressionId(
+            self.expressions
+                .alloc_with_id(|id| Expression::Struct(f(StructExpressionId(ExpressionId(id))))),
+        ))
+    }
+    pub fn alloc_enum_expression(
+        &mut self,
+        f: impl FnOnce(EnumExpressionId) -> EnumExpression,
+    ) -> EnumExpressionId {
+        EnumExpressionId(ExpressionId(
+            self.expressions
+                .alloc_with_id(|id| Expression::Enum(f(EnumExpressionId(ExpressionId(id))))),
+        ))
+    }
     pub fn alloc_constant_expression(
         &mut self,
         f: impl FnOnce(ConstantExpressionId) -> ConstantExpression,
@@ -615,6 +706,15 @@ impl Heap {
             self.statements.alloc_with_id(|id| Statement::If(f(IfStatementId(StatementId(id))))),
         ))
     }
+    pub fn alloc_switch_statement(
+        &mut self,
+        f: impl FnOnce(SwitchStatementId) -> SwitchStatement,
+    ) -> SwitchStatementId {
+        SwitchStatementId(StatementId(
+            self.statements
+                .alloc_with_id(|id| Statement::Switch(f(SwitchStatementId(StatementId(id))))),
+        ))
+    }
     pub fn alloc_end_if_statement(
         &mut self,
         f: impl FnOnce(EndIfStatementId) -> EndIfStatement,
@@ -642,6 +742,23 @@ impl Heap {
                 .alloc_with_id(|id| Statement::EndWhile(f(EndWhileStatementId(StatementId(id))))),
         ))
     }
+    pub fn alloc_for_statement(
+        &mut self,
+        f: impl FnOnce(ForStatementId) -> ForStatement,
+    ) -> ForStatementId {
+        ForStatementId(StatementId(
+            self.statements.alloc_with_id(|id| Statement::For(f(ForStatementId(StatementId(id))))),
+        ))
+    }
+    pub fn alloc_end_for_statement(
+        &mut self,
+        f: impl FnOnce(EndForStatementId) -> EndForStatement,
+    ) -> EndForStatementId {
+        EndForStatementId(StatementId(
+            self.statements
+                .alloc_with_id(|id| Statement::EndFor(f(EndForStatementId(StatementId(id))))),
+        ))
+    }
     pub fn alloc_break_statement(
         &mut self,
         f: impl FnOnce(BreakStatementId) -> BreakStatement,
@@ -758,6 +875,12 @@ impl Heap {
                 .alloc_with_id(|id| Definition::Function(f(FunctionId(DefinitionId(id))))),
         ))
     }
+    pub fn alloc_struct(&mut self, f: impl FnOnce(StructId) -> Struct) -> StructId {
+        StructId(self.structs.alloc_with_id(|id| f(StructId(id))))
+    }
+    pub fn alloc_enum(&mut self, f: impl FnOnce(EnumId) -> Enum) -> EnumId {
+        EnumId(self.enums.alloc_with_id(|id| f(EnumId(id))))
+    }
     pub fn alloc_pragma(&mut self, f: impl FnOnce(PragmaId) -> Pragma) -> PragmaId {
         PragmaId(self.pragmas.alloc_with_id(|id| f(PragmaId(id))))
     }
@@ -786,6 +909,13 @@ impl Heap {
         ))
     }
 
+    // The protocol description, or imported module, that defines the definition
+    pub fn root_of(&self, def: DefinitionId) -> Option<RootId> {
+        self.protocol_descriptions
+            .iter()
+            .find(|(_, root)| root.definitions.contains(&def))
+            .map(|(_, root)| root.this)
+    }
     pub fn get_external_identifier(&mut self, ident: &[u8]) -> ExternalIdentifierId {
         for (_, id) in self.identifiers.iter() {
             if id.is_external() && id.ident() == ident {
@@ -880,6 +1010,20 @@ impl Index<DefinitionId> for Heap {
     }
 }
 
+impl Index<StructId> for Heap {
+    type Output = Struct;
+    fn index(&self, index: StructId) -> &Self::Output {
+        &self.structs[index.0]
+    }
+}
+
+impl Index<EnumId> for Heap {
+    type Output = Enum;
+    fn index(&self, index: EnumId) -> &Self::Output {
+        &self.enums[index.0]
+    }
+}
+
 impl Index<ComponentId> for Heap {
     type Output = Component;
     fn index(&self, index: ComponentId) -> &Self::Output {
@@ -982,6 +1126,13 @@ impl Index<IfStatementId> for Heap {
     }
 }
 
+impl Index<SwitchStatementId> for Heap {
+    type Output = SwitchStatement;
+    fn index(&self, index: SwitchStatementId) -> &Self::Output {
+        self.statements[(index.0).0].as_switch()
+    }
+}
+
 impl Index<EndIfStatementId> for Heap {
     type Output = EndIfStatement;
     fn index(&self, index: EndIfStatementId) -> &Self::Output {
@@ -1002,6 +1153,26 @@ impl IndexMut<WhileStatementId> for Heap {
     }
 }
 
+impl Index<ForStatementId> for Heap {
+    type Output = ForStatement;
+    fn index(&self, index: ForStatementId) -> &Self::Output {
+        self.statements[(index.0).0].as_for()
+    }
+}
+
+impl IndexMut<ForStatementId> for Heap {
+    fn index_mut(&mut self, index: ForStatementId) -> &mut Self::Output {
+        self.statements[(index.0).0].as_for_mut()
+    }
+}
+
+impl Index<EndForStatementId> for Heap {
+    type Output = EndForStatement;
+    fn index(&self, index: EndForStatementId) -> &Self::Output {
+        self.statements[(index.0).0].as_end_for()
+    }
+}
+
 impl Index<BreakStatementId> for Heap {
     type Output = BreakStatement;
     fn index(&self, index: BreakStatementId) -> &Self::Output {
@@ -1124,6 +1295,19 @@ impl Index<BinaryExpressionId> for Heap {
     }
 }
 
+impl IndexMut<BinaryExpressionId> for Heap {
+    fn index_mut(&mut self, index: BinaryExpressionId) -> &mut Self::Output {
+        self.expressions[(index.0).0].as_binary_mut()
+    }
+}
+
+impl Index<CastExpressionId> for Heap {
+    type Output = CastExpression;
+    fn index(&self, index: CastExpressionId) -> &Self::Output {
+        self.expressions[(index.0).0].as_cast()
+    }
+}
+
 impl Index<UnaryExpressionId> for Heap {
     type Output = UnaryExpression;
     fn index(&self, index: UnaryExpressionId) -> &Self::Output {
@@ -1152,6 +1336,12 @@ impl Index<SelectExpressionId> for Heap {
     }
 }
 
+impl IndexMut<SelectExpressionId> for Heap {
+    fn index_mut(&mut self, index: SelectExpressionId) -> &mut Self::Output {
+        self.expressions[(index.0).0].as_select_mut()
+    }
+}
+
 impl Index<ArrayExpressionId> for Heap {
     type Output = ArrayExpression;
     fn index(&self, index: ArrayExpressionId) -> &Self::Output {
@@ -1159,6 +1349,32 @@ impl Index<ArrayExpressionId> for Heap {
     }
 }
 
+impl Index<StructExpressionId> for Heap {
+    type Output = StructExpression;
+    fn index(&self, index: StructExpressionId) -> &Self::Output {
+        self.expressions[(index.0).0].as_struct()
+    }
+}
+
+impl IndexMut<StructExpressionId> for Heap {
+    fn index_mut(&mut self, index: StructExpressionId) -> &mut Self::Output {
+        self.expressions[(index.0).0].as_struct_mut()
+    }
+}
+
+impl Index<EnumExpressionId> for Heap {
+    type Output = EnumExpression;
+    fn index(&self, index: EnumExpressionId) -> &Self::Output {
+        self.expressions[(index.0).0].as_enum()
+    }
+}
+
+impl IndexMut<SwitchStatementId> for Heap {
+    fn index_mut(&mut self, index: SwitchStatementId) -> &mut Self::Output {
+        self.statements[(index.0).0].as_switch_mut()
+    }
+}
+
 impl Index<ConstantExpressionId> for Heap {
     type Output = ConstantExpression;
     fn index(&self, index: ConstantExpressionId) -> &Self::Output {
@@ -1166,6 +1382,12 @@ impl Index<ConstantExpressionId> for Heap {
     }
 }
 
+impl IndexMut<ConstantExpressionId> for Heap {
+    fn index_mut(&mut self, index: ConstantExpressionId) -> &mut Self::Output {
+        self.expressions[(index.0).0].as_constant_mut()
+    }
+}
+
 impl Index<CallExpressionId> for Heap {
     type Output = CallExpression;
     fn index(&self, index: CallExpressionId) -> &Self::Output {
@@ -1207,6 +1429,8 @@ pub struct Root {
     pub pragmas: Vec<PragmaId>,
     pub imports: Vec<ImportId>,
     pub definitions: Vec<DefinitionId>,
+    pub structs: Vec<StructId>,
+    pub enums: Vec<EnumId>,
     // Pase 2: linker
     pub declarations: Vec<DeclarationId>,
 }
@@ -1228,6 +1452,20 @@ impl Root {
         }
         None
     }
+    pub fn get_struct_ident(&self, h: &Heap, id: &[u8]) -> Option<StructId> {
+        self.structs.iter().copied().find(|&def| h[h[def].identifier].ident() == id)
+    }
+    pub fn get_enum_ident(&self, h: &Heap, id: &[u8]) -> Option<EnumId> {
+        self.enums.iter().copied().find(|&def| h[h[def].identifier].ident() == id)
+    }
+    pub fn get_declaration_ident(&self, h: &Heap, id: &[u8]) -> Option<DeclarationId> {
+        for &decl in self.declarations.iter() {
+            if h[h[decl].identifier()].ident() == id {
+                return Some(decl);
+            }
+        }
+        None
+    }
     pub fn get_declaration(&self, h: &Heap, id: IdentifierId) -> Option<DeclarationId> {
         for &decl in self.declarations.iter() {
             if h[h[decl].identifier()] == h[id] {
@@ -1236,6 +1474,27 @@ impl Root {
         }
         None
     }
+    pub fn version(&self, h: &Heap) -> Option<i64> {
+        self.pragmas.iter().find_map(|&pragma| match h[pragma].value {
+            PragmaValue::Version(version) => Some(version),
+            _ => None,
+        })
+    }
+    pub fn module<'a>(&self, h: &'a Heap) -> Option<&'a [u8]> {
+        self.pragmas.iter().find_map(|&pragma| match &h[pragma].value {
+            PragmaValue::Module(name) => Some(&name[..]),
+            _ => None,
+        })
+    }
+    pub fn features<'a>(&self, h: &'a Heap) -> Vec<&'a [u8]> {
+        let mut result = Vec::new();
+        for &pragma in self.pragmas.iter() {
+            if let PragmaValue::Feature(feature) = &h[pragma].value {
+                result.push(&feature[..]);
+            }
+        }
+        result
+    }
 }
 
 impl SyntaxElement for Root {
@@ -1249,7 +1508,14 @@ pub struct Pragma {
     pub this: PragmaId,
     // Phase 1: parser
     pub position: InputPosition,
-    pub value: Vec<u8>,
+    pub value: PragmaValue,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PragmaValue {
+    Version(i64),
+    Module(Vec<u8>),
+    Feature(Vec<u8>),
 }
 
 impl SyntaxElement for Pragma {
@@ -1297,7 +1563,7 @@ impl Identifier {
             _ => panic!("Unable to cast `Identifier` to `ExternalIdentifier`"),
         }
     }
-    fn ident(&self) -> &[u8] {
+    pub fn ident(&self) -> &[u8] {
         match self {
             Identifier::External(eid) => eid.ident(),
             Identifier::Source(sid) => sid.ident(),
@@ -1353,6 +1619,9 @@ pub struct SourceIdentifier {
 }
 
 impl SourceIdentifier {
+    pub fn span(&self) -> InputSpan {
+        self.position.span(self.value.len())
+    }
     pub fn ident(&self) -> &[u8] {
         &self.value
     }
@@ -1491,11 +1760,68 @@ pub enum Constant {
     Integer(IntegerData),
 }
 
+impl Constant {
+    /// The value and type of an integer or character constant, which may be
+    /// negated by a unary minus. Integers are decimal, or hexadecimal after
+    /// `0x`, and characters are a single byte or an escape such as `'\n'`.
+    /// The constant has the integer type the context expects if its value
+    /// fits that type, and otherwise int or long; characters default to
+    /// byte. There is no such type if the value does not fit a long.
+    pub fn integer(&self, negated: bool, context: Option<&Type>) -> Option<(i64, Type)> {
+        let (value, default) = match self {
+            Constant::Integer(data) => {
+                let raw = std::str::from_utf8(data).ok()?;
+                let value = match raw.strip_prefix("0x").or_else(|| raw.strip_prefix("0X")) {
+                    Some(digits) => i128::from_str_radix(digits, 16).ok()?,
+                    None => raw.parse::<i128>().ok()?,
+                };
+                (value, Type::INT)
+            }
+            Constant::Character(data) => {
+                let code = match data.as_slice() {
+                    [b] if *b != b'\\' => *b,
+                    [b'\\', b'n'] => b'\n',
+                    [b'\\', b'r'] => b'\r',
+                    [b'\\', b't'] => b'\t',
+                    [b'\\', b'0'] => 0,
+                    [b'\\', b'\\'] => b'\\',
+                    _ => return None,
+                };
+                (i128::from(code), Type::BYTE)
+            }
+            _ => return None,
+        };
+        let value = if negated { -value } else { value };
+        let fits = |the_type: &Type| {
+            let (min, max) = match the_type {
+                t if *t == Type::BYTE => (i8::MIN as i128, i8::MAX as i128),
+                t if *t == Type::SHORT => (i16::MIN as i128, i16::MAX as i128),
+                t if *t == Type::INT => (i32::MIN as i128, i32::MAX as i128),
+                t if *t == Type::LONG => (i64::MIN as i128, i64::MAX as i128),
+                _ => return false,
+            };
+            min <= value && value <= max
+        };
+        let the_type = match context {
+            Some(t) if fits(t) => t.clone(),
+            _ if fits(&default) => default,
+            _ if fits(&Type::INT) => Type::INT,
+            _ if fits(&Type::LONG) => Type::LONG,
+            _ => return None,
+        };
+        Some((value as i64, the_type))
+    }
+}
+
 #[derive(Debug, Clone)]
 pub enum Method {
     Get,
     Fires,
     Create,
+    // A random long, outside synchronous blocks
+    Random,
+    // A nondeterministic boolean, inside synchronous blocks
+    Choose,
     Symbolic(SourceIdentifierId),
 }
 
@@ -1518,6 +1844,7 @@ pub enum Scope {
     Definition(DefinitionId),
     Block(BlockStatementId),
     Synchronous(SynchronousStatementId),
+    For(ForStatementId),
 }
 
 impl Scope {
@@ -1540,6 +1867,7 @@ impl VariableScope for Scope {
             Scope::Definition(def) => h[*def].parent_scope(h),
             Scope::Block(stmt) => h[*stmt].parent_scope(h),
             Scope::Synchronous(stmt) => h[*stmt].parent_scope(h),
+            Scope::For(stmt) => h[*stmt].parent_scope(h),
         }
     }
     fn get_variable(&self, h: &Heap, id: SourceIdentifierId) -> Option<VariableId> {
@@ -1547,6 +1875,7 @@ impl VariableScope for Scope {
             Scope::Definition(def) => h[*def].get_variable(h, id),
             Scope::Block(stmt) => h[*stmt].get_variable(h, id),
             Scope::Synchronous(stmt) => h[*stmt].get_variable(h, id),
+            Scope::For(stmt) => h[*stmt].get_variable(h, id),
         }
     }
 }
@@ -1805,6 +2134,55 @@ impl SyntaxElement for Function {
     }
 }
 
+#[derive(Debug, Clone)]
+pub struct Struct {
+    pub this: StructId,
+    // Phase 1: parser
+    pub position: InputPosition,
+    pub identifier: SourceIdentifierId,
+    pub fields: Vec<StructField>,
+}
+
+impl Struct {
+    pub fn get_field(&self, h: &Heap, id: SourceIdentifierId) -> Option<usize> {
+        self.fields.iter().position(|field| h[field.identifier] == h[id])
+    }
+}
+
+impl SyntaxElement for Struct {
+    fn position(&self) -> InputPosition {
+        self.position
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Enum {
+    pub this: EnumId,
+    // Phase 1: parser
+    pub position: InputPosition,
+    pub identifier: SourceIdentifierId,
+    pub variants: Vec<SourceIdentifierId>,
+}
+
+impl Enum {
+    pub fn get_variant(&self, h: &Heap, id: SourceIdentifierId) -> Option<usize> {
+        self.variants.iter().position(|&variant| h[variant] == h[id])
+    }
+}
+
+impl SyntaxElement for Enum {
+    fn position(&self) -> InputPosition {
+        self.position
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct StructField {
+    pub position: InputPosition,
+    pub type_annotation: TypeAnnotationId,
+    pub identifier: SourceIdentifierId,
+}
+
 #[derive(Debug, Clone)]
 pub enum Declaration {
     Defined(DefinedDeclaration),
@@ -1918,9 +2296,12 @@ pub enum Statement {
     Skip(SkipStatement),
     Labeled(LabeledStatement),
     If(IfStatement),
+    Switch(SwitchStatement),
     EndIf(EndIfStatement),
     While(WhileStatement),
     EndWhile(EndWhileStatement),
+    For(ForStatement),
+    EndFor(EndForStatement),
     Break(BreakStatement),
     Continue(ContinueStatement),
     Synchronous(SynchronousStatement),
@@ -1982,6 +2363,18 @@ impl Statement {
             _ => panic!("Unable to cast `Statement` to `IfStatement`"),
         }
     }
+    pub fn as_switch(&self) -> &SwitchStatement {
+        match self {
+            Statement::Switch(result) => result,
+            _ => panic!("Unable to cast `Statement` to `SwitchStatement`"),
+        }
+    }
+    pub fn as_switch_mut(&mut self) -> &mut SwitchStatement {
+        match self {
+            Statement::Switch(result) => result,
+            _ => panic!("Unable to cast `Statement` to `SwitchStatement`"),
+        }
+    }
     pub fn as_end_if(&self) -> &EndIfStatement {
         match self {
             Statement::EndIf(result) => result,
@@ -2012,6 +2405,24 @@ impl Statement {
             _ => panic!("Unable to cast `Statement` to `EndWhileStatement`"),
         }
     }
+    pub fn as_for(&self) -> &ForStatement {
+        match self {
+            Statement::For(result) => result,
+            _ => panic!("Unable to cast `Statement` to `ForStatement`"),
+        }
+    }
+    pub fn as_for_mut(&mut self) -> &mut ForStatement {
+        match self {
+            Statement::For(result) => result,
+            _ => panic!("Unable to cast `Statement` to `ForStatement`"),
+        }
+    }
+    pub fn as_end_for(&self) -> &EndForStatement {
+        match self {
+            Statement::EndFor(result) => result,
+            _ => panic!("Unable to cast `Statement` to `EndForStatement`"),
+        }
+    }
     pub fn as_break(&self) -> &BreakStatement {
         match self {
             Statement::Break(result) => result,
@@ -2106,9 +2517,12 @@ impl Statement {
             Statement::Skip(stmt) => stmt.next = Some(next),
             Statement::Labeled(stmt) => panic!(),
             Statement::If(stmt) => panic!(),
+            Statement::Switch(_) => panic!(),
             Statement::EndIf(stmt) => stmt.next = Some(next),
             Statement::While(stmt) => panic!(), // although while has a next field, it is linked manually
             Statement::EndWhile(stmt) => stmt.next = Some(next),
+            Statement::For(_) => panic!(), // the body is linked manually, as for while
+            Statement::EndFor(stmt) => stmt.next = Some(next),
             Statement::Break(stmt) => panic!(),
             Statement::Continue(stmt) => panic!(),
             Statement::Synchronous(stmt) => panic!(),
@@ -2131,9 +2545,12 @@ impl SyntaxElement for Statement {
             Statement::Skip(stmt) => stmt.position(),
             Statement::Labeled(stmt) => stmt.position(),
             Statement::If(stmt) => stmt.position(),
+            Statement::Switch(stmt) => stmt.position(),
             Statement::EndIf(stmt) => stmt.position(),
             Statement::While(stmt) => stmt.position(),
             Statement::EndWhile(stmt) => stmt.position(),
+            Statement::For(stmt) => stmt.position(),
+            Statement::EndFor(stmt) => stmt.position(),
             Statement::Break(stmt) => stmt.position(),
             Statement::Continue(stmt) => stmt.position(),
             Statement::Synchronous(stmt) => stmt.position(),
@@ -2162,24 +2579,28 @@ pub struct BlockStatement {
 
 impl BlockStatement {
     pub fn parent_block(&self, h: &Heap) -> Option<BlockStatementId> {
-        let parent = self.parent_scope.unwrap();
-        match parent {
-            Scope::Definition(_) => {
-                // If the parent scope is a definition, then there is no
-                // parent block.
-                None
-            }
-            Scope::Synchronous(parent) => {
-                // It is always the case that when this function is called,
-                // the parent of a synchronous statement is a block statement:
-                // nested synchronous statements are flagged illegal,
-                // and that happens before resolving variables that
-                // creates the parent_scope references in the first place.
-                Some(h[parent].parent_scope(h).unwrap().to_block())
-            }
-            Scope::Block(parent) => {
-                // A variable scope is either a definition, sync, or block.
-                Some(parent)
+        let mut parent = self.parent_scope.unwrap();
+        loop {
+            match parent {
+                Scope::Definition(_) => {
+                    // If the parent scope is a definition, then there is no
+                    // parent block.
+                    return None;
+                }
+                Scope::Synchronous(stmt) => {
+                    // Nested synchronous statements are flagged illegal, so the
+                    // parent of a synchronous statement is a block statement,
+                    // or a for statement within one.
+                    parent = h[stmt].parent_scope(h).unwrap();
+                }
+                Scope::For(stmt) => {
+                    // The loop variable is scoped to the for statement, which is no block.
+                    parent = h[stmt].parent_scope(h).unwrap();
+                }
+                Scope::Block(parent) => {
+                    // A variable scope is either a definition, sync, for, or block.
+                    return Some(parent);
+                }
             }
         }
     }
@@ -2333,6 +2754,31 @@ impl SyntaxElement for IfStatement {
     }
 }
 
+#[derive(Debug, Clone)]
+pub struct SwitchStatement {
+    pub this: SwitchStatementId,
+    // Phase 1: parser
+    pub position: InputPosition,
+    pub subject: ExpressionId,
+    pub cases: Vec<SwitchCase>,
+    pub default: Option<StatementId>,
+    // Phase 3: type checker
+    pub definition: Option<EnumId>,
+}
+
+impl SyntaxElement for SwitchStatement {
+    fn position(&self) -> InputPosition {
+        self.position
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct SwitchCase {
+    pub position: InputPosition,
+    pub variants: Vec<SourceIdentifierId>,
+    pub body: StatementId,
+}
+
 #[derive(Debug, Clone)]
 pub struct EndIfStatement {
     pub this: EndIfStatementId,
@@ -2379,6 +2825,58 @@ impl SyntaxElement for EndWhileStatement {
     }
 }
 
+#[derive(Debug, Clone)]
+pub struct ForStatement {
+    pub this: ForStatementId,
+    // Phase 1: parser
+    pub position: InputPosition,
+    // Iterates over the elements of the subject, rather than its indices
+    pub each: bool,
+    pub variable: LocalId,
+    pub subject: ExpressionId,
+    pub body: StatementId,
+    // Phase 2: linker
+    pub parent_scope: Option<Scope>,
+    pub next: Option<EndForStatementId>,
+    pub in_sync: Option<SynchronousStatementId>,
+}
+
+impl SyntaxElement for ForStatement {
+    fn position(&self) -> InputPosition {
+        self.position
+    }
+}
+
+impl VariableScope for ForStatement {
+    fn parent_scope(&self, _h: &Heap) -> Option<Scope> {
+        self.parent_scope
+    }
+    fn get_variable(&self, h: &Heap, id: SourceIdentifierId) -> Option<VariableId> {
+        if h[h[self.variable].identifier] == h[id] {
+            Some(self.variable.0)
+        } else {
+            None
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct EndForStatement {
+    pub this: EndForStatementId,
+    // Phase 2: linker
+    pub position: InputPosition, // of corresponding for
+    // Unlike the end of a while, this is where every iteration starts: it binds
+    // the loop variable and continues at the body, or ends the loop
+    pub start: ForStatementId,
+    pub next: Option<StatementId>,
+}
+
+impl SyntaxElement for EndForStatement {
+    fn position(&self) -> InputPosition {
+        self.position
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct BreakStatement {
     pub this: BreakStatementId,
@@ -2386,7 +2884,7 @@ pub struct BreakStatement {
     pub position: InputPosition,
     pub label: Option<SourceIdentifierId>,
     // Phase 2: linker
-    pub target: Option<EndWhileStatementId>,
+    pub target: Option<StatementId>, // the end of a while, or what follows a for
 }
 
 impl SyntaxElement for BreakStatement {
@@ -2402,7 +2900,7 @@ pub struct ContinueStatement {
     pub position: InputPosition,
     pub label: Option<SourceIdentifierId>,
     // Phase 2: linker
-    pub target: Option<WhileStatementId>,
+    pub target: Option<StatementId>, // a while, or the end of a for
 }
 
 impl SyntaxElement for ContinueStatement {
@@ -2557,10 +3055,13 @@ pub enum Expression {
     Conditional(ConditionalExpression),
     Binary(BinaryExpression),
     Unary(UnaryExpression),
+    Cast(CastExpression),
     Indexing(IndexingExpression),
     Slicing(SlicingExpression),
     Select(SelectExpression),
     Array(ArrayExpression),
+    Struct(StructExpression),
+    Enum(EnumExpression),
     Constant(ConstantExpression),
     Call(CallExpression),
     Variable(VariableExpression),
@@ -2585,12 +3086,24 @@ impl Expression {
             _ => panic!("Unable to cast `Expression` to `BinaryExpression`"),
         }
     }
+    pub fn as_binary_mut(&mut self) -> &mut BinaryExpression {
+        match self {
+            Expression::Binary(result) => result,
+            _ => panic!("Unable to cast `Expression` to `BinaryExpression`"),
+        }
+    }
     pub fn as_unary(&self) -> &UnaryExpression {
         match self {
             Expression::Unary(result) => result,
             _ => panic!("Unable to cast `Expression` to `UnaryExpression`"),
         }
     }
+    pub fn as_cast(&self) -> &CastExpression {
+        match self {
+            Expression::Cast(result) => result,
+            _ => panic!("Unable to cast `Expression` to `CastExpression`"),
+        }
+    }
     pub fn as_indexing(&self) -> &IndexingExpression {
         match self {
             Expression::Indexing(result) => result,
@@ -2609,18 +3122,48 @@ impl Expression {
             _ => panic!("Unable to cast `Expression` to `SelectExpression`"),
         }
     }
+    pub fn as_select_mut(&mut self) -> &mut SelectExpression {
+        match self {
+            Expression::Select(result) => result,
+            _ => panic!("Unable to cast `Expression` to `SelectExpression`"),
+        }
+    }
     pub fn as_array(&self) -> &ArrayExpression {
         match self {
             Expression::Array(result) => result,
             _ => panic!("Unable to cast `Expression` to `ArrayExpression`"),
         }
     }
+    pub fn as_struct(&self) -> &StructExpression {
+        match self {
+            Expression::Struct(result) => result,
+            _ => panic!("Unable to cast `Expression` to `StructExpression`"),
+        }
+    }
+    pub fn as_struct_mut(&mut self) -> &mut StructExpression {
+        match self {
+            Expression::Struct(result) => result,
+            _ => panic!("Unable to cast `Expression` to `StructExpression`"),
+        }
+    }
+    pub fn as_enum(&self) -> &EnumExpression {
+        match self {
+            Expression::Enum(result) => result,
+            _ => panic!("Unable to cast `Expression` to `EnumExpression`"),
+        }
+    }
     pub fn as_constant(&self) -> &ConstantExpression {
         match self {
             Expression::Constant(result) => result,
             _ => panic!("Unable to cast `Expression` to `ConstantExpression`"),
         }
     }
+    pub fn as_constant_mut(&mut self) -> &mut ConstantExpression {
+        match self {
+            Expression::Constant(result) => result,
+            _ => panic!("Unable to cast `Expression` to `ConstantExpression`"),
+        }
+    }
     pub fn as_call(&self) -> &CallExpression {
         match self {
             Expression::Call(result) => result,
@@ -2645,6 +3188,25 @@ impl Expression {
             _ => panic!("Unable to cast `Expression` to `VariableExpression`"),
         }
     }
+    // The source of the whole expression, including its operands
+    pub fn span(&self) -> InputSpan {
+        match self {
+            Expression::Assignment(expr) => expr.span,
+            Expression::Conditional(expr) => expr.span,
+            Expression::Binary(expr) => expr.span,
+            Expression::Unary(expr) => expr.span,
+            Expression::Cast(expr) => expr.span,
+            Expression::Indexing(expr) => expr.span,
+            Expression::Slicing(expr) => expr.span,
+            Expression::Select(expr) => expr.span,
+            Expression::Array(expr) => expr.span,
+            Expression::Struct(expr) => expr.span,
+            Expression::Enum(expr) => expr.span,
+            Expression::Constant(expr) => expr.span,
+            Expression::Call(expr) => expr.span,
+            Expression::Variable(expr) => expr.span,
+        }
+    }
 }
 
 impl SyntaxElement for Expression {
@@ -2654,10 +3216,13 @@ impl SyntaxElement for Expression {
             Expression::Conditional(expr) => expr.position(),
             Expression::Binary(expr) => expr.position(),
             Expression::Unary(expr) => expr.position(),
+            Expression::Cast(expr) => expr.position(),
             Expression::Indexing(expr) => expr.position(),
             Expression::Slicing(expr) => expr.position(),
             Expression::Select(expr) => expr.position(),
             Expression::Array(expr) => expr.position(),
+            Expression::Struct(expr) => expr.position(),
+            Expression::Enum(expr) => expr.position(),
             Expression::Constant(expr) => expr.position(),
             Expression::Call(expr) => expr.position(),
             Expression::Variable(expr) => expr.position(),
@@ -2685,6 +3250,7 @@ pub struct AssignmentExpression {
     pub this: AssignmentExpressionId,
     // Phase 1: parser
     pub position: InputPosition,
+    pub span: InputSpan,
     pub left: ExpressionId,
     pub operation: AssignmentOperator,
     pub right: ExpressionId,
@@ -2701,6 +3267,7 @@ pub struct ConditionalExpression {
     pub this: ConditionalExpressionId,
     // Phase 1: parser
     pub position: InputPosition,
+    pub span: InputSpan,
     pub test: ExpressionId,
     pub true_expression: ExpressionId,
     pub false_expression: ExpressionId,
@@ -2740,9 +3307,12 @@ pub struct BinaryExpression {
     pub this: BinaryExpressionId,
     // Phase 1: parser
     pub position: InputPosition,
+    pub span: InputSpan,
     pub left: ExpressionId,
     pub operation: BinaryOperator,
     pub right: ExpressionId,
+    // Phase 3: type checker
+    pub the_type: Option<Type>,
 }
 
 impl SyntaxElement for BinaryExpression {
@@ -2768,6 +3338,7 @@ pub struct UnaryExpression {
     pub this: UnaryExpressionId,
     // Phase 1: parser
     pub position: InputPosition,
+    pub span: InputSpan,
     pub operation: UnaryOperation,
     pub expression: ExpressionId,
 }
@@ -2778,11 +3349,30 @@ impl SyntaxElement for UnaryExpression {
     }
 }
 
+// Conversion of an integer to another integer type, which is truncated to
+// fit a narrower type
+#[derive(Debug, Clone)]
+pub struct CastExpression {
+    pub this: CastExpressionId,
+    // Phase 1: parser
+    pub position: InputPosition,
+    pub span: InputSpan,
+    pub the_type: Type,
+    pub expression: ExpressionId,
+}
+
+impl SyntaxElement for CastExpression {
+    fn position(&self) -> InputPosition {
+        self.position
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct IndexingExpression {
     pub this: IndexingExpressionId,
     // Phase 1: parser
     pub position: InputPosition,
+    pub span: InputSpan,
     pub subject: ExpressionId,
     pub index: ExpressionId,
 }
@@ -2798,6 +3388,7 @@ pub struct SlicingExpression {
     pub this: SlicingExpressionId,
     // Phase 1: parser
     pub position: InputPosition,
+    pub span: InputSpan,
     pub subject: ExpressionId,
     pub from_index: ExpressionId,
     pub to_index: ExpressionId,
@@ -2814,8 +3405,11 @@ pub struct SelectExpression {
     pub this: SelectExpressionId,
     // Phase 1: parser
     pub position: InputPosition,
+    pub span: InputSpan,
     pub subject: ExpressionId,
     pub field: Field,
+    // Phase 3: type checker
+    pub field_index: Option<usize>,
 }
 
 impl SyntaxElement for SelectExpression {
@@ -2829,6 +3423,7 @@ pub struct ArrayExpression {
     pub this: ArrayExpressionId,
     // Phase 1: parser
     pub position: InputPosition,
+    pub span: InputSpan,
     pub elements: Vec<ExpressionId>,
 }
 
@@ -2838,11 +3433,46 @@ impl SyntaxElement for ArrayExpression {
     }
 }
 
+#[derive(Debug, Clone)]
+pub struct StructExpression {
+    pub this: StructExpressionId,
+    // Phase 1: parser
+    pub position: InputPosition,
+    pub span: InputSpan,
+    pub identifier: SourceIdentifierId,
+    pub fields: Vec<(SourceIdentifierId, ExpressionId)>,
+    // Phase 3: type checker
+    pub definition: Option<StructId>,
+}
+
+impl SyntaxElement for StructExpression {
+    fn position(&self) -> InputPosition {
+        self.position
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct EnumExpression {
+    pub this: EnumExpressionId,
+    // Phase 1: parser
+    pub position: InputPosition,
+    pub span: InputSpan,
+    pub identifier: SourceIdentifierId,
+    pub variant: SourceIdentifierId,
+}
+
+impl SyntaxElement for EnumExpression {
+    fn position(&self) -> InputPosition {
+        self.position
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct CallExpression {
     pub this: CallExpressionId,
     // Phase 1: parser
     pub position: InputPosition,
+    pub span: InputSpan,
     pub method: Method,
     pub arguments: Vec<ExpressionId>,
     // Phase 2: linker
@@ -2860,7 +3490,10 @@ pub struct ConstantExpression {
     pub this: ConstantExpressionId,
     // Phase 1: parser
     pub position: InputPosition,
+    pub span: InputSpan,
     pub value: Constant,
+    // Phase 3: type checker
+    pub the_type: Option<Type>,
 }
 
 impl SyntaxElement for ConstantExpression {
@@ -2874,6 +3507,7 @@ pub struct VariableExpression {
     pub this: VariableExpressionId,
     // Phase 1: parser
     pub position: InputPosition,
+    pub span: InputSpan,
     pub identifier: SourceIdentifierId,
     // Phase 2: linker
     pub declaration: Option<VariableId>,
@@ -0,0 +1,697 @@
+use crate::protocol::analysis::{reachable_statements, successors};
+use crate::protocol::ast::*;
+use crate::protocol::inputsource::*;
+use crate::protocol::printer::*;
+
+use std::collections::{BTreeSet, HashMap, HashSet};
+
+/* Compiles a primitive component to a constraint automaton, by symbolically executing its
+synchronous blocks. The states of the automaton are the synchronous blocks of the component,
+where it waits for the next round. Every path through a block, and from its end to the next
+block, is a transition: it fires the ports that the path gets from and puts on, and all other
+ports of the component are silent. The data constraints of a transition are the conditions that
+the path takes, and the messages that it puts. The memory of the component, its parameters and
+the variables declared outside of synchronous blocks, is updated by the assignments on the path.
+
+Expressions are shown in terms of the values that memory cells have at the start of the round,
+where `d(p)` is the message that passes port `p`. Conditions that are not fires tests are taken
+either way, unless the opposite condition was taken before on the same path, so a transition may
+be unsatisfiable. A path passes every statement at most twice, so loops are unrolled a bounded
+number of times; longer paths are left out, and the automaton is marked incomplete. */
+pub fn automaton(h: &Heap, def: DefinitionId) -> Automaton {
+    let mut exporter = Exporter { h, complete: true };
+    let body = h[def].body();
+    let mut blocks: Vec<SynchronousStatementId> = reachable_statements(h, body)
+        .into_iter()
+        .filter_map(|stmt| match &h[stmt] {
+            Statement::Synchronous(sync) => Some(sync.this),
+            _ => None,
+        })
+        .collect();
+    blocks.sort_by_key(|&sync| (h[sync].position.line(), h[sync].position.column()));
+    let state = |target: Option<StatementId>| {
+        target.map(|stmt| blocks.iter().position(|&sync| sync.upcast() == stmt).unwrap())
+    };
+
+    let mut initial = Vec::new();
+    for (target, _) in exporter.paths(body, Path::default()) {
+        if !initial.contains(&state(target)) {
+            initial.push(state(target));
+        }
+    }
+    let mut transitions = Vec::new();
+    for (from, &sync) in blocks.iter().enumerate() {
+        let path = Path { in_sync: true, ..Path::default() };
+        for (target, path) in exporter.paths(h[sync].body, path) {
+            if path.fire.intersection(&path.silent).next().is_some() {
+                continue;
+            }
+            let mut updates: Vec<String> = path
+                .assigned
+                .iter()
+                .filter(|var| !path.scoped.contains(var))
+                .map(|&var| format!("{} := {}", name(h, var), path.values[&var].0))
+                .collect();
+            updates.extend(path.effects);
+            let transition = Transition {
+                from,
+                to: state(target),
+                fire: path.fire,
+                guards: path.guards,
+                data: path.data,
+                updates,
+            };
+            if !transitions.contains(&transition) {
+                transitions.push(transition);
+            }
+        }
+    }
+
+    let mut ports = Vec::new();
+    let mut memory = Vec::new();
+    for &param in h[def].parameters().iter() {
+        let the_type = &h[h[param].type_annotation].the_type;
+        match the_type.primitive {
+            PrimitiveType::Input | PrimitiveType::Output => {
+                ports.push((name(h, param.upcast()), the_type.to_string()))
+            }
+            _ => memory.push(name(h, param.upcast())),
+        }
+    }
+    // Variables declared in synchronous blocks are not kept across rounds
+    let mut within = HashSet::new();
+    for &sync in blocks.iter() {
+        within.extend(block_statements(h, sync));
+    }
+    for stmt in reachable_statements(h, body) {
+        if let Statement::Local(LocalStatement::Memory(local)) = &h[stmt] {
+            if !within.contains(&stmt) {
+                memory.push(name(h, local.variable.upcast()));
+            }
+        }
+    }
+
+    Automaton {
+        name: h[h[def].identifier()].to_string(),
+        ports,
+        memory,
+        states: blocks.iter().map(|&sync| h[sync].position).collect(),
+        initial,
+        transitions,
+        complete: exporter.complete,
+    }
+}
+
+// The number of times that a path may pass the same statement
+const VISITS: usize = 2;
+// The number of paths that are explored from a single statement
+const PATHS: usize = 1024;
+
+#[derive(Debug, Clone)]
+pub struct Automaton {
+    name: String,
+    // The names and types of the port parameters
+    ports: Vec<(String, String)>,
+    memory: Vec<String>,
+    // The positions of the synchronous blocks
+    states: Vec<InputPosition>,
+    // The states that are reached first, where none stands for the end of the component
+    initial: Vec<Option<usize>>,
+    transitions: Vec<Transition>,
+    complete: bool,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct Transition {
+    from: usize,
+    to: Option<usize>,
+    fire: BTreeSet<String>,
+    guards: Vec<String>,
+    data: Vec<String>,
+    updates: Vec<String>,
+}
+
+impl Automaton {
+    pub fn to_json(&self) -> String {
+        let strings = |items: &mut dyn Iterator<Item = &String>| {
+            let items: Vec<String> = items.map(|item| json_string(item)).collect();
+            format!("[{}]", items.join(", "))
+        };
+        let state = |state: Option<usize>| state.map_or("null".to_string(), |s| s.to_string());
+        let mut out = String::from("{\n");
+        out.push_str(&format!("  \"name\": {},\n", json_string(&self.name)));
+        let ports: Vec<String> = self
+            .ports
+            .iter()
+            .map(|(name, the_type)| {
+                format!("{{\"name\": {}, \"type\": {}}}", json_string(name), json_string(the_type))
+            })
+            .collect();
+        out.push_str(&format!("  \"ports\": [{}],\n", ports.join(", ")));
+        out.push_str(&format!("  \"memory\": {},\n", strings(&mut self.memory.iter())));
+        let states: Vec<String> = self
+            .states
+            .iter()
+            .enumerate()
+            .map(|(id, pos)| {
+                format!(
+                    "{{\"id\": {}, \"line\": {}, \"column\": {}}}",
+                    id,
+                    pos.line(),
+                    pos.column()
+                )
+            })
+            .collect();
+        out.push_str(&format!("  \"states\": [{}],\n", states.join(", ")));
+        let initial: Vec<String> = self.initial.iter().map(|&s| state(s)).collect();
+        out.push_str(&format!("  \"initial\": [{}],\n", initial.join(", ")));
+        out.push_str("  \"transitions\": [");
+        for (index, t) in self.transitions.iter().enumerate() {
+            out.push_str(if index == 0 { "\n" } else { ",\n" });
+            out.push_str(&format!(
+                "    {{\"from\": {}, \"to\": {}, \"fire\": {}, \"guards\": {}, \"data\": {}, \"updates\": {}}}",
+                t.from,
+                state(t.to),
+                strings(&mut t.fire.iter()),
+                strings(&mut t.guards.iter()),
+                strings(&mut t.data.iter()),
+                strings(&mut t.updates.iter()),
+            ));
+        }
+        if !self.transitions.is_empty() {
+            out.push_str("\n  ");
+        }
+        out.push_str("],\n");
+        out.push_str(&format!("  \"complete\": {}\n}}\n", self.complete));
+        out
+    }
+    // States are labeled with the positions of their synchronous blocks, and transitions with
+    // their firing set, followed by a line for every data constraint and update
+    pub fn to_dot(&self) -> String {
+        let node = |state: Option<usize>| state.map_or("end".to_string(), |s| format!("s{}", s));
+        let mut out = format!("digraph {} {{\n", dot_string(&self.name));
+        out.push_str("\tnode [shape=circle];\n");
+        out.push_str("\tstart [shape=point];\n");
+        for (id, pos) in self.states.iter().enumerate() {
+            out.push_str(&format!("\ts{} [label=\"{}:{}\"];\n", id, pos.line(), pos.column()));
+        }
+        let ends = self.initial.contains(&None) || self.transitions.iter().any(|t| t.to.is_none());
+        if ends {
+            out.push_str("\tend [shape=doublecircle, label=\"\"];\n");
+        }
+        for &state in self.initial.iter() {
+            out.push_str(&format!("\tstart -> {};\n", node(state)));
+        }
+        for t in self.transitions.iter() {
+            let fire: Vec<&str> = t.fire.iter().map(|port| &port[..]).collect();
+            let mut lines = vec![format!("{{{}}}", fire.join(", "))];
+            lines.extend(t.guards.iter().chain(t.data.iter()).chain(t.updates.iter()).cloned());
+            let label: Vec<String> = lines.iter().map(|line| dot_string(line)).collect();
+            // Every line is left aligned
+            let label: Vec<&str> = label.iter().map(|line| &line[1..line.len() - 1]).collect();
+            out.push_str(&format!(
+                "\ts{} -> {} [label=\"{}\\l\"];\n",
+                t.from,
+                node(t.to),
+                label.join("\\l")
+            ));
+        }
+        out.push_str("}\n");
+        out
+    }
+}
+
+fn json_string(text: &str) -> String {
+    let mut out = String::from("\"");
+    for c in text.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+pub(crate) fn dot_string(text: &str) -> String {
+    let mut out = String::from("\"");
+    for c in text.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+fn name(h: &Heap, var: VariableId) -> String {
+    h[h[var].identifier()].to_string()
+}
+
+// The statements of a synchronous block, up to its end
+fn block_statements(h: &Heap, sync: SynchronousStatementId) -> HashSet<StatementId> {
+    let mut seen: HashSet<StatementId> = HashSet::new();
+    let mut pending = vec![h[sync].body];
+    while let Some(stmt) = pending.pop() {
+        if let Statement::EndSynchronous(_) = &h[stmt] {
+            continue;
+        }
+        if seen.insert(stmt) {
+            pending.extend(successors(h, stmt));
+        }
+    }
+    seen
+}
+
+// An expression shown as text, with the precedence of its outermost operator
+type Term = (String, u8);
+
+fn wrap(term: Term, precedence: u8) -> String {
+    if term.1 < precedence {
+        format!("({})", term.0)
+    } else {
+        term.0
+    }
+}
+
+fn binary_term(operation: &BinaryOperator, left: Term, right: Term) -> Term {
+    let (operator, precedence) = binary_operator(operation);
+    let text = format!("{} {} {}", wrap(left, precedence), operator, wrap(right, precedence + 1));
+    (text, precedence)
+}
+
+// The operator that a compound assignment applies, or none for a plain one
+fn compound(operation: &AssignmentOperator) -> Option<BinaryOperator> {
+    match operation {
+        AssignmentOperator::Set => None,
+        AssignmentOperator::Multiplied => Some(BinaryOperator::Multiply),
+        AssignmentOperator::Divided => Some(BinaryOperator::Divide),
+        AssignmentOperator::Remained => Some(BinaryOperator::Remainder),
+        AssignmentOperator::Added => Some(BinaryOperator::Add),
+        AssignmentOperator::Subtracted => Some(BinaryOperator::Subtract),
+        AssignmentOperator::ShiftedLeft => Some(BinaryOperator::ShiftLeft),
+        AssignmentOperator::ShiftedRight => Some(BinaryOperator::ShiftRight),
+        AssignmentOperator::BitwiseAnded => Some(BinaryOperator::BitwiseAnd),
+        AssignmentOperator::BitwiseXored => Some(BinaryOperator::BitwiseXor),
+        AssignmentOperator::BitwiseOred => Some(BinaryOperator::BitwiseOr),
+    }
+}
+
+// The comparison that holds exactly when the given one does not
+fn opposite(operation: &BinaryOperator) -> Option<BinaryOperator> {
+    match operation {
+        BinaryOperator::Equality => Some(BinaryOperator::Inequality),
+        BinaryOperator::Inequality => Some(BinaryOperator::Equality),
+        BinaryOperator::LessThan => Some(BinaryOperator::GreaterThanEqual),
+        BinaryOperator::GreaterThanEqual => Some(BinaryOperator::LessThan),
+        BinaryOperator::GreaterThan => Some(BinaryOperator::LessThanEqual),
+        BinaryOperator::LessThanEqual => Some(BinaryOperator::GreaterThan),
+        _ => None,
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+struct Path {
+    visits: HashMap<StatementId, usize>,
+    in_sync: bool,
+    fire: BTreeSet<String>,
+    silent: BTreeSet<String>,
+    // The conditions taken
+    guards: Vec<String>,
+    data: Vec<String>,
+    // The values assigned to variables, in the order they were first assigned
+    values: HashMap<VariableId, Term>,
+    assigned: Vec<VariableId>,
+    // Assignments to parts of variables, such as elements of arrays
+    effects: Vec<String>,
+    // Variables declared in the synchronous block, which are not kept after it
+    scoped: HashSet<VariableId>,
+}
+
+impl Path {
+    fn assign(&mut self, var: VariableId, value: Term) {
+        if !self.assigned.contains(&var) {
+            self.assigned.push(var);
+        }
+        self.values.insert(var, value);
+    }
+    // Takes a condition, resulting in false if the path took its negation before
+    fn take(&mut self, condition: String, negation: String) -> bool {
+        if self.guards.contains(&negation) {
+            return false;
+        }
+        if !self.guards.contains(&condition) {
+            self.guards.push(condition);
+        }
+        true
+    }
+}
+
+struct Exporter<'a> {
+    h: &'a Heap,
+    complete: bool,
+}
+
+impl Exporter<'_> {
+    // The paths from a statement to the next synchronous block that they reach, or to the end
+    // of the component
+    fn paths(&mut self, start: StatementId, path: Path) -> Vec<(Option<StatementId>, Path)> {
+        let h = self.h;
+        let mut result = Vec::new();
+        let mut pending = vec![(start, path)];
+        while let Some((stmt, mut path)) = pending.pop() {
+            if result.len() + pending.len() > PATHS {
+                self.complete = false;
+                break;
+            }
+            let visits = path.visits.entry(stmt).or_insert(0);
+            *visits += 1;
+            if *visits > VISITS {
+                self.complete = false;
+                continue;
+            }
+            let branches: Vec<(Option<StatementId>, Path)> = match &h[stmt] {
+                Statement::Synchronous(_) => {
+                    result.push((Some(stmt), path));
+                    continue;
+                }
+                Statement::EndSynchronous(stmt) => {
+                    path.in_sync = false;
+                    vec![(stmt.next, path)]
+                }
+                Statement::Local(LocalStatement::Memory(stmt)) => {
+                    let value = self.term(stmt.initial, &mut path);
+                    let var = stmt.variable.upcast();
+                    if path.in_sync {
+                        path.scoped.insert(var);
+                    }
+                    path.assign(var, value);
+                    vec![(stmt.next, path)]
+                }
+                Statement::Expression(stmt) => {
+                    self.term(stmt.expression, &mut path);
+                    vec![(stmt.next, path)]
+                }
+                Statement::Put(stmt) => {
+                    let port = self.term(stmt.port, &mut path).0;
+                    let message = self.term(stmt.message, &mut path).0;
+                    path.data.push(format!("d({}) = {}", port, message));
+                    path.fire.insert(port);
+                    vec![(stmt.next, path)]
+                }
+                Statement::Assert(stmt) => {
+                    let paths = self.decide(stmt.expression, true, path);
+                    paths.into_iter().map(|path| (stmt.next, path)).collect()
+                }
+                Statement::If(stmt) => {
+                    let mut branches = Vec::new();
+                    for path in self.decide(stmt.test, true, path.clone()) {
+                        branches.push((Some(stmt.true_body), path));
+                    }
+                    for path in self.decide(stmt.test, false, path) {
+                        branches.push((Some(stmt.false_body), path));
+                    }
+                    branches
+                }
+                Statement::While(stmt) => {
+                    let mut branches = Vec::new();
+                    for path in self.decide(stmt.test, true, path.clone()) {
+                        branches.push((Some(stmt.body), path));
+                    }
+                    for path in self.decide(stmt.test, false, path) {
+                        branches.push((stmt.next.map(EndWhileStatementId::upcast), path));
+                    }
+                    branches
+                }
+                Statement::Switch(stmt) => self.switch(stmt, path),
+                _ => {
+                    let next = successors(h, stmt);
+                    if next.is_empty() {
+                        vec![(None, path)]
+                    } else {
+                        next.into_iter().map(|next| (Some(next), path.clone())).collect()
+                    }
+                }
+            };
+            for (next, path) in branches.into_iter().rev() {
+                match next {
+                    Some(next) => pending.push((next, path)),
+                    None => result.push((None, path)),
+                }
+            }
+        }
+        result
+    }
+    // Every case of a switch statement is taken when the subject is one of its variants, and
+    // the default case when it is none of them
+    fn switch(
+        &mut self,
+        stmt: &SwitchStatement,
+        mut path: Path,
+    ) -> Vec<(Option<StatementId>, Path)> {
+        let h = self.h;
+        let subject = wrap(self.term(stmt.subject, &mut path), EQUALITY);
+        let enumeration = &h[h[stmt.definition.unwrap()].identifier];
+        let condition = |variant: SourceIdentifierId, operator: &str| {
+            format!("{} {} {}::{}", subject, operator, enumeration, h[variant])
+        };
+        let mut branches = Vec::new();
+        for case in stmt.cases.iter() {
+            let mut branch = path.clone();
+            let conditions: Vec<String> =
+                case.variants.iter().map(|&variant| condition(variant, "==")).collect();
+            let conditions = conditions.join(" || ");
+            let negation = format!("!({})", conditions);
+            if branch.take(conditions, negation) {
+                branches.push((Some(case.body), branch));
+            }
+        }
+        if let Some(default) = stmt.default {
+            let mut branch = path;
+            let mut taken = true;
+            for &variant in stmt.cases.iter().flat_map(|case| case.variants.iter()) {
+                taken = taken && branch.take(condition(variant, "!="), condition(variant, "=="));
+            }
+            if taken {
+                branches.push((Some(default), branch));
+            }
+        }
+        branches
+    }
+    // The paths on which a condition has the given outcome
+    fn decide(&mut self, expr: ExpressionId, value: bool, mut path: Path) -> Vec<Path> {
+        let h = self.h;
+        match &h[expr] {
+            Expression::Constant(constant) => match constant.value {
+                Constant::True if value => vec![path],
+                Constant::False if !value => vec![path],
+                _ => Vec::new(),
+            },
+            Expression::Unary(unary) if unary.operation == UnaryOperation::LogicalNot => {
+                self.decide(unary.expression, !value, path)
+            }
+            Expression::Binary(binary)
+                if binary.operation == BinaryOperator::LogicalAnd
+                    || binary.operation == BinaryOperator::LogicalOr =>
+            {
+                // The right operand is only evaluated if the left one does not decide
+                let deciding = binary.operation == BinaryOperator::LogicalOr;
+                let mut result = Vec::new();
+                for path in self.decide(binary.left, !deciding, path.clone()) {
+                    result.extend(self.decide(binary.right, value, path));
+                }
+                if value == deciding {
+                    result.extend(self.decide(binary.left, deciding, path));
+                }
+                result
+            }
+            Expression::Call(call) if matches!(call.method, Method::Fires) => {
+                let port = self.term(call.arguments[0], &mut path).0;
+                let (fire, silent) = if value {
+                    (&mut path.fire, &path.silent)
+                } else {
+                    (&mut path.silent, &path.fire)
+                };
+                if silent.contains(&port) {
+                    return Vec::new();
+                }
+                fire.insert(port);
+                vec![path]
+            }
+            _ => {
+                let (condition, negation) = match &h[expr] {
+                    Expression::Binary(binary) if opposite(&binary.operation).is_some() => {
+                        let left = self.term(binary.left, &mut path);
+                        let right = self.term(binary.right, &mut path);
+                        let operation = opposite(&binary.operation).unwrap();
+                        let negation = binary_term(&operation, left.clone(), right.clone());
+                        (binary_term(&binary.operation, left, right).0, negation.0)
+                    }
+                    _ => {
+                        let condition = self.term(expr, &mut path);
+                        let negation = format!("!{}", wrap(condition.clone(), POSTFIX));
+                        (condition.0, negation)
+                    }
+                };
+                let taken = match value {
+                    true => path.take(condition, negation),
+                    false => path.take(negation, condition),
+                };
+                if taken {
+                    vec![path]
+                } else {
+                    Vec::new()
+                }
+            }
+        }
+    }
+    // Shows an expression in terms of the values at the start of the path, while applying its
+    // assignments and firing the ports that it gets from
+    fn term(&mut self, expr: ExpressionId, path: &mut Path) -> Term {
+        let h = self.h;
+        match &h[expr] {
+            Expression::Assignment(expr) => {
+                let mut value = self.term(expr.right, path);
+                if let Some(operation) = compound(&expr.operation) {
+                    let old = self.term(expr.left, path);
+                    value = binary_term(&operation, old, value);
+                }
+                self.store(expr.left, value.clone(), path);
+                value
+            }
+            Expression::Conditional(expr) => {
+                let test = self.term(expr.test, path);
+                let true_expression = self.term(expr.true_expression, path);
+                let false_expression = self.term(expr.false_expression, path);
+                let text = format!(
+                    "{} ? {} : {}",
+                    wrap(test, CONCATENATE),
+                    wrap(true_expression, ASSIGNMENT),
+                    wrap(false_expression, ASSIGNMENT)
+                );
+                (text, CONDITIONAL)
+            }
+            Expression::Binary(expr) => {
+                let left = self.term(expr.left, path);
+                let right = self.term(expr.right, path);
+                binary_term(&expr.operation, left, right)
+            }
+            Expression::Unary(expr) => {
+                let operand = self.term(expr.expression, path);
+                let one = ("1".to_string(), POSTFIX);
+                let prefix = match expr.operation {
+                    UnaryOperation::Positive => "+",
+                    UnaryOperation::Negative => "-",
+                    UnaryOperation::BitwiseNot => "~",
+                    UnaryOperation::LogicalNot => "!",
+                    UnaryOperation::PreIncrement | UnaryOperation::PostIncrement => {
+                        let value = binary_term(&BinaryOperator::Add, operand.clone(), one);
+                        self.store(expr.expression, value.clone(), path);
+                        let pre = expr.operation == UnaryOperation::PreIncrement;
+                        return if pre { value } else { operand };
+                    }
+                    UnaryOperation::PreDecrement | UnaryOperation::PostDecrement => {
+                        let value = binary_term(&BinaryOperator::Subtract, operand.clone(), one);
+                        self.store(expr.expression, value.clone(), path);
+                        let pre = expr.operation == UnaryOperation::PreDecrement;
+                        return if pre { value } else { operand };
+                    }
+                };
+                // Consecutive prefix operators could be read as another one, as in - -x
+                let precedence = if operand.1 == PREFIX { POSTFIX } else { PREFIX };
+                (format!("{}{}", prefix, wrap(operand, precedence)), PREFIX)
+            }
+            Expression::Cast(expr) => {
+                let operand = self.term(expr.expression, path);
+                (format!("({}) {}", expr.the_type, wrap(operand, PREFIX)), PREFIX)
+            }
+            Expression::Indexing(expr) => {
+                let subject = self.term(expr.subject, path);
+                let index = self.term(expr.index, path);
+                (format!("{}[{}]", wrap(subject, POSTFIX), index.0), POSTFIX)
+            }
+            Expression::Slicing(expr) => {
+                let subject = self.term(expr.subject, path);
+                let from = self.term(expr.from_index, path);
+                let to = self.term(expr.to_index, path);
+                (format!("{}[{} : {}]", wrap(subject, POSTFIX), from.0, to.0), POSTFIX)
+            }
+            Expression::Select(expr) => {
+                let subject = wrap(self.term(expr.subject, path), POSTFIX);
+                match expr.field {
+                    Field::Length => (format!("{}.length", subject), POSTFIX),
+                    Field::Symbolic(field) => (format!("{}.{}", subject, h[field]), POSTFIX),
+                }
+            }
+            Expression::Array(expr) => {
+                let elements: Vec<String> =
+                    expr.elements.iter().map(|&element| self.term(element, path).0).collect();
+                (format!("{{{}}}", elements.join(", ")), POSTFIX)
+            }
+            Expression::Struct(expr) => {
+                if expr.fields.is_empty() {
+                    return (format!("{} {{}}", h[expr.identifier]), POSTFIX);
+                }
+                let fields: Vec<String> = expr
+                    .fields
+                    .iter()
+                    .map(|&(field, value)| format!("{}: {}", h[field], self.term(value, path).0))
+                    .collect();
+                (format!("{} {{ {} }}", h[expr.identifier], fields.join(", ")), POSTFIX)
+            }
+            Expression::Call(expr) => {
+                let arguments: Vec<String> =
+                    expr.arguments.iter().map(|&arg| self.term(arg, path).0).collect();
+                let method = match expr.method {
+                    Method::Get => {
+                        path.fire.insert(arguments[0].clone());
+                        return (format!("d({})", arguments[0]), POSTFIX);
+                    }
+                    Method::Fires => "fires".to_string(),
+                    Method::Create => "create".to_string(),
+                    Method::Random => "random".to_string(),
+                    Method::Choose => "choose".to_string(),
+                    Method::Symbolic(id) => h[id].to_string(),
+                };
+                (format!("{}({})", method, arguments.join(", ")), POSTFIX)
+            }
+            Expression::Enum(expr) => {
+                (format!("{}::{}", h[expr.identifier], h[expr.variant]), POSTFIX)
+            }
+            Expression::Constant(expr) => {
+                let text = match &expr.value {
+                    Constant::Null => "null".to_string(),
+                    Constant::True => "true".to_string(),
+                    Constant::False => "false".to_string(),
+                    Constant::Character(data) => format!("'{}'", String::from_utf8_lossy(data)),
+                    Constant::Integer(data) => String::from_utf8_lossy(data).to_string(),
+                };
+                (text, POSTFIX)
+            }
+            Expression::Variable(expr) => match expr.declaration {
+                Some(var) if path.values.contains_key(&var) => path.values[&var].clone(),
+                _ => (h[expr.identifier].to_string(), POSTFIX),
+            },
+        }
+    }
+    // Assigns a value to a variable, or to a part of one
+    fn store(&mut self, target: ExpressionId, value: Term, path: &mut Path) {
+        let h = self.h;
+        match &h[target] {
+            Expression::Variable(VariableExpression { declaration: Some(var), .. }) => {
+                path.assign(*var, value)
+            }
+            _ => {
+                let target = self.term(target, path);
+                path.effects.push(format!("{} := {}", target.0, value.0));
+            }
+        }
+    }
+}
@@ -3,21 +3,19 @@ use std::fmt;
 use std::fmt::{Debug, Display, Formatter};
 use std::{i16, i32, i64, i8};
 
+use byteorder::{BigEndian, ByteOrder};
+
 use crate::common::*;
 
 use crate::protocol::ast::*;
 use crate::protocol::inputsource::*;
+use crate::protocol::library;
 use crate::protocol::parser::*;
 use crate::protocol::EvalContext;
 
 const MAX_RECURSION: usize = 1024;
 
-const BYTE_MIN: i64 = i8::MIN as i64;
-const BYTE_MAX: i64 = i8::MAX as i64;
-const SHORT_MIN: i64 = i16::MIN as i64;
 const SHORT_MAX: i64 = i16::MAX as i64;
-const INT_MIN: i64 = i32::MIN as i64;
-const INT_MAX: i64 = i32::MAX as i64;
 
 const MESSAGE_MAX_LENGTH: i64 = SHORT_MAX;
 
@@ -28,7 +26,7 @@ trait ValueImpl {
     fn is_type_compatible(&self, t: &Type) -> bool;
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub enum Value {
     Input(InputValue),
     Output(OutputValue),
@@ -46,6 +44,8 @@ pub enum Value {
     ShortArray(ShortArrayValue),
     IntArray(IntArrayValue),
     LongArray(LongArrayValue),
+    Struct(StructValue),
+    Enum(EnumValue),
 }
 impl Value {
     pub fn receive_message(buffer: &Payload) -> Value {
@@ -59,32 +59,151 @@ impl Value {
                     // Only messages within the expected length are allowed
                     Value::Message(MessageValue(None))
                 } else {
-                    Value::Message(MessageValue(Some(Payload::new(0))))
+                    let length: usize = length.try_into().unwrap();
+                    Value::Message(MessageValue(Some(Payload::from(vec![0; length]))))
                 }
             }
             _ => unimplemented!(),
         }
     }
-    fn from_constant(constant: &Constant) -> Value {
-        match constant {
+    fn from_port(primitive: &PrimitiveType, port: Port) -> Option<Value> {
+        match primitive {
+            PrimitiveType::Input => Some(Value::Input(InputValue(port))),
+            PrimitiveType::Output => Some(Value::Output(OutputValue(port))),
+            _ => None,
+        }
+    }
+    // Argument of a main component, if it fits the type of its parameter;
+    // port arguments take the next ports in order
+    pub fn from_component_arg(
+        arg: &ComponentArg,
+        t: &Type,
+        ports: &mut impl Iterator<Item = Port>,
+    ) -> Option<Value> {
+        let element = Type { primitive: t.primitive.clone(), array: false };
+        let elements = match arg {
+            ComponentArg::Ports(length) if t.array => {
+                let mut elements = Vec::new();
+                for _ in 0..*length {
+                    elements.push(Value::from_port(&t.primitive, ports.next()?)?);
+                }
+                Some(elements)
+            }
+            ComponentArg::Array(args) if t.array => Some(
+                args.iter()
+                    .map(|arg| Value::from_component_arg(arg, &element, ports))
+                    .collect::<Option<Vec<_>>>()?,
+            ),
+            _ => None,
+        };
+        let value = match (arg, elements) {
+            (_, Some(elements)) if elements.is_empty() => Value::empty_array(t),
+            (_, Some(elements)) => Value::create_array(elements),
+            (ComponentArg::Port, None) => Value::from_port(&t.primitive, ports.next()?)?,
+            (ComponentArg::Boolean(value), None) => Value::from(*value),
+            (ComponentArg::Integer(value), None) => match t.primitive {
+                PrimitiveType::Byte => Value::Byte(ByteValue((*value).try_into().ok()?)),
+                PrimitiveType::Short => Value::Short(ShortValue((*value).try_into().ok()?)),
+                PrimitiveType::Int => Value::Int(IntValue((*value).try_into().ok()?)),
+                PrimitiveType::Long => Value::Long(LongValue(*value)),
+                _ => return None,
+            },
+            (ComponentArg::Message(payload), None) => {
+                Value::Message(MessageValue(Some(payload.clone())))
+            }
+            _ => return None,
+        };
+        if value.is_type_compatible(t) {
+            Some(value)
+        } else {
+            None
+        }
+    }
+    // Index of the field of a struct at the given position
+    fn field(index: usize) -> Value {
+        Value::Int(IntValue(index.try_into().unwrap()))
+    }
+    fn empty_array(t: &Type) -> Value {
+        assert!(t.array);
+        match t.primitive {
+            PrimitiveType::Input => Value::InputArray(InputArrayValue(Vec::new())),
+            PrimitiveType::Output => Value::OutputArray(OutputArrayValue(Vec::new())),
+            PrimitiveType::Message => Value::MessageArray(MessageArrayValue(Vec::new())),
+            PrimitiveType::Boolean => Value::BooleanArray(BooleanArrayValue(Vec::new())),
+            PrimitiveType::Byte => Value::ByteArray(ByteArrayValue(Vec::new())),
+            PrimitiveType::Short => Value::ShortArray(ShortArrayValue(Vec::new())),
+            PrimitiveType::Int => Value::IntArray(IntArrayValue(Vec::new())),
+            PrimitiveType::Long => Value::LongArray(LongArrayValue(Vec::new())),
+            _ => unimplemented!(),
+        }
+    }
+    fn create_array(elements: Vec<Value>) -> Value {
+        // The element type is the widest type of the elements; the empty array
+        // has no elements to go by, and gets its type once it is stored
+        let mut the_type = match elements.first() {
+            None => return Value::empty_array(&Type::LONG_ARRAY),
+            Some(first) => first.exact_type(),
+        };
+        for elem in elements.iter() {
+            if !elem.is_type_compatible(&the_type) {
+                the_type = elem.exact_type();
+            }
+        }
+        let elements = elements.iter().map(|elem| elem.as_type(&the_type));
+        match the_type.primitive {
+            PrimitiveType::Input => Value::InputArray(InputArrayValue(
+                elements
+                    .map(|elem| if let Value::Input(v) = elem { v } else { unreachable!() })
+                    .collect(),
+            )),
+            PrimitiveType::Output => Value::OutputArray(OutputArrayValue(
+                elements
+                    .map(|elem| if let Value::Output(v) = elem { v } else { unreachable!() })
+                    .collect(),
+            )),
+            PrimitiveType::Message => Value::MessageArray(MessageArrayValue(
+                elements
+                    .map(|elem| if let Value::Message(v) = elem { v } else { unreachable!() })
+                    .collect(),
+            )),
+            PrimitiveType::Boolean => Value::BooleanArray(BooleanArrayValue(
+                elements
+                    .map(|elem| if let Value::Boolean(v) = elem { v } else { unreachable!() })
+                    .collect(),
+            )),
+            PrimitiveType::Byte => Value::ByteArray(ByteArrayValue(
+                elements
+                    .map(|elem| if let Value::Byte(v) = elem { v } else { unreachable!() })
+                    .collect(),
+            )),
+            PrimitiveType::Short => Value::ShortArray(ShortArrayValue(
+                elements
+                    .map(|elem| if let Value::Short(v) = elem { v } else { unreachable!() })
+                    .collect(),
+            )),
+            PrimitiveType::Int => Value::IntArray(IntArrayValue(
+                elements
+                    .map(|elem| if let Value::Int(v) = elem { v } else { unreachable!() })
+                    .collect(),
+            )),
+            PrimitiveType::Long => Value::LongArray(LongArrayValue(
+                elements
+                    .map(|elem| if let Value::Long(v) = elem { v } else { unreachable!() })
+                    .collect(),
+            )),
+            _ => unimplemented!(),
+        }
+    }
+    fn from_constant(expr: &ConstantExpression, negated: bool) -> Value {
+        match &expr.value {
             Constant::Null => Value::Message(MessageValue(None)),
             Constant::True => Value::Boolean(BooleanValue(true)),
             Constant::False => Value::Boolean(BooleanValue(false)),
-            Constant::Integer(data) => {
-                // Convert raw ASCII data to UTF-8 string
-                let raw = String::from_utf8_lossy(data);
-                let val = raw.parse::<i64>().unwrap();
-                if val >= BYTE_MIN && val <= BYTE_MAX {
-                    Value::Byte(ByteValue(val as i8))
-                } else if val >= SHORT_MIN && val <= SHORT_MAX {
-                    Value::Short(ShortValue(val as i16))
-                } else if val >= INT_MIN && val <= INT_MAX {
-                    Value::Int(IntValue(val as i32))
-                } else {
-                    Value::Long(LongValue(val))
-                }
+            // The type checker has typed the integer and character constants
+            constant => {
+                let (value, the_type) = constant.integer(negated, expr.the_type.as_ref()).unwrap();
+                Value::integer(&the_type.primitive, value.into(), false).unwrap()
             }
-            Constant::Character(data) => unimplemented!(),
         }
     }
     fn set(&mut self, index: &Value, value: &Value) -> Option<Value> {
@@ -94,54 +213,128 @@ impl Value {
             Value::Byte(_) | Value::Short(_) | Value::Int(_) | Value::Long(_) => {
                 let index = i64::from(index);
                 if index < 0 || index >= MESSAGE_MAX_LENGTH {
-                    // It is inconsistent to update out of bounds
+                    // It is a fault to update out of bounds
                     return None;
                 }
                 the_index = index.try_into().unwrap();
             }
             _ => unreachable!(),
         }
+        // Fields of a struct are indexed by their position, and keep their type
+        if let Value::Struct(StructValue { fields, .. }) = self {
+            let slot = fields.get_mut(the_index)?;
+            *slot = value.as_type(&slot.exact_type());
+            return Some(slot.clone());
+        }
         // The subject must be either a message or an array
         // And the value and the subject must be compatible
+        let value = &match self {
+            Value::Message(_) => value.clone(),
+            _ => value.as_type(&Type { primitive: self.exact_type().primitive, array: false }),
+        };
         match (self, value) {
             (Value::Message(MessageValue(None)), _) => {
-                // It is inconsistent to update the null message
+                // It is a fault to update the null message
                 None
             }
             (Value::Message(MessageValue(Some(payload))), Value::Byte(ByteValue(b))) => {
                 if *b < 0 {
-                    // It is inconsistent to update with a negative value
+                    // It is a fault to update with a negative value
                     return None;
                 }
                 if let Some(slot) = payload.as_mut_slice().get_mut(the_index) {
                     *slot = (*b).try_into().unwrap();
                     Some(value.clone())
                 } else {
-                    // It is inconsistent to update out of bounds
+                    // It is a fault to update out of bounds
                     None
                 }
             }
             (Value::Message(MessageValue(Some(payload))), Value::Short(ShortValue(b))) => {
-                if *b < 0 || *b > BYTE_MAX as i16 {
-                    // It is inconsistent to update with a negative value or a too large value
+                if *b < 0 || *b > u8::MAX as i16 {
+                    // It is a fault to update with a negative value or a too large value
                     return None;
                 }
                 if let Some(slot) = payload.as_mut_slice().get_mut(the_index) {
                     *slot = (*b).try_into().unwrap();
                     Some(value.clone())
                 } else {
-                    // It is inconsistent to update out of bounds
+                    // It is a fault to update out of bounds
+                    None
+                }
+            }
+            (Value::InputArray(InputArrayValue(vec)), Value::Input(v)) => {
+                if let Some(slot) = vec.get_mut(the_index) {
+                    *slot = v.clone();
+                    Some(value.clone())
+                } else {
+                    // It is a fault to update out of bounds
+                    None
+                }
+            }
+            (Value::OutputArray(OutputArrayValue(vec)), Value::Output(v)) => {
+                if let Some(slot) = vec.get_mut(the_index) {
+                    *slot = v.clone();
+                    Some(value.clone())
+                } else {
+                    // It is a fault to update out of bounds
+                    None
+                }
+            }
+            (Value::MessageArray(MessageArrayValue(vec)), Value::Message(v)) => {
+                if let Some(slot) = vec.get_mut(the_index) {
+                    *slot = v.clone();
+                    Some(value.clone())
+                } else {
+                    // It is a fault to update out of bounds
+                    None
+                }
+            }
+            (Value::BooleanArray(BooleanArrayValue(vec)), Value::Boolean(v)) => {
+                if let Some(slot) = vec.get_mut(the_index) {
+                    *slot = v.clone();
+                    Some(value.clone())
+                } else {
+                    // It is a fault to update out of bounds
+                    None
+                }
+            }
+            (Value::ByteArray(ByteArrayValue(vec)), Value::Byte(v)) => {
+                if let Some(slot) = vec.get_mut(the_index) {
+                    *slot = v.clone();
+                    Some(value.clone())
+                } else {
+                    // It is a fault to update out of bounds
+                    None
+                }
+            }
+            (Value::ShortArray(ShortArrayValue(vec)), Value::Short(v)) => {
+                if let Some(slot) = vec.get_mut(the_index) {
+                    *slot = v.clone();
+                    Some(value.clone())
+                } else {
+                    // It is a fault to update out of bounds
+                    None
+                }
+            }
+            (Value::IntArray(IntArrayValue(vec)), Value::Int(v)) => {
+                if let Some(slot) = vec.get_mut(the_index) {
+                    *slot = v.clone();
+                    Some(value.clone())
+                } else {
+                    // It is a fault to update out of bounds
+                    None
+                }
+            }
+            (Value::LongArray(LongArrayValue(vec)), Value::Long(v)) => {
+                if let Some(slot) = vec.get_mut(the_index) {
+                    *slot = v.clone();
+                    Some(value.clone())
+                } else {
+                    // It is a fault to update out of bounds
                     None
                 }
             }
-            (Value::InputArray(_), Value::Input(_)) => todo!(),
-            (Value::OutputArray(_), Value::Output(_)) => todo!(),
-            (Value::MessageArray(_), Value::Message(_)) => todo!(),
-            (Value::BooleanArray(_), Value::Boolean(_)) => todo!(),
-            (Value::ByteArray(_), Value::Byte(_)) => todo!(),
-            (Value::ShortArray(_), Value::Short(_)) => todo!(),
-            (Value::IntArray(_), Value::Int(_)) => todo!(),
-            (Value::LongArray(_), Value::Long(_)) => todo!(),
             _ => unreachable!(),
         }
     }
@@ -152,7 +345,7 @@ impl Value {
             Value::Byte(_) | Value::Short(_) | Value::Int(_) | Value::Long(_) => {
                 let index = i64::from(index);
                 if index < 0 || index >= MESSAGE_MAX_LENGTH {
-                    // It is inconsistent to update out of bounds
+                    // It is a fault to read out of bounds
                     return None;
                 }
                 the_index = index.try_into().unwrap();
@@ -162,33 +355,164 @@ impl Value {
         // The subject must be either a message or an array
         match self {
             Value::Message(MessageValue(None)) => {
-                // It is inconsistent to read from the null message
+                // It is a fault to read from the null message
                 None
             }
             Value::Message(MessageValue(Some(payload))) => {
                 if let Some(slot) = payload.as_slice().get(the_index) {
                     Some(Value::Short(ShortValue((*slot).try_into().unwrap())))
                 } else {
-                    // It is inconsistent to update out of bounds
+                    // It is a fault to read out of bounds
                     None
                 }
             }
-            Value::InputArray(_) => todo!(),
-            Value::OutputArray(_) => todo!(),
-            Value::MessageArray(_) => todo!(),
-            Value::BooleanArray(_) => todo!(),
-            Value::ByteArray(_) => todo!(),
-            Value::ShortArray(_) => todo!(),
-            Value::IntArray(_) => todo!(),
-            Value::LongArray(_) => todo!(),
+            Value::InputArray(InputArrayValue(vec)) => {
+                // It is a fault to read out of bounds
+                vec.get(the_index).map(|v| Value::Input(v.clone()))
+            }
+            Value::OutputArray(OutputArrayValue(vec)) => {
+                // It is a fault to read out of bounds
+                vec.get(the_index).map(|v| Value::Output(v.clone()))
+            }
+            Value::MessageArray(MessageArrayValue(vec)) => {
+                // It is a fault to read out of bounds
+                vec.get(the_index).map(|v| Value::Message(v.clone()))
+            }
+            Value::BooleanArray(BooleanArrayValue(vec)) => {
+                // It is a fault to read out of bounds
+                vec.get(the_index).map(|v| Value::Boolean(v.clone()))
+            }
+            Value::ByteArray(ByteArrayValue(vec)) => {
+                // It is a fault to read out of bounds
+                vec.get(the_index).map(|v| Value::Byte(v.clone()))
+            }
+            Value::ShortArray(ShortArrayValue(vec)) => {
+                // It is a fault to read out of bounds
+                vec.get(the_index).map(|v| Value::Short(v.clone()))
+            }
+            Value::IntArray(IntArrayValue(vec)) => {
+                // It is a fault to read out of bounds
+                vec.get(the_index).map(|v| Value::Int(v.clone()))
+            }
+            Value::LongArray(LongArrayValue(vec)) => {
+                // It is a fault to read out of bounds
+                vec.get(the_index).map(|v| Value::Long(v.clone()))
+            }
+            Value::Struct(StructValue { fields, .. }) => fields.get(the_index).cloned(),
             _ => unreachable!(),
         }
     }
+    fn set_nested(&mut self, index: &[Value], value: &Value) -> Option<Value> {
+        // Updates an element of an element, such as a byte of a message in an array
+        match index {
+            [] => unreachable!(),
+            [index] => self.set(index, value),
+            [index, rest @ ..] => {
+                let mut element = self.get(index)?;
+                let result = element.set_nested(rest, value)?;
+                self.set(index, &element)?;
+                Some(result)
+            }
+        }
+    }
+    fn slice(&self, from: &Value, to: &Value) -> Option<Value> {
+        // The range includes the start index and excludes the end index; it is
+        // inconsistent to slice with negative indices, or out of bounds
+        let from: usize = i64::from(from).try_into().ok()?;
+        let to: usize = i64::from(to).try_into().ok()?;
+        // The subject must be either a message or an array
+        match self {
+            Value::Message(MessageValue(None)) => {
+                // It is a fault to slice the null message
+                None
+            }
+            Value::Message(MessageValue(Some(payload))) => payload
+                .as_slice()
+                .get(from..to)
+                .map(|s| Value::Message(MessageValue(Some(s.iter().copied().collect())))),
+            Value::InputArray(InputArrayValue(vec)) => {
+                vec.get(from..to).map(|s| Value::InputArray(InputArrayValue(s.to_vec())))
+            }
+            Value::OutputArray(OutputArrayValue(vec)) => {
+                vec.get(from..to).map(|s| Value::OutputArray(OutputArrayValue(s.to_vec())))
+            }
+            Value::MessageArray(MessageArrayValue(vec)) => {
+                vec.get(from..to).map(|s| Value::MessageArray(MessageArrayValue(s.to_vec())))
+            }
+            Value::BooleanArray(BooleanArrayValue(vec)) => {
+                vec.get(from..to).map(|s| Value::BooleanArray(BooleanArrayValue(s.to_vec())))
+            }
+            Value::ByteArray(ByteArrayValue(vec)) => {
+                vec.get(from..to).map(|s| Value::ByteArray(ByteArrayValue(s.to_vec())))
+            }
+            Value::ShortArray(ShortArrayValue(vec)) => {
+                vec.get(from..to).map(|s| Value::ShortArray(ShortArrayValue(s.to_vec())))
+            }
+            Value::IntArray(IntArrayValue(vec)) => {
+                vec.get(from..to).map(|s| Value::IntArray(IntArrayValue(s.to_vec())))
+            }
+            Value::LongArray(LongArrayValue(vec)) => {
+                vec.get(from..to).map(|s| Value::LongArray(LongArrayValue(s.to_vec())))
+            }
+            _ => unreachable!(),
+        }
+    }
+    fn write_buffer(&self, offset: &Value, value: &Value) -> Option<Value> {
+        // Values are written in big-endian byte order at the given offset; it is
+        // inconsistent to write beyond the end of the message, and a fault to
+        // write to the null message
+        let offset: usize = i64::from(offset).try_into().ok()?;
+        let mut payload = match self {
+            Value::Message(MessageValue(None)) => return None,
+            Value::Message(MessageValue(Some(payload))) => payload.clone(),
+            _ => unreachable!(),
+        };
+        let slice = payload.as_mut_slice();
+        match value {
+            Value::Byte(ByteValue(v)) => *slice.get_mut(offset)? = *v as u8,
+            Value::Short(ShortValue(v)) => {
+                BigEndian::write_i16(slice.get_mut(offset..offset + 2)?, *v)
+            }
+            Value::Int(IntValue(v)) => BigEndian::write_i32(slice.get_mut(offset..offset + 4)?, *v),
+            Value::Long(LongValue(v)) => {
+                BigEndian::write_i64(slice.get_mut(offset..offset + 8)?, *v)
+            }
+            _ => unreachable!(),
+        }
+        Some(Value::Message(MessageValue(Some(payload))))
+    }
+    fn read_buffer(&self, offset: &Value, the_type: &Type) -> Option<Value> {
+        // Values are read in big-endian byte order from the given offset; it is
+        // inconsistent to read beyond the end of the message, and a fault to
+        // read from the null message
+        let offset: usize = i64::from(offset).try_into().ok()?;
+        let slice = match self {
+            Value::Message(MessageValue(None)) => return None,
+            Value::Message(MessageValue(Some(payload))) => payload.as_slice(),
+            _ => unreachable!(),
+        };
+        Some(match the_type.primitive {
+            PrimitiveType::Byte => Value::Byte(ByteValue(*slice.get(offset)? as i8)),
+            PrimitiveType::Short => {
+                Value::Short(ShortValue(BigEndian::read_i16(slice.get(offset..offset + 2)?)))
+            }
+            PrimitiveType::Int => {
+                Value::Int(IntValue(BigEndian::read_i32(slice.get(offset..offset + 4)?)))
+            }
+            PrimitiveType::Long => {
+                Value::Long(LongValue(BigEndian::read_i64(slice.get(offset..offset + 8)?)))
+            }
+            _ => unreachable!(),
+        })
+    }
+    fn is_null(&self) -> bool {
+        matches!(self, Value::Message(MessageValue(None)))
+    }
     fn length(&self) -> Option<Value> {
         // The subject must be either a message or an array
         match self {
             Value::Message(MessageValue(None)) => {
-                // It is inconsistent to get length from the null message
+                // It is a fault to get length from the null message
                 None
             }
             Value::Message(MessageValue(Some(buffer))) => {
@@ -221,156 +545,378 @@ impl Value {
             _ => unreachable!(),
         }
     }
-    fn plus(&self, other: &Value) -> Value {
+    fn integer(primitive: &PrimitiveType, value: i128, checked: bool) -> Option<Value> {
+        // Truncates the value to the integer type, keeping the low-order bits;
+        // if overflow is checked, a value that does not fit is inconsistent
+        let result = match primitive {
+            PrimitiveType::Byte => Value::Byte(ByteValue(value as i8)),
+            PrimitiveType::Short => Value::Short(ShortValue(value as i16)),
+            PrimitiveType::Int => Value::Int(IntValue(value as i32)),
+            PrimitiveType::Long => Value::Long(LongValue(value as i64)),
+            _ => unimplemented!(),
+        };
+        if checked && i128::from(i64::from(&result)) != value {
+            None
+        } else {
+            Some(result)
+        }
+    }
+    fn arithmetic(
+        &self,
+        other: &Value,
+        checked: bool,
+        operation: fn(i128, i128) -> Option<i128>,
+    ) -> Option<Value> {
+        // The operation is exact on the widened operands, and the result has
+        // the larger of the operand types; it is a fault to divide by zero
+        let primitive = match (self, other) {
+            (Value::Long(_), _) | (_, Value::Long(_)) => PrimitiveType::Long,
+            (Value::Int(_), _) | (_, Value::Int(_)) => PrimitiveType::Int,
+            (Value::Short(_), _) | (_, Value::Short(_)) => PrimitiveType::Short,
+            _ => PrimitiveType::Byte,
+        };
+        let value = operation(i64::from(self).into(), i64::from(other).into())?;
+        Value::integer(&primitive, value, checked)
+    }
+    fn plus(&self, other: &Value, checked: bool) -> Option<Value> {
+        self.arithmetic(other, checked, i128::checked_add)
+    }
+    fn minus(&self, other: &Value, checked: bool) -> Option<Value> {
+        self.arithmetic(other, checked, i128::checked_sub)
+    }
+    fn times(&self, other: &Value, checked: bool) -> Option<Value> {
+        self.arithmetic(other, checked, i128::checked_mul)
+    }
+    fn divide(&self, other: &Value, checked: bool) -> Option<Value> {
+        self.arithmetic(other, checked, i128::checked_div)
+    }
+    fn modulus(&self, other: &Value, checked: bool) -> Option<Value> {
+        self.arithmetic(other, checked, i128::checked_rem)
+    }
+    fn bitwise_and(&self, other: &Value) -> Value {
         match (self, other) {
             (Value::Byte(ByteValue(s)), Value::Byte(ByteValue(o))) => {
-                Value::Byte(ByteValue(*s + *o))
+                Value::Byte(ByteValue(*s & *o))
             }
             (Value::Byte(ByteValue(s)), Value::Short(ShortValue(o))) => {
-                Value::Short(ShortValue(*s as i16 + *o))
+                Value::Short(ShortValue(*s as i16 & *o))
             }
             (Value::Byte(ByteValue(s)), Value::Int(IntValue(o))) => {
-                Value::Int(IntValue(*s as i32 + *o))
+                Value::Int(IntValue(*s as i32 & *o))
             }
             (Value::Byte(ByteValue(s)), Value::Long(LongValue(o))) => {
-                Value::Long(LongValue(*s as i64 + *o))
+                Value::Long(LongValue(*s as i64 & *o))
             }
             (Value::Short(ShortValue(s)), Value::Byte(ByteValue(o))) => {
-                Value::Short(ShortValue(*s + *o as i16))
+                Value::Short(ShortValue(*s & *o as i16))
             }
             (Value::Short(ShortValue(s)), Value::Short(ShortValue(o))) => {
-                Value::Short(ShortValue(*s + *o))
+                Value::Short(ShortValue(*s & *o))
             }
             (Value::Short(ShortValue(s)), Value::Int(IntValue(o))) => {
-                Value::Int(IntValue(*s as i32 + *o))
+                Value::Int(IntValue(*s as i32 & *o))
             }
             (Value::Short(ShortValue(s)), Value::Long(LongValue(o))) => {
-                Value::Long(LongValue(*s as i64 + *o))
+                Value::Long(LongValue(*s as i64 & *o))
             }
             (Value::Int(IntValue(s)), Value::Byte(ByteValue(o))) => {
-                Value::Int(IntValue(*s + *o as i32))
+                Value::Int(IntValue(*s & *o as i32))
             }
             (Value::Int(IntValue(s)), Value::Short(ShortValue(o))) => {
-                Value::Int(IntValue(*s + *o as i32))
+                Value::Int(IntValue(*s & *o as i32))
             }
-            (Value::Int(IntValue(s)), Value::Int(IntValue(o))) => Value::Int(IntValue(*s + *o)),
+            (Value::Int(IntValue(s)), Value::Int(IntValue(o))) => Value::Int(IntValue(*s & *o)),
             (Value::Int(IntValue(s)), Value::Long(LongValue(o))) => {
-                Value::Long(LongValue(*s as i64 + *o))
+                Value::Long(LongValue(*s as i64 & *o))
             }
             (Value::Long(LongValue(s)), Value::Byte(ByteValue(o))) => {
-                Value::Long(LongValue(*s + *o as i64))
+                Value::Long(LongValue(*s & *o as i64))
             }
             (Value::Long(LongValue(s)), Value::Short(ShortValue(o))) => {
-                Value::Long(LongValue(*s + *o as i64))
+                Value::Long(LongValue(*s & *o as i64))
             }
             (Value::Long(LongValue(s)), Value::Int(IntValue(o))) => {
-                Value::Long(LongValue(*s + *o as i64))
+                Value::Long(LongValue(*s & *o as i64))
             }
             (Value::Long(LongValue(s)), Value::Long(LongValue(o))) => {
-                Value::Long(LongValue(*s + *o))
+                Value::Long(LongValue(*s & *o))
             }
             _ => unimplemented!(),
         }
     }
-    fn minus(&self, other: &Value) -> Value {
+    fn bitwise_or(&self, other: &Value) -> Value {
         match (self, other) {
             (Value::Byte(ByteValue(s)), Value::Byte(ByteValue(o))) => {
-                Value::Byte(ByteValue(*s - *o))
+                Value::Byte(ByteValue(*s | *o))
             }
             (Value::Byte(ByteValue(s)), Value::Short(ShortValue(o))) => {
-                Value::Short(ShortValue(*s as i16 - *o))
+                Value::Short(ShortValue(*s as i16 | *o))
             }
             (Value::Byte(ByteValue(s)), Value::Int(IntValue(o))) => {
-                Value::Int(IntValue(*s as i32 - *o))
+                Value::Int(IntValue(*s as i32 | *o))
             }
             (Value::Byte(ByteValue(s)), Value::Long(LongValue(o))) => {
-                Value::Long(LongValue(*s as i64 - *o))
+                Value::Long(LongValue(*s as i64 | *o))
             }
             (Value::Short(ShortValue(s)), Value::Byte(ByteValue(o))) => {
-                Value::Short(ShortValue(*s - *o as i16))
+                Value::Short(ShortValue(*s | *o as i16))
             }
             (Value::Short(ShortValue(s)), Value::Short(ShortValue(o))) => {
-                Value::Short(ShortValue(*s - *o))
+                Value::Short(ShortValue(*s | *o))
             }
             (Value::Short(ShortValue(s)), Value::Int(IntValue(o))) => {
-                Value::Int(IntValue(*s as i32 - *o))
+                Value::Int(IntValue(*s as i32 | *o))
             }
             (Value::Short(ShortValue(s)), Value::Long(LongValue(o))) => {
-                Value::Long(LongValue(*s as i64 - *o))
+                Value::Long(LongValue(*s as i64 | *o))
             }
             (Value::Int(IntValue(s)), Value::Byte(ByteValue(o))) => {
-                Value::Int(IntValue(*s - *o as i32))
+                Value::Int(IntValue(*s | *o as i32))
             }
             (Value::Int(IntValue(s)), Value::Short(ShortValue(o))) => {
-                Value::Int(IntValue(*s - *o as i32))
+                Value::Int(IntValue(*s | *o as i32))
             }
-            (Value::Int(IntValue(s)), Value::Int(IntValue(o))) => Value::Int(IntValue(*s - *o)),
+            (Value::Int(IntValue(s)), Value::Int(IntValue(o))) => Value::Int(IntValue(*s | *o)),
             (Value::Int(IntValue(s)), Value::Long(LongValue(o))) => {
-                Value::Long(LongValue(*s as i64 - *o))
+                Value::Long(LongValue(*s as i64 | *o))
             }
             (Value::Long(LongValue(s)), Value::Byte(ByteValue(o))) => {
-                Value::Long(LongValue(*s - *o as i64))
+                Value::Long(LongValue(*s | *o as i64))
             }
             (Value::Long(LongValue(s)), Value::Short(ShortValue(o))) => {
-                Value::Long(LongValue(*s - *o as i64))
+                Value::Long(LongValue(*s | *o as i64))
             }
             (Value::Long(LongValue(s)), Value::Int(IntValue(o))) => {
-                Value::Long(LongValue(*s - *o as i64))
+                Value::Long(LongValue(*s | *o as i64))
             }
             (Value::Long(LongValue(s)), Value::Long(LongValue(o))) => {
-                Value::Long(LongValue(*s - *o))
+                Value::Long(LongValue(*s | *o))
             }
             _ => unimplemented!(),
         }
     }
-    fn modulus(&self, other: &Value) -> Value {
+    fn bitwise_xor(&self, other: &Value) -> Value {
         match (self, other) {
             (Value::Byte(ByteValue(s)), Value::Byte(ByteValue(o))) => {
-                Value::Byte(ByteValue(*s % *o))
+                Value::Byte(ByteValue(*s ^ *o))
             }
             (Value::Byte(ByteValue(s)), Value::Short(ShortValue(o))) => {
-                Value::Short(ShortValue(*s as i16 % *o))
+                Value::Short(ShortValue(*s as i16 ^ *o))
             }
             (Value::Byte(ByteValue(s)), Value::Int(IntValue(o))) => {
-                Value::Int(IntValue(*s as i32 % *o))
+                Value::Int(IntValue(*s as i32 ^ *o))
             }
             (Value::Byte(ByteValue(s)), Value::Long(LongValue(o))) => {
-                Value::Long(LongValue(*s as i64 % *o))
+                Value::Long(LongValue(*s as i64 ^ *o))
             }
             (Value::Short(ShortValue(s)), Value::Byte(ByteValue(o))) => {
-                Value::Short(ShortValue(*s % *o as i16))
+                Value::Short(ShortValue(*s ^ *o as i16))
             }
             (Value::Short(ShortValue(s)), Value::Short(ShortValue(o))) => {
-                Value::Short(ShortValue(*s % *o))
+                Value::Short(ShortValue(*s ^ *o))
             }
             (Value::Short(ShortValue(s)), Value::Int(IntValue(o))) => {
-                Value::Int(IntValue(*s as i32 % *o))
+                Value::Int(IntValue(*s as i32 ^ *o))
             }
             (Value::Short(ShortValue(s)), Value::Long(LongValue(o))) => {
-                Value::Long(LongValue(*s as i64 % *o))
+                Value::Long(LongValue(*s as i64 ^ *o))
             }
             (Value::Int(IntValue(s)), Value::Byte(ByteValue(o))) => {
-                Value::Int(IntValue(*s % *o as i32))
+                Value::Int(IntValue(*s ^ *o as i32))
             }
             (Value::Int(IntValue(s)), Value::Short(ShortValue(o))) => {
-                Value::Int(IntValue(*s % *o as i32))
+                Value::Int(IntValue(*s ^ *o as i32))
             }
-            (Value::Int(IntValue(s)), Value::Int(IntValue(o))) => Value::Int(IntValue(*s % *o)),
+            (Value::Int(IntValue(s)), Value::Int(IntValue(o))) => Value::Int(IntValue(*s ^ *o)),
             (Value::Int(IntValue(s)), Value::Long(LongValue(o))) => {
-                Value::Long(LongValue(*s as i64 % *o))
+                Value::Long(LongValue(*s as i64 ^ *o))
             }
             (Value::Long(LongValue(s)), Value::Byte(ByteValue(o))) => {
-                Value::Long(LongValue(*s % *o as i64))
+                Value::Long(LongValue(*s ^ *o as i64))
             }
             (Value::Long(LongValue(s)), Value::Short(ShortValue(o))) => {
-                Value::Long(LongValue(*s % *o as i64))
+                Value::Long(LongValue(*s ^ *o as i64))
             }
             (Value::Long(LongValue(s)), Value::Int(IntValue(o))) => {
-                Value::Long(LongValue(*s % *o as i64))
+                Value::Long(LongValue(*s ^ *o as i64))
             }
             (Value::Long(LongValue(s)), Value::Long(LongValue(o))) => {
-                Value::Long(LongValue(*s % *o))
+                Value::Long(LongValue(*s ^ *o))
+            }
+            _ => unimplemented!(),
+        }
+    }
+
+    fn shift_left(&self, other: &Value) -> Option<Value> {
+        // The result has the type of the left operand; it is a fault to shift
+        // by a negative amount, or by at least the width of that type
+        let amount: u32 = i64::from(other).try_into().ok()?;
+        match self {
+            Value::Byte(ByteValue(s)) => s.checked_shl(amount).map(|r| Value::Byte(ByteValue(r))),
+            Value::Short(ShortValue(s)) => {
+                s.checked_shl(amount).map(|r| Value::Short(ShortValue(r)))
+            }
+            Value::Int(IntValue(s)) => s.checked_shl(amount).map(|r| Value::Int(IntValue(r))),
+            Value::Long(LongValue(s)) => s.checked_shl(amount).map(|r| Value::Long(LongValue(r))),
+            _ => unimplemented!(),
+        }
+    }
+    fn shift_right(&self, other: &Value) -> Option<Value> {
+        // Arithmetic shift, i.e. the sign bit of the left operand is preserved
+        let amount: u32 = i64::from(other).try_into().ok()?;
+        match self {
+            Value::Byte(ByteValue(s)) => s.checked_shr(amount).map(|r| Value::Byte(ByteValue(r))),
+            Value::Short(ShortValue(s)) => {
+                s.checked_shr(amount).map(|r| Value::Short(ShortValue(r)))
+            }
+            Value::Int(IntValue(s)) => s.checked_shr(amount).map(|r| Value::Int(IntValue(r))),
+            Value::Long(LongValue(s)) => s.checked_shr(amount).map(|r| Value::Long(LongValue(r))),
+            _ => unimplemented!(),
+        }
+    }
+    fn concatenate(&self, other: &Value) -> Option<Value> {
+        match (self, other) {
+            (Value::Message(MessageValue(None)), Value::Message(_))
+            | (Value::Message(_), Value::Message(MessageValue(None))) => {
+                // It is a fault to concatenate the null message
+                None
+            }
+            (Value::Message(MessageValue(Some(s))), Value::Message(MessageValue(Some(o)))) => {
+                let mut payload = s.clone();
+                payload.concat_with(o);
+                Some(Value::Message(MessageValue(Some(payload))))
+            }
+            _ => {
+                // Both operands are arrays; an empty array has no definite element type
+                if other.length().map(i64::from) == Some(0) {
+                    return Some(self.clone());
+                }
+                if self.length().map(i64::from) == Some(0) {
+                    return Some(other.clone());
+                }
+                // Integer arrays are promoted to the wider element type
+                let s = self.as_type(&other.exact_type());
+                let (s, o) = if s.exact_type() == other.exact_type() {
+                    (s, other.clone())
+                } else {
+                    (self.clone(), other.as_type(&self.exact_type()))
+                };
+                Some(match (s, o) {
+                    (
+                        Value::InputArray(InputArrayValue(mut s)),
+                        Value::InputArray(InputArrayValue(o)),
+                    ) => {
+                        s.extend(o);
+                        Value::InputArray(InputArrayValue(s))
+                    }
+                    (
+                        Value::OutputArray(OutputArrayValue(mut s)),
+                        Value::OutputArray(OutputArrayValue(o)),
+                    ) => {
+                        s.extend(o);
+                        Value::OutputArray(OutputArrayValue(s))
+                    }
+                    (
+                        Value::MessageArray(MessageArrayValue(mut s)),
+                        Value::MessageArray(MessageArrayValue(o)),
+                    ) => {
+                        s.extend(o);
+                        Value::MessageArray(MessageArrayValue(s))
+                    }
+                    (
+                        Value::BooleanArray(BooleanArrayValue(mut s)),
+                        Value::BooleanArray(BooleanArrayValue(o)),
+                    ) => {
+                        s.extend(o);
+                        Value::BooleanArray(BooleanArrayValue(s))
+                    }
+                    (
+                        Value::ByteArray(ByteArrayValue(mut s)),
+                        Value::ByteArray(ByteArrayValue(o)),
+                    ) => {
+                        s.extend(o);
+                        Value::ByteArray(ByteArrayValue(s))
+                    }
+                    (
+                        Value::ShortArray(ShortArrayValue(mut s)),
+                        Value::ShortArray(ShortArrayValue(o)),
+                    ) => {
+                        s.extend(o);
+                        Value::ShortArray(ShortArrayValue(s))
+                    }
+                    (Value::IntArray(IntArrayValue(mut s)), Value::IntArray(IntArrayValue(o))) => {
+                        s.extend(o);
+                        Value::IntArray(IntArrayValue(s))
+                    }
+                    (
+                        Value::LongArray(LongArrayValue(mut s)),
+                        Value::LongArray(LongArrayValue(o)),
+                    ) => {
+                        s.extend(o);
+                        Value::LongArray(LongArrayValue(s))
+                    }
+                    _ => unimplemented!(),
+                })
+            }
+        }
+    }
+    fn as_type(&self, t: &Type) -> Value {
+        // Promotes integers (and arrays of integers) to a wider type, and gives the
+        // empty array the element type of its destination. Other values are unchanged.
+        if self.exact_type() == *t {
+            return self.clone();
+        }
+        match (self, &t.primitive, t.array) {
+            (Value::Byte(_), PrimitiveType::Short, false) => {
+                Value::Short(ShortValue(i16::from(self)))
+            }
+            (Value::Byte(_), PrimitiveType::Int, false)
+            | (Value::Short(_), PrimitiveType::Int, false) => Value::Int(IntValue(i32::from(self))),
+            (Value::Byte(_), PrimitiveType::Long, false)
+            | (Value::Short(_), PrimitiveType::Long, false)
+            | (Value::Int(_), PrimitiveType::Long, false) => {
+                Value::Long(LongValue(i64::from(self)))
+            }
+            (_, _, true) if self.exact_type().array && self.length().map(i64::from) == Some(0) => {
+                Value::empty_array(t)
+            }
+            (Value::ByteArray(ByteArrayValue(vec)), PrimitiveType::Short, true) => {
+                Value::ShortArray(ShortArrayValue(
+                    vec.iter().map(|x| ShortValue(x.0.into())).collect(),
+                ))
+            }
+            (Value::ByteArray(ByteArrayValue(vec)), PrimitiveType::Int, true) => {
+                Value::IntArray(IntArrayValue(vec.iter().map(|x| IntValue(x.0.into())).collect()))
+            }
+            (Value::ByteArray(ByteArrayValue(vec)), PrimitiveType::Long, true) => Value::LongArray(
+                LongArrayValue(vec.iter().map(|x| LongValue(x.0.into())).collect()),
+            ),
+            (Value::ShortArray(ShortArrayValue(vec)), PrimitiveType::Int, true) => {
+                Value::IntArray(IntArrayValue(vec.iter().map(|x| IntValue(x.0.into())).collect()))
+            }
+            (Value::ShortArray(ShortArrayValue(vec)), PrimitiveType::Long, true) => {
+                Value::LongArray(LongArrayValue(
+                    vec.iter().map(|x| LongValue(x.0.into())).collect(),
+                ))
             }
+            (Value::IntArray(IntArrayValue(vec)), PrimitiveType::Long, true) => Value::LongArray(
+                LongArrayValue(vec.iter().map(|x| LongValue(x.0.into())).collect()),
+            ),
+            _ => self.clone(),
+        }
+    }
+    fn negate(&self, checked: bool) -> Option<Value> {
+        Value::integer(&self.exact_type().primitive, -i128::from(i64::from(self)), checked)
+    }
+    fn cast(&self, primitive: &PrimitiveType) -> Value {
+        Value::integer(primitive, i64::from(self).into(), false).unwrap()
+    }
+    fn complement(&self) -> Value {
+        match self {
+            Value::Byte(ByteValue(s)) => Value::Byte(ByteValue(!*s)),
+            Value::Short(ShortValue(s)) => Value::Short(ShortValue(!*s)),
+            Value::Int(IntValue(s)) => Value::Int(IntValue(!*s)),
+            Value::Long(LongValue(s)) => Value::Long(LongValue(!*s)),
             _ => unimplemented!(),
         }
     }
@@ -427,7 +973,42 @@ impl Value {
             (Value::Message(MessageValue(s)), Value::Message(MessageValue(o))) => {
                 Value::Boolean(BooleanValue(*s == *o))
             }
-            _ => unimplemented!(),
+            (Value::Boolean(BooleanValue(s)), Value::Boolean(BooleanValue(o))) => {
+                Value::Boolean(BooleanValue(*s == *o))
+            }
+            (Value::Input(InputValue(s)), Value::Input(InputValue(o))) => {
+                Value::Boolean(BooleanValue(*s == *o))
+            }
+            (Value::Output(OutputValue(s)), Value::Output(OutputValue(o))) => {
+                Value::Boolean(BooleanValue(*s == *o))
+            }
+            (
+                Value::Struct(StructValue { fields: s, .. }),
+                Value::Struct(StructValue { fields: o, .. }),
+            ) => {
+                // Structs are equal if they have equal fields
+                Value::from(s.iter().zip(o.iter()).all(|(s, o)| s.eq(o).as_boolean().0))
+            }
+            (
+                Value::Enum(EnumValue { variant: s, .. }),
+                Value::Enum(EnumValue { variant: o, .. }),
+            ) => Value::Boolean(BooleanValue(*s == *o)),
+            _ => {
+                // Arrays are equal if they have equal elements
+                assert!(self.exact_type().array && other.exact_type().array);
+                let length = i64::from(&self.length().unwrap());
+                if length != i64::from(&other.length().unwrap()) {
+                    return Value::from(false);
+                }
+                for index in 0..length {
+                    let index = Value::Long(LongValue(index));
+                    let element = self.get(&index).unwrap();
+                    if !element.eq(&other.get(&index).unwrap()).as_boolean().0 {
+                        return Value::from(false);
+                    }
+                }
+                Value::from(true)
+            }
         }
     }
     fn neq(&self, other: &Value) -> Value {
@@ -483,7 +1064,7 @@ impl Value {
             (Value::Message(MessageValue(s)), Value::Message(MessageValue(o))) => {
                 Value::Boolean(BooleanValue(*s != *o))
             }
-            _ => unimplemented!(),
+            _ => Value::from(!self.eq(other).as_boolean().0),
         }
     }
     fn lt(&self, other: &Value) -> Value {
@@ -720,6 +1301,11 @@ impl From<bool> for Value {
         Value::Boolean(BooleanValue(b))
     }
 }
+impl From<i64> for Value {
+    fn from(l: i64) -> Self {
+        Value::Long(LongValue(l))
+    }
+}
 impl From<Value> for bool {
     fn from(val: Value) -> Self {
         match val {
@@ -836,6 +1422,8 @@ impl ValueImpl for Value {
             Value::ShortArray(val) => val.exact_type(),
             Value::IntArray(val) => val.exact_type(),
             Value::LongArray(val) => val.exact_type(),
+            Value::Struct(val) => val.exact_type(),
+            Value::Enum(val) => val.exact_type(),
         }
     }
     fn is_type_compatible(&self, t: &Type) -> bool {
@@ -856,6 +1444,8 @@ impl ValueImpl for Value {
             Value::ShortArray(val) => val.is_type_compatible(t),
             Value::IntArray(val) => val.is_type_compatible(t),
             Value::LongArray(val) => val.is_type_compatible(t),
+            Value::Struct(val) => val.is_type_compatible(t),
+            Value::Enum(val) => val.is_type_compatible(t),
         }
     }
 }
@@ -880,12 +1470,14 @@ impl Display for Value {
             Value::ShortArray(val) => disp = val,
             Value::IntArray(val) => disp = val,
             Value::LongArray(val) => disp = val,
+            Value::Struct(val) => disp = val,
+            Value::Enum(val) => disp = val,
         }
         disp.fmt(f)
     }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub struct InputValue(pub Port);
 
 impl Display for InputValue {
@@ -910,7 +1502,7 @@ impl ValueImpl for InputValue {
     }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub struct OutputValue(pub Port);
 
 impl Display for OutputValue {
@@ -935,7 +1527,7 @@ impl ValueImpl for OutputValue {
     }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub struct MessageValue(pub Option<Payload>);
 
 impl Display for MessageValue {
@@ -944,11 +1536,18 @@ impl Display for MessageValue {
             None => write!(f, "null"),
             Some(payload) => {
                 // format print up to 10 bytes
-                let mut slice = payload.as_slice();
+                let slice = payload.as_slice();
+                write!(f, "#msg({};", slice.len())?;
+                for (i, b) in slice.iter().take(10).enumerate() {
+                    if i > 0 {
+                        write!(f, ",")?;
+                    }
+                    write!(f, "{}", b)?;
+                }
                 if slice.len() > 10 {
-                    slice = &slice[..10];
+                    write!(f, ",...")?;
                 }
-                f.debug_list().entries(slice.iter().copied()).finish()
+                write!(f, ")")
             }
         }
     }
@@ -970,7 +1569,7 @@ impl ValueImpl for MessageValue {
     }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub struct BooleanValue(bool);
 
 impl Display for BooleanValue {
@@ -999,7 +1598,7 @@ impl ValueImpl for BooleanValue {
     }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub struct ByteValue(i8);
 
 impl Display for ByteValue {
@@ -1027,7 +1626,7 @@ impl ValueImpl for ByteValue {
     }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub struct ShortValue(i16);
 
 impl Display for ShortValue {
@@ -1054,7 +1653,7 @@ impl ValueImpl for ShortValue {
     }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub struct IntValue(i32);
 
 impl Display for IntValue {
@@ -1080,7 +1679,7 @@ impl ValueImpl for IntValue {
     }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub struct LongValue(i64);
 
 impl Display for LongValue {
@@ -1105,7 +1704,7 @@ impl ValueImpl for LongValue {
     }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub struct InputArrayValue(Vec<InputValue>);
 
 impl Display for InputArrayValue {
@@ -1139,7 +1738,7 @@ impl ValueImpl for InputArrayValue {
     }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub struct OutputArrayValue(Vec<OutputValue>);
 
 impl Display for OutputArrayValue {
@@ -1173,7 +1772,7 @@ impl ValueImpl for OutputArrayValue {
     }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub struct MessageArrayValue(Vec<MessageValue>);
 
 impl Display for MessageArrayValue {
@@ -1207,7 +1806,7 @@ impl ValueImpl for MessageArrayValue {
     }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub struct BooleanArrayValue(Vec<BooleanValue>);
 
 impl Display for BooleanArrayValue {
@@ -1241,7 +1840,7 @@ impl ValueImpl for BooleanArrayValue {
     }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub struct ByteArrayValue(Vec<ByteValue>);
 
 impl Display for ByteArrayValue {
@@ -1275,7 +1874,7 @@ impl ValueImpl for ByteArrayValue {
     }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub struct ShortArrayValue(Vec<ShortValue>);
 
 impl Display for ShortArrayValue {
@@ -1309,7 +1908,7 @@ impl ValueImpl for ShortArrayValue {
     }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub struct IntArrayValue(Vec<IntValue>);
 
 impl Display for IntArrayValue {
@@ -1343,7 +1942,7 @@ impl ValueImpl for IntArrayValue {
     }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub struct LongArrayValue(Vec<LongValue>);
 
 impl Display for LongArrayValue {
@@ -1377,418 +1976,1193 @@ impl ValueImpl for LongArrayValue {
     }
 }
 
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct StructValue {
+    name: Vec<u8>,
+    // Values of the fields, in the order in which they are defined
+    fields: Vec<Value>,
+}
+
+impl Display for StructValue {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{}{{", String::from_utf8_lossy(&self.name))?;
+        let mut first = true;
+        for v in self.fields.iter() {
+            if !first {
+                write!(f, ",")?;
+            }
+            write!(f, "{}", v)?;
+            first = false;
+        }
+        write!(f, "}}")
+    }
+}
+
+impl ValueImpl for StructValue {
+    fn exact_type(&self) -> Type {
+        Type { primitive: PrimitiveType::Symbolic(self.name.clone()), array: false }
+    }
+    fn is_type_compatible(&self, t: &Type) -> bool {
+        *t == self.exact_type()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct EnumValue {
+    name: Vec<u8>,
+    variant: Vec<u8>,
+}
+
+impl Display for EnumValue {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}::{}",
+            String::from_utf8_lossy(&self.name),
+            String::from_utf8_lossy(&self.variant)
+        )
+    }
+}
+
+impl ValueImpl for EnumValue {
+    fn exact_type(&self) -> Type {
+        Type { primitive: PrimitiveType::Symbolic(self.name.clone()), array: false }
+    }
+    fn is_type_compatible(&self, t: &Type) -> bool {
+        *t == self.exact_type()
+    }
+}
+
+// Instructions of the stack machine that definitions are compiled to. Operands
+// are taken from, and results pushed onto, the stack of the current frame.
 #[derive(Debug, Clone)]
-struct Store {
-    map: HashMap<VariableId, Value>,
+enum Instruction {
+    Constant(Value),
+    Pop,
+    Dup,
+    // Local variables are addressed by their slot in the frame
+    Load(usize),
+    Store(usize),
+    // Element of a local array or message, by an index on the stack
+    LoadIndexed(usize),
+    // Nested element of a local, leaving the indexes on the stack
+    PeekIndexed(usize, usize),
+    // Update a nested element of a local, consuming the indexes
+    StoreIndexed(usize, usize),
+    LoadLength(usize),
+    Binary(BinaryOperator),
+    Unary(UnaryOperation),
+    Cast(PrimitiveType),
+    Index,
+    Slice,
+    Length,
+    Array(usize),
+    // Struct of some name, from values of its fields in the given order
+    Struct(Vec<u8>, Vec<(usize, Type)>),
+    Create,
+    Unassignable,
+    Jump(usize),
+    JumpIfFalse(usize),
+    JumpIfTrue(usize),
+    // Call to the code at some index in the program, with a number of arguments
+    Call(usize, usize),
+    Return,
+    ReadBuffer(Type),
+    WriteBuffer(Type),
+    Fires,
+    Get,
+    Put,
+    Random,
+    // The outcome of the next nondeterministic choice in the synchronous block
+    Choose,
+    Channel(usize, usize),
+    New(usize, usize),
+    SyncStart,
+    SyncEnd,
+    Assert,
+    Halt,
+}
+
+// Part of an assignable expression: an index into an array or message, or
+// a field of a struct
+enum Selector {
+    Index(ExpressionId),
+    Field(usize, InputPosition),
+}
+
+#[derive(Debug)]
+struct Code {
+    definition: DefinitionId,
+    // Types of the local slots, parameters first; temporaries have no type
+    types: Vec<Option<Type>>,
+    parameters: usize,
+    return_type: Option<Type>,
+    instructions: Vec<Instruction>,
+    // Source positions of the instructions, for evaluation errors, and the
+    // expressions that are underlined with them
+    positions: Vec<InputPosition>,
+    spans: Vec<Option<InputSpan>>,
+    // Whether integer overflow is inconsistent, rather than wrapping around,
+    // as enabled by the checked_overflow feature of its protocol description
+    checked_overflow: bool,
+}
+
+#[derive(Debug)]
+pub struct Program {
+    codes: Vec<Code>,
+    indices: HashMap<DefinitionId, usize>,
+}
+
+impl Program {
+    // Compiles the definitions of the root, and those they depend on
+    pub fn compile(h: &Heap, root: RootId) -> Program {
+        let mut compiler = Compiler::new(h);
+        for &def in h[root].definitions.iter() {
+            compiler.index(def);
+        }
+        for &decl in h[root].declarations.iter() {
+            if let Declaration::Defined(decl) = &h[decl] {
+                compiler.index(decl.definition);
+            }
+        }
+        let mut codes = Vec::new();
+        while codes.len() < compiler.definitions.len() {
+            let def = compiler.definitions[codes.len()];
+            codes.push(compiler.compile(def));
+        }
+        Program { codes, indices: compiler.indices }
+    }
 }
-impl Store {
-    fn new() -> Self {
-        Store { map: HashMap::new() }
+
+struct Compiler<'a> {
+    h: &'a Heap,
+    // Definitions in the order of their code in the program
+    definitions: Vec<DefinitionId>,
+    indices: HashMap<DefinitionId, usize>,
+    // State of the definition that is being compiled
+    types: Vec<Option<Type>>,
+    slots: HashMap<VariableId, usize>,
+    instructions: Vec<Instruction>,
+    positions: Vec<InputPosition>,
+    spans: Vec<Option<InputSpan>>,
+    labels: HashMap<StatementId, usize>,
+    patches: Vec<(usize, StatementId)>,
+    pending: Vec<StatementId>,
+    // Slots of the subject and the index of every for statement
+    loops: HashMap<StatementId, (usize, usize)>,
+    position: InputPosition,
+    // Position and span of the expression that is being compiled
+    site: Option<(InputPosition, InputSpan)>,
+}
+
+impl<'a> Compiler<'a> {
+    fn new(h: &'a Heap) -> Self {
+        Compiler {
+            h,
+            definitions: Vec::new(),
+            indices: HashMap::new(),
+            types: Vec::new(),
+            slots: HashMap::new(),
+            instructions: Vec::new(),
+            positions: Vec::new(),
+            spans: Vec::new(),
+            labels: HashMap::new(),
+            patches: Vec::new(),
+            pending: Vec::new(),
+            loops: HashMap::new(),
+            position: InputPosition::default(),
+            site: None,
+        }
+    }
+    fn index(&mut self, def: DefinitionId) -> usize {
+        if let Some(&index) = self.indices.get(&def) {
+            return index;
+        }
+        let index = self.definitions.len();
+        self.definitions.push(def);
+        self.indices.insert(def, index);
+        index
+    }
+    fn compile(&mut self, def: DefinitionId) -> Code {
+        let h = self.h;
+        for &param in h[def].parameters().iter() {
+            self.slot(param.upcast());
+        }
+        let parameters = self.types.len();
+        // Statements are laid out by following their successors, and every
+        // branch that is not yet laid out continues at its own chain later
+        self.chain(Some(h[def].body()));
+        while let Some(stmt) = self.pending.pop() {
+            if !self.labels.contains_key(&stmt) {
+                self.chain(Some(stmt));
+            }
+        }
+        for (at, stmt) in std::mem::take(&mut self.patches) {
+            let target = self.labels[&stmt];
+            self.patch(at, target);
+        }
+        let return_type = match &h[def] {
+            Definition::Function(fun) => Some(h[fun.return_type].the_type.clone()),
+            Definition::Component(_) => None,
+        };
+        let checked_overflow = match h.root_of(def) {
+            Some(root) => h[root].features(h).contains(&&b"checked_overflow"[..]),
+            None => false,
+        };
+        self.slots.clear();
+        self.labels.clear();
+        self.loops.clear();
+        Code {
+            definition: def,
+            types: std::mem::take(&mut self.types),
+            parameters,
+            return_type,
+            instructions: std::mem::take(&mut self.instructions),
+            positions: std::mem::take(&mut self.positions),
+            spans: std::mem::take(&mut self.spans),
+            checked_overflow,
+        }
+    }
+    fn slot(&mut self, var: VariableId) -> usize {
+        if let Some(&slot) = self.slots.get(&var) {
+            return slot;
+        }
+        let slot = self.types.len();
+        self.types.push(Some(self.h[var].the_type(self.h).clone()));
+        self.slots.insert(var, slot);
+        slot
+    }
+    fn temporary(&mut self) -> usize {
+        self.types.push(None);
+        self.types.len() - 1
+    }
+    fn emit(&mut self, instruction: Instruction, position: InputPosition) -> usize {
+        self.instructions.push(instruction);
+        self.positions.push(position);
+        // Instructions at the position of an expression underline all of it
+        self.spans.push(self.site.filter(|&(at, _)| at == position).map(|(_, span)| span));
+        self.instructions.len() - 1
+    }
+    fn patch(&mut self, at: usize, target: usize) {
+        match &mut self.instructions[at] {
+            Instruction::Jump(x) | Instruction::JumpIfFalse(x) | Instruction::JumpIfTrue(x) => {
+                *x = target
+            }
+            _ => unreachable!(),
+        }
     }
-    fn initialize(&mut self, h: &Heap, var: VariableId, value: Value) {
-        // Ensure value is compatible with type of variable
-        let the_type = h[var].the_type(h);
-        assert!(value.is_type_compatible(the_type));
-        // Overwrite mapping
-        self.map.insert(var, value.clone());
-    }
-    fn update(
-        &mut self,
-        h: &Heap,
-        ctx: &mut EvalContext,
-        lexpr: ExpressionId,
-        value: Value,
-    ) -> EvalResult {
-        match &h[lexpr] {
-            Expression::Variable(var) => {
-                let var = var.declaration.unwrap();
-                // Ensure value is compatible with type of variable
-                let the_type = h[var].the_type(h);
-                assert!(value.is_type_compatible(the_type));
-                // Overwrite mapping
-                self.map.insert(var, value.clone());
-                Ok(value)
-            }
-            Expression::Indexing(indexing) => {
-                // Evaluate index expression, which must be some integral type
-                let index = self.eval(h, ctx, indexing.index)?;
-                // Mutable reference to the subject
-                let subject;
-                match &h[indexing.subject] {
-                    Expression::Variable(var) => {
-                        let var = var.declaration.unwrap();
-                        subject = self.map.get_mut(&var).unwrap();
+    fn here(&self) -> usize {
+        self.instructions.len()
+    }
+    // Jumps to the code of a statement, which is possibly laid out later
+    fn jump_to(&mut self, instruction: Instruction, stmt: StatementId) {
+        let at = self.emit(instruction, self.position);
+        self.patches.push((at, stmt));
+        self.pending.push(stmt);
+    }
+    fn chain(&mut self, mut position: Option<StatementId>) {
+        while let Some(stmt) = position {
+            if self.labels.contains_key(&stmt) {
+                self.jump_to(Instruction::Jump(0), stmt);
+                return;
+            }
+            self.labels.insert(stmt, self.here());
+            position = self.statement(stmt);
+        }
+        // Evaluation ends without a next statement
+        self.emit(Instruction::Halt, self.position);
+    }
+    // Compiles a statement, resulting in the statement that follows it
+    fn statement(&mut self, stmt: StatementId) -> Option<StatementId> {
+        let h = self.h;
+        self.position = h[stmt].position();
+        match &h[stmt] {
+            Statement::Block(stmt) => Some(stmt.first()),
+            Statement::Local(stmt) => {
+                match stmt {
+                    LocalStatement::Memory(stmt) => {
+                        self.expression(stmt.initial);
+                        let slot = self.slot(stmt.variable.upcast());
+                        self.emit(Instruction::Store(slot), stmt.position);
+                        self.emit(Instruction::Pop, stmt.position);
+                    }
+                    LocalStatement::Channel(stmt) => {
+                        let from = self.slot(stmt.from.upcast());
+                        let to = self.slot(stmt.to.upcast());
+                        self.emit(Instruction::Channel(from, to), stmt.position);
                     }
-                    _ => unreachable!(),
                 }
-                match subject.set(&index, &value) {
-                    Some(value) => Ok(value),
-                    None => Err(EvalContinuation::Inconsistent),
+                stmt.next()
+            }
+            Statement::Skip(stmt) => stmt.next,
+            Statement::Labeled(stmt) => Some(stmt.body),
+            Statement::If(stmt) => {
+                self.expression(stmt.test);
+                self.jump_to(Instruction::JumpIfFalse(0), stmt.false_body);
+                Some(stmt.true_body)
+            }
+            Statement::EndIf(stmt) => stmt.next,
+            Statement::Switch(stmt) => {
+                self.expression(stmt.subject);
+                let subject = self.temporary();
+                self.emit(Instruction::Store(subject), stmt.position);
+                self.emit(Instruction::Pop, stmt.position);
+                let def = &h[stmt.definition.unwrap()];
+                let name = h[def.identifier].ident().to_vec();
+                for case in stmt.cases.iter() {
+                    for &variant in case.variants.iter() {
+                        let value = Value::Enum(EnumValue {
+                            name: name.clone(),
+                            variant: h[variant].ident().to_vec(),
+                        });
+                        self.emit(Instruction::Load(subject), case.position);
+                        self.emit(Instruction::Constant(value), case.position);
+                        self.emit(Instruction::Binary(BinaryOperator::Equality), case.position);
+                        self.jump_to(Instruction::JumpIfTrue(0), case.body);
+                    }
+                }
+                // Without a default the cases are exhaustive, so the last one matches
+                stmt.default.or_else(|| stmt.cases.last().map(|case| case.body))
+            }
+            Statement::While(stmt) => {
+                self.expression(stmt.test);
+                match stmt.next {
+                    Some(next) => self.jump_to(Instruction::JumpIfFalse(0), next.upcast()),
+                    None => unreachable!(),
+                }
+                Some(stmt.body)
+            }
+            Statement::EndWhile(stmt) => stmt.next,
+            Statement::For(stmt) => {
+                // The subject is evaluated once, and every iteration starts at the end
+                self.expression(stmt.subject);
+                let subject = self.temporary();
+                let index = self.temporary();
+                self.emit(Instruction::Store(subject), stmt.position);
+                self.emit(Instruction::Pop, stmt.position);
+                self.emit(Instruction::Constant(Value::Int(IntValue(0))), stmt.position);
+                self.emit(Instruction::Store(index), stmt.position);
+                self.emit(Instruction::Pop, stmt.position);
+                self.loops.insert(stmt.this.upcast(), (subject, index));
+                stmt.next.map(EndForStatementId::upcast)
+            }
+            Statement::EndFor(stmt) => {
+                let (subject, index) = self.loops[&stmt.start.upcast()];
+                let start = &h[stmt.start];
+                self.emit(Instruction::Load(index), stmt.position);
+                self.emit(Instruction::LoadLength(subject), stmt.position);
+                self.emit(Instruction::Binary(BinaryOperator::LessThan), stmt.position);
+                match stmt.next {
+                    Some(next) => self.jump_to(Instruction::JumpIfFalse(0), next),
+                    None => {
+                        // The loop ends the body of a component
+                        let at = self.emit(Instruction::JumpIfTrue(0), stmt.position);
+                        self.emit(Instruction::Halt, stmt.position);
+                        self.patch(at, self.here());
+                    }
+                }
+                self.emit(Instruction::Load(index), stmt.position);
+                if start.each {
+                    self.emit(Instruction::LoadIndexed(subject), stmt.position);
+                }
+                let variable = self.slot(start.variable.upcast());
+                self.emit(Instruction::Store(variable), stmt.position);
+                self.emit(Instruction::Pop, stmt.position);
+                self.emit(Instruction::Load(index), stmt.position);
+                self.emit(Instruction::Constant(Value::Int(IntValue(1))), stmt.position);
+                self.emit(Instruction::Binary(BinaryOperator::Add), stmt.position);
+                self.emit(Instruction::Store(index), stmt.position);
+                self.emit(Instruction::Pop, stmt.position);
+                Some(start.body)
+            }
+            Statement::Break(stmt) => stmt.target,
+            Statement::Continue(stmt) => stmt.target,
+            Statement::Synchronous(stmt) => {
+                self.emit(Instruction::SyncStart, stmt.position);
+                Some(stmt.body)
+            }
+            Statement::EndSynchronous(stmt) => {
+                self.emit(Instruction::SyncEnd, stmt.position);
+                stmt.next
+            }
+            Statement::Return(stmt) => {
+                self.expression(stmt.expression);
+                self.emit(Instruction::Return, stmt.position);
+                None
+            }
+            Statement::Assert(stmt) => {
+                self.expression(stmt.expression);
+                self.emit(Instruction::Assert, stmt.position);
+                stmt.next
+            }
+            Statement::Goto(stmt) => stmt.target.map(|x| x.upcast()),
+            Statement::New(stmt) => {
+                let expr = &h[stmt.expression];
+                for &arg in expr.arguments.iter() {
+                    self.expression(arg);
                 }
+                let def = h[expr.declaration.unwrap()].as_defined().definition;
+                let index = self.index(def);
+                self.emit(Instruction::New(index, expr.arguments.len()), stmt.position);
+                stmt.next
+            }
+            Statement::Put(stmt) => {
+                self.expression(stmt.port);
+                self.expression(stmt.message);
+                // Putting a null message is reported at the message
+                self.emit(Instruction::Put, h[stmt.message].position());
+                stmt.next
+            }
+            Statement::Expression(stmt) => {
+                self.expression(stmt.expression);
+                self.emit(Instruction::Pop, stmt.position);
+                stmt.next
             }
-            _ => unimplemented!("{:?}", h[lexpr]),
         }
     }
-    fn get(&mut self, h: &Heap, ctx: &mut EvalContext, rexpr: ExpressionId) -> EvalResult {
-        match &h[rexpr] {
-            Expression::Variable(var) => {
-                let var = var.declaration.unwrap();
-                let value = self
-                    .map
-                    .get(&var)
-                    .expect(&format!("Uninitialized variable {:?}", h[h[var].identifier()]));
-                Ok(value.clone())
-            }
-            Expression::Indexing(indexing) => {
-                // Evaluate index expression, which must be some integral type
-                let index = self.eval(h, ctx, indexing.index)?;
-                // Reference to subject
-                let subject;
-                match &h[indexing.subject] {
-                    Expression::Variable(var) => {
-                        let var = var.declaration.unwrap();
-                        subject = self.map.get(&var).unwrap();
-                    }
-                    q => unreachable!("Reached {:?}", q),
+    // Pushes the indexes of an assignable expression, resulting in the slot
+    // of the variable it updates and the depth of the indexing
+    fn assignable(&mut self, lexpr: ExpressionId) -> Option<(usize, usize)> {
+        let h = self.h;
+        // Fields of structs are updated like elements of arrays
+        let mut indexes = Vec::new();
+        let mut base = lexpr;
+        loop {
+            match &h[base] {
+                Expression::Indexing(indexing) => {
+                    indexes.push(Selector::Index(indexing.index));
+                    base = indexing.subject;
                 }
-                match subject.get(&index) {
-                    Some(value) => Ok(value),
-                    None => Err(EvalContinuation::Inconsistent),
+                Expression::Select(select) if select.field_index.is_some() => {
+                    indexes.push(Selector::Field(select.field_index.unwrap(), select.position));
+                    base = select.subject;
                 }
+                _ => break,
             }
-            Expression::Select(selecting) => {
-                // Reference to subject
-                let subject;
-                match &h[selecting.subject] {
-                    Expression::Variable(var) => {
-                        let var = var.declaration.unwrap();
-                        subject = self.map.get(&var).unwrap();
+        }
+        match &h[base] {
+            Expression::Variable(var) => {
+                for index in indexes.iter().rev() {
+                    match *index {
+                        Selector::Index(expr) => self.expression(expr),
+                        Selector::Field(field, position) => {
+                            self.emit(Instruction::Constant(Value::field(field)), position);
+                        }
                     }
-                    q => unreachable!("Reached {:?}", q),
-                }
-                match subject.length() {
-                    Some(value) => Ok(value),
-                    None => Err(EvalContinuation::Inconsistent),
                 }
+                Some((self.slot(var.declaration.unwrap()), indexes.len()))
             }
-            _ => unimplemented!("{:?}", h[rexpr]),
+            _ => None,
+        }
+    }
+    fn load_assignable(&mut self, slot: usize, depth: usize, position: InputPosition) {
+        if depth == 0 {
+            self.emit(Instruction::Load(slot), position);
+        } else {
+            self.emit(Instruction::PeekIndexed(slot, depth), position);
         }
     }
-    fn eval(&mut self, h: &Heap, ctx: &mut EvalContext, expr: ExpressionId) -> EvalResult {
+    fn store_assignable(&mut self, slot: usize, depth: usize, position: InputPosition) {
+        if depth == 0 {
+            self.emit(Instruction::Store(slot), position);
+        } else {
+            self.emit(Instruction::StoreIndexed(slot, depth), position);
+        }
+    }
+    fn expression(&mut self, expr: ExpressionId) {
+        let site = self.site.replace((self.h[expr].position(), self.h[expr].span()));
+        self.expression_impl(expr);
+        self.site = site;
+    }
+    fn expression_impl(&mut self, expr: ExpressionId) {
+        let h = self.h;
+        let position = h[expr].position();
         match &h[expr] {
             Expression::Assignment(expr) => {
-                let value = self.eval(h, ctx, expr.right)?;
-                match expr.operation {
-                    AssignmentOperator::Set => {
-                        self.update(h, ctx, expr.left, value.clone())?;
+                let left = match self.assignable(expr.left) {
+                    Some(left) => left,
+                    None => {
+                        self.emit(Instruction::Unassignable, h[expr.left].position());
+                        return;
                     }
-                    AssignmentOperator::Added => {
-                        let old = self.get(h, ctx, expr.left)?;
-                        self.update(h, ctx, expr.left, old.plus(&value))?;
+                };
+                let (slot, depth) = left;
+                let operation = match expr.operation {
+                    AssignmentOperator::Set => None,
+                    AssignmentOperator::Multiplied => Some(BinaryOperator::Multiply),
+                    AssignmentOperator::Divided => Some(BinaryOperator::Divide),
+                    AssignmentOperator::Remained => Some(BinaryOperator::Remainder),
+                    AssignmentOperator::Added => Some(BinaryOperator::Add),
+                    AssignmentOperator::Subtracted => Some(BinaryOperator::Subtract),
+                    AssignmentOperator::ShiftedLeft => Some(BinaryOperator::ShiftLeft),
+                    AssignmentOperator::ShiftedRight => Some(BinaryOperator::ShiftRight),
+                    AssignmentOperator::BitwiseAnded => Some(BinaryOperator::BitwiseAnd),
+                    AssignmentOperator::BitwiseXored => Some(BinaryOperator::BitwiseXor),
+                    AssignmentOperator::BitwiseOred => Some(BinaryOperator::BitwiseOr),
+                };
+                match operation {
+                    None => self.expression(expr.right),
+                    Some(operation) => {
+                        self.load_assignable(slot, depth, h[expr.left].position());
+                        self.expression(expr.right);
+                        self.emit(Instruction::Binary(operation), position);
                     }
-                    AssignmentOperator::Subtracted => {
-                        let old = self.get(h, ctx, expr.left)?;
-                        self.update(h, ctx, expr.left, old.minus(&value))?;
-                    }
-                    _ => unimplemented!("{:?}", expr),
                 }
-                Ok(value)
+                // The result is the value as it is stored
+                self.store_assignable(slot, depth, h[expr.left].position());
             }
             Expression::Conditional(expr) => {
-                let test = self.eval(h, ctx, expr.test)?;
-                if test.as_boolean().0 {
-                    self.eval(h, ctx, expr.true_expression)
-                } else {
-                    self.eval(h, ctx, expr.false_expression)
-                }
+                self.expression(expr.test);
+                let to_false = self.emit(Instruction::JumpIfFalse(0), position);
+                self.expression(expr.true_expression);
+                let to_end = self.emit(Instruction::Jump(0), position);
+                self.patch(to_false, self.here());
+                self.expression(expr.false_expression);
+                self.patch(to_end, self.here());
             }
             Expression::Binary(expr) => {
-                let left = self.eval(h, ctx, expr.left)?;
-                let right;
-                match expr.operation {
-                    BinaryOperator::LogicalAnd => {
-                        if left.as_boolean().0 == false {
-                            return Ok(left);
-                        }
-                        right = self.eval(h, ctx, expr.right)?;
-                        right.as_boolean(); // panics if not a boolean
-                        return Ok(right);
+                // Integer operands are converted to the type of the operation,
+                // except for the amount of a shift
+                let cast = expr.the_type.as_ref().map(|t| Instruction::Cast(t.primitive.clone()));
+                self.expression(expr.left);
+                if let Some(cast) = &cast {
+                    self.emit(cast.clone(), position);
+                }
+                let shortcut = match expr.operation {
+                    BinaryOperator::LogicalAnd => Some(Instruction::JumpIfFalse(0)),
+                    BinaryOperator::LogicalOr => Some(Instruction::JumpIfTrue(0)),
+                    _ => None,
+                };
+                match shortcut {
+                    // The left operand is the result if it decides the outcome
+                    Some(jump) => {
+                        self.emit(Instruction::Dup, position);
+                        let to_end = self.emit(jump, position);
+                        self.emit(Instruction::Pop, position);
+                        self.expression(expr.right);
+                        self.patch(to_end, self.here());
                     }
-                    BinaryOperator::LogicalOr => {
-                        if left.as_boolean().0 == true {
-                            return Ok(left);
+                    None => {
+                        self.expression(expr.right);
+                        let shift = matches!(
+                            expr.operation,
+                            BinaryOperator::ShiftLeft | BinaryOperator::ShiftRight
+                        );
+                        if let (Some(cast), false) = (cast, shift) {
+                            self.emit(cast, position);
                         }
-                        right = self.eval(h, ctx, expr.right)?;
-                        right.as_boolean(); // panics if not a boolean
-                        return Ok(right);
+                        self.emit(Instruction::Binary(expr.operation.clone()), position);
                     }
-                    _ => {}
-                }
-                right = self.eval(h, ctx, expr.right)?;
-                match expr.operation {
-                    BinaryOperator::Equality => Ok(left.eq(&right)),
-                    BinaryOperator::Inequality => Ok(left.neq(&right)),
-                    BinaryOperator::LessThan => Ok(left.lt(&right)),
-                    BinaryOperator::LessThanEqual => Ok(left.lte(&right)),
-                    BinaryOperator::GreaterThan => Ok(left.gt(&right)),
-                    BinaryOperator::GreaterThanEqual => Ok(left.gte(&right)),
-                    BinaryOperator::Remainder => Ok(left.modulus(&right)),
-                    _ => unimplemented!("{:?}", expr.operation),
                 }
             }
             Expression::Unary(expr) => {
-                let mut value = self.eval(h, ctx, expr.expression)?;
-                match expr.operation {
-                    UnaryOperation::PostIncrement => {
-                        self.update(h, ctx, expr.expression, value.plus(&ONE))?;
+                let (operation, post) = match expr.operation {
+                    UnaryOperation::PostIncrement => (BinaryOperator::Add, true),
+                    UnaryOperation::PreIncrement => (BinaryOperator::Add, false),
+                    UnaryOperation::PostDecrement => (BinaryOperator::Subtract, true),
+                    UnaryOperation::PreDecrement => (BinaryOperator::Subtract, false),
+                    UnaryOperation::Positive => {
+                        self.expression(expr.expression);
+                        return;
                     }
-                    UnaryOperation::PreIncrement => {
-                        value = value.plus(&ONE);
-                        self.update(h, ctx, expr.expression, value.clone())?;
+                    // The type checker has typed a negated integer constant as a whole
+                    UnaryOperation::Negative => {
+                        match &h[expr.expression] {
+                            Expression::Constant(constant)
+                                if matches!(constant.value, Constant::Integer(_)) =>
+                            {
+                                let value = Value::from_constant(constant, true);
+                                self.emit(Instruction::Constant(value), position);
+                            }
+                            _ => {
+                                self.expression(expr.expression);
+                                self.emit(Instruction::Unary(expr.operation.clone()), position);
+                            }
+                        }
+                        return;
                     }
-                    UnaryOperation::PostDecrement => {
-                        self.update(h, ctx, expr.expression, value.minus(&ONE))?;
+                    _ => {
+                        self.expression(expr.expression);
+                        self.emit(Instruction::Unary(expr.operation.clone()), position);
+                        return;
                     }
-                    UnaryOperation::PreDecrement => {
-                        value = value.minus(&ONE);
-                        self.update(h, ctx, expr.expression, value.clone())?;
+                };
+                let (slot, depth) = match self.assignable(expr.expression) {
+                    Some(operand) => operand,
+                    None => {
+                        self.emit(Instruction::Unassignable, h[expr.expression].position());
+                        return;
                     }
-                    _ => unimplemented!(),
+                };
+                let lposition = h[expr.expression].position();
+                self.load_assignable(slot, depth, lposition);
+                // The old value is kept aside, as the indexes are below it
+                let old = if post { Some(self.temporary()) } else { None };
+                if let Some(old) = old {
+                    self.emit(Instruction::Store(old), position);
+                }
+                self.emit(Instruction::Constant(ONE), position);
+                self.emit(Instruction::Binary(operation), position);
+                self.store_assignable(slot, depth, lposition);
+                if let Some(old) = old {
+                    self.emit(Instruction::Pop, position);
+                    self.emit(Instruction::Load(old), position);
                 }
-                Ok(value)
             }
-            Expression::Indexing(expr) => self.get(h, ctx, expr.this.upcast()),
-            Expression::Slicing(expr) => unimplemented!(),
-            Expression::Select(expr) => self.get(h, ctx, expr.this.upcast()),
+            Expression::Cast(expr) => {
+                self.expression(expr.expression);
+                self.emit(Instruction::Cast(expr.the_type.primitive.clone()), position);
+            }
+            Expression::Indexing(expr) => match &h[expr.subject] {
+                Expression::Variable(var) => {
+                    self.expression(expr.index);
+                    let slot = self.slot(var.declaration.unwrap());
+                    self.emit(Instruction::LoadIndexed(slot), position);
+                }
+                _ => {
+                    self.expression(expr.subject);
+                    self.expression(expr.index);
+                    self.emit(Instruction::Index, position);
+                }
+            },
+            Expression::Slicing(expr) => {
+                self.expression(expr.subject);
+                self.expression(expr.from_index);
+                self.expression(expr.to_index);
+                self.emit(Instruction::Slice, position);
+            }
+            // Fields of structs are selected like elements of arrays
+            Expression::Select(expr) => match (expr.field_index, &h[expr.subject]) {
+                (Some(field), Expression::Variable(var)) => {
+                    self.emit(Instruction::Constant(Value::field(field)), position);
+                    let slot = self.slot(var.declaration.unwrap());
+                    self.emit(Instruction::LoadIndexed(slot), position);
+                }
+                (Some(field), _) => {
+                    self.expression(expr.subject);
+                    self.emit(Instruction::Constant(Value::field(field)), position);
+                    self.emit(Instruction::Index, position);
+                }
+                (None, Expression::Variable(var)) => {
+                    let slot = self.slot(var.declaration.unwrap());
+                    self.emit(Instruction::LoadLength(slot), position);
+                }
+                (None, _) => {
+                    self.expression(expr.subject);
+                    self.emit(Instruction::Length, position);
+                }
+            },
             Expression::Array(expr) => {
-                let mut elements = Vec::new();
                 for &elem in expr.elements.iter() {
-                    elements.push(self.eval(h, ctx, elem)?);
-                }
-                todo!()
-            }
-            Expression::Constant(expr) => Ok(Value::from_constant(&expr.value)),
-            Expression::Call(expr) => match expr.method {
-                Method::Create => {
-                    assert_eq!(1, expr.arguments.len());
-                    let length = self.eval(h, ctx, expr.arguments[0])?;
-                    Ok(Value::create_message(length))
-                }
-                Method::Fires => {
-                    assert_eq!(1, expr.arguments.len());
-                    let value = self.eval(h, ctx, expr.arguments[0])?;
-                    match ctx.fires(value.clone()) {
-                        None => Err(EvalContinuation::BlockFires(value)),
-                        Some(result) => Ok(result),
-                    }
+                    self.expression(elem);
+                }
+                self.emit(Instruction::Array(expr.elements.len()), position);
+            }
+            Expression::Struct(expr) => {
+                let def = &h[expr.definition.unwrap()];
+                let mut order = Vec::new();
+                for &(field, value) in expr.fields.iter() {
+                    let index = def.get_field(h, field).unwrap();
+                    order.push((index, h[def.fields[index].type_annotation].the_type.clone()));
+                    self.expression(value);
+                }
+                let name = h[expr.identifier].ident().to_vec();
+                self.emit(Instruction::Struct(name, order), position);
+            }
+            Expression::Enum(expr) => {
+                let value = Value::Enum(EnumValue {
+                    name: h[expr.identifier].ident().to_vec(),
+                    variant: h[expr.variant].ident().to_vec(),
+                });
+                self.emit(Instruction::Constant(value), position);
+            }
+            Expression::Constant(expr) => {
+                self.emit(Instruction::Constant(Value::from_constant(expr, false)), position);
+            }
+            Expression::Call(expr) => self.call(expr),
+            Expression::Variable(expr) => {
+                let slot = self.slot(expr.declaration.unwrap());
+                self.emit(Instruction::Load(slot), position);
+            }
+        }
+    }
+    fn call(&mut self, expr: &CallExpression) {
+        let h = self.h;
+        let position = expr.position;
+        let instruction = match expr.method {
+            Method::Create => Instruction::Create,
+            Method::Fires => Instruction::Fires,
+            Method::Get => Instruction::Get,
+            Method::Random => Instruction::Random,
+            Method::Choose => Instruction::Choose,
+            Method::Symbolic(_) => match &h[expr.declaration.unwrap()] {
+                Declaration::Defined(decl) => {
+                    let index = self.index(decl.definition);
+                    Instruction::Call(index, expr.arguments.len())
                 }
-                Method::Get => {
-                    assert_eq!(1, expr.arguments.len());
-                    let value = self.eval(h, ctx, expr.arguments[0])?;
-                    match ctx.get(value.clone()) {
-                        None => Err(EvalContinuation::BlockGet(value)),
-                        Some(result) => Ok(result),
+                // Only functions of std.buf are imported
+                Declaration::Imported(decl) => {
+                    let return_type = match &decl.signature {
+                        Signature::Function(sig) => sig.return_type.clone(),
+                        Signature::Component(_) => unreachable!(),
+                    };
+                    if library::is_buffer_write(h, decl.this.upcast()) {
+                        self.buffer_write(expr, return_type);
+                        return;
                     }
+                    Instruction::ReadBuffer(return_type)
                 }
-                Method::Symbolic(symbol) => unimplemented!(),
             },
-            Expression::Variable(expr) => self.get(h, ctx, expr.this.upcast()),
-        }
+        };
+        for &arg in expr.arguments.iter() {
+            self.expression(arg);
+        }
+        // Recursion errors are reported at the calling statement
+        let position = match instruction {
+            Instruction::Call(_, _) => self.position,
+            _ => position,
+        };
+        self.emit(instruction, position);
+    }
+    // Updates the message argument, and results in the value written
+    fn buffer_write(&mut self, expr: &CallExpression, return_type: Type) {
+        let h = self.h;
+        let position = expr.position;
+        let message = expr.arguments[0];
+        let (slot, depth) = match self.assignable(message) {
+            Some(message) => message,
+            None => {
+                self.emit(Instruction::Unassignable, h[message].position());
+                return;
+            }
+        };
+        self.load_assignable(slot, depth, h[message].position());
+        self.expression(expr.arguments[1]);
+        self.expression(expr.arguments[2]);
+        self.emit(Instruction::WriteBuffer(return_type), position);
+        let value = self.temporary();
+        self.emit(Instruction::Store(value), position);
+        self.emit(Instruction::Pop, position);
+        self.store_assignable(slot, depth, h[message].position());
+        self.emit(Instruction::Pop, position);
+        self.emit(Instruction::Load(value), position);
     }
 }
 
 type EvalResult = Result<Value, EvalContinuation>;
+
+// A division fails on a zero divisor, which is a fault, or on checked overflow, which is not
+fn division_fault(divisor: &Value) -> Option<&'static str> {
+    if i64::from(divisor) == 0 {
+        Some("Division by zero")
+    } else {
+        None
+    }
+}
+// The value of a binary operation, or the message of its fault; an operation that fails
+// without a message, on checked overflow, is inconsistent
+fn binary(
+    operation: &BinaryOperator,
+    left: &Value,
+    right: &Value,
+    checked: bool,
+) -> Result<Value, Option<&'static str>> {
+    match operation {
+        BinaryOperator::Equality => Ok(left.eq(right)),
+        BinaryOperator::Inequality => Ok(left.neq(right)),
+        BinaryOperator::LessThan => Ok(left.lt(right)),
+        BinaryOperator::LessThanEqual => Ok(left.lte(right)),
+        BinaryOperator::GreaterThan => Ok(left.gt(right)),
+        BinaryOperator::GreaterThanEqual => Ok(left.gte(right)),
+        BinaryOperator::Add => left.plus(right, checked).ok_or(None),
+        BinaryOperator::Subtract => left.minus(right, checked).ok_or(None),
+        BinaryOperator::Multiply => left.times(right, checked).ok_or(None),
+        BinaryOperator::Divide => left.divide(right, checked).ok_or_else(|| division_fault(right)),
+        BinaryOperator::Remainder => {
+            left.modulus(right, checked).ok_or_else(|| division_fault(right))
+        }
+        BinaryOperator::ShiftLeft => left.shift_left(right).ok_or(Some("Shift out of range")),
+        BinaryOperator::ShiftRight => left.shift_right(right).ok_or(Some("Shift out of range")),
+        BinaryOperator::BitwiseAnd => Ok(left.bitwise_and(right)),
+        BinaryOperator::BitwiseOr => Ok(left.bitwise_or(right)),
+        BinaryOperator::BitwiseXor => Ok(left.bitwise_xor(right)),
+        BinaryOperator::Concatenate => left.concatenate(right).ok_or(Some("Null message")),
+        // Logical operators are compiled to jumps
+        BinaryOperator::LogicalAnd | BinaryOperator::LogicalOr => unreachable!(),
+    }
+}
+fn index_fault(subject: &Value) -> &'static str {
+    if subject.is_null() {
+        "Null message"
+    } else {
+        "Index out of bounds"
+    }
+}
+// A store at an index that exists only fails if the value does not fit a byte of a message
+fn store_fault(subject: &Value, index: &[Value]) -> &'static str {
+    let (last, outer) = index.split_last().unwrap();
+    let container = if outer.is_empty() {
+        subject.clone()
+    } else {
+        match get_nested(subject, outer) {
+            Ok(container) => container,
+            Err(message) => return message,
+        }
+    };
+    match container.get(last) {
+        Some(_) => "Message byte out of range",
+        None => index_fault(&container),
+    }
+}
+fn get_nested(subject: &Value, index: &[Value]) -> Result<Value, &'static str> {
+    let mut value = subject.get(&index[0]).ok_or_else(|| index_fault(subject))?;
+    for index in index[1..].iter() {
+        value = value.get(index).ok_or_else(|| index_fault(&value))?;
+    }
+    Ok(value)
+}
+
 pub enum EvalContinuation {
     Stepping,
     Inconsistent,
     Terminal,
     SyncBlockStart,
     SyncBlockEnd,
-    NewComponent(DeclarationId, Vec<Value>),
+    NewComponent(DefinitionId, Vec<Value>),
     BlockFires(Value),
     BlockGet(Value),
+    // The outcome of a nondeterministic choice has not been decided
+    BlockChoose,
     Put(Value, Value),
+    Error(EvalError),
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+struct Frame {
+    code: usize,
+    pc: usize,
+    locals: Vec<Option<Value>>,
+    stack: Vec<Value>,
+}
+
+impl Frame {
+    fn new(program: &Program, code: usize, args: Vec<Value>) -> Self {
+        let types = &program.codes[code].types;
+        assert_eq!(program.codes[code].parameters, args.len());
+        let mut locals = vec![None; types.len()];
+        for (slot, value) in args.into_iter().enumerate() {
+            let the_type = types[slot].as_ref().unwrap();
+            assert!(value.is_type_compatible(the_type));
+            locals[slot] = Some(value.as_type(the_type));
+        }
+        Frame { code, pc: 0, locals, stack: Vec::new() }
+    }
+    fn pop(&mut self) -> Value {
+        self.stack.pop().unwrap()
+    }
+    fn pop_many(&mut self, count: usize) -> Vec<Value> {
+        self.stack.split_off(self.stack.len() - count)
+    }
+    fn store(&mut self, code: &Code, slot: usize, value: Value) -> Value {
+        // Ensure value is compatible with type of variable
+        let value = match &code.types[slot] {
+            Some(the_type) => {
+                let value = value.as_type(the_type);
+                assert!(value.is_type_compatible(the_type));
+                value
+            }
+            None => value,
+        };
+        self.locals[slot] = Some(value.clone());
+        value
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub struct Prompt {
-    definition: DefinitionId,
-    store: Store,
-    position: Option<StatementId>,
+    // Frames of the functions being evaluated, the current one last
+    frames: Vec<Frame>,
+    // Nondeterministic choices made in the current synchronous block
+    choices: usize,
 }
 
 impl Prompt {
-    pub fn new(h: &Heap, def: DefinitionId, args: &Vec<Value>) -> Self {
-        let mut prompt =
-            Prompt { definition: def, store: Store::new(), position: Some((&h[def]).body()) };
-        prompt.set_arguments(h, args);
-        prompt
-    }
-    fn set_arguments(&mut self, h: &Heap, args: &Vec<Value>) {
-        let def = &h[self.definition];
-        let params = def.parameters();
-        assert_eq!(params.len(), args.len());
-        for (param, value) in params.iter().zip(args.iter()) {
-            let hparam = &h[*param];
-            let type_annot = &h[hparam.type_annotation];
-            assert!(value.is_type_compatible(&type_annot.the_type));
-            self.store.initialize(h, param.upcast(), value.clone());
-        }
-    }
-    pub fn step(&mut self, h: &Heap, ctx: &mut EvalContext) -> EvalResult {
-        if self.position.is_none() {
-            return Err(EvalContinuation::Terminal);
-        }
-        let stmt = &h[self.position.unwrap()];
-        match stmt {
-            Statement::Block(stmt) => {
-                // Continue to first statement
-                self.position = Some(stmt.first());
-                Err(EvalContinuation::Stepping)
-            }
-            Statement::Local(stmt) => {
-                match stmt {
-                    LocalStatement::Memory(stmt) => {
-                        // Evaluate initial expression
-                        let value = self.store.eval(h, ctx, stmt.initial)?;
-                        // Update store
-                        self.store.initialize(h, stmt.variable.upcast(), value);
-                    }
-                    LocalStatement::Channel(stmt) => {
-                        let [from, to] = ctx.new_channel();
-                        // Store the values in the declared variables
-                        self.store.initialize(h, stmt.from.upcast(), from);
-                        self.store.initialize(h, stmt.to.upcast(), to);
-                    }
+    pub fn new(program: &Program, def: DefinitionId, args: &[Value]) -> Self {
+        let code = program.indices[&def];
+        Prompt { frames: vec![Frame::new(program, code, args.to_vec())], choices: 0 }
+    }
+    pub fn step(&mut self, program: &Program, ctx: &mut EvalContext) -> EvalResult {
+        let depth = self.frames.len();
+        let frame = self.frames.last_mut().unwrap();
+        let code = &program.codes[frame.code];
+        let pc = frame.pc;
+        frame.pc += 1;
+        let fault = |message| {
+            let error = EvalError::new(code.positions[pc], message);
+            Err(EvalContinuation::Error(match code.spans[pc] {
+                Some(span) => error.with_span(span),
+                None => error,
+            }))
+        };
+        match &code.instructions[pc] {
+            Instruction::Constant(value) => frame.stack.push(value.clone()),
+            Instruction::Pop => {
+                frame.pop();
+            }
+            Instruction::Dup => {
+                let value = frame.stack.last().unwrap().clone();
+                frame.stack.push(value);
+            }
+            Instruction::Load(slot) => match &frame.locals[*slot] {
+                Some(value) => {
+                    let value = value.clone();
+                    frame.stack.push(value);
+                }
+                None => return fault("Uninitialized variable"),
+            },
+            Instruction::Store(slot) => {
+                let value = frame.pop();
+                let value = frame.store(code, *slot, value);
+                frame.stack.push(value);
+            }
+            Instruction::LoadIndexed(slot) => {
+                let index = frame.pop();
+                let subject = frame.locals[*slot].as_ref().unwrap();
+                match subject.get(&index) {
+                    Some(value) => frame.stack.push(value),
+                    None => return fault(index_fault(subject)),
                 }
-                // Continue to next statement
-                self.position = stmt.next();
-                Err(EvalContinuation::Stepping)
             }
-            Statement::Skip(stmt) => {
-                // Continue to next statement
-                self.position = stmt.next;
-                Err(EvalContinuation::Stepping)
+            Instruction::PeekIndexed(slot, depth) => {
+                let index = &frame.stack[frame.stack.len() - depth..];
+                let subject = frame.locals[*slot].as_ref().unwrap();
+                match get_nested(subject, index) {
+                    Ok(value) => frame.stack.push(value),
+                    Err(message) => return fault(message),
+                }
             }
-            Statement::Labeled(stmt) => {
-                // Continue to next statement
-                self.position = Some(stmt.body);
-                Err(EvalContinuation::Stepping)
+            Instruction::StoreIndexed(slot, depth) => {
+                let value = frame.pop();
+                let index = frame.pop_many(*depth);
+                let subject = frame.locals[*slot].as_mut().unwrap();
+                match subject.set_nested(&index, &value) {
+                    Some(value) => frame.stack.push(value),
+                    None => return fault(store_fault(subject, &index)),
+                }
             }
-            Statement::If(stmt) => {
-                // Evaluate test
-                let value = self.store.eval(h, ctx, stmt.test)?;
-                // Continue with either branch
-                if value.as_boolean().0 {
-                    self.position = Some(stmt.true_body);
-                } else {
-                    self.position = Some(stmt.false_body);
+            Instruction::LoadLength(slot) => match frame.locals[*slot].as_ref().unwrap().length() {
+                Some(value) => frame.stack.push(value),
+                None => return fault("Null message"),
+            },
+            Instruction::Binary(operation) => {
+                let right = frame.pop();
+                let left = frame.pop();
+                match binary(operation, &left, &right, code.checked_overflow) {
+                    Ok(value) => frame.stack.push(value),
+                    Err(Some(message)) => return fault(message),
+                    Err(None) => return Err(EvalContinuation::Inconsistent),
                 }
-                Err(EvalContinuation::Stepping)
             }
-            Statement::EndIf(stmt) => {
-                // Continue to next statement
-                self.position = stmt.next;
-                Err(EvalContinuation::Stepping)
+            Instruction::Unary(operation) => {
+                let value = frame.pop();
+                let value = match operation {
+                    UnaryOperation::Negative => match value.negate(code.checked_overflow) {
+                        Some(value) => value,
+                        None => return Err(EvalContinuation::Inconsistent),
+                    },
+                    UnaryOperation::BitwiseNot => value.complement(),
+                    UnaryOperation::LogicalNot => Value::from(!value.as_boolean().0),
+                    // Increments and decrements are compiled to assignments
+                    _ => unreachable!(),
+                };
+                frame.stack.push(value);
             }
-            Statement::While(stmt) => {
-                // Evaluate test
-                let value = self.store.eval(h, ctx, stmt.test)?;
-                // Either continue with body, or go to next
-                if value.as_boolean().0 {
-                    self.position = Some(stmt.body);
-                } else {
-                    self.position = stmt.next.map(|x| x.upcast());
+            Instruction::Cast(primitive) => {
+                let value = frame.pop();
+                frame.stack.push(value.cast(primitive));
+            }
+            Instruction::Index => {
+                let index = frame.pop();
+                let subject = frame.pop();
+                match subject.get(&index) {
+                    Some(value) => frame.stack.push(value),
+                    None => return fault(index_fault(&subject)),
                 }
-                Err(EvalContinuation::Stepping)
             }
-            Statement::EndWhile(stmt) => {
-                // Continue to next statement
-                self.position = stmt.next;
-                Err(EvalContinuation::Stepping)
+            Instruction::Slice => {
+                let to_index = frame.pop();
+                let from_index = frame.pop();
+                let subject = frame.pop();
+                match subject.slice(&from_index, &to_index) {
+                    Some(value) => frame.stack.push(value),
+                    None if subject.is_null() => return fault("Null message"),
+                    None => return Err(EvalContinuation::Inconsistent),
+                }
             }
-            Statement::Synchronous(stmt) => {
-                // Continue to next statement, and signal upward
-                self.position = Some(stmt.body);
-                Err(EvalContinuation::SyncBlockStart)
+            Instruction::Length => match frame.pop().length() {
+                Some(value) => frame.stack.push(value),
+                None => return fault("Null message"),
+            },
+            Instruction::Array(count) => {
+                let elements = frame.pop_many(*count);
+                frame.stack.push(Value::create_array(elements));
+            }
+            Instruction::Struct(name, order) => {
+                let values = frame.pop_many(order.len());
+                let mut fields = vec![None; order.len()];
+                for (value, (index, the_type)) in values.iter().zip(order.iter()) {
+                    fields[*index] = Some(value.as_type(the_type));
+                }
+                let fields = fields.into_iter().map(Option::unwrap).collect();
+                frame.stack.push(Value::Struct(StructValue { name: name.clone(), fields }));
+            }
+            Instruction::Create => {
+                let length = frame.pop();
+                frame.stack.push(Value::create_message(length));
+            }
+            Instruction::Unassignable => return fault("Unassignable expression"),
+            Instruction::Jump(target) => frame.pc = *target,
+            Instruction::JumpIfFalse(target) => {
+                if !frame.pop().as_boolean().0 {
+                    frame.pc = *target;
+                }
             }
-            Statement::EndSynchronous(stmt) => {
-                // Continue to next statement, and signal upward
-                self.position = stmt.next;
-                Err(EvalContinuation::SyncBlockEnd)
+            Instruction::JumpIfTrue(target) => {
+                if frame.pop().as_boolean().0 {
+                    frame.pc = *target;
+                }
             }
-            Statement::Break(stmt) => {
-                // Continue to end of while
-                self.position = stmt.target.map(EndWhileStatementId::upcast);
-                Err(EvalContinuation::Stepping)
+            Instruction::Call(callee, count) => {
+                if depth > MAX_RECURSION {
+                    return fault("Recursion too deep");
+                }
+                let args = frame.pop_many(*count);
+                let callee = Frame::new(program, *callee, args);
+                self.frames.push(callee);
+            }
+            Instruction::Return => {
+                // Return values are promoted to the return type
+                let value = frame.pop().as_type(code.return_type.as_ref().unwrap());
+                self.frames.pop();
+                match self.frames.last_mut() {
+                    None => return Ok(value),
+                    // Continue with the caller, which now has the value of the call
+                    Some(caller) => caller.stack.push(value),
+                }
             }
-            Statement::Continue(stmt) => {
-                // Continue to beginning of while
-                self.position = stmt.target.map(WhileStatementId::upcast);
-                Err(EvalContinuation::Stepping)
+            Instruction::ReadBuffer(the_type) => {
+                let offset = frame.pop();
+                let message = frame.pop();
+                match message.read_buffer(&offset, the_type) {
+                    Some(value) => frame.stack.push(value),
+                    None if message.is_null() => return fault("Null message"),
+                    None => return Err(EvalContinuation::Inconsistent),
+                }
             }
-            Statement::Assert(stmt) => {
-                // Evaluate expression
-                let value = self.store.eval(h, ctx, stmt.expression)?;
-                if value.as_boolean().0 {
-                    // Continue to next statement
-                    self.position = stmt.next;
-                    Err(EvalContinuation::Stepping)
-                } else {
-                    // Assertion failed: inconsistent
-                    Err(EvalContinuation::Inconsistent)
+            Instruction::WriteBuffer(the_type) => {
+                let value = frame.pop().as_type(the_type);
+                let offset = frame.pop();
+                let message = frame.pop();
+                match message.write_buffer(&offset, &value) {
+                    Some(message) => {
+                        frame.stack.push(message);
+                        frame.stack.push(value);
+                    }
+                    None if message.is_null() => return fault("Null message"),
+                    None => return Err(EvalContinuation::Inconsistent),
                 }
             }
-            Statement::Return(stmt) => {
-                // Evaluate expression
-                let value = self.store.eval(h, ctx, stmt.expression)?;
-                // Done with evaluation
-                Ok(value)
+            Instruction::Fires => {
+                // The instruction is evaluated again once unblocked
+                let port = frame.stack.last().unwrap().clone();
+                match ctx.fires(port.clone()) {
+                    None => {
+                        frame.pc = pc;
+                        return Err(EvalContinuation::BlockFires(port));
+                    }
+                    Some(result) => {
+                        frame.pop();
+                        frame.stack.push(result);
+                    }
+                }
             }
-            Statement::Goto(stmt) => {
-                // Continue to target
-                self.position = stmt.target.map(|x| x.upcast());
-                Err(EvalContinuation::Stepping)
+            Instruction::Get => {
+                let port = frame.stack.last().unwrap().clone();
+                match ctx.get(port.clone()) {
+                    None => {
+                        frame.pc = pc;
+                        return Err(EvalContinuation::BlockGet(port));
+                    }
+                    Some(result) => {
+                        frame.pop();
+                        frame.stack.push(result);
+                    }
+                }
             }
-            Statement::New(stmt) => {
-                let expr = &h[stmt.expression];
-                let mut args = Vec::new();
-                for &arg in expr.arguments.iter() {
-                    let value = self.store.eval(h, ctx, arg)?;
-                    args.push(value);
+            Instruction::Put => {
+                let message = frame.pop();
+                let port = frame.pop();
+                if message.is_null() {
+                    return fault("Null message");
                 }
-                self.position = stmt.next;
-                Err(EvalContinuation::NewComponent(expr.declaration.unwrap(), args))
+                return Err(EvalContinuation::Put(port, message));
+            }
+            Instruction::Channel(from, to) => {
+                let [from_value, to_value] = ctx.new_channel();
+                frame.store(code, *from, from_value);
+                frame.store(code, *to, to_value);
+            }
+            Instruction::New(definition, count) => {
+                let args = frame.pop_many(*count);
+                let def = program.codes[*definition].definition;
+                return Err(EvalContinuation::NewComponent(def, args));
+            }
+            Instruction::Random => frame.stack.push(ctx.random()),
+            Instruction::Choose => match ctx.choose(self.choices) {
+                // The instruction is evaluated again once decided
+                None => {
+                    frame.pc = pc;
+                    return Err(EvalContinuation::BlockChoose);
+                }
+                Some(result) => {
+                    self.choices += 1;
+                    frame.stack.push(result);
+                }
+            },
+            Instruction::SyncStart => {
+                self.choices = 0;
+                return Err(EvalContinuation::SyncBlockStart);
             }
-            Statement::Put(stmt) => {
-                // Evaluate port and message
-                let port = self.store.eval(h, ctx, stmt.port)?;
-                let message = self.store.eval(h, ctx, stmt.message)?;
-                // Continue to next statement
-                self.position = stmt.next;
-                // Signal the put upwards
-                Err(EvalContinuation::Put(port, message))
+            Instruction::SyncEnd => return Err(EvalContinuation::SyncBlockEnd),
+            Instruction::Assert => {
+                if !frame.pop().as_boolean().0 {
+                    // Assertion failed: inconsistent
+                    return Err(EvalContinuation::Inconsistent);
+                }
             }
-            Statement::Expression(stmt) => {
-                // Evaluate expression
-                let value = self.store.eval(h, ctx, stmt.expression)?;
-                // Continue to next statement
-                self.position = stmt.next;
-                Err(EvalContinuation::Stepping)
+            Instruction::Halt => {
+                frame.pc = pc;
+                return Err(EvalContinuation::Terminal);
             }
         }
+        Err(EvalContinuation::Stepping)
     }
-    fn compute_function(h: &Heap, fun: FunctionId, args: &Vec<Value>) -> Option<Value> {
-        let mut prompt = Self::new(h, fun.upcast(), args);
+    fn compute_function(
+        program: &Program,
+        fun: FunctionId,
+        args: &[Value],
+    ) -> Result<Option<Value>, EvalError> {
+        let mut prompt = Self::new(program, fun.upcast(), args);
         let mut context = EvalContext::None;
         loop {
-            let result = prompt.step(h, &mut context);
+            let result = prompt.step(program, &mut context);
             match result {
-                Ok(val) => return Some(val),
+                Ok(val) => return Ok(Some(val)),
                 Err(cont) => match cont {
                     EvalContinuation::Stepping => continue,
-                    EvalContinuation::Inconsistent => return None,
-                    // Functions never terminate without returning
+                    EvalContinuation::Inconsistent => return Ok(None),
+                    EvalContinuation::Error(error) => return Err(error),
+                    // Functions return on every path, which the parser checks
                     EvalContinuation::Terminal => unreachable!(),
                     // Functions never encounter any blocking behavior
                     EvalContinuation::SyncBlockStart => unreachable!(),
@@ -1796,6 +3170,7 @@ impl Prompt {
                     EvalContinuation::NewComponent(_, _) => unreachable!(),
                     EvalContinuation::BlockFires(val) => unreachable!(),
                     EvalContinuation::BlockGet(val) => unreachable!(),
+                    EvalContinuation::BlockChoose => unreachable!(),
                     EvalContinuation::Put(port, msg) => unreachable!(),
                 },
             }
@@ -1824,8 +3199,9 @@ mod tests {
         let pd = parser.parse(&mut heap).unwrap();
         let def = heap[pd].get_definition_ident(&heap, b"test").unwrap();
         let fun = heap[def].as_function().this;
+        let program = Program::compile(&heap, pd);
         let args = Vec::new();
-        let result = Prompt::compute_function(&heap, fun, &args).unwrap();
+        let result = Prompt::compute_function(&program, fun, &args).unwrap().unwrap();
         let valstr: String = format!("{}", result);
         println!("{}", valstr);
 
@@ -1837,4 +3213,38 @@ mod tests {
 
         assert_eq!(valstr, lavstr);
     }
+    #[test_resources("testdata/eval/negative/*.pdl")]
+    fn batch2(resource: &str) {
+        let path = Path::new(resource);
+        let expect = path.with_extension("txt");
+        let mut heap = Heap::new();
+        let mut source = InputSource::from_file(path).unwrap();
+        let mut parser = Parser::new(&mut source);
+        let pd = parser.parse(&mut heap).unwrap();
+        let def = heap[pd].get_definition_ident(&heap, b"test").unwrap();
+        let fun = heap[def].as_function().this;
+        let program = Program::compile(&heap, pd);
+        let args = Vec::new();
+        let result = Prompt::compute_function(&program, fun, &args);
+        // Without an expected error, the function is inconsistent and has no result
+        if !expect.exists() {
+            assert!(matches!(result, Ok(None)), "Expected inconsistency, found {:?}", result);
+            return;
+        }
+        let err = match result {
+            Ok(result) => panic!("Expected evaluation error, found {:?}", result),
+            Err(err) => err,
+        };
+        let mut vec: Vec<u8> = Vec::new();
+        err.write(&source, &mut vec).unwrap();
+        let rendered = String::from_utf8_lossy(&vec);
+
+        let mut cev: Vec<u8> = Vec::new();
+        let mut f = File::open(expect).unwrap();
+        f.read_to_end(&mut cev).unwrap();
+        let expected = String::from_utf8_lossy(&cev);
+
+        // The error is rendered with its position and the source line it points at
+        assert_eq!(expected, rendered, "Unexpected evaluation error for {}", resource);
+    }
 }
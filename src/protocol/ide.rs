@@ -0,0 +1,268 @@
+use crate::common::*;
+use crate::protocol::analysis;
+use crate::protocol::ast::*;
+use crate::protocol::inputsource::*;
+use crate::protocol::library;
+use crate::protocol::parser::*;
+
+use super::diagnostic;
+
+/* The queries of an editor about a protocol description that is being edited. The description
+is parsed as far as it goes: the definitions with errors are still declared, and the definitions
+without errors are resolved, so that most of the description can be navigated while some of it
+is broken. Lines and columns start at 1, and columns count bytes. */
+pub struct Document {
+    heap: Heap,
+    sources: Vec<InputSource>,
+    root: Option<RootId>,
+    diagnostics: Vec<PdlDiagnostic>,
+    references: Vec<Reference>,
+}
+
+// A span in the named source; the main description has an empty name
+#[derive(Eq, PartialEq, Clone, Debug)]
+pub struct Location {
+    pub filename: String,
+    pub line: usize,
+    pub column: usize,
+    pub end_line: usize,
+    pub end_column: usize,
+}
+
+// What is shown of an identifier: its declaration, and the module it was declared in if that is
+// not the main description
+#[derive(Eq, PartialEq, Clone, Debug)]
+pub struct Hover {
+    pub declaration: String,
+    pub module: Option<String>,
+}
+
+#[derive(Eq, PartialEq, Copy, Clone, Debug)]
+pub enum SymbolKind {
+    Primitive,
+    Composite,
+    Function,
+    Struct,
+    Enum,
+}
+
+// A definition of the main description, located at its name
+#[derive(Eq, PartialEq, Clone, Debug)]
+pub struct Symbol {
+    pub name: String,
+    pub kind: SymbolKind,
+    pub detail: String,
+    pub location: Location,
+}
+
+// An identifier in the main description, and what it refers to
+struct Reference {
+    span: InputSpan,
+    target: Target,
+}
+
+enum Target {
+    Variable(VariableId),
+    Definition(DefinitionId),
+    Declaration(DeclarationId),
+}
+
+impl Document {
+    pub fn new(buffer: &[u8], given: &PdlModules) -> Self {
+        let mut heap = Heap::new();
+        let mut modules = library::Modules::new(given);
+        let mut source = modules.add(InputSource::from_buffer(buffer).unwrap());
+        let (root, errors) = Parser::new(&mut source).parse_partial(&mut heap, &mut modules);
+        let sources = modules.sources;
+        let mut diagnostics: Vec<PdlDiagnostic> = errors
+            .iter()
+            .map(|err| {
+                diagnostic(&sources, Severity::Error, err.position(), err.span(), err.message())
+            })
+            .collect();
+        let mut references = Vec::new();
+        if let Some(root) = root {
+            let h = &mut heap;
+            for &def in h[root].definitions.iter() {
+                let span = h[h[def].identifier()].span();
+                references.push(Reference { span, target: Target::Definition(def) });
+            }
+            // Only the definitions without errors are certain to resolve
+            if errors.is_empty() {
+                for &def in h[root].definitions.iter() {
+                    if !h[def].is_component() {
+                        continue;
+                    }
+                    diagnostics.extend(analysis::analyze(h, def).iter().map(|warning| {
+                        let pos = warning.position();
+                        diagnostic(&sources, Severity::Warning, pos, None, warning.message())
+                    }));
+                }
+            }
+            let mut collect = CollectReferences { references: &mut references };
+            collect.visit_protocol_description(h, root).unwrap();
+        }
+        Document { heap, sources, root, diagnostics, references }
+    }
+    pub fn diagnostics(&self) -> &[PdlDiagnostic] {
+        &self.diagnostics
+    }
+    // The text of a line of the named source, to which the columns of its locations refer
+    pub fn line(&self, filename: &str, line: usize) -> Option<&[u8]> {
+        let source = self.sources.iter().find(|source| source.filename() == filename)?;
+        source.line(line)
+    }
+    // The declaration of the identifier at the given position of the main description. The
+    // functions of std.buf are built in, and have no location.
+    pub fn definition(&self, line: usize, column: usize) -> Option<Location> {
+        let h = &self.heap;
+        let span = match self.reference(line, column)?.target {
+            Target::Variable(var) => h[h[var].identifier()].span(),
+            Target::Definition(def) => h[h[def].identifier()].span(),
+            Target::Declaration(decl) => match &h[decl] {
+                Declaration::Defined(defined) => h[h[defined.definition].identifier()].span(),
+                Declaration::Imported(_) => return None,
+            },
+        };
+        Some(self.location(span))
+    }
+    // The type of the variable, or the signature of the definition, at the given position
+    pub fn hover(&self, line: usize, column: usize) -> Option<Hover> {
+        let h = &self.heap;
+        Some(match self.reference(line, column)?.target {
+            Target::Variable(var) => Hover {
+                declaration: format!("{} {}", h[var].the_type(h), h[h[var].identifier()]),
+                module: None,
+            },
+            Target::Definition(def) => self.definition_hover(def),
+            Target::Declaration(decl) => match &h[decl] {
+                Declaration::Defined(defined) => self.definition_hover(defined.definition),
+                Declaration::Imported(imported) => Hover {
+                    declaration: signature(h, &imported.signature),
+                    module: Some(String::from_utf8_lossy(&h[imported.import].value).to_string()),
+                },
+            },
+        })
+    }
+    // The definitions, structs and enums of the main description, in order of their position
+    pub fn symbols(&self) -> Vec<Symbol> {
+        let h = &self.heap;
+        let root = match self.root {
+            Some(root) => &h[root],
+            None => return Vec::new(),
+        };
+        let mut symbols = Vec::new();
+        for &def in root.definitions.iter() {
+            let kind = match &h[def] {
+                Definition::Component(Component::Primitive(_)) => SymbolKind::Primitive,
+                Definition::Component(Component::Composite(_)) => SymbolKind::Composite,
+                Definition::Function(_) => SymbolKind::Function,
+            };
+            symbols.push(self.symbol(h[def].identifier(), kind, definition(h, def)));
+        }
+        for &def in root.structs.iter() {
+            let detail = format!("struct {}", h[h[def].identifier]);
+            symbols.push(self.symbol(h[def].identifier, SymbolKind::Struct, detail));
+        }
+        for &def in root.enums.iter() {
+            let detail = format!("enum {}", h[h[def].identifier]);
+            symbols.push(self.symbol(h[def].identifier, SymbolKind::Enum, detail));
+        }
+        symbols.sort_by_key(|symbol| (symbol.location.line, symbol.location.column));
+        symbols
+    }
+    fn symbol(&self, identifier: SourceIdentifierId, kind: SymbolKind, detail: String) -> Symbol {
+        let identifier = &self.heap[identifier];
+        let location = self.location(identifier.span());
+        Symbol { name: identifier.to_string(), kind, detail, location }
+    }
+    fn reference(&self, line: usize, column: usize) -> Option<&Reference> {
+        self.references.iter().find(|reference| {
+            let (start, end) = (reference.span.start(), reference.span.end());
+            start.file() == 0
+                && start.line() == line
+                && start.column() <= column
+                && column < end.column()
+        })
+    }
+    fn definition_hover(&self, def: DefinitionId) -> Hover {
+        let h = &self.heap;
+        let file = h[def].position().file();
+        let module = if file == 0 { None } else { Some(self.sources[file].filename().to_string()) };
+        Hover { declaration: definition(h, def), module }
+    }
+    fn location(&self, span: InputSpan) -> Location {
+        let (start, end) = (span.start(), span.end());
+        Location {
+            filename: self.sources[start.file()].filename().to_string(),
+            line: start.line(),
+            column: start.column(),
+            end_line: end.line(),
+            end_column: end.column(),
+        }
+    }
+}
+
+// The header of a definition, with the names of its parameters
+fn definition(h: &Heap, def: DefinitionId) -> String {
+    let head = match &h[def] {
+        Definition::Component(Component::Primitive(_)) => "primitive".to_string(),
+        Definition::Component(Component::Composite(_)) => "composite".to_string(),
+        Definition::Function(fun) => h[fun.return_type].the_type.to_string(),
+    };
+    let parameters: Vec<String> = h[def]
+        .parameters()
+        .iter()
+        .map(|&param| {
+            let param = &h[param];
+            format!("{} {}", h[param.type_annotation].the_type, h[param.identifier])
+        })
+        .collect();
+    format!("{} {}({})", head, h[h[def].identifier()], parameters.join(", "))
+}
+
+// The header of a built-in definition, which only has the types of its parameters
+fn signature(h: &Heap, signature: &Signature) -> String {
+    let (head, identifier, arity) = match signature {
+        Signature::Component(com) => ("component".to_string(), com.identifier, &com.arity),
+        Signature::Function(fun) => (fun.return_type.to_string(), fun.identifier, &fun.arity),
+    };
+    let types: Vec<String> = arity.iter().map(|the_type| the_type.to_string()).collect();
+    format!("{} {}({})", head, h[identifier], types.join(", "))
+}
+
+struct CollectReferences<'a> {
+    references: &'a mut Vec<Reference>,
+}
+
+impl Visitor for CollectReferences<'_> {
+    fn visit_import(&mut self, _h: &mut Heap, _import: ImportId) -> VisitorResult {
+        Ok(())
+    }
+    fn visit_variable_declaration(&mut self, h: &mut Heap, decl: VariableId) -> VisitorResult {
+        let span = h[h[decl].identifier()].span();
+        self.references.push(Reference { span, target: Target::Variable(decl) });
+        Ok(())
+    }
+    fn visit_call_expression(&mut self, h: &mut Heap, expr: CallExpressionId) -> VisitorResult {
+        if let (Method::Symbolic(identifier), Some(decl)) = (&h[expr].method, h[expr].declaration) {
+            let span = h[*identifier].span();
+            self.references.push(Reference { span, target: Target::Declaration(decl) });
+        }
+        for arg in h[expr].arguments.clone() {
+            self.visit_expression(h, arg)?;
+        }
+        Ok(())
+    }
+    fn visit_variable_expression(
+        &mut self,
+        h: &mut Heap,
+        expr: VariableExpressionId,
+    ) -> VisitorResult {
+        if let Some(var) = h[expr].declaration {
+            let span = h[h[expr].identifier].span();
+            self.references.push(Reference { span, target: Target::Variable(var) });
+        }
+        Ok(())
+    }
+}
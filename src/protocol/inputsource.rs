@@ -9,46 +9,21 @@ use backtrace::Backtrace;
 pub struct InputSource {
     filename: String,
     input: Vec<u8>,
+    file: usize,
     line: usize,
     column: usize,
     offset: usize,
 }
 
-static STD_LIB_PDL: &'static [u8] = b"
-primitive forward(in i, out o) {
-    while(true) synchronous() put(o, get(i));
-}
-primitive sync(in i, out o) {
-    while(true) synchronous() if(fires(i)) put(o, get(i));
-}
-primitive alternator_2(in i, out l, out r) {
-    while(true) {
-        synchronous() put(l, get(i));
-        synchronous() put(r, get(i));
-    }
-}
-primitive replicator_2(in i, out l, out r) {
-    while(true) synchronous() if(fires(i)) {
-        msg m = get(i);
-        put(l, m);
-        put(r, m);
-    }
-}
-primitive merger_2(in l, in r, out o) {
-    while(true) synchronous {
-        if(fires(l)) put(o, get(l));
-        else         put(o, get(r));
-    }
-}";
-
 impl InputSource {
     // Constructors
     pub fn new<R: io::Read, S: ToString>(filename: S, reader: &mut R) -> io::Result<InputSource> {
-        let mut vec = STD_LIB_PDL.to_vec();
+        let mut vec = Vec::new();
         reader.read_to_end(&mut vec)?;
         Ok(InputSource {
             filename: filename.to_string(),
             input: vec,
+            file: 0,
             line: 1,
             column: 1,
             offset: 0,
@@ -73,13 +48,43 @@ impl InputSource {
     pub fn from_buffer(buffer: &[u8]) -> io::Result<InputSource> {
         InputSource::new(String::new(), &mut Box::new(buffer))
     }
+    // Index of this source among the sources of a protocol description,
+    // recorded in every position that is read from it
+    pub fn set_file(&mut self, file: usize) {
+        self.file = file;
+    }
+    pub fn filename(&self) -> &str {
+        &self.filename
+    }
+    // The text of a line, without its line break. Lines break where positions start a new
+    // line: at a line feed, or a carriage return that is not followed by one.
+    pub fn line(&self, line: usize) -> Option<&[u8]> {
+        let input = &self.input;
+        let breaks = |i: &usize| match input[*i] {
+            b'\n' => true,
+            b'\r' => input.get(*i + 1) != Some(&b'\n'),
+            _ => false,
+        };
+        let mut start = 0;
+        for _ in 1..line {
+            start = (start..input.len()).find(breaks)? + 1;
+        }
+        let end = (start..input.len()).find(breaks).unwrap_or(input.len());
+        Some(&input[start..end])
+    }
     // Internal methods
     pub fn pos(&self) -> InputPosition {
-        InputPosition { line: self.line, column: self.column, offset: self.offset }
+        InputPosition { file: self.file, line: self.line, column: self.column, offset: self.offset }
     }
     pub fn error<S: ToString>(&self, message: S) -> ParseError {
         self.pos().parse_error(message)
     }
+    // Continue reading at a position that was read before
+    pub fn seek(&mut self, pos: InputPosition) {
+        self.line = pos.line;
+        self.column = pos.column;
+        self.offset = pos.offset;
+    }
     pub fn is_eof(&self) -> bool {
         self.next() == None
     }
@@ -120,14 +125,32 @@ impl fmt::Display for InputSource {
     }
 }
 
-#[derive(Debug, Clone, Copy, Default)]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
 pub struct InputPosition {
+    file: usize,
     line: usize,
     column: usize,
     offset: usize,
 }
 
 impl InputPosition {
+    pub fn file(&self) -> usize {
+        self.file
+    }
+    pub fn line(&self) -> usize {
+        self.line
+    }
+    pub fn column(&self) -> usize {
+        self.column
+    }
+    pub fn offset(&self) -> usize {
+        self.offset
+    }
+    // The span of the len characters that start at this position, on its line
+    pub fn span(&self, len: usize) -> InputSpan {
+        let end = InputPosition { column: self.column + len, offset: self.offset + len, ..*self };
+        InputSpan { start: *self, end }
+    }
     fn context<'a>(&self, source: &'a InputSource) -> &'a [u8] {
         let start = self.offset - (self.column - 1);
         let mut end = self.offset;
@@ -141,10 +164,10 @@ impl InputPosition {
         &source.input[start..end]
     }
     fn parse_error<S: ToString>(&self, message: S) -> ParseError {
-        ParseError { position: *self, message: message.to_string(), backtrace: Backtrace::new() }
+        ParseError::new(*self, message)
     }
     fn eval_error<S: ToString>(&self, message: S) -> EvalError {
-        EvalError { position: *self, message: message.to_string(), backtrace: Backtrace::new() }
+        EvalError::new(*self, message)
     }
 }
 
@@ -154,6 +177,31 @@ impl fmt::Display for InputPosition {
     }
 }
 
+// The source from a start position up to an end position, which is just
+// past the last character of the span
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct InputSpan {
+    start: InputPosition,
+    end: InputPosition,
+}
+
+impl InputSpan {
+    pub fn new(start: InputPosition, end: InputPosition) -> InputSpan {
+        InputSpan { start, end }
+    }
+    pub fn start(&self) -> InputPosition {
+        self.start
+    }
+    pub fn end(&self) -> InputPosition {
+        self.end
+    }
+    pub fn contains(&self, pos: InputPosition) -> bool {
+        self.start.file == pos.file
+            && self.start.offset <= pos.offset
+            && pos.offset < self.end.offset
+    }
+}
+
 pub trait SyntaxElement {
     fn position(&self) -> InputPosition;
     fn error<S: ToString>(&self, message: S) -> EvalError {
@@ -161,41 +209,137 @@ pub trait SyntaxElement {
     }
 }
 
+// What is shown of an error besides its message: the span of the source
+// that is underlined, remarks at related positions, and a suggested fix
+#[derive(Debug, Clone, Default)]
+struct Labels {
+    span: Option<InputSpan>,
+    notes: Vec<(InputPosition, String)>,
+    hint: Option<String>,
+}
+
+impl Labels {
+    fn write<'a, A: io::Write>(
+        &self,
+        kind: &str,
+        position: InputPosition,
+        message: &str,
+        source: &InputSource,
+        sources: &dyn Fn(usize) -> Option<&'a InputSource>,
+        writer: &mut A,
+    ) -> io::Result<()> {
+        write_label(kind, position, self.span, message, source, writer)?;
+        for (position, note) in self.notes.iter() {
+            match sources(position.file) {
+                Some(source) => write_label("Note", *position, None, note, source, writer)?,
+                None => writeln!(writer, "Note at {}: {}", position, note)?,
+            }
+        }
+        if let Some(hint) = &self.hint {
+            writeln!(writer, "Hint: {}", hint)?;
+        }
+        Ok(())
+    }
+}
+
+// Writes a message, followed by the line of the position and an arrow at
+// the position, which extends over the span as far as it is on that line
+fn write_label<A: io::Write>(
+    kind: &str,
+    position: InputPosition,
+    span: Option<InputSpan>,
+    message: &str,
+    source: &InputSource,
+    writer: &mut A,
+) -> io::Result<()> {
+    if !source.filename.is_empty() {
+        writeln!(writer, "{} at {}:{}: {}", kind, source.filename, position, message)?;
+    } else {
+        writeln!(writer, "{} at {}: {}", kind, position, message)?;
+    }
+    let line = position.context(source);
+    writeln!(writer, "{}", String::from_utf8_lossy(line))?;
+    let (mut start, mut end) = (position.column, position.column + 1);
+    if let Some(span) = span.filter(|span| span.start.offset <= position.offset) {
+        if span.start.line == position.line {
+            start = span.start.column;
+        } else {
+            start = 1;
+        }
+        if span.end.line == position.line {
+            end = span.end.column.max(end);
+        } else {
+            end = line.len() + 1;
+        }
+    }
+    let mut arrow: Vec<u8> = Vec::new();
+    for pos in 1..start {
+        let c = line[pos - 1];
+        if c == b'\t' {
+            arrow.push(b'\t')
+        } else {
+            arrow.push(b' ')
+        }
+    }
+    for _ in start..end {
+        arrow.push(b'^');
+    }
+    writeln!(writer, "{}", String::from_utf8_lossy(&arrow))
+}
+
 #[derive(Debug, Clone)]
 pub struct ParseError {
     position: InputPosition,
     message: String,
+    labels: Box<Labels>,
     backtrace: Backtrace,
 }
 
 impl ParseError {
     pub fn new<S: ToString>(position: InputPosition, message: S) -> ParseError {
-        ParseError { position, message: message.to_string(), backtrace: Backtrace::new() }
+        ParseError {
+            position,
+            message: message.to_string(),
+            labels: Box::default(),
+            backtrace: Backtrace::new(),
+        }
+    }
+    // Underlines the span around the position of the error
+    pub fn with_span(mut self, span: InputSpan) -> ParseError {
+        self.labels.span = Some(span);
+        self
+    }
+    pub fn with_note<S: ToString>(mut self, position: InputPosition, note: S) -> ParseError {
+        self.labels.notes.push((position, note.to_string()));
+        self
+    }
+    pub fn with_hint<S: ToString>(mut self, hint: S) -> ParseError {
+        self.labels.hint = Some(hint.to_string());
+        self
+    }
+    pub fn position(&self) -> InputPosition {
+        self.position
+    }
+    pub fn span(&self) -> Option<InputSpan> {
+        self.labels.span
+    }
+    pub fn message(&self) -> &str {
+        &self.message
     }
     // Diagnostic methods
     pub fn write<A: io::Write>(&self, source: &InputSource, writer: &mut A) -> io::Result<()> {
-        if !source.filename.is_empty() {
-            writeln!(
-                writer,
-                "Parse error at {}:{}: {}",
-                source.filename, self.position, self.message
-            )?;
-        } else {
-            writeln!(writer, "Parse error at {}: {}", self.position, self.message)?;
-        }
-        let line = self.position.context(source);
-        writeln!(writer, "{}", String::from_utf8_lossy(line))?;
-        let mut arrow: Vec<u8> = Vec::new();
-        for pos in 1..self.position.column {
-            let c = line[pos - 1];
-            if c == b'\t' {
-                arrow.push(b'\t')
-            } else {
-                arrow.push(b' ')
-            }
-        }
-        arrow.push(b'^');
-        writeln!(writer, "{}", String::from_utf8_lossy(&arrow))
+        let sources = |file| if file == source.file { Some(source) } else { None };
+        self.labels.write("Parse error", self.position, &self.message, source, &sources, writer)
+    }
+    // Notes are shown in the source that their position refers to
+    pub fn write_in<A: io::Write>(
+        &self,
+        sources: &[InputSource],
+        writer: &mut A,
+    ) -> io::Result<()> {
+        let source = &sources[self.position.file];
+        let sources = |file| sources.get(file);
+        self.labels.write("Parse error", self.position, &self.message, source, &sources, writer)
     }
     pub fn print(&self, source: &InputSource) {
         self.write(source, &mut std::io::stdout()).unwrap()
@@ -240,37 +384,63 @@ impl fmt::Display for DisplayParseError<'_> {
 pub struct EvalError {
     position: InputPosition,
     message: String,
+    labels: Box<Labels>,
     backtrace: Backtrace,
 }
 
 impl EvalError {
     pub fn new<S: ToString>(position: InputPosition, message: S) -> EvalError {
-        EvalError { position, message: message.to_string(), backtrace: Backtrace::new() }
+        EvalError {
+            position,
+            message: message.to_string(),
+            labels: Box::default(),
+            backtrace: Backtrace::new(),
+        }
+    }
+    // Underlines the span around the position of the error
+    pub fn with_span(mut self, span: InputSpan) -> EvalError {
+        self.labels.span = Some(span);
+        self
+    }
+    pub fn with_note<S: ToString>(mut self, position: InputPosition, note: S) -> EvalError {
+        self.labels.notes.push((position, note.to_string()));
+        self
+    }
+    pub fn with_hint<S: ToString>(mut self, hint: S) -> EvalError {
+        self.labels.hint = Some(hint.to_string());
+        self
+    }
+    pub fn position(&self) -> InputPosition {
+        self.position
     }
     // Diagnostic methods
     pub fn write<A: io::Write>(&self, source: &InputSource, writer: &mut A) -> io::Result<()> {
-        if !source.filename.is_empty() {
-            writeln!(
-                writer,
-                "Evaluation error at {}:{}: {}",
-                source.filename, self.position, self.message
-            )?;
-        } else {
-            writeln!(writer, "Evaluation error at {}: {}", self.position, self.message)?;
-        }
-        let line = self.position.context(source);
-        writeln!(writer, "{}", String::from_utf8_lossy(line))?;
-        let mut arrow: Vec<u8> = Vec::new();
-        for pos in 1..self.position.column {
-            let c = line[pos - 1];
-            if c == b'\t' {
-                arrow.push(b'\t')
-            } else {
-                arrow.push(b' ')
-            }
-        }
-        arrow.push(b'^');
-        writeln!(writer, "{}", String::from_utf8_lossy(&arrow))
+        let sources = |file| if file == source.file { Some(source) } else { None };
+        self.labels.write(
+            "Evaluation error",
+            self.position,
+            &self.message,
+            source,
+            &sources,
+            writer,
+        )
+    }
+    // Notes are shown in the source that their position refers to
+    pub fn write_in<A: io::Write>(
+        &self,
+        sources: &[InputSource],
+        writer: &mut A,
+    ) -> io::Result<()> {
+        let source = &sources[self.position.file];
+        let sources = |file| sources.get(file);
+        self.labels.write(
+            "Evaluation error",
+            self.position,
+            &self.message,
+            source,
+            &sources,
+            writer,
+        )
     }
     pub fn print(&self, source: &InputSource) {
         self.write(source, &mut std::io::stdout()).unwrap()
@@ -403,4 +573,41 @@ mod tests {
         assert!(is.next() == Some(b'e'));
         is.consume();
     }
+
+    #[test]
+    fn test_labels() {
+        let mut is = InputSource::from_string("int x = y + 1;\nint y;\n").unwrap();
+        let mut other = InputSource::from_string("int z;\n").unwrap();
+        other.set_file(1);
+        for _ in 0..8 {
+            is.consume();
+        }
+        let start = is.pos();
+        for _ in 0..2 {
+            is.consume();
+        }
+        let position = is.pos();
+        let span = InputSpan::new(start, start.span(5).end());
+        assert!(span.contains(position) && !span.contains(span.end()));
+        for _ in 0..9 {
+            is.consume();
+        }
+        let er = position
+            .parse_error("Type mismatch")
+            .with_span(span)
+            .with_note(is.pos(), "Declared here")
+            .with_note(other.pos(), "Also declared here")
+            .with_hint("Swap them");
+        let mut vec: Vec<u8> = Vec::new();
+        er.write(&is, &mut vec).unwrap();
+        assert_eq!(
+            "Parse error at 1:11: Type mismatch\nint x = y + 1;\n        ^^^^^\n\
+             Note at 2:5: Declared here\nint y;\n    ^\n\
+             Note at 1:1: Also declared here\nHint: Swap them\n",
+            String::from_utf8_lossy(&vec)
+        );
+        let mut vec: Vec<u8> = Vec::new();
+        er.write_in(&[is, other], &mut vec).unwrap();
+        assert!(String::from_utf8_lossy(&vec).contains("Also declared here\nint z;\n^\n"));
+    }
 }
@@ -3,6 +3,12 @@ use crate::protocol::inputsource::*;
 
 const MAX_LEVEL: usize = 128;
 
+// The language version that a protocol description may declare with #version
+pub const VERSION: i64 = 100;
+
+// The opt-in language features that a protocol description may enable with #feature
+const FEATURES: &[&[u8]] = &[b"checked_overflow"];
+
 fn is_vchar(x: Option<u8>) -> bool {
     if let Some(c) = x {
         c >= 0x21 && c <= 0x7E
@@ -74,11 +80,21 @@ fn lowercase(x: u8) -> u8 {
 pub struct Lexer<'a> {
     source: &'a mut InputSource,
     level: usize,
+    // Errors that the lexer recovered from, in the order they were found
+    errors: Vec<ParseError>,
+    // Definitions in which the lexer recovered from an error
+    broken: Vec<DefinitionId>,
 }
 
 impl Lexer<'_> {
     pub fn new(source: &mut InputSource) -> Lexer {
-        Lexer { source, level: 0 }
+        Lexer { source, level: 0, errors: Vec::new(), broken: Vec::new() }
+    }
+    pub fn errors(&self) -> &[ParseError] {
+        &self.errors
+    }
+    pub fn broken(&self) -> &[DefinitionId] {
+        &self.broken
     }
     fn consume_line(&mut self) -> Result<Vec<u8>, ParseError> {
         let mut result: Vec<u8> = Vec::new();
@@ -231,6 +247,111 @@ impl Lexer<'_> {
         }
         Ok(result)
     }
+    // Symbols imported from user modules are qualified by the module name
+    fn consume_qualified_ident(&mut self) -> Result<Vec<u8>, ParseError> {
+        let mut result = self.consume_ident()?;
+        while self.has_string(b".") && is_ident_start(self.source.lookahead(1)) {
+            self.consume_string(b".")?;
+            result.push(b'.');
+            result.append(&mut self.consume_ident()?);
+        }
+        Ok(result)
+    }
+
+    // Error recovery
+
+    fn recover_statement(
+        &mut self,
+        err: ParseError,
+        start: InputPosition,
+        level: usize,
+    ) -> Result<(), ParseError> {
+        self.source.seek(start);
+        self.level = level;
+        self.skip_statement()?;
+        // Errors that run into the end of the file are left to the enclosing definition
+        if self.source.is_eof() || self.source.pos() == start {
+            return Err(err);
+        }
+        self.errors.push(err);
+        Ok(())
+    }
+    fn skip_statement(&mut self) -> Result<(), ParseError> {
+        /* Skips up to the end of the statement: a semicolon or the closing
+        brace of a block, outside of any parentheses, brackets or braces. The
+        brace that closes the enclosing block is left in place. */
+        let mut depth = 0;
+        let mut block = false;
+        let mut prev = None;
+        loop {
+            self.consume_whitespace(false)?;
+            let next = self.source.next();
+            match next {
+                None => return Ok(()),
+                Some(b'}') if depth == 0 => return Ok(()),
+                Some(b'{') | Some(b'(') | Some(b'[') => {
+                    if depth == 0 && next == Some(b'{') {
+                        // Braces after a condition or a keyword enclose a block, others an array
+                        block = prev.is_none() || prev == Some(b')') || is_ident_rest(prev);
+                    }
+                    depth += 1;
+                }
+                Some(b'}') | Some(b')') | Some(b']') => depth -= 1,
+                Some(b'\'') => {
+                    self.skip_character();
+                    prev = next;
+                    continue;
+                }
+                _ => {}
+            }
+            self.source.consume();
+            prev = next;
+            if depth == 0 && (next == Some(b';') || next == Some(b'}') && block) {
+                self.consume_whitespace(false)?;
+                if next == Some(b'}') && self.has_string(b";") {
+                    self.source.consume();
+                    self.consume_whitespace(false)?;
+                }
+                if !self.has_keyword(b"else") {
+                    return Ok(());
+                }
+                block = false;
+            }
+        }
+    }
+    fn skip_definition(&mut self) -> Result<(), ParseError> {
+        // Skips up to and including the brace that closes the body of the definition
+        let mut depth = 0;
+        loop {
+            self.consume_whitespace(false)?;
+            let next = self.source.next();
+            match next {
+                None => return Ok(()),
+                Some(b'{') => depth += 1,
+                Some(b'}') if depth > 0 => depth -= 1,
+                Some(b'\'') => {
+                    self.skip_character();
+                    continue;
+                }
+                _ => {}
+            }
+            self.source.consume();
+            if next == Some(b'}') && depth == 0 {
+                return Ok(());
+            }
+        }
+    }
+    fn skip_character(&mut self) {
+        self.source.consume();
+        let mut next = self.source.next();
+        while next.is_some() && next != Some(b'\'') && next != Some(b'\n') {
+            self.source.consume();
+            next = self.source.next();
+        }
+        if next == Some(b'\'') {
+            self.source.consume();
+        }
+    }
 
     // Statement keywords
 
@@ -238,7 +359,12 @@ impl Lexer<'_> {
         self.has_keyword(b"channel")
             || self.has_keyword(b"skip")
             || self.has_keyword(b"if")
+            || self.has_keyword(b"switch")
+            || self.has_keyword(b"case")
+            || self.has_keyword(b"default")
             || self.has_keyword(b"while")
+            || self.has_keyword(b"for")
+            || self.has_keyword(b"foreach")
             || self.has_keyword(b"break")
             || self.has_keyword(b"continue")
             || self.has_keyword(b"synchronous")
@@ -262,6 +388,8 @@ impl Lexer<'_> {
         self.has_keyword(b"get")
             || self.has_keyword(b"fires")
             || self.has_keyword(b"create")
+            || self.has_keyword(b"random")
+            || self.has_keyword(b"choose")
             || self.has_keyword(b"length")
     }
 
@@ -290,6 +418,25 @@ impl Lexer<'_> {
         self.consume_ident()?;
         Ok(())
     }
+    fn consume_method_identifier(
+        &mut self,
+        h: &mut Heap,
+    ) -> Result<SourceIdentifierId, ParseError> {
+        if self.has_statement_keyword() || self.has_type_keyword() || self.has_builtin_keyword() {
+            return Err(self.source.error("Expected identifier"));
+        }
+        let position = self.source.pos();
+        let value = self.consume_qualified_ident()?;
+        let id = h.alloc_source_identifier(|this| SourceIdentifier { this, position, value });
+        Ok(id)
+    }
+    fn consume_method_identifier_spilled(&mut self) -> Result<(), ParseError> {
+        if self.has_statement_keyword() || self.has_type_keyword() || self.has_builtin_keyword() {
+            return Err(self.source.error("Expected identifier"));
+        }
+        self.consume_qualified_ident()?;
+        Ok(())
+    }
 
     // Types and type annotations
 
@@ -429,9 +576,11 @@ impl Lexer<'_> {
             let operation = self.consume_assignment_operator()?;
             self.consume_whitespace(false)?;
             let right = self.consume_expression(h)?;
+            let span = InputSpan::new(h[left].span().start(), h[right].span().end());
             Ok(h.alloc_assignment_expression(|this| AssignmentExpression {
                 this,
                 position,
+                span,
                 left,
                 operation,
                 right,
@@ -505,9 +654,11 @@ impl Lexer<'_> {
             self.consume_string(b":")?;
             self.consume_whitespace(false)?;
             let false_expression = self.consume_expression(h)?;
+            let span = InputSpan::new(h[test].span().start(), h[false_expression].span().end());
             Ok(h.alloc_conditional_expression(|this| ConditionalExpression {
                 this,
                 position,
+                span,
                 test,
                 true_expression,
                 false_expression,
@@ -528,13 +679,16 @@ impl Lexer<'_> {
             self.consume_whitespace(false)?;
             let right = self.consume_lor_expression(h)?;
             self.consume_whitespace(false)?;
+            let span = InputSpan::new(h[left].span().start(), h[right].span().end());
             result = h
                 .alloc_binary_expression(|this| BinaryExpression {
                     this,
                     position,
+                    span,
                     left,
                     operation,
                     right,
+                    the_type: None,
                 })
                 .upcast();
         }
@@ -551,13 +705,16 @@ impl Lexer<'_> {
             self.consume_whitespace(false)?;
             let right = self.consume_land_expression(h)?;
             self.consume_whitespace(false)?;
+            let span = InputSpan::new(h[left].span().start(), h[right].span().end());
             result = h
                 .alloc_binary_expression(|this| BinaryExpression {
                     this,
                     position,
+                    span,
                     left,
                     operation,
                     right,
+                    the_type: None,
                 })
                 .upcast();
         }
@@ -574,13 +731,16 @@ impl Lexer<'_> {
             self.consume_whitespace(false)?;
             let right = self.consume_bor_expression(h)?;
             self.consume_whitespace(false)?;
+            let span = InputSpan::new(h[left].span().start(), h[right].span().end());
             result = h
                 .alloc_binary_expression(|this| BinaryExpression {
                     this,
                     position,
+                    span,
                     left,
                     operation,
                     right,
+                    the_type: None,
                 })
                 .upcast();
         }
@@ -597,13 +757,16 @@ impl Lexer<'_> {
             self.consume_whitespace(false)?;
             let right = self.consume_xor_expression(h)?;
             self.consume_whitespace(false)?;
+            let span = InputSpan::new(h[left].span().start(), h[right].span().end());
             result = h
                 .alloc_binary_expression(|this| BinaryExpression {
                     this,
                     position,
+                    span,
                     left,
                     operation,
                     right,
+                    the_type: None,
                 })
                 .upcast();
         }
@@ -620,13 +783,16 @@ impl Lexer<'_> {
             self.consume_whitespace(false)?;
             let right = self.consume_band_expression(h)?;
             self.consume_whitespace(false)?;
+            let span = InputSpan::new(h[left].span().start(), h[right].span().end());
             result = h
                 .alloc_binary_expression(|this| BinaryExpression {
                     this,
                     position,
+                    span,
                     left,
                     operation,
                     right,
+                    the_type: None,
                 })
                 .upcast();
         }
@@ -643,13 +809,16 @@ impl Lexer<'_> {
             self.consume_whitespace(false)?;
             let right = self.consume_eq_expression(h)?;
             self.consume_whitespace(false)?;
+            let span = InputSpan::new(h[left].span().start(), h[right].span().end());
             result = h
                 .alloc_binary_expression(|this| BinaryExpression {
                     this,
                     position,
+                    span,
                     left,
                     operation,
                     right,
+                    the_type: None,
                 })
                 .upcast();
         }
@@ -672,13 +841,16 @@ impl Lexer<'_> {
             self.consume_whitespace(false)?;
             let right = self.consume_rel_expression(h)?;
             self.consume_whitespace(false)?;
+            let span = InputSpan::new(h[left].span().start(), h[right].span().end());
             result = h
                 .alloc_binary_expression(|this| BinaryExpression {
                     this,
                     position,
+                    span,
                     left,
                     operation,
                     right,
+                    the_type: None,
                 })
                 .upcast();
         }
@@ -711,13 +883,16 @@ impl Lexer<'_> {
             self.consume_whitespace(false)?;
             let right = self.consume_shift_expression(h)?;
             self.consume_whitespace(false)?;
+            let span = InputSpan::new(h[left].span().start(), h[right].span().end());
             result = h
                 .alloc_binary_expression(|this| BinaryExpression {
                     this,
                     position,
+                    span,
                     left,
                     operation,
                     right,
+                    the_type: None,
                 })
                 .upcast();
         }
@@ -742,13 +917,16 @@ impl Lexer<'_> {
             self.consume_whitespace(false)?;
             let right = self.consume_add_expression(h)?;
             self.consume_whitespace(false)?;
+            let span = InputSpan::new(h[left].span().start(), h[right].span().end());
             result = h
                 .alloc_binary_expression(|this| BinaryExpression {
                     this,
                     position,
+                    span,
                     left,
                     operation,
                     right,
+                    the_type: None,
                 })
                 .upcast();
         }
@@ -773,13 +951,16 @@ impl Lexer<'_> {
             self.consume_whitespace(false)?;
             let right = self.consume_mul_expression(h)?;
             self.consume_whitespace(false)?;
+            let span = InputSpan::new(h[left].span().start(), h[right].span().end());
             result = h
                 .alloc_binary_expression(|this| BinaryExpression {
                     this,
                     position,
+                    span,
                     left,
                     operation,
                     right,
+                    the_type: None,
                 })
                 .upcast();
         }
@@ -808,19 +989,70 @@ impl Lexer<'_> {
             self.consume_whitespace(false)?;
             let right = self.consume_prefix_expression(h)?;
             self.consume_whitespace(false)?;
+            let span = InputSpan::new(h[left].span().start(), h[right].span().end());
             result = h
                 .alloc_binary_expression(|this| BinaryExpression {
                     this,
                     position,
+                    span,
                     left,
                     operation,
                     right,
+                    the_type: None,
                 })
                 .upcast();
         }
         Ok(result)
     }
+    fn has_cast(&mut self) -> bool {
+        /* A parenthesized integer type keyword starts a cast, since it
+        cannot start a parenthesized expression. */
+        let backup = self.source.clone();
+        let mut result = false;
+        if self.consume_string(b"(").is_ok() && self.consume_whitespace(false).is_ok() {
+            let is_integer = self.has_keyword(b"byte")
+                || self.has_keyword(b"short")
+                || self.has_keyword(b"int")
+                || self.has_keyword(b"long");
+            if is_integer
+                && self.consume_primitive_type().is_ok()
+                && self.consume_whitespace(false).is_ok()
+            {
+                result = self.has_string(b")");
+            }
+        }
+        *self.source = backup;
+        result
+    }
+    fn consume_cast_expression(&mut self, h: &mut Heap) -> Result<CastExpressionId, ParseError> {
+        let position = self.source.pos();
+        self.consume_string(b"(")?;
+        self.consume_whitespace(false)?;
+        let primitive = self.consume_primitive_type()?;
+        self.consume_whitespace(false)?;
+        self.consume_string(b")")?;
+        self.consume_whitespace(false)?;
+        if self.level >= MAX_LEVEL {
+            return Err(self.source.error("Too deeply nested expression"));
+        }
+        self.level += 1;
+        let result = self.consume_prefix_expression(h);
+        self.level -= 1;
+        let expression = result?;
+        let span = InputSpan::new(position, h[expression].span().end());
+        let the_type = Type { primitive, array: false };
+        Ok(h.alloc_cast_expression(|this| CastExpression {
+            this,
+            position,
+            span,
+            the_type,
+            expression,
+        }))
+    }
     fn consume_prefix_expression(&mut self, h: &mut Heap) -> Result<ExpressionId, ParseError> {
+        if self.has_cast() {
+            return Ok(self.consume_cast_expression(h)?.upcast());
+        }
         if self.has_string(b"+")
             || self.has_string(b"-")
             || self.has_string(b"~")
@@ -859,10 +1091,12 @@ impl Lexer<'_> {
             let result = self.consume_prefix_expression(h);
             self.level -= 1;
             let expression = result?;
+            let span = InputSpan::new(position, h[expression].span().end());
             return Ok(h
                 .alloc_unary_expression(|this| UnaryExpression {
                     this,
                     position,
+                    span,
                     operation,
                     expression,
                 })
@@ -883,11 +1117,13 @@ impl Lexer<'_> {
                 self.consume_string(b"++")?;
                 let operation = UnaryOperation::PostIncrement;
                 let expression = result;
+                let span = InputSpan::new(h[expression].span().start(), self.source.pos());
                 self.consume_whitespace(false)?;
                 result = h
                     .alloc_unary_expression(|this| UnaryExpression {
                         this,
                         position,
+                        span,
                         operation,
                         expression,
                     })
@@ -896,11 +1132,13 @@ impl Lexer<'_> {
                 self.consume_string(b"--")?;
                 let operation = UnaryOperation::PostDecrement;
                 let expression = result;
+                let span = InputSpan::new(h[expression].span().start(), self.source.pos());
                 self.consume_whitespace(false)?;
                 result = h
                     .alloc_unary_expression(|this| UnaryExpression {
                         this,
                         position,
+                        span,
                         operation,
                         expression,
                     })
@@ -911,6 +1149,7 @@ impl Lexer<'_> {
                 let subject = result;
                 let index = self.consume_expression(h)?;
                 self.consume_whitespace(false)?;
+                let mut to_index = None;
                 if self.has_string(b"..") || self.has_string(b":") {
                     position = self.source.pos();
                     if self.has_string(b"..") {
@@ -919,12 +1158,17 @@ impl Lexer<'_> {
                         self.consume_string(b":")?;
                     }
                     self.consume_whitespace(false)?;
-                    let to_index = self.consume_expression(h)?;
+                    to_index = Some(self.consume_expression(h)?);
                     self.consume_whitespace(false)?;
+                }
+                self.consume_string(b"]")?;
+                let span = InputSpan::new(h[subject].span().start(), self.source.pos());
+                if let Some(to_index) = to_index {
                     result = h
                         .alloc_slicing_expression(|this| SlicingExpression {
                             this,
                             position,
+                            span,
                             subject,
                             from_index: index,
                             to_index,
@@ -935,12 +1179,12 @@ impl Lexer<'_> {
                         .alloc_indexing_expression(|this| IndexingExpression {
                             this,
                             position,
+                            span,
                             subject,
                             index,
                         })
                         .upcast();
                 }
-                self.consume_string(b"]")?;
                 self.consume_whitespace(false)?;
             } else {
                 assert!(self.has_string(b"."));
@@ -954,12 +1198,15 @@ impl Lexer<'_> {
                 } else {
                     field = Field::Symbolic(self.consume_identifier(h)?);
                 }
+                let span = InputSpan::new(h[subject].span().start(), self.source.pos());
                 result = h
                     .alloc_select_expression(|this| SelectExpression {
                         this,
                         position,
+                        span,
                         subject,
                         field,
+                        field_index: None,
                     })
                     .upcast();
             }
@@ -983,6 +1230,12 @@ impl Lexer<'_> {
         if self.has_call_expression() {
             return Ok(self.consume_call_expression(h)?.upcast());
         }
+        if self.has_struct_expression() {
+            return Ok(self.consume_struct_expression(h)?.upcast());
+        }
+        if self.has_enum_expression() {
+            return Ok(self.consume_enum_expression(h)?.upcast());
+        }
         Ok(self.consume_variable_expression(h)?.upcast())
     }
     fn consume_array_expression(&mut self, h: &mut Heap) -> Result<ArrayExpressionId, ParseError> {
@@ -1002,7 +1255,80 @@ impl Lexer<'_> {
             }
         }
         self.consume_string(b"}")?;
-        Ok(h.alloc_array_expression(|this| ArrayExpression { this, position, elements }))
+        let span = InputSpan::new(position, self.source.pos());
+        Ok(h.alloc_array_expression(|this| ArrayExpression { this, position, span, elements }))
+    }
+    fn has_struct_expression(&mut self) -> bool {
+        /* Like call expressions, we look ahead the identifier to see if
+        we can find an opening brace: this signals a struct expression. */
+        let backup = self.source.clone();
+        let mut result = false;
+        if self.consume_identifier_spilled().is_ok() && self.consume_whitespace(false).is_ok() {
+            result = self.has_string(b"{");
+        }
+        *self.source = backup;
+        result
+    }
+    fn consume_struct_expression(
+        &mut self,
+        h: &mut Heap,
+    ) -> Result<StructExpressionId, ParseError> {
+        let position = self.source.pos();
+        let identifier = self.consume_identifier(h)?;
+        self.consume_whitespace(false)?;
+        let mut fields = Vec::new();
+        self.consume_string(b"{")?;
+        self.consume_whitespace(false)?;
+        if !self.has_string(b"}") {
+            while self.source.next().is_some() {
+                let field = self.consume_identifier(h)?;
+                self.consume_whitespace(false)?;
+                self.consume_string(b":")?;
+                self.consume_whitespace(false)?;
+                fields.push((field, self.consume_expression(h)?));
+                self.consume_whitespace(false)?;
+                if self.has_string(b"}") {
+                    break;
+                }
+                self.consume_string(b",")?;
+                self.consume_whitespace(false)?;
+            }
+        }
+        self.consume_string(b"}")?;
+        let span = InputSpan::new(position, self.source.pos());
+        Ok(h.alloc_struct_expression(|this| StructExpression {
+            this,
+            position,
+            span,
+            identifier,
+            fields,
+            definition: None,
+        }))
+    }
+    fn has_enum_expression(&mut self) -> bool {
+        let backup = self.source.clone();
+        let mut result = false;
+        if self.consume_identifier_spilled().is_ok() && self.consume_whitespace(false).is_ok() {
+            result = self.has_string(b"::");
+        }
+        *self.source = backup;
+        result
+    }
+    fn consume_enum_expression(&mut self, h: &mut Heap) -> Result<EnumExpressionId, ParseError> {
+        let position = self.source.pos();
+        let identifier = self.consume_identifier(h)?;
+        self.consume_whitespace(false)?;
+        self.consume_string(b"::")?;
+        self.consume_whitespace(false)?;
+        let variant = self.consume_identifier(h)?;
+        let span = InputSpan::new(position, self.source.pos());
+        Ok(h.alloc_enum_expression(|this| EnumExpression {
+            this,
+            position,
+            span,
+            identifier,
+            variant,
+        }))
     }
     fn has_constant(&self) -> bool {
         is_constant(self.source.next())
@@ -1049,7 +1375,14 @@ impl Lexer<'_> {
             }
             value = Constant::Integer(data);
         }
-        Ok(h.alloc_constant_expression(|this| ConstantExpression { this, position, value }))
+        let span = InputSpan::new(position, self.source.pos());
+        Ok(h.alloc_constant_expression(|this| ConstantExpression {
+            this,
+            position,
+            span,
+            value,
+            the_type: None,
+        }))
     }
     fn has_call_expression(&mut self) -> bool {
         /* We prevent ambiguity with variables, by looking ahead
@@ -1060,7 +1393,7 @@ impl Lexer<'_> {
         }
         let backup = self.source.clone();
         let mut result = false;
-        match self.consume_identifier_spilled() {
+        match self.consume_method_identifier_spilled() {
             Ok(_) => match self.consume_whitespace(false) {
                 Ok(_) => {
                     result = self.has_string(b"(");
@@ -1084,8 +1417,14 @@ impl Lexer<'_> {
         } else if self.has_keyword(b"create") {
             self.consume_keyword(b"create")?;
             method = Method::Create;
+        } else if self.has_keyword(b"random") {
+            self.consume_keyword(b"random")?;
+            method = Method::Random;
+        } else if self.has_keyword(b"choose") {
+            self.consume_keyword(b"choose")?;
+            method = Method::Choose;
         } else {
-            let identifier = self.consume_identifier(h)?;
+            let identifier = self.consume_method_identifier(h)?;
             method = Method::Symbolic(identifier)
         }
         self.consume_whitespace(false)?;
@@ -1104,9 +1443,11 @@ impl Lexer<'_> {
             }
         }
         self.consume_string(b")")?;
+        let span = InputSpan::new(position, self.source.pos());
         Ok(h.alloc_call_expression(|this| CallExpression {
             this,
             position,
+            span,
             method,
             arguments,
             declaration: None,
@@ -1118,9 +1459,11 @@ impl Lexer<'_> {
     ) -> Result<VariableExpressionId, ParseError> {
         let position = self.source.pos();
         let identifier = self.consume_identifier(h)?;
+        let span = InputSpan::new(position, self.source.pos());
         Ok(h.alloc_variable_expression(|this| VariableExpression {
             this,
             position,
+            span,
             identifier,
             declaration: None,
         }))
@@ -1164,8 +1507,12 @@ impl Lexer<'_> {
             Ok(self.consume_skip_statement(h)?.upcast())
         } else if self.has_keyword(b"if") {
             Ok(self.consume_if_statement(h)?.upcast())
+        } else if self.has_keyword(b"switch") {
+            Ok(self.consume_switch_statement(h)?.upcast())
         } else if self.has_keyword(b"while") {
             Ok(self.consume_while_statement(h)?.upcast())
+        } else if self.has_keyword(b"for") || self.has_keyword(b"foreach") {
+            Ok(self.consume_for_statement(h)?.upcast())
         } else if self.has_keyword(b"break") {
             Ok(self.consume_break_statement(h)?.upcast())
         } else if self.has_keyword(b"continue") {
@@ -1223,11 +1570,19 @@ impl Lexer<'_> {
         self.consume_string(b"{")?;
         self.consume_whitespace(false)?;
         while self.has_local_statement() {
-            statements.push(self.consume_local_statement(h)?.upcast());
+            let (start, level) = (self.source.pos(), self.level);
+            match self.consume_local_statement(h) {
+                Ok(stmt) => statements.push(stmt.upcast()),
+                Err(err) => self.recover_statement(err, start, level)?,
+            }
             self.consume_whitespace(false)?;
         }
         while !self.has_string(b"}") {
-            statements.push(self.consume_statement(h)?);
+            let (start, level) = (self.source.pos(), self.level);
+            match self.consume_statement(h) {
+                Ok(stmt) => statements.push(stmt),
+                Err(err) => self.recover_statement(err, start, level)?,
+            }
             self.consume_whitespace(false)?;
         }
         self.consume_string(b"}")?;
@@ -1353,6 +1708,52 @@ impl Lexer<'_> {
         }
         Ok(h.alloc_if_statement(|this| IfStatement { this, position, test, true_body, false_body }))
     }
+    fn consume_switch_statement(&mut self, h: &mut Heap) -> Result<SwitchStatementId, ParseError> {
+        let position = self.source.pos();
+        self.consume_keyword(b"switch")?;
+        self.consume_whitespace(false)?;
+        let subject = self.consume_paren_expression(h)?;
+        self.consume_whitespace(false)?;
+        self.consume_string(b"{")?;
+        self.consume_whitespace(false)?;
+        let mut cases = Vec::new();
+        while self.has_keyword(b"case") {
+            let position = self.source.pos();
+            self.consume_keyword(b"case")?;
+            self.consume_whitespace(true)?;
+            let mut variants = vec![self.consume_identifier(h)?];
+            self.consume_whitespace(false)?;
+            while self.has_string(b",") {
+                self.consume_string(b",")?;
+                self.consume_whitespace(false)?;
+                variants.push(self.consume_identifier(h)?);
+                self.consume_whitespace(false)?;
+            }
+            self.consume_string(b":")?;
+            self.consume_whitespace(false)?;
+            let body = self.consume_statement(h)?;
+            self.consume_whitespace(false)?;
+            cases.push(SwitchCase { position, variants, body });
+        }
+        let mut default = None;
+        if self.has_keyword(b"default") {
+            self.consume_keyword(b"default")?;
+            self.consume_whitespace(false)?;
+            self.consume_string(b":")?;
+            self.consume_whitespace(false)?;
+            default = Some(self.consume_statement(h)?);
+            self.consume_whitespace(false)?;
+        }
+        self.consume_string(b"}")?;
+        Ok(h.alloc_switch_statement(|this| SwitchStatement {
+            this,
+            position,
+            subject,
+            cases,
+            default,
+            definition: None,
+        }))
+    }
     fn consume_while_statement(&mut self, h: &mut Heap) -> Result<WhileStatementId, ParseError> {
         let position = self.source.pos();
         self.consume_keyword(b"while")?;
@@ -1369,6 +1770,49 @@ impl Lexer<'_> {
             in_sync: None,
         }))
     }
+    fn consume_for_statement(&mut self, h: &mut Heap) -> Result<ForStatementId, ParseError> {
+        // Either for (int i : a) stmt, over the indices of array a,
+        // or foreach (T x : a) stmt, over its elements
+        let position = self.source.pos();
+        let each = self.has_keyword(b"foreach");
+        if each {
+            self.consume_keyword(b"foreach")?;
+        } else {
+            self.consume_keyword(b"for")?;
+        }
+        self.consume_whitespace(false)?;
+        self.consume_string(b"(")?;
+        self.consume_whitespace(false)?;
+        let variable_position = self.source.pos();
+        let type_annotation = self.consume_type_annotation(h)?;
+        self.consume_whitespace(true)?;
+        let identifier = self.consume_identifier(h)?;
+        let variable = h.alloc_local(|this| Local {
+            this,
+            position: variable_position,
+            type_annotation,
+            identifier,
+        });
+        self.consume_whitespace(false)?;
+        self.consume_string(b":")?;
+        self.consume_whitespace(false)?;
+        let subject = self.consume_expression(h)?;
+        self.consume_whitespace(false)?;
+        self.consume_string(b")")?;
+        self.consume_whitespace(false)?;
+        let body = self.consume_statement(h)?;
+        Ok(h.alloc_for_statement(|this| ForStatement {
+            this,
+            position,
+            each,
+            variable,
+            subject,
+            body,
+            parent_scope: None,
+            next: None,
+            in_sync: None,
+        }))
+    }
     fn consume_break_statement(&mut self, h: &mut Heap) -> Result<BreakStatementId, ParseError> {
         let position = self.source.pos();
         self.consume_keyword(b"break")?;
@@ -1433,7 +1877,7 @@ impl Lexer<'_> {
         self.consume_keyword(b"return")?;
         self.consume_whitespace(false)?;
         let expression;
-        if self.has_string(b"(") {
+        if self.has_string(b"(") && !self.has_cast() {
             expression = self.consume_paren_expression(h)?;
         } else {
             expression = self.consume_expression(h)?;
@@ -1447,7 +1891,7 @@ impl Lexer<'_> {
         self.consume_keyword(b"assert")?;
         self.consume_whitespace(false)?;
         let expression;
-        if self.has_string(b"(") {
+        if self.has_string(b"(") && !self.has_cast() {
             expression = self.consume_paren_expression(h)?;
         } else {
             expression = self.consume_expression(h)?;
@@ -1578,6 +2022,53 @@ impl Lexer<'_> {
             body,
         }))
     }
+    fn has_struct_definition(&self) -> bool {
+        self.has_keyword(b"struct")
+    }
+    fn consume_struct_definition(&mut self, h: &mut Heap) -> Result<StructId, ParseError> {
+        let position = self.source.pos();
+        self.consume_keyword(b"struct")?;
+        self.consume_whitespace(true)?;
+        let identifier = self.consume_identifier(h)?;
+        self.consume_whitespace(false)?;
+        let mut fields = Vec::new();
+        self.consume_string(b"{")?;
+        self.consume_whitespace(false)?;
+        while !self.has_string(b"}") {
+            let position = self.source.pos();
+            let type_annotation = self.consume_type_annotation(h)?;
+            self.consume_whitespace(true)?;
+            let identifier = self.consume_identifier(h)?;
+            self.consume_whitespace(false)?;
+            self.consume_string(b";")?;
+            self.consume_whitespace(false)?;
+            fields.push(StructField { position, type_annotation, identifier });
+        }
+        self.consume_string(b"}")?;
+        Ok(h.alloc_struct(|this| Struct { this, position, identifier, fields }))
+    }
+    fn has_enum_definition(&self) -> bool {
+        self.has_keyword(b"enum")
+    }
+    fn consume_enum_definition(&mut self, h: &mut Heap) -> Result<EnumId, ParseError> {
+        let position = self.source.pos();
+        self.consume_keyword(b"enum")?;
+        self.consume_whitespace(true)?;
+        let identifier = self.consume_identifier(h)?;
+        self.consume_whitespace(false)?;
+        self.consume_string(b"{")?;
+        self.consume_whitespace(false)?;
+        let mut variants = vec![self.consume_identifier(h)?];
+        self.consume_whitespace(false)?;
+        while self.has_string(b",") {
+            self.consume_string(b",")?;
+            self.consume_whitespace(false)?;
+            variants.push(self.consume_identifier(h)?);
+            self.consume_whitespace(false)?;
+        }
+        self.consume_string(b"}")?;
+        Ok(h.alloc_enum(|this| Enum { this, position, identifier, variants }))
+    }
     fn has_pragma(&self) -> bool {
         if let Some(c) = self.source.next() {
             c == b'#'
@@ -1592,12 +2083,62 @@ impl Lexer<'_> {
             return Err(self.source.error("Expected pragma"));
         }
         self.source.consume();
-        if !is_vchar(self.source.next()) {
-            return Err(self.source.error("Expected pragma"));
+        let value;
+        if self.has_keyword(b"version") {
+            self.consume_keyword(b"version")?;
+            self.consume_pragma_whitespace()?;
+            let position = self.source.pos();
+            let mut digits = String::new();
+            while let Some(c) = self.source.next().filter(u8::is_ascii_digit) {
+                digits.push(c as char);
+                self.source.consume();
+            }
+            let version = match digits.parse::<i64>() {
+                Ok(version) => version,
+                Err(_) => return Err(ParseError::new(position, "Expected version number")),
+            };
+            // Descriptions written for another version are rejected before they are lexed
+            if version != VERSION {
+                return Err(ParseError::new(
+                    position,
+                    format!("Unsupported version: {}, expected {}", version, VERSION),
+                ));
+            }
+            value = PragmaValue::Version(version);
+        } else if self.has_keyword(b"module") {
+            self.consume_keyword(b"module")?;
+            self.consume_pragma_whitespace()?;
+            value = PragmaValue::Module(self.consume_qualified_ident()?);
+        } else if self.has_keyword(b"feature") {
+            self.consume_keyword(b"feature")?;
+            self.consume_pragma_whitespace()?;
+            let position = self.source.pos();
+            let feature = self.consume_ident()?;
+            if !FEATURES.contains(&&feature[..]) {
+                return Err(ParseError::new(
+                    position,
+                    format!("Unknown feature: {}", String::from_utf8_lossy(&feature)),
+                ));
+            }
+            value = PragmaValue::Feature(feature);
+        } else {
+            return Err(self.source.error("Unknown pragma"));
+        }
+        let end = self.source.pos();
+        if !self.consume_line()?.iter().all(|&c| is_wsp(Some(c))) {
+            return Err(ParseError::new(end, "Expected end of line"));
         }
-        let value = self.consume_line()?;
         Ok(h.alloc_pragma(|this| Pragma { this, position, value }))
     }
+    fn consume_pragma_whitespace(&mut self) -> Result<(), ParseError> {
+        if !is_wsp(self.source.next()) {
+            return Err(self.source.error("Expected whitespace"));
+        }
+        while is_wsp(self.source.next()) {
+            self.source.consume();
+        }
+        Ok(())
+    }
     fn has_import(&self) -> bool {
         self.has_keyword(b"import")
     }
@@ -1619,13 +2160,37 @@ impl Lexer<'_> {
         Ok(h.alloc_import(|this| Import { this, position, value }))
     }
     pub fn consume_protocol_description(&mut self, h: &mut Heap) -> Result<RootId, ParseError> {
+        // The first error is returned, and all errors are kept
+        match self.consume_protocol_description_impl(h) {
+            Ok(pd) => Ok(pd),
+            Err(err) => {
+                self.errors.push(err);
+                Err(self.errors[0].clone())
+            }
+        }
+    }
+    fn consume_protocol_description_impl(&mut self, h: &mut Heap) -> Result<RootId, ParseError> {
         let position = self.source.pos();
-        let mut pragmas = Vec::new();
+        let mut pragmas: Vec<PragmaId> = Vec::new();
         let mut imports = Vec::new();
         let mut definitions = Vec::new();
+        let mut structs = Vec::new();
+        let mut enums = Vec::new();
         self.consume_whitespace(false)?;
         while self.has_pragma() {
             let pragma = self.consume_pragma(h)?;
+            // Features may be enabled by several pragmas, the others occur at most once
+            for &old in pragmas.iter() {
+                let kind = match (&h[old].value, &h[pragma].value) {
+                    (PragmaValue::Version(_), PragmaValue::Version(_)) => "version",
+                    (PragmaValue::Module(_), PragmaValue::Module(_)) => "module",
+                    _ => continue,
+                };
+                return Err(ParseError::new(
+                    h[pragma].position,
+                    format!("Duplicate pragma: {}", kind),
+                ));
+            }
             pragmas.push(pragma);
             self.consume_whitespace(false)?;
         }
@@ -1636,10 +2201,31 @@ impl Lexer<'_> {
         }
         // do-while block
         while {
-            let def = self.consume_symbol_definition(h)?;
-            definitions.push(def);
+            let start = self.source.pos();
+            let recovered = self.errors.len();
+            let result = if self.has_struct_definition() {
+                self.consume_struct_definition(h).map(|def| structs.push(def))
+            } else if self.has_enum_definition() {
+                self.consume_enum_definition(h).map(|def| enums.push(def))
+            } else {
+                self.consume_symbol_definition(h).map(|def| definitions.push(def))
+            };
+            match result {
+                Ok(()) if self.errors.len() > recovered => {
+                    self.broken.push(*definitions.last().unwrap());
+                }
+                Ok(()) => {}
+                Err(err) => {
+                    self.source.seek(start);
+                    self.level = 0;
+                    self.skip_definition()?;
+                    self.errors.push(err);
+                }
+            }
             self.consume_whitespace(false)?;
-            self.has_symbol_definition()
+            self.has_struct_definition()
+                || self.has_enum_definition()
+                || self.has_symbol_definition()
         } {}
         // end of file
         if !self.source.is_eof() {
@@ -1651,6 +2237,8 @@ impl Lexer<'_> {
             pragmas,
             imports,
             definitions,
+            structs,
+            enums,
             declarations: Vec::new(),
         }))
     }
@@ -1,25 +1,270 @@
+use std::path::PathBuf;
+
+use crate::common::PdlModules;
 use crate::protocol::ast::*;
 use crate::protocol::inputsource::*;
+use crate::protocol::parser::*;
 
-pub fn get_declarations(h: &mut Heap, i: ImportId) -> Result<Vec<DeclarationId>, ParseError> {
+// The Reo connectors of std.reo, written in PDL itself
+static STD_REO_PDL: &[u8] = b"
+primitive sync(in a, out b) {
+    while (true) {
+        synchronous {
+            if (fires(a)) put(b, get(a));
+        }
+    }
+}
+primitive syncdrain(in a, in b) {
+    while (true) {
+        synchronous {
+            if (fires(a)) {
+                get(a);
+                get(b);
+            }
+        }
+    }
+}
+primitive syncspout(out a, out b) {
+    while (true) {
+        synchronous {
+            if (fires(a)) {
+                put(a, create(0));
+                put(b, create(0));
+            }
+        }
+    }
+}
+primitive asyncdrain(in a, in b) {
+    while (true) {
+        synchronous {
+            if (fires(a)) get(a);
+            else if (fires(b)) get(b);
+        }
+    }
+}
+primitive asyncspout(out a, out b) {
+    while (true) {
+        synchronous {
+            if (fires(a)) put(a, create(0));
+            else if (fires(b)) put(b, create(0));
+        }
+    }
+}
+primitive merger(in[] a, out b) {
+    while (true) {
+        synchronous {
+            foreach (in i : a) {
+                if (fires(i)) {
+                    put(b, get(i));
+                    break;
+                }
+            }
+        }
+    }
+}
+primitive router(in a, out[] b) {
+    while (true) {
+        synchronous {
+            if (fires(a)) {
+                msg m = get(a);
+                int i = 0;
+                while (i < b.length) {
+                    if (fires(b[i])) {
+                        put(b[i], m);
+                        break;
+                    } else i++;
+                }
+                assert i < b.length;
+            }
+        }
+    }
+}
+primitive consensus(in[] a, out b) {
+    while (true) {
+        synchronous {
+            if (fires(b)) {
+                msg m = get(a[0]);
+                int i = 1;
+                while (i < a.length) {
+                    assert get(a[i]) == m;
+                    i++;
+                }
+                put(b, m);
+            }
+        }
+    }
+}
+primitive replicator(in a, out[] b) {
+    while (true) {
+        synchronous {
+            if (fires(a)) {
+                msg m = get(a);
+                foreach (out o : b) put(o, m);
+            }
+        }
+    }
+}
+primitive alternator(in[] a, out b) {
+    msg[] buffer = {};
+    while (true) {
+        synchronous {
+            if (buffer.length > 0) {
+                if (fires(b)) {
+                    put(b, buffer[0]);
+                    buffer = buffer[1:buffer.length];
+                }
+            } else if (a.length > 0) {
+                if (fires(a[0])) {
+                    foreach (in i : a) {
+                        msg m = get(i);
+                        buffer = buffer @ {m};
+                    }
+                    put(b, buffer[0]);
+                    buffer = buffer[1:buffer.length];
+                }
+            }
+        }
+    }
+}
+primitive roundrobin(in a, out[] b) {
+    int i = 0;
+    while (true) {
+        synchronous {
+            if (fires(a)) {
+                put(b[i], get(a));
+                i = (i + 1) % b.length;
+            }
+        }
+    }
+}
+primitive node(in[] a, out[] b) {
+    while (true) {
+        synchronous {
+            int i = 0;
+            while (i < a.length) {
+                if (fires(a[i])) {
+                    msg m = get(a[i]);
+                    int j = 0;
+                    while (j < b.length) {
+                        put(b[j], m);
+                        j++;
+                    }
+                    break;
+                } else i++;
+            }
+        }
+    }
+}
+composite fifo(in a, out b) {
+    new xfifo(a, b, null);
+}
+primitive xfifo(in a, out b, msg m) {
+    while (true) {
+        synchronous {
+            if (m == null) {
+                if (fires(a)) m = get(a);
+            } else if (fires(b)) {
+                put(b, m);
+                m = null;
+            }
+        }
+    }
+}
+primitive nfifo(in a, out b, int n) {
+    msg[] buffer = {};
+    while (true) {
+        synchronous {
+            if (buffer.length > 0 && fires(b)) {
+                put(b, buffer[0]);
+                buffer = buffer[1:buffer.length];
+            } else if (buffer.length < n && fires(a)) {
+                msg m = get(a);
+                buffer = buffer @ {m};
+            }
+        }
+    }
+}
+primitive ufifo(in a, out b) {
+    msg[] buffer = {};
+    while (true) {
+        synchronous {
+            if (buffer.length > 0 && fires(b)) {
+                put(b, buffer[0]);
+                buffer = buffer[1:buffer.length];
+            } else if (fires(a)) {
+                msg m = get(a);
+                buffer = buffer @ {m};
+            }
+        }
+    }
+}
+";
+
+// The sources of a protocol description and of everything it imports; positions
+// refer to their source by its index in this list
+pub struct Modules {
+    given: PdlModules,
+    pub sources: Vec<InputSource>,
+    // The user modules that are being imported, innermost last
+    importing: Vec<Vec<u8>>,
+}
+
+impl Modules {
+    pub fn new(given: &PdlModules) -> Self {
+        Modules { given: given.clone(), sources: Vec::new(), importing: Vec::new() }
+    }
+    pub fn add(&mut self, mut source: InputSource) -> InputSource {
+        source.set_file(self.sources.len());
+        self.sources.push(source.clone());
+        source
+    }
+    fn find(&self, h: &Heap, i: ImportId) -> Result<InputSource, ParseError> {
+        let name = &h[i].value;
+        for (module, buffer) in self.given.sources.iter() {
+            if module == name {
+                let filename = String::from_utf8_lossy(name);
+                return Ok(InputSource::new(filename, &mut &buffer[..]).unwrap());
+            }
+        }
+        let mut relative = PathBuf::new();
+        for segment in name.split(|&c| c == b'.') {
+            relative.push(String::from_utf8_lossy(segment).as_ref());
+        }
+        relative.set_extension("pdl");
+        for dir in self.given.search_path.iter() {
+            let path = dir.join(&relative);
+            if path.is_file() {
+                return InputSource::from_file(&path).map_err(|err| {
+                    ParseError::new(h[i].position, format!("Unreadable import: {}", err))
+                });
+            }
+        }
+        Err(ParseError::new(h[i].position, "Unknown import"))
+    }
+}
+
+pub fn get_declarations(
+    h: &mut Heap,
+    i: ImportId,
+    modules: &mut Modules,
+) -> Result<Vec<DeclarationId>, ParseError> {
     if h[i].value == b"std.reo" {
+        // The connectors are parsed from their bundled source, and are thus
+        // instantiated like any other component definition
+        let mut source = modules.add(std_reo_source());
+        let root = Parser::new(&mut source).parse_modules(h, modules)?;
         let mut vec = Vec::new();
-        vec.push(cd(h, i, b"sync", &[Type::INPUT, Type::OUTPUT]));
-        vec.push(cd(h, i, b"syncdrain", &[Type::INPUT, Type::INPUT]));
-        vec.push(cd(h, i, b"syncspout", &[Type::OUTPUT, Type::OUTPUT]));
-        vec.push(cd(h, i, b"asyncdrain", &[Type::INPUT, Type::INPUT]));
-        vec.push(cd(h, i, b"asyncspout", &[Type::OUTPUT, Type::OUTPUT]));
-        vec.push(cd(h, i, b"merger", &[Type::INPUT_ARRAY, Type::OUTPUT]));
-        vec.push(cd(h, i, b"router", &[Type::INPUT, Type::OUTPUT_ARRAY]));
-        vec.push(cd(h, i, b"consensus", &[Type::INPUT_ARRAY, Type::OUTPUT]));
-        vec.push(cd(h, i, b"replicator", &[Type::INPUT, Type::OUTPUT_ARRAY]));
-        vec.push(cd(h, i, b"alternator", &[Type::INPUT_ARRAY, Type::OUTPUT]));
-        vec.push(cd(h, i, b"roundrobin", &[Type::INPUT, Type::OUTPUT_ARRAY]));
-        vec.push(cd(h, i, b"node", &[Type::INPUT_ARRAY, Type::OUTPUT_ARRAY]));
-        vec.push(cd(h, i, b"fifo", &[Type::INPUT, Type::OUTPUT]));
-        vec.push(cd(h, i, b"xfifo", &[Type::INPUT, Type::OUTPUT, Type::MESSAGE]));
-        vec.push(cd(h, i, b"nfifo", &[Type::INPUT, Type::OUTPUT, Type::INT]));
-        vec.push(cd(h, i, b"ufifo", &[Type::INPUT, Type::OUTPUT]));
+        for definition in h[root].definitions.clone() {
+            let signature = Signature::from_definition(h, definition);
+            vec.push(
+                h.alloc_defined_declaration(|this| DefinedDeclaration {
+                    this,
+                    definition,
+                    signature,
+                })
+                .upcast(),
+            );
+        }
         Ok(vec)
     } else if h[i].value == b"std.buf" {
         let mut vec = Vec::new();
@@ -33,18 +278,65 @@ pub fn get_declarations(h: &mut Heap, i: ImportId) -> Result<Vec<DeclarationId>,
         vec.push(fd(h, i, b"readLong", Type::LONG, &[Type::MESSAGE, Type::INT]));
         Ok(vec)
     } else {
-        Err(ParseError::new(h[i].position, "Unknown import"))
+        let name = h[i].value.clone();
+        if modules.importing.contains(&name) {
+            let message = format!("Cyclic import: {}", String::from_utf8_lossy(&name));
+            return Err(ParseError::new(h[i].position, message));
+        }
+        let mut source = modules.find(h, i)?;
+        source = modules.add(source);
+        modules.importing.push(name.clone());
+        let root = Parser::new(&mut source).parse_modules(h, modules);
+        modules.importing.pop();
+        let root = root?;
+        if let Some(module) = h[root].module(h) {
+            if module != &name[..] {
+                let message = format!("Module name mismatch: {}", String::from_utf8_lossy(module));
+                return Err(ParseError::new(h[i].position, message));
+            }
+        }
+        // The definitions of a user module are declared under its last name segment
+        let mut prefix = name.rsplit(|&c| c == b'.').next().unwrap().to_vec();
+        prefix.push(b'.');
+        let mut vec = Vec::new();
+        for definition in h[root].definitions.clone() {
+            let mut ident = prefix.clone();
+            ident.extend_from_slice(h[h[definition].identifier()].ident());
+            let identifier = h.get_external_identifier(&ident).upcast();
+            let signature = match Signature::from_definition(h, definition) {
+                Signature::Component(sig) => {
+                    Signature::Component(ComponentSignature { identifier, ..sig })
+                }
+                Signature::Function(sig) => {
+                    Signature::Function(FunctionSignature { identifier, ..sig })
+                }
+            };
+            vec.push(
+                h.alloc_defined_declaration(|this| DefinedDeclaration {
+                    this,
+                    definition,
+                    signature,
+                })
+                .upcast(),
+            );
+        }
+        Ok(vec)
     }
 }
 
-fn cd(h: &mut Heap, import: ImportId, ident: &[u8], sig: &[Type]) -> DeclarationId {
-    let identifier = h.get_external_identifier(ident).upcast();
-    h.alloc_imported_declaration(|this| ImportedDeclaration {
-        this,
-        import,
-        signature: Signature::Component(ComponentSignature { identifier, arity: sig.to_vec() }),
-    })
-    .upcast()
+pub fn std_reo_source() -> InputSource {
+    InputSource::new("std.reo", &mut &STD_REO_PDL[..]).unwrap()
+}
+
+// The write functions of std.buf update the message given as first argument
+pub fn is_buffer_write(h: &Heap, decl: DeclarationId) -> bool {
+    match &h[decl] {
+        Declaration::Imported(imported) => {
+            let ident = &h[h[decl].identifier()].as_external().value;
+            h[imported.import].value == b"std.buf" && ident.starts_with(b"write")
+        }
+        Declaration::Defined(_) => false,
+    }
 }
 
 fn fd(h: &mut Heap, import: ImportId, ident: &[u8], ret: Type, sig: &[Type]) -> DeclarationId {
@@ -1,9 +1,14 @@
+mod analysis;
 mod ast;
+mod automaton;
 mod eval;
+pub mod ide;
 pub mod inputsource;
 mod lexer;
 mod library;
 mod parser;
+mod printer;
+mod topology;
 
 use crate::common::*;
 use crate::protocol::ast::*;
@@ -13,8 +18,9 @@ use crate::protocol::parser::*;
 
 pub struct ProtocolDescriptionImpl {
     heap: Heap,
-    source: InputSource,
+    sources: Vec<InputSource>,
     root: RootId,
+    program: Program,
 }
 
 impl std::fmt::Debug for ProtocolDescriptionImpl {
@@ -23,28 +29,93 @@ impl std::fmt::Debug for ProtocolDescriptionImpl {
     }
 }
 
-impl ProtocolDescription for ProtocolDescriptionImpl {
-    type S = ComponentStateImpl;
-
-    fn parse(buffer: &[u8]) -> Result<Self, String> {
+impl ProtocolDescriptionImpl {
+    // Main components are either defined in the protocol description, or imported from std.reo
+    // or a user module
+    fn main_definition(&self, identifier: &[u8]) -> Option<DefinitionId> {
+        let h = &self.heap;
+        let decl = h[self.root].get_declaration_ident(h, identifier)?;
+        match &h[decl] {
+            Declaration::Defined(defined) => Some(defined.definition),
+            Declaration::Imported(_) => None,
+        }
+    }
+    // Parses the description, or returns all errors with the sources they refer to
+    fn parse_all(
+        buffer: &[u8],
+        given: &PdlModules,
+    ) -> Result<Self, (Vec<ParseError>, Vec<InputSource>)> {
         let mut heap = Heap::new();
-        let mut source = InputSource::from_buffer(buffer).unwrap();
+        let mut modules = library::Modules::new(given);
+        let mut source = modules.add(InputSource::from_buffer(buffer).unwrap());
         let mut parser = Parser::new(&mut source);
-        match parser.parse(&mut heap) {
+        match parser.parse_all(&mut heap, &mut modules) {
             Ok(root) => {
-                return Ok(ProtocolDescriptionImpl { heap, source, root });
+                // Definitions are evaluated from their compiled code
+                let program = Program::compile(&heap, root);
+                let sources = modules.sources;
+                Ok(ProtocolDescriptionImpl { heap, sources, root, program })
+            }
+            Err(errors) => Err((errors, modules.sources)),
+        }
+    }
+    // Errors are shown in the source that their position refers to
+    fn render_error(&self, error: &EvalError) -> String {
+        let mut vec: Vec<u8> = Vec::new();
+        error.write_in(&self.sources, &mut vec).unwrap();
+        String::from_utf8_lossy(&vec).to_string()
+    }
+}
+
+impl ProtocolDescription for ProtocolDescriptionImpl {
+    type S = ComponentStateImpl;
+
+    fn parse_with_modules(buffer: &[u8], given: &PdlModules) -> Result<Self, String> {
+        ProtocolDescriptionImpl::parse_all(buffer, given).map_err(|(errors, sources)| {
+            let mut vec: Vec<u8> = Vec::new();
+            for err in errors.iter() {
+                err.write_in(&sources, &mut vec).unwrap();
             }
-            Err(err) => {
-                let mut vec: Vec<u8> = Vec::new();
+            String::from_utf8_lossy(&vec).to_string()
+        })
+    }
+    fn diagnose(buffer: &[u8], given: &PdlModules) -> Vec<PdlDiagnostic> {
+        match ProtocolDescriptionImpl::parse_all(buffer, given) {
+            Ok(_) => Vec::new(),
+            Err((errors, sources)) => errors
+                .iter()
+                .map(|err| {
+                    let pos = err.position();
+                    diagnostic(&sources, Severity::Error, pos, err.span(), err.message())
+                })
+                .collect(),
+        }
+    }
+    fn format(buffer: &[u8]) -> Result<String, String> {
+        let mut source = InputSource::from_buffer(buffer).unwrap();
+        printer::format(&mut source).map_err(|errors| {
+            let mut vec: Vec<u8> = Vec::new();
+            for err in errors.iter() {
                 err.write(&source, &mut vec).unwrap();
-                Err(String::from_utf8_lossy(&vec).to_string())
             }
+            String::from_utf8_lossy(&vec).to_string()
+        })
+    }
+    fn pragmas(&self) -> PdlPragmas {
+        let root = &self.heap[self.root];
+        PdlPragmas {
+            version: root.version(&self.heap),
+            module_name: root.module(&self.heap).map(<[u8]>::to_vec),
+            features: root.features(&self.heap).into_iter().map(<[u8]>::to_vec).collect(),
         }
     }
-    fn component_polarities(&self, identifier: &[u8]) -> Result<Vec<Polarity>, MainComponentErr> {
+    fn component_polarities(
+        &self,
+        identifier: &[u8],
+        args: Option<&[ComponentArg]>,
+    ) -> Result<Vec<Polarity>, MainComponentErr> {
         let h = &self.heap;
-        let root = &h[self.root];
-        let def = root.get_definition_ident(h, identifier);
+        let def = self.main_definition(identifier);
         if def.is_none() {
             return Err(MainComponentErr::NoSuchComponent);
         }
@@ -52,52 +123,142 @@ impl ProtocolDescription for ProtocolDescriptionImpl {
         if !def.is_component() {
             return Err(MainComponentErr::NoSuchComponent);
         }
-        for &param in def.parameters().iter() {
-            let param = &h[param];
-            let type_annot = &h[param.type_annotation];
-            if type_annot.the_type.array {
-                return Err(MainComponentErr::NonPortTypeParameters);
-            }
-            match type_annot.the_type.primitive {
-                PrimitiveType::Input | PrimitiveType::Output => continue,
-                _ => {
-                    return Err(MainComponentErr::NonPortTypeParameters);
-                }
+        let params = def.parameters();
+        if let Some(args) = args {
+            if args.len() != params.len() {
+                return Err(MainComponentErr::WrongNumberOfParamaters { expected: params.len() });
             }
         }
         let mut result = Vec::new();
-        for &param in def.parameters().iter() {
+        for (param_index, &param) in params.iter().enumerate() {
             let param = &h[param];
-            let type_annot = &h[param.type_annotation];
-            let ptype = &type_annot.the_type.primitive;
-            if ptype == &PrimitiveType::Input {
-                result.push(Polarity::Getter)
-            } else if ptype == &PrimitiveType::Output {
-                result.push(Polarity::Putter)
-            } else {
-                unreachable!()
+            let the_type = &h[param.type_annotation].the_type;
+            let arg = args.map_or(&ComponentArg::Port, |args| &args[param_index]);
+            // Arguments are checked against dummy ports, as none are bound yet
+            let mut bound = 0;
+            let mut ports = std::iter::repeat(Port(0)).inspect(|_| bound += 1);
+            if Value::from_component_arg(arg, the_type, &mut ports).is_none() {
+                return Err(match args {
+                    None => MainComponentErr::NonPortTypeParameters,
+                    Some(_) => MainComponentErr::WrongArgumentType { param_index },
+                });
+            }
+            let polarity = match the_type.primitive {
+                PrimitiveType::Input => Polarity::Getter,
+                _ => Polarity::Putter,
+            };
+            for _ in 0..bound {
+                result.push(polarity);
             }
         }
         Ok(result)
     }
-    fn new_main_component(&self, identifier: &[u8], ports: &[Port]) -> ComponentStateImpl {
-        let mut args = Vec::new();
-        for (&x, y) in ports.iter().zip(self.component_polarities(identifier).unwrap()) {
-            match y {
-                Polarity::Getter => args.push(Value::Input(InputValue(x))),
-                Polarity::Putter => args.push(Value::Output(OutputValue(x))),
+    fn analyze(&self, identifier: &[u8]) -> Vec<PdlDiagnostic> {
+        let def = match self.main_definition(identifier) {
+            Some(def) if self.heap[def].is_component() => def,
+            _ => return Vec::new(),
+        };
+        analysis::analyze(&self.heap, def)
+            .iter()
+            .map(|warning| {
+                let pos = warning.position();
+                diagnostic(&self.sources, Severity::Warning, pos, None, warning.message())
+            })
+            .collect()
+    }
+    fn automaton(&self, identifier: &[u8], format: AutomatonFormat) -> Option<String> {
+        let def = self.main_definition(identifier)?;
+        match &self.heap[def] {
+            Definition::Component(Component::Primitive(_)) => {}
+            _ => return None,
+        }
+        let automaton = automaton::automaton(&self.heap, def);
+        Some(match format {
+            AutomatonFormat::Json => automaton.to_json(),
+            AutomatonFormat::Dot => automaton.to_dot(),
+        })
+    }
+    fn topology(&self, identifier: &[u8]) -> Option<String> {
+        let def = self.main_definition(identifier)?;
+        match &self.heap[def] {
+            Definition::Component(Component::Composite(_)) => {
+                Some(topology::topology(&self.heap, def))
             }
+            _ => None,
         }
+    }
+    fn new_main_component(
+        &self,
+        identifier: &[u8],
+        args: Option<&[ComponentArg]>,
+        ports: &[Port],
+    ) -> ComponentStateImpl {
         let h = &self.heap;
-        let root = &h[self.root];
-        let def = root.get_definition_ident(h, identifier).unwrap();
-        ComponentStateImpl { prompt: Prompt::new(h, def, &args) }
+        let def = self.main_definition(identifier).unwrap();
+        let mut ports = ports.iter().copied();
+        let mut values = Vec::new();
+        for (param_index, &param) in h[def].parameters().iter().enumerate() {
+            let the_type = &h[h[param].type_annotation].the_type;
+            let arg = args.map_or(&ComponentArg::Port, |args| &args[param_index]);
+            values.push(Value::from_component_arg(arg, the_type, &mut ports).unwrap());
+        }
+        ComponentStateImpl::new(&self.program, def, &values)
+    }
+}
+
+// Without a span, a diagnostic ends at the character after its position
+fn diagnostic(
+    sources: &[InputSource],
+    severity: Severity,
+    pos: InputPosition,
+    span: Option<InputSpan>,
+    message: &str,
+) -> PdlDiagnostic {
+    let end = match span {
+        Some(span) => span.end(),
+        None => pos.span(1).end(),
+    };
+    PdlDiagnostic {
+        severity,
+        filename: sources[pos.file()].filename().to_string(),
+        line: pos.line(),
+        column: pos.column(),
+        end_line: end.line(),
+        end_column: end.column(),
+        message: message.to_string(),
     }
 }
 
 #[derive(Debug, Clone)]
 pub struct ComponentStateImpl {
     prompt: Prompt,
+    // Steps taken in the current synchronous round
+    steps: usize,
+}
+// States are equal regardless of the steps they took in the last round
+impl PartialEq for ComponentStateImpl {
+    fn eq(&self, other: &Self) -> bool {
+        self.prompt == other.prompt
+    }
+}
+impl Eq for ComponentStateImpl {}
+impl Hash for ComponentStateImpl {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.prompt.hash(state)
+    }
+}
+impl ComponentStateImpl {
+    fn new(program: &Program, def: DefinitionId, args: &[Value]) -> Self {
+        ComponentStateImpl { prompt: Prompt::new(program, def, args), steps: 0 }
+    }
+    // Counts a step, and returns false once the step budget is exceeded
+    fn count_step(&mut self, step_budget: Option<usize>) -> bool {
+        self.steps += 1;
+        match step_budget {
+            None => true,
+            Some(budget) => self.steps <= budget,
+        }
+    }
 }
 impl ComponentState for ComponentStateImpl {
     type D = ProtocolDescriptionImpl;
@@ -107,24 +268,30 @@ impl ComponentState for ComponentStateImpl {
         context: &mut C,
         pd: &ProtocolDescriptionImpl,
     ) -> MonoBlocker {
+        // A new round starts with a fresh step budget
+        let step_budget = context.step_budget();
+        self.steps = 0;
         let mut context = EvalContext::Mono(context);
         loop {
-            let result = self.prompt.step(&pd.heap, &mut context);
+            if !self.count_step(step_budget) {
+                return MonoBlocker::StepBudgetExhausted;
+            }
+            let result = self.prompt.step(&pd.program, &mut context);
             match result {
                 // In component definitions, there are no return statements
                 Ok(_) => unreachable!(),
                 Err(cont) => match cont {
                     EvalContinuation::Stepping => continue,
                     EvalContinuation::Inconsistent => return MonoBlocker::Inconsistent,
+                    EvalContinuation::Error(error) => {
+                        return MonoBlocker::Fault(pd.render_error(&error));
+                    }
                     EvalContinuation::Terminal => return MonoBlocker::ComponentExit,
                     EvalContinuation::SyncBlockStart => return MonoBlocker::SyncBlockStart,
                     // Not possible to end sync block if never entered one
                     EvalContinuation::SyncBlockEnd => unreachable!(),
-                    EvalContinuation::NewComponent(decl, args) => {
-                        // Look up definition (TODO for now, assume it is a definition)
-                        let h = &pd.heap;
-                        let def = h[decl].as_defined().definition;
-                        let init_state = ComponentStateImpl { prompt: Prompt::new(h, def, &args) };
+                    EvalContinuation::NewComponent(def, args) => {
+                        let init_state = ComponentStateImpl::new(&pd.program, def, &args);
                         context.new_component(&args, init_state);
                         // Continue stepping
                         continue;
@@ -132,6 +299,7 @@ impl ComponentState for ComponentStateImpl {
                     // Outside synchronous blocks, no fires/get/put happens
                     EvalContinuation::BlockFires(_) => unreachable!(),
                     EvalContinuation::BlockGet(_) => unreachable!(),
+                    EvalContinuation::BlockChoose => unreachable!(),
                     EvalContinuation::Put(_, _) => unreachable!(),
                 },
             }
@@ -143,15 +311,22 @@ impl ComponentState for ComponentStateImpl {
         context: &mut C,
         pd: &ProtocolDescriptionImpl,
     ) -> PolyBlocker {
+        let step_budget = context.step_budget();
         let mut context = EvalContext::Poly(context);
         loop {
-            let result = self.prompt.step(&pd.heap, &mut context);
+            if !self.count_step(step_budget) {
+                return PolyBlocker::StepBudgetExhausted;
+            }
+            let result = self.prompt.step(&pd.program, &mut context);
             match result {
                 // Inside synchronous blocks, there are no return statements
                 Ok(_) => unreachable!(),
                 Err(cont) => match cont {
                     EvalContinuation::Stepping => continue,
                     EvalContinuation::Inconsistent => return PolyBlocker::Inconsistent,
+                    EvalContinuation::Error(error) => {
+                        return PolyBlocker::Fault(pd.render_error(&error));
+                    }
                     // First need to exit synchronous block before definition may end
                     EvalContinuation::Terminal => unreachable!(),
                     // No nested synchronous blocks
@@ -168,6 +343,7 @@ impl ComponentState for ComponentStateImpl {
                         }
                         _ => unreachable!(),
                     },
+                    EvalContinuation::BlockChoose => return PolyBlocker::CouldntChoose,
                     EvalContinuation::BlockGet(port) => match port {
                         Value::Output(OutputValue(port)) => {
                             return PolyBlocker::CouldntReadMsg(port);
@@ -190,10 +366,8 @@ impl ComponentState for ComponentStateImpl {
                         }
                         let payload;
                         match message {
-                            Value::Message(MessageValue(None)) => {
-                                // Putting a null message is inconsistent
-                                return PolyBlocker::Inconsistent;
-                            }
+                            // Putting a null message is an evaluation error
+                            Value::Message(MessageValue(None)) => unreachable!(),
                             Value::Message(MessageValue(Some(buffer))) => {
                                 // Create a copy of the payload
                                 payload = buffer.clone();
@@ -214,13 +388,20 @@ pub enum EvalContext<'a> {
     None,
 }
 impl EvalContext<'_> {
-    // fn random(&mut self) -> LongValue {
-    //     match self {
-    //         EvalContext::None => unreachable!(),
-    //         EvalContext::Mono(_context) => todo!(),
-    //         EvalContext::Poly(_) => unreachable!(),
-    //     }
-    // }
+    fn random(&mut self) -> Value {
+        match self {
+            EvalContext::None => unreachable!(),
+            EvalContext::Mono(context) => Value::from(context.new_random() as i64),
+            EvalContext::Poly(_) => unreachable!(),
+        }
+    }
+    fn choose(&mut self, index: usize) -> Option<Value> {
+        match self {
+            EvalContext::None => unreachable!(),
+            EvalContext::Mono(_) => unreachable!(),
+            EvalContext::Poly(context) => context.choose(index).map(Value::from),
+        }
+    }
     fn new_component(&mut self, args: &[Value], init_state: ComponentStateImpl) -> () {
         match self {
             EvalContext::None => unreachable!(),
@@ -1,3 +1,8 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::fmt::{Display, Formatter};
+
+use crate::common::PdlModules;
 use crate::protocol::ast::*;
 use crate::protocol::inputsource::*;
 use crate::protocol::lexer::*;
@@ -5,9 +10,9 @@ use crate::protocol::library;
 
 // The following indirection is needed due to a bug in the cbindgen tool.
 type Unit = ();
-type VisitorResult = Result<Unit, ParseError>;
+pub(crate) type VisitorResult = Result<Unit, ParseError>;
 
-trait Visitor: Sized {
+pub(crate) trait Visitor: Sized {
     fn visit_protocol_description(&mut self, h: &mut Heap, pd: RootId) -> VisitorResult {
         recursive_protocol_description(self, h, pd)
     }
@@ -72,9 +77,15 @@ trait Visitor: Sized {
     fn visit_if_statement(&mut self, h: &mut Heap, stmt: IfStatementId) -> VisitorResult {
         recursive_if_statement(self, h, stmt)
     }
+    fn visit_switch_statement(&mut self, h: &mut Heap, stmt: SwitchStatementId) -> VisitorResult {
+        recursive_switch_statement(self, h, stmt)
+    }
     fn visit_while_statement(&mut self, h: &mut Heap, stmt: WhileStatementId) -> VisitorResult {
         recursive_while_statement(self, h, stmt)
     }
+    fn visit_for_statement(&mut self, h: &mut Heap, stmt: ForStatementId) -> VisitorResult {
+        recursive_for_statement(self, h, stmt)
+    }
     fn visit_break_statement(&mut self, _h: &mut Heap, _stmt: BreakStatementId) -> VisitorResult {
         Ok(())
     }
@@ -138,6 +149,9 @@ trait Visitor: Sized {
     fn visit_unary_expression(&mut self, h: &mut Heap, expr: UnaryExpressionId) -> VisitorResult {
         recursive_unary_expression(self, h, expr)
     }
+    fn visit_cast_expression(&mut self, h: &mut Heap, expr: CastExpressionId) -> VisitorResult {
+        recursive_cast_expression(self, h, expr)
+    }
     fn visit_indexing_expression(
         &mut self,
         h: &mut Heap,
@@ -158,9 +172,15 @@ trait Visitor: Sized {
     fn visit_array_expression(&mut self, h: &mut Heap, expr: ArrayExpressionId) -> VisitorResult {
         recursive_array_expression(self, h, expr)
     }
+    fn visit_struct_expression(&mut self, h: &mut Heap, expr: StructExpressionId) -> VisitorResult {
+        recursive_struct_expression(self, h, expr)
+    }
     fn visit_call_expression(&mut self, h: &mut Heap, expr: CallExpressionId) -> VisitorResult {
         recursive_call_expression(self, h, expr)
     }
+    fn visit_enum_expression(&mut self, _h: &mut Heap, _expr: EnumExpressionId) -> VisitorResult {
+        Ok(())
+    }
     fn visit_constant_expression(
         &mut self,
         _h: &mut Heap,
@@ -294,9 +314,12 @@ fn recursive_statement<T: Visitor>(this: &mut T, h: &mut Heap, stmt: StatementId
         Statement::Skip(stmt) => this.visit_skip_statement(h, stmt.this),
         Statement::Labeled(stmt) => this.visit_labeled_statement(h, stmt.this),
         Statement::If(stmt) => this.visit_if_statement(h, stmt.this),
+        Statement::Switch(stmt) => this.visit_switch_statement(h, stmt.this),
         Statement::EndIf(stmt) => unreachable!(), // pseudo-statement
         Statement::While(stmt) => this.visit_while_statement(h, stmt.this),
         Statement::EndWhile(stmt) => unreachable!(), // pseudo-statement
+        Statement::For(stmt) => this.visit_for_statement(h, stmt.this),
+        Statement::EndFor(_) => unreachable!(), // pseudo-statement
         Statement::Break(stmt) => this.visit_break_statement(h, stmt.this),
         Statement::Continue(stmt) => this.visit_continue_statement(h, stmt.this),
         Statement::Synchronous(stmt) => this.visit_synchronous_statement(h, stmt.this),
@@ -361,6 +384,21 @@ fn recursive_if_statement<T: Visitor>(
     this.visit_statement(h, h[stmt].false_body)
 }
 
+fn recursive_switch_statement<T: Visitor>(
+    this: &mut T,
+    h: &mut Heap,
+    stmt: SwitchStatementId,
+) -> VisitorResult {
+    this.visit_expression(h, h[stmt].subject)?;
+    for case in h[stmt].cases.clone().iter() {
+        this.visit_statement(h, case.body)?;
+    }
+    if let Some(default) = h[stmt].default {
+        this.visit_statement(h, default)?;
+    }
+    Ok(())
+}
+
 fn recursive_while_statement<T: Visitor>(
     this: &mut T,
     h: &mut Heap,
@@ -370,6 +408,16 @@ fn recursive_while_statement<T: Visitor>(
     this.visit_statement(h, h[stmt].body)
 }
 
+fn recursive_for_statement<T: Visitor>(
+    this: &mut T,
+    h: &mut Heap,
+    stmt: ForStatementId,
+) -> VisitorResult {
+    this.visit_expression(h, h[stmt].subject)?;
+    recursive_local_as_variable(this, h, h[stmt].variable)?;
+    this.visit_statement(h, h[stmt].body)
+}
+
 fn recursive_synchronous_statement<T: Visitor>(
     this: &mut T,
     h: &mut Heap,
@@ -432,10 +480,13 @@ fn recursive_expression<T: Visitor>(
         Expression::Conditional(expr) => this.visit_conditional_expression(h, expr.this),
         Expression::Binary(expr) => this.visit_binary_expression(h, expr.this),
         Expression::Unary(expr) => this.visit_unary_expression(h, expr.this),
+        Expression::Cast(expr) => this.visit_cast_expression(h, expr.this),
         Expression::Indexing(expr) => this.visit_indexing_expression(h, expr.this),
         Expression::Slicing(expr) => this.visit_slicing_expression(h, expr.this),
         Expression::Select(expr) => this.visit_select_expression(h, expr.this),
         Expression::Array(expr) => this.visit_array_expression(h, expr.this),
+        Expression::Struct(expr) => this.visit_struct_expression(h, expr.this),
+        Expression::Enum(expr) => this.visit_enum_expression(h, expr.this),
         Expression::Constant(expr) => this.visit_constant_expression(h, expr.this),
         Expression::Call(expr) => this.visit_call_expression(h, expr.this),
         Expression::Variable(expr) => this.visit_variable_expression(h, expr.this),
@@ -478,6 +529,14 @@ fn recursive_unary_expression<T: Visitor>(
     this.visit_expression(h, h[expr].expression)
 }
 
+fn recursive_cast_expression<T: Visitor>(
+    this: &mut T,
+    h: &mut Heap,
+    expr: CastExpressionId,
+) -> VisitorResult {
+    this.visit_expression(h, h[expr].expression)
+}
+
 fn recursive_indexing_expression<T: Visitor>(
     this: &mut T,
     h: &mut Heap,
@@ -516,6 +575,17 @@ fn recursive_array_expression<T: Visitor>(
     Ok(())
 }
 
+fn recursive_struct_expression<T: Visitor>(
+    this: &mut T,
+    h: &mut Heap,
+    expr: StructExpressionId,
+) -> VisitorResult {
+    for &(_, expr) in h[expr].fields.clone().iter() {
+        this.visit_expression(h, expr)?;
+    }
+    Ok(())
+}
+
 fn recursive_call_expression<T: Visitor>(
     this: &mut T,
     h: &mut Heap,
@@ -636,9 +706,29 @@ impl Visitor for FunctionStatementReturns {
         assert!(len > 0);
         self.visit_statement(h, h[block].statements[len - 1])
     }
+    fn visit_local_statement(&mut self, h: &mut Heap, stmt: LocalStatementId) -> VisitorResult {
+        self.function_error(h[stmt].position())
+    }
     fn visit_skip_statement(&mut self, h: &mut Heap, stmt: SkipStatementId) -> VisitorResult {
         self.function_error(h[stmt].position)
     }
+    // The body of a loop may be left by a break or its test, or not run at all
+    fn visit_while_statement(&mut self, h: &mut Heap, stmt: WhileStatementId) -> VisitorResult {
+        self.function_error(h[stmt].position)
+    }
+    fn visit_for_statement(&mut self, h: &mut Heap, stmt: ForStatementId) -> VisitorResult {
+        self.function_error(h[stmt].position)
+    }
+    fn visit_synchronous_statement(
+        &mut self,
+        h: &mut Heap,
+        stmt: SynchronousStatementId,
+    ) -> VisitorResult {
+        self.function_error(h[stmt].position)
+    }
+    fn visit_put_statement(&mut self, h: &mut Heap, stmt: PutStatementId) -> VisitorResult {
+        self.function_error(h[stmt].position)
+    }
     fn visit_break_statement(&mut self, h: &mut Heap, stmt: BreakStatementId) -> VisitorResult {
         self.function_error(h[stmt].position)
     }
@@ -727,15 +817,23 @@ impl Visitor for ComponentStatementReturnNew {
 
 struct CheckBuiltinOccurrences {
     legal: bool,
+    // Functions may be called both inside and outside synchronous blocks
+    function: bool,
 }
 
 impl CheckBuiltinOccurrences {
     fn new() -> Self {
-        CheckBuiltinOccurrences { legal: false }
+        CheckBuiltinOccurrences { legal: false, function: false }
     }
 }
 
 impl Visitor for CheckBuiltinOccurrences {
+    fn visit_function_definition(&mut self, h: &mut Heap, def: FunctionId) -> VisitorResult {
+        self.function = true;
+        recursive_function_definition(self, h, def)?;
+        self.function = false;
+        Ok(())
+    }
     fn visit_synchronous_statement(
         &mut self,
         h: &mut Heap,
@@ -749,47 +847,63 @@ impl Visitor for CheckBuiltinOccurrences {
     }
     fn visit_call_expression(&mut self, h: &mut Heap, expr: CallExpressionId) -> VisitorResult {
         match h[expr].method {
-            Method::Get | Method::Fires => {
+            Method::Get | Method::Fires | Method::Choose => {
                 if !self.legal {
                     return Err(ParseError::new(h[expr].position, "Illegal built-in occurrence"));
                 }
             }
+            Method::Random => {
+                if self.legal || self.function {
+                    return Err(ParseError::new(h[expr].position, "Illegal built-in occurrence"));
+                }
+            }
             _ => {}
         }
         recursive_call_expression(self, h, expr)
     }
 }
 
-struct BuildSymbolDeclarations {
+struct BuildSymbolDeclarations<'a> {
     declarations: Vec<DeclarationId>,
+    modules: &'a mut library::Modules,
 }
 
-impl BuildSymbolDeclarations {
-    fn new() -> Self {
-        BuildSymbolDeclarations { declarations: Vec::new() }
+impl<'a> BuildSymbolDeclarations<'a> {
+    fn new(modules: &'a mut library::Modules) -> Self {
+        BuildSymbolDeclarations { declarations: Vec::new(), modules }
     }
     fn checked_add(&mut self, h: &mut Heap, decl: DeclarationId) -> VisitorResult {
         for &old in self.declarations.iter() {
             let id = h[decl].identifier();
             if h[id] == h[h[old].identifier()] {
-                return match h[decl].clone() {
-                    Declaration::Defined(defined) => Err(ParseError::new(
-                        h[defined.definition].position(),
-                        format!("Defined symbol clash: {}", h[id]),
-                    )),
-                    Declaration::Imported(imported) => Err(ParseError::new(
-                        h[imported.import].position(),
-                        format!("Imported symbol clash: {}", h[id]),
-                    )),
+                let message = match h[decl] {
+                    Declaration::Defined(_) => format!("Defined symbol clash: {}", h[id]),
+                    Declaration::Imported(_) => format!("Imported symbol clash: {}", h[id]),
                 };
+                let note = match h[old] {
+                    Declaration::Defined(_) => "First definition here",
+                    Declaration::Imported(_) => "First imported here",
+                };
+                return Err(ParseError::new(
+                    BuildSymbolDeclarations::declared_at(h, decl),
+                    message,
+                )
+                .with_note(BuildSymbolDeclarations::declared_at(h, old), note));
             }
         }
         self.declarations.push(decl);
         Ok(())
     }
+    // Declarations are made at their definition, or at the import of their module
+    fn declared_at(h: &Heap, decl: DeclarationId) -> InputPosition {
+        match &h[decl] {
+            Declaration::Defined(defined) => h[defined.definition].position(),
+            Declaration::Imported(imported) => h[imported.import].position(),
+        }
+    }
 }
 
-impl Visitor for BuildSymbolDeclarations {
+impl Visitor for BuildSymbolDeclarations<'_> {
     fn visit_protocol_description(&mut self, h: &mut Heap, pd: RootId) -> VisitorResult {
         recursive_protocol_description(self, h, pd)?;
         // Move all collected declarations to the protocol description
@@ -797,7 +911,7 @@ impl Visitor for BuildSymbolDeclarations {
         Ok(())
     }
     fn visit_import(&mut self, h: &mut Heap, import: ImportId) -> VisitorResult {
-        let vec = library::get_declarations(h, import)?;
+        let vec = library::get_declarations(h, import, self.modules)?;
         // Destructively iterate over the vector
         for decl in vec {
             self.checked_add(h, decl)?;
@@ -829,9 +943,21 @@ impl LinkCallExpressions {
         h: &Heap,
         id: SourceIdentifierId,
     ) -> Result<DeclarationId, ParseError> {
-        match h[self.pd.unwrap()].get_declaration(h, id.upcast()) {
+        let pd = self.pd.unwrap();
+        match h[pd].get_declaration(h, id.upcast()) {
             Some(id) => Ok(id),
-            None => Err(ParseError::new(h[id].position, "Unresolved method")),
+            None => {
+                let error = ParseError::new(h[id].position, "Unresolved method");
+                let names = h[pd].declarations.iter().map(|&decl| h[h[decl].identifier()].ident());
+                Err(match similar_name(h[id].ident(), names) {
+                    Some(name) => error.with_hint(format!(
+                        "A method with a similar name exists: {}",
+                        String::from_utf8_lossy(name)
+                    )),
+                    None => error,
+                }
+                .with_span(h[id].span()))
+            }
         }
     }
 }
@@ -925,6 +1051,18 @@ impl Visitor for BuildScope {
         self.scope = old;
         Ok(())
     }
+    fn visit_for_statement(&mut self, h: &mut Heap, stmt: ForStatementId) -> VisitorResult {
+        assert!(self.scope.is_some());
+        let old = self.scope;
+        // First store the current scope
+        h[stmt].parent_scope = self.scope;
+        // Then move scope down to current for
+        self.scope = Some(Scope::For(stmt));
+        recursive_for_statement(self, h, stmt)?;
+        // Move scope back up
+        self.scope = old;
+        Ok(())
+    }
     fn visit_expression(&mut self, h: &mut Heap, expr: ExpressionId) -> VisitorResult {
         Ok(())
     }
@@ -942,9 +1080,14 @@ impl ResolveVariables {
         if let Some(var) = self.find_variable(h, id) {
             Ok(var)
         } else {
-            Err(ParseError::new(h[id].position, "Unresolved variable"))
+            Err(ParseError::new(h[id].position, "Unresolved variable").with_span(h[id].span()))
         }
     }
+    fn clash(h: &Heap, id: SourceIdentifierId, old: VariableId) -> ParseError {
+        ParseError::new(h[id].position, "Declared variable clash")
+            .with_span(h[id].span())
+            .with_note(h[h[old].identifier()].position, "Variable declared here")
+    }
     fn find_variable(&self, h: &Heap, id: SourceIdentifierId) -> Option<VariableId> {
         ResolveVariables::find_variable_impl(h, self.scope, id)
     }
@@ -988,7 +1131,7 @@ impl Visitor for ResolveVariables {
         let check_same = self.find_variable(h, id);
         if let Some(check_same) = check_same {
             if check_same != decl {
-                return Err(ParseError::new(h[id].position, "Declared variable clash"));
+                return Err(ResolveVariables::clash(h, id, check_same));
             }
         }
         recursive_variable_declaration(self, h, decl)
@@ -998,9 +1141,8 @@ impl Visitor for ResolveVariables {
         let var = h[stmt].variable;
         let id = h[var].identifier;
         // First check whether variable with same identifier is in scope
-        let check_duplicate = self.find_variable(h, id);
-        if !check_duplicate.is_none() {
-            return Err(ParseError::new(h[id].position, "Declared variable clash"));
+        if let Some(old) = self.find_variable(h, id) {
+            return Err(ResolveVariables::clash(h, id, old));
         }
         // Then check the expression's variables (this should not refer to own variable)
         recursive_memory_statement(self, h, stmt)?;
@@ -1017,9 +1159,8 @@ impl Visitor for ResolveVariables {
         {
             let var = h[stmt].from;
             let id = h[var].identifier;
-            let check_duplicate = self.find_variable(h, id);
-            if !check_duplicate.is_none() {
-                return Err(ParseError::new(h[id].position, "Declared variable clash"));
+            if let Some(old) = self.find_variable(h, id) {
+                return Err(ResolveVariables::clash(h, id, old));
             }
             let mut block = &mut h[self.scope.unwrap().to_block()];
             block.locals.push(var);
@@ -1028,9 +1169,8 @@ impl Visitor for ResolveVariables {
         {
             let var = h[stmt].to;
             let id = h[var].identifier;
-            let check_duplicate = self.find_variable(h, id);
-            if !check_duplicate.is_none() {
-                return Err(ParseError::new(h[id].position, "Declared variable clash"));
+            if let Some(old) = self.find_variable(h, id) {
+                return Err(ResolveVariables::clash(h, id, old));
             }
             let mut block = &mut h[self.scope.unwrap().to_block()];
             block.locals.push(var);
@@ -1057,6 +1197,17 @@ impl Visitor for ResolveVariables {
         self.scope = old;
         Ok(())
     }
+    fn visit_for_statement(&mut self, h: &mut Heap, stmt: ForStatementId) -> VisitorResult {
+        assert!(self.scope.is_some());
+        // The subject is resolved outside of the scope of the loop variable
+        self.visit_expression(h, h[stmt].subject)?;
+        let old = self.scope;
+        self.scope = Some(Scope::For(stmt));
+        recursive_local_as_variable(self, h, h[stmt].variable)?;
+        self.visit_statement(h, h[stmt].body)?;
+        self.scope = old;
+        Ok(())
+    }
     fn visit_variable_expression(
         &mut self,
         h: &mut Heap,
@@ -1124,6 +1275,23 @@ impl Visitor for LinkStatements {
         self.prev = Some(UniqueStatementId(pseudo));
         Ok(())
     }
+    fn visit_switch_statement(&mut self, h: &mut Heap, stmt: SwitchStatementId) -> VisitorResult {
+        // As for if statements, a pseudo-statement combines all cases into one next statement
+        let position = h[stmt].position;
+        let pseudo =
+            h.alloc_end_if_statement(|this| EndIfStatement { this, position, next: None }).upcast();
+        let mut bodies: Vec<StatementId> = h[stmt].cases.iter().map(|case| case.body).collect();
+        bodies.extend(h[stmt].default);
+        for body in bodies {
+            assert!(self.prev.is_none());
+            self.visit_statement(h, body)?;
+            if let Some(UniqueStatementId(prev)) = self.prev.take() {
+                h[prev].link_next(pseudo);
+            }
+        }
+        self.prev = Some(UniqueStatementId(pseudo));
+        Ok(())
+    }
     fn visit_while_statement(&mut self, h: &mut Heap, stmt: WhileStatementId) -> VisitorResult {
         // We allocate a pseudo-statement, to which the break statement finds its target
         let position = h[stmt].position;
@@ -1142,6 +1310,28 @@ impl Visitor for LinkStatements {
         self.prev = Some(UniqueStatementId(pseudo.upcast()));
         Ok(())
     }
+    fn visit_for_statement(&mut self, h: &mut Heap, stmt: ForStatementId) -> VisitorResult {
+        // We allocate a pseudo-statement, at which every iteration starts:
+        // the for statement itself only evaluates the subject once
+        let position = h[stmt].position;
+        let pseudo = h.alloc_end_for_statement(|this| EndForStatement {
+            this,
+            position,
+            start: stmt,
+            next: None,
+        });
+        h[stmt].next = Some(pseudo);
+        assert!(self.prev.is_none());
+        self.visit_statement(h, h[stmt].body)?;
+        // The body's next statement loops back to the pseudo-statement
+        // Note: continue statements also loop back to the pseudo-statement
+        if let Some(UniqueStatementId(prev)) = self.prev.take() {
+            h[prev].link_next(pseudo.upcast());
+        }
+        // Leaving the loop, the pseudo-statement continues at the next statement
+        self.prev = Some(UniqueStatementId(pseudo.upcast()));
+        Ok(())
+    }
     fn visit_break_statement(&mut self, _h: &mut Heap, _stmt: BreakStatementId) -> VisitorResult {
         Ok(())
     }
@@ -1241,6 +1431,10 @@ impl Visitor for BuildLabels {
         h[stmt].in_sync = self.sync_enclosure;
         recursive_while_statement(self, h, stmt)
     }
+    fn visit_for_statement(&mut self, h: &mut Heap, stmt: ForStatementId) -> VisitorResult {
+        h[stmt].in_sync = self.sync_enclosure;
+        recursive_for_statement(self, h, stmt)
+    }
     fn visit_synchronous_statement(
         &mut self,
         h: &mut Heap,
@@ -1259,13 +1453,29 @@ impl Visitor for BuildLabels {
 
 struct ResolveLabels {
     block: Option<BlockStatementId>,
-    while_enclosure: Option<WhileStatementId>,
+    loop_enclosure: Option<StatementId>,
     sync_enclosure: Option<SynchronousStatementId>,
 }
 
 impl ResolveLabels {
     fn new() -> Self {
-        ResolveLabels { block: None, while_enclosure: None, sync_enclosure: None }
+        ResolveLabels { block: None, loop_enclosure: None, sync_enclosure: None }
+    }
+    // The synchronous scope of a loop, and the targets of breaking from it and continuing it
+    fn loop_targets(
+        h: &Heap,
+        stmt: StatementId,
+    ) -> Option<(Option<SynchronousStatementId>, Option<StatementId>, Option<StatementId>)> {
+        match &h[stmt] {
+            Statement::While(stmt) => {
+                Some((stmt.in_sync, stmt.next.map(|next| next.upcast()), Some(stmt.this.upcast())))
+            }
+            Statement::For(stmt) => {
+                let end = stmt.next.unwrap();
+                Some((stmt.in_sync, h[end].next, Some(end.upcast())))
+            }
+            _ => None,
+        }
     }
     fn check_duplicate_impl(
         h: &Heap,
@@ -1339,42 +1549,51 @@ impl Visitor for ResolveLabels {
         recursive_labeled_statement(self, h, stmt)
     }
     fn visit_while_statement(&mut self, h: &mut Heap, stmt: WhileStatementId) -> VisitorResult {
-        let old = self.while_enclosure;
-        self.while_enclosure = Some(stmt);
+        let old = self.loop_enclosure;
+        self.loop_enclosure = Some(stmt.upcast());
         recursive_while_statement(self, h, stmt)?;
-        self.while_enclosure = old;
+        self.loop_enclosure = old;
+        Ok(())
+    }
+    fn visit_for_statement(&mut self, h: &mut Heap, stmt: ForStatementId) -> VisitorResult {
+        let old = self.loop_enclosure;
+        self.loop_enclosure = Some(stmt.upcast());
+        recursive_for_statement(self, h, stmt)?;
+        self.loop_enclosure = old;
         Ok(())
     }
     fn visit_break_statement(&mut self, h: &mut Heap, stmt: BreakStatementId) -> VisitorResult {
-        let the_while;
+        let the_loop;
         if let Some(label) = h[stmt].label {
             let target = self.get_target(h, label)?;
-            let target = &h[h[target].body];
-            if !target.is_while() {
-                return Err(ParseError::new(
-                    h[stmt].position,
-                    "Illegal break: target not a while statement",
-                ));
-            }
-            the_while = target.as_while();
+            the_loop = match ResolveLabels::loop_targets(h, h[target].body) {
+                Some(the_loop) => the_loop,
+                None => {
+                    return Err(ParseError::new(
+                        h[stmt].position,
+                        "Illegal break: target not a while or for statement",
+                    ))
+                }
+            };
         // TODO: check if break is nested under while
         } else {
-            if self.while_enclosure.is_none() {
+            if self.loop_enclosure.is_none() {
                 return Err(ParseError::new(
                     h[stmt].position,
-                    "Illegal break: no surrounding while statement",
+                    "Illegal break: no surrounding while or for statement",
                 ));
             }
-            the_while = &h[self.while_enclosure.unwrap()];
+            the_loop = ResolveLabels::loop_targets(h, self.loop_enclosure.unwrap()).unwrap();
             // break is always nested under while, by recursive vistor
         }
-        if the_while.in_sync != self.sync_enclosure {
+        let (in_sync, target, _) = the_loop;
+        if in_sync != self.sync_enclosure {
             return Err(ParseError::new(
                 h[stmt].position,
                 "Illegal break: synchronous statement escape",
             ));
         }
-        h[stmt].target = the_while.next;
+        h[stmt].target = target;
         Ok(())
     }
     fn visit_continue_statement(
@@ -1382,35 +1601,37 @@ impl Visitor for ResolveLabels {
         h: &mut Heap,
         stmt: ContinueStatementId,
     ) -> VisitorResult {
-        let the_while;
+        let the_loop;
         if let Some(label) = h[stmt].label {
             let target = self.get_target(h, label)?;
-            let target = &h[h[target].body];
-            if !target.is_while() {
-                return Err(ParseError::new(
-                    h[stmt].position,
-                    "Illegal continue: target not a while statement",
-                ));
-            }
-            the_while = target.as_while();
+            the_loop = match ResolveLabels::loop_targets(h, h[target].body) {
+                Some(the_loop) => the_loop,
+                None => {
+                    return Err(ParseError::new(
+                        h[stmt].position,
+                        "Illegal continue: target not a while or for statement",
+                    ))
+                }
+            };
         // TODO: check if continue is nested under while
         } else {
-            if self.while_enclosure.is_none() {
+            if self.loop_enclosure.is_none() {
                 return Err(ParseError::new(
                     h[stmt].position,
-                    "Illegal continue: no surrounding while statement",
+                    "Illegal continue: no surrounding while or for statement",
                 ));
             }
-            the_while = &h[self.while_enclosure.unwrap()];
+            the_loop = ResolveLabels::loop_targets(h, self.loop_enclosure.unwrap()).unwrap();
             // continue is always nested under while, by recursive vistor
         }
-        if the_while.in_sync != self.sync_enclosure {
+        let (in_sync, _, target) = the_loop;
+        if in_sync != self.sync_enclosure {
             return Err(ParseError::new(
                 h[stmt].position,
                 "Illegal continue: synchronous statement escape",
             ));
         }
-        h[stmt].target = Some(the_while.this);
+        h[stmt].target = target;
         Ok(())
     }
     fn visit_synchronous_statement(
@@ -1504,6 +1725,13 @@ impl Visitor for AssignableExpressions {
             }
         }
     }
+    fn visit_cast_expression(&mut self, h: &mut Heap, expr: CastExpressionId) -> VisitorResult {
+        if self.assignable {
+            self.error(h[expr].position)
+        } else {
+            recursive_cast_expression(self, h, expr)
+        }
+    }
     fn visit_indexing_expression(
         &mut self,
         h: &mut Heap,
@@ -1543,11 +1771,40 @@ impl Visitor for AssignableExpressions {
             recursive_array_expression(self, h, expr)
         }
     }
+    fn visit_struct_expression(&mut self, h: &mut Heap, expr: StructExpressionId) -> VisitorResult {
+        if self.assignable {
+            self.error(h[expr].position)
+        } else {
+            recursive_struct_expression(self, h, expr)
+        }
+    }
     fn visit_call_expression(&mut self, h: &mut Heap, expr: CallExpressionId) -> VisitorResult {
         if self.assignable {
             self.error(h[expr].position)
         } else {
-            recursive_call_expression(self, h, expr)
+            match h[expr].declaration {
+                Some(decl)
+                    if library::is_buffer_write(h, decl) && !h[expr].arguments.is_empty() =>
+                {
+                    // The message written to must be assignable
+                    let args = h[expr].arguments.clone();
+                    self.assignable = true;
+                    self.visit_expression(h, args[0])?;
+                    self.assignable = false;
+                    for &arg in args[1..].iter() {
+                        self.visit_expression(h, arg)?;
+                    }
+                    Ok(())
+                }
+                _ => recursive_call_expression(self, h, expr),
+            }
+        }
+    }
+    fn visit_enum_expression(&mut self, h: &mut Heap, expr: EnumExpressionId) -> VisitorResult {
+        if self.assignable {
+            self.error(h[expr].position)
+        } else {
+            Ok(())
         }
     }
     fn visit_constant_expression(
@@ -1619,6 +1876,13 @@ impl Visitor for IndexableExpressions {
             recursive_unary_expression(self, h, expr)
         }
     }
+    fn visit_cast_expression(&mut self, h: &mut Heap, expr: CastExpressionId) -> VisitorResult {
+        if self.indexable {
+            self.error(h[expr].position)
+        } else {
+            recursive_cast_expression(self, h, expr)
+        }
+    }
     fn visit_indexing_expression(
         &mut self,
         h: &mut Heap,
@@ -1660,6 +1924,13 @@ impl Visitor for IndexableExpressions {
         self.indexable = old;
         Ok(())
     }
+    fn visit_struct_expression(&mut self, h: &mut Heap, expr: StructExpressionId) -> VisitorResult {
+        let old = self.indexable;
+        self.indexable = false;
+        recursive_struct_expression(self, h, expr)?;
+        self.indexable = old;
+        Ok(())
+    }
     fn visit_call_expression(&mut self, h: &mut Heap, expr: CallExpressionId) -> VisitorResult {
         let old = self.indexable;
         self.indexable = false;
@@ -1667,6 +1938,13 @@ impl Visitor for IndexableExpressions {
         self.indexable = old;
         Ok(())
     }
+    fn visit_enum_expression(&mut self, h: &mut Heap, expr: EnumExpressionId) -> VisitorResult {
+        if self.indexable {
+            self.error(h[expr].position)
+        } else {
+            Ok(())
+        }
+    }
     fn visit_constant_expression(
         &mut self,
         h: &mut Heap,
@@ -1732,6 +2010,13 @@ impl Visitor for SelectableExpressions {
             recursive_unary_expression(self, h, expr)
         }
     }
+    fn visit_cast_expression(&mut self, h: &mut Heap, expr: CastExpressionId) -> VisitorResult {
+        if self.selectable {
+            self.error(h[expr].position)
+        } else {
+            recursive_cast_expression(self, h, expr)
+        }
+    }
     fn visit_indexing_expression(
         &mut self,
         h: &mut Heap,
@@ -1768,6 +2053,13 @@ impl Visitor for SelectableExpressions {
         self.selectable = old;
         Ok(())
     }
+    fn visit_struct_expression(&mut self, h: &mut Heap, expr: StructExpressionId) -> VisitorResult {
+        let old = self.selectable;
+        self.selectable = false;
+        recursive_struct_expression(self, h, expr)?;
+        self.selectable = old;
+        Ok(())
+    }
     fn visit_call_expression(&mut self, h: &mut Heap, expr: CallExpressionId) -> VisitorResult {
         let old = self.selectable;
         self.selectable = false;
@@ -1775,6 +2067,13 @@ impl Visitor for SelectableExpressions {
         self.selectable = old;
         Ok(())
     }
+    fn visit_enum_expression(&mut self, h: &mut Heap, expr: EnumExpressionId) -> VisitorResult {
+        if self.selectable {
+            self.error(h[expr].position)
+        } else {
+            Ok(())
+        }
+    }
     fn visit_constant_expression(
         &mut self,
         h: &mut Heap,
@@ -1788,6 +2087,840 @@ impl Visitor for SelectableExpressions {
     }
 }
 
+// ====================
+// Type checking
+// ====================
+
+/// The type of an expression as inferred by the type checker. Integer
+/// constants have the type their context expects if their value fits it, and
+/// otherwise int or long (see `Constant::integer`); the empty array
+/// expression is compatible with an array of any element type.
+///
+/// Integer types are ordered byte < short < int < long. A value of a smaller
+/// integer type is implicitly widened where a larger one is expected; integer
+/// arrays are widened element-wise. Values are never implicitly narrowed:
+/// an explicit cast such as `(byte) x` converts an integer to any integer
+/// type, keeping only the low-order bits. Arithmetic, bitwise operations and
+/// shifts are performed in the type the context expects, or int if it expects
+/// no integer, unless an operand has a larger type.
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum InferredType {
+    Exact(Type),
+    EmptyArray,
+}
+
+fn integer_rank(primitive: &PrimitiveType) -> Option<usize> {
+    match primitive {
+        PrimitiveType::Byte => Some(0),
+        PrimitiveType::Short => Some(1),
+        PrimitiveType::Int => Some(2),
+        PrimitiveType::Long => Some(3),
+        _ => None,
+    }
+}
+
+impl InferredType {
+    fn exact(&self) -> Option<&Type> {
+        match self {
+            InferredType::Exact(t) => Some(t),
+            InferredType::EmptyArray => None,
+        }
+    }
+    fn is_integer(&self) -> bool {
+        match self.exact() {
+            Some(t) => !t.array && integer_rank(&t.primitive).is_some(),
+            None => false,
+        }
+    }
+    fn is_boolean(&self) -> bool {
+        self.exact() == Some(&Type::BOOLEAN)
+    }
+    fn is_array(&self) -> bool {
+        match self {
+            InferredType::Exact(t) => t.array,
+            InferredType::EmptyArray => true,
+        }
+    }
+    fn is_message(&self) -> bool {
+        self.exact() == Some(&Type::MESSAGE)
+    }
+    fn is_port(&self) -> bool {
+        self.exact() == Some(&Type::INPUT) || self.exact() == Some(&Type::OUTPUT)
+    }
+    fn assignable_to(&self, target: &Type) -> bool {
+        match self {
+            InferredType::Exact(t) if t.array != target.array => false,
+            InferredType::Exact(t) => {
+                match (integer_rank(&t.primitive), integer_rank(&target.primitive)) {
+                    (Some(from), Some(to)) => from <= to,
+                    _ => t == target,
+                }
+            }
+            InferredType::EmptyArray => target.array,
+        }
+    }
+    // Returns the type to which both operands are implicitly converted, if any.
+    fn unify(&self, other: &InferredType) -> Option<InferredType> {
+        match (self, other) {
+            (InferredType::EmptyArray, t) | (t, InferredType::EmptyArray) if t.is_array() => {
+                Some(t.clone())
+            }
+            (InferredType::Exact(s), InferredType::Exact(o)) if s.array == o.array => {
+                match (integer_rank(&s.primitive), integer_rank(&o.primitive)) {
+                    (Some(sr), Some(or)) => {
+                        Some(if sr >= or { self.clone() } else { other.clone() })
+                    }
+                    _ if s == o => Some(self.clone()),
+                    _ => None,
+                }
+            }
+            _ => None,
+        }
+    }
+    fn element(&self) -> Option<InferredType> {
+        match self {
+            InferredType::Exact(t) if t.array => {
+                Some(InferredType::Exact(Type { primitive: t.primitive.clone(), array: false }))
+            }
+            // Indexing a message results in one of its bytes, which is unsigned
+            InferredType::Exact(t) if *t == Type::MESSAGE => Some(InferredType::Exact(Type::SHORT)),
+            _ => None,
+        }
+    }
+}
+
+impl Display for InferredType {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            InferredType::Exact(t) => write!(f, "{}", t),
+            InferredType::EmptyArray => write!(f, "{{}}"),
+        }
+    }
+}
+
+// The candidate that is most like a name that is not found: the name
+// qualified by a module, or one that differs from it in at most two edits
+fn similar_name<'a>(name: &[u8], candidates: impl Iterator<Item = &'a [u8]>) -> Option<&'a [u8]> {
+    let mut best = None;
+    for candidate in candidates {
+        let distance = if candidate.ends_with(name)
+            && candidate[..candidate.len() - name.len()].ends_with(b".")
+        {
+            0
+        } else {
+            edit_distance(name, candidate)
+        };
+        if distance <= 2 && distance < name.len() && best.map_or(true, |(d, _)| distance < d) {
+            best = Some((distance, candidate));
+        }
+    }
+    best.map(|(_, candidate)| candidate)
+}
+
+fn edit_distance(a: &[u8], b: &[u8]) -> usize {
+    let mut row: Vec<usize> = (0..=b.len()).collect();
+    for (i, x) in a.iter().enumerate() {
+        let mut diagonal = row[0];
+        row[0] = i + 1;
+        for (j, y) in b.iter().enumerate() {
+            let substitution = if x == y { diagonal } else { diagonal + 1 };
+            diagonal = row[j + 1];
+            row[j + 1] = substitution.min(row[j] + 1).min(diagonal + 1);
+        }
+    }
+    row[b.len()]
+}
+
+struct CheckTypes {
+    pd: Option<RootId>,
+    return_type: Option<Type>,
+    // The type the context expects of the expression being checked, if any
+    hint: Option<Type>,
+    types: HashMap<ExpressionId, InferredType>,
+}
+
+impl CheckTypes {
+    fn new() -> Self {
+        CheckTypes { pd: None, return_type: None, hint: None, types: HashMap::new() }
+    }
+    // Resolves the struct definition of a (non-array) struct type
+    fn get_struct(&self, h: &Heap, the_type: &Type) -> Option<StructId> {
+        match &the_type.primitive {
+            PrimitiveType::Symbolic(name) if !the_type.array => {
+                h[self.pd.unwrap()].get_struct_ident(h, name)
+            }
+            _ => None,
+        }
+    }
+    // Resolves the enum definition of a (non-array) enum type
+    fn get_enum(&self, h: &Heap, the_type: &Type) -> Option<EnumId> {
+        match &the_type.primitive {
+            PrimitiveType::Symbolic(name) if !the_type.array => {
+                h[self.pd.unwrap()].get_enum_ident(h, name)
+            }
+            _ => None,
+        }
+    }
+    // Kind of the user-defined type of some name, if it is defined
+    fn type_kind(&self, h: &Heap, name: &[u8]) -> Option<&'static str> {
+        let pd = &h[self.pd.unwrap()];
+        if pd.get_struct_ident(h, name).is_some() {
+            Some("struct")
+        } else if pd.get_enum_ident(h, name).is_some() {
+            Some("enum")
+        } else {
+            None
+        }
+    }
+    fn check_annotation(&self, h: &Heap, annotation: TypeAnnotationId) -> VisitorResult {
+        let the_type = &h[annotation].the_type;
+        if let PrimitiveType::Symbolic(name) = &the_type.primitive {
+            match self.type_kind(h, name) {
+                None => {
+                    return self.error(
+                        h[annotation].position,
+                        format!("Unknown type: {}", String::from_utf8_lossy(name)),
+                    )
+                }
+                Some(kind) if the_type.array => {
+                    return self.error(
+                        h[annotation].position,
+                        format!("Type mismatch: arrays of {}s are not supported", kind),
+                    )
+                }
+                Some(_) => {}
+            }
+        }
+        Ok(())
+    }
+    fn check_struct(&self, h: &Heap, def: StructId) -> VisitorResult {
+        let pd = self.pd.unwrap();
+        let identifier = h[def].identifier;
+        let name = h[identifier].ident();
+        let other = match h[pd].get_struct_ident(h, name) {
+            Some(other) if other != def => Some(h[other].position),
+            _ => h[pd].get_enum_ident(h, name).map(|other| h[other].position),
+        };
+        if let Some(other) = other {
+            let message = format!("Defined type clash: {}", h[identifier]);
+            return Err(CheckTypes::clash(h[def].position, message, other));
+        }
+        for (index, field) in h[def].fields.iter().enumerate() {
+            if let Some(first) = h[def].get_field(h, field.identifier).filter(|&x| x != index) {
+                let message = format!("Duplicate field: {}", h[field.identifier]);
+                return Err(CheckTypes::clash(
+                    field.position,
+                    message,
+                    h[def].fields[first].position,
+                ));
+            }
+            self.check_annotation(h, field.type_annotation)?;
+        }
+        Ok(())
+    }
+    fn check_enum(&self, h: &Heap, def: EnumId) -> VisitorResult {
+        let pd = self.pd.unwrap();
+        let identifier = h[def].identifier;
+        if let Some(other) = h[pd].get_enum_ident(h, h[identifier].ident()).filter(|&x| x != def) {
+            let message = format!("Defined type clash: {}", h[identifier]);
+            return Err(CheckTypes::clash(h[def].position, message, h[other].position));
+        }
+        for (index, &variant) in h[def].variants.iter().enumerate() {
+            if let Some(first) = h[def].get_variant(h, variant).filter(|&x| x != index) {
+                let message = format!("Duplicate variant: {}", h[variant]);
+                let first = h[h[def].variants[first]].position;
+                return Err(CheckTypes::clash(h[variant].position, message, first));
+            }
+        }
+        Ok(())
+    }
+    fn error<S: ToString>(&self, position: InputPosition, message: S) -> VisitorResult {
+        Err(ParseError::new(position, message))
+    }
+    // Clashes are noted at the other definition, whichever comes first
+    fn clash(position: InputPosition, message: String, other: InputPosition) -> ParseError {
+        let note = if other.offset() < position.offset() {
+            "First definition here"
+        } else {
+            "Also defined here"
+        };
+        ParseError::new(position, message).with_note(other, note)
+    }
+    // Mismatches underline the whole expression
+    fn mismatch<S: ToString>(&self, h: &Heap, expr: ExpressionId, message: S) -> VisitorResult {
+        Err(ParseError::new(h[expr].position(), message).with_span(h[expr].span()))
+    }
+    fn check(&mut self, h: &mut Heap, expr: ExpressionId) -> Result<InferredType, ParseError> {
+        self.check_as(h, expr, None)
+    }
+    // Checks an expression in a context that expects the given type, which
+    // types its integer constants
+    fn check_as(
+        &mut self,
+        h: &mut Heap,
+        expr: ExpressionId,
+        hint: Option<Type>,
+    ) -> Result<InferredType, ParseError> {
+        let outer = std::mem::replace(&mut self.hint, hint);
+        let result = self.visit_expression(h, expr);
+        self.hint = outer;
+        result?;
+        Ok(self.types[&expr].clone())
+    }
+    fn expect(&mut self, h: &mut Heap, expr: ExpressionId, expected: &Type) -> VisitorResult {
+        let found = self.check_as(h, expr, Some(expected.clone()))?;
+        if found.assignable_to(expected) {
+            Ok(())
+        } else {
+            self.mismatch(h, expr, format!("Type mismatch: expected {}, found {}", expected, found))
+        }
+    }
+    fn expect_integer(&mut self, h: &mut Heap, expr: ExpressionId) -> VisitorResult {
+        let found = self.check(h, expr)?;
+        if found.is_integer() {
+            Ok(())
+        } else {
+            self.mismatch(h, expr, format!("Type mismatch: expected integer, found {}", found))
+        }
+    }
+    fn result(&mut self, expr: ExpressionId, the_type: InferredType) -> VisitorResult {
+        self.types.insert(expr, the_type);
+        Ok(())
+    }
+    fn check_arity(
+        &self,
+        position: InputPosition,
+        arguments: &[ExpressionId],
+        expected: usize,
+    ) -> VisitorResult {
+        if arguments.len() == expected {
+            Ok(())
+        } else {
+            self.error(
+                position,
+                format!(
+                    "Wrong number of arguments: expected {}, found {}",
+                    expected,
+                    arguments.len()
+                ),
+            )
+        }
+    }
+    fn check_arguments(
+        &mut self,
+        h: &mut Heap,
+        position: InputPosition,
+        arguments: &[ExpressionId],
+        arity: &[Type],
+    ) -> VisitorResult {
+        self.check_arity(position, arguments, arity.len())?;
+        for (&arg, param) in arguments.iter().zip(arity.iter()) {
+            self.expect(h, arg, param)?;
+        }
+        Ok(())
+    }
+}
+
+impl Visitor for CheckTypes {
+    fn visit_protocol_description(&mut self, h: &mut Heap, pd: RootId) -> VisitorResult {
+        self.pd = Some(pd);
+        for &def in h[pd].structs.iter() {
+            self.check_struct(h, def)?;
+        }
+        for &def in h[pd].enums.iter() {
+            self.check_enum(h, def)?;
+        }
+        recursive_protocol_description(self, h, pd)
+    }
+    fn visit_function_definition(&mut self, h: &mut Heap, def: FunctionId) -> VisitorResult {
+        assert!(self.return_type.is_none());
+        self.check_annotation(h, h[def].return_type)?;
+        self.return_type = Some(h[h[def].return_type].the_type.clone());
+        recursive_function_definition(self, h, def)?;
+        self.return_type = None;
+        Ok(())
+    }
+    fn visit_parameter_declaration(&mut self, h: &mut Heap, decl: ParameterId) -> VisitorResult {
+        self.check_annotation(h, h[decl].type_annotation)
+    }
+    fn visit_memory_statement(&mut self, h: &mut Heap, stmt: MemoryStatementId) -> VisitorResult {
+        let annotation = h[h[stmt].variable].type_annotation;
+        self.check_annotation(h, annotation)?;
+        let the_type = h[annotation].the_type.clone();
+        self.expect(h, h[stmt].initial, &the_type)
+    }
+    fn visit_if_statement(&mut self, h: &mut Heap, stmt: IfStatementId) -> VisitorResult {
+        self.expect(h, h[stmt].test, &Type::BOOLEAN)?;
+        self.visit_statement(h, h[stmt].true_body)?;
+        self.visit_statement(h, h[stmt].false_body)
+    }
+    fn visit_switch_statement(&mut self, h: &mut Heap, stmt: SwitchStatementId) -> VisitorResult {
+        let subject = self.check(h, h[stmt].subject)?;
+        let def = match subject.exact().and_then(|t| self.get_enum(h, t)) {
+            Some(def) => def,
+            None => {
+                return self.mismatch(
+                    h,
+                    h[stmt].subject,
+                    format!("Type mismatch: expected enum, found {}", subject),
+                )
+            }
+        };
+        let mut covered = vec![false; h[def].variants.len()];
+        for case in h[stmt].cases.clone().iter() {
+            for &variant in case.variants.iter() {
+                let index = match h[def].get_variant(h, variant) {
+                    Some(index) => index,
+                    None => return self.error(h[variant].position, "Unknown variant"),
+                };
+                if covered[index] {
+                    return self
+                        .error(h[variant].position, format!("Duplicate case: {}", h[variant]));
+                }
+                covered[index] = true;
+            }
+            self.visit_statement(h, case.body)?;
+        }
+        match h[stmt].default {
+            Some(default) => self.visit_statement(h, default)?,
+            None => {
+                // Without a default, the cases must cover every variant
+                if let Some(index) = covered.iter().position(|&done| !done) {
+                    let variant = h[def].variants[index];
+                    return self.error(h[stmt].position, format!("Missing case: {}", h[variant]));
+                }
+            }
+        }
+        h[stmt].definition = Some(def);
+        Ok(())
+    }
+    fn visit_while_statement(&mut self, h: &mut Heap, stmt: WhileStatementId) -> VisitorResult {
+        self.expect(h, h[stmt].test, &Type::BOOLEAN)?;
+        self.visit_statement(h, h[stmt].body)
+    }
+    fn visit_for_statement(&mut self, h: &mut Heap, stmt: ForStatementId) -> VisitorResult {
+        let variable = h[stmt].variable;
+        let annotation = h[variable].type_annotation;
+        self.check_annotation(h, annotation)?;
+        let the_type = h[annotation].the_type.clone();
+        // The elements of an array constant have the type of the variable
+        let hint = if h[stmt].each { Some(Type { array: true, ..the_type.clone() }) } else { None };
+        let subject = self.check_as(h, h[stmt].subject, hint)?;
+        if !subject.is_array() && !subject.is_message() {
+            return self.mismatch(
+                h,
+                h[stmt].subject,
+                format!("Type mismatch: cannot iterate over {}", subject),
+            );
+        }
+        // The empty array has no elements, of any type
+        let element =
+            if h[stmt].each { subject.element() } else { Some(InferredType::Exact(Type::INT)) };
+        if let Some(element) = element {
+            if !element.assignable_to(&the_type) {
+                return self.error(
+                    h[variable].position,
+                    format!("Type mismatch: expected {}, found {}", the_type, element),
+                );
+            }
+        }
+        self.visit_statement(h, h[stmt].body)
+    }
+    fn visit_return_statement(&mut self, h: &mut Heap, stmt: ReturnStatementId) -> VisitorResult {
+        // Only functions may return, and every function has a return type
+        let the_type = self.return_type.clone().unwrap();
+        self.expect(h, h[stmt].expression, &the_type)
+    }
+    fn visit_assert_statement(&mut self, h: &mut Heap, stmt: AssertStatementId) -> VisitorResult {
+        self.expect(h, h[stmt].expression, &Type::BOOLEAN)
+    }
+    fn visit_put_statement(&mut self, h: &mut Heap, stmt: PutStatementId) -> VisitorResult {
+        self.expect(h, h[stmt].port, &Type::OUTPUT)?;
+        self.expect(h, h[stmt].message, &Type::MESSAGE)
+    }
+
+    fn visit_assignment_expression(
+        &mut self,
+        h: &mut Heap,
+        expr: AssignmentExpressionId,
+    ) -> VisitorResult {
+        let left = self.check(h, h[expr].left)?;
+        // The left-hand side is assignable, and thus has an exact type
+        let the_type = left.exact().unwrap().clone();
+        let right = self.check_as(h, h[expr].right, Some(the_type.clone()))?;
+        let legal = match h[expr].operation {
+            AssignmentOperator::Set => right.assignable_to(&the_type),
+            _ => left.is_integer() && right.assignable_to(&the_type),
+        };
+        if !legal {
+            return self.mismatch(
+                h,
+                expr.upcast(),
+                format!("Type mismatch: cannot assign {} to {}", right, left),
+            );
+        }
+        self.result(expr.upcast(), left)
+    }
+    fn visit_conditional_expression(
+        &mut self,
+        h: &mut Heap,
+        expr: ConditionalExpressionId,
+    ) -> VisitorResult {
+        let hint = self.hint.clone();
+        self.expect(h, h[expr].test, &Type::BOOLEAN)?;
+        let left = self.check_as(h, h[expr].true_expression, hint.clone())?;
+        let right = self.check_as(h, h[expr].false_expression, hint)?;
+        match left.unify(&right) {
+            Some(the_type) => self.result(expr.upcast(), the_type),
+            None => self.mismatch(
+                h,
+                expr.upcast(),
+                format!("Type mismatch: incompatible branches {} and {}", left, right),
+            ),
+        }
+    }
+    fn visit_binary_expression(&mut self, h: &mut Heap, expr: BinaryExpressionId) -> VisitorResult {
+        // The operands of operations that result in a value of their type are
+        // checked in the context of the operation, except for shift amounts
+        let (left_hint, right_hint) = match h[expr].operation {
+            BinaryOperator::LogicalOr
+            | BinaryOperator::LogicalAnd
+            | BinaryOperator::Equality
+            | BinaryOperator::Inequality
+            | BinaryOperator::LessThan
+            | BinaryOperator::GreaterThan
+            | BinaryOperator::LessThanEqual
+            | BinaryOperator::GreaterThanEqual => (None, None),
+            BinaryOperator::ShiftLeft | BinaryOperator::ShiftRight => (self.hint.clone(), None),
+            _ => (self.hint.clone(), self.hint.clone()),
+        };
+        // Integer operations are performed in the type the context expects,
+        // or int if it expects none, unless an operand is larger
+        let context = match &self.hint {
+            Some(t) if !t.array && integer_rank(&t.primitive).is_some() => t.clone(),
+            _ => Type::INT,
+        };
+        let context = InferredType::Exact(context);
+        let left = self.check_as(h, h[expr].left, left_hint)?;
+        let right = self.check_as(h, h[expr].right, right_hint)?;
+        let integers = left.is_integer() && right.is_integer();
+        let result = match h[expr].operation {
+            BinaryOperator::Concatenate => match left.unify(&right) {
+                Some(t) if t.is_array() || t.is_message() => Some(t),
+                _ => None,
+            },
+            BinaryOperator::LogicalOr | BinaryOperator::LogicalAnd => {
+                if left.is_boolean() && right.is_boolean() {
+                    Some(InferredType::Exact(Type::BOOLEAN))
+                } else {
+                    None
+                }
+            }
+            BinaryOperator::Equality | BinaryOperator::Inequality => {
+                left.unify(&right).map(|_| InferredType::Exact(Type::BOOLEAN))
+            }
+            BinaryOperator::LessThan
+            | BinaryOperator::GreaterThan
+            | BinaryOperator::LessThanEqual
+            | BinaryOperator::GreaterThanEqual => {
+                if integers {
+                    Some(InferredType::Exact(Type::BOOLEAN))
+                } else {
+                    None
+                }
+            }
+            BinaryOperator::ShiftLeft | BinaryOperator::ShiftRight => {
+                if integers {
+                    left.unify(&context)
+                } else {
+                    None
+                }
+            }
+            BinaryOperator::BitwiseOr
+            | BinaryOperator::BitwiseXor
+            | BinaryOperator::BitwiseAnd
+            | BinaryOperator::Add
+            | BinaryOperator::Subtract
+            | BinaryOperator::Multiply
+            | BinaryOperator::Divide
+            | BinaryOperator::Remainder => {
+                if integers {
+                    left.unify(&right).and_then(|t| t.unify(&context))
+                } else {
+                    None
+                }
+            }
+        };
+        match result {
+            Some(the_type) => {
+                if the_type.is_integer() {
+                    h[expr].the_type = the_type.exact().cloned();
+                }
+                self.result(expr.upcast(), the_type)
+            }
+            None => self.mismatch(
+                h,
+                expr.upcast(),
+                format!("Type mismatch: incompatible operands {} and {}", left, right),
+            ),
+        }
+    }
+    fn visit_unary_expression(&mut self, h: &mut Heap, expr: UnaryExpressionId) -> VisitorResult {
+        // A negated integer constant is typed by its negated value, so that
+        // the smallest value of each integer type is a constant of that type
+        if let (UnaryOperation::Negative, Expression::Constant(constant)) =
+            (&h[expr].operation, &h[h[expr].expression])
+        {
+            if let Constant::Integer(_) = constant.value {
+                let operand = constant.this;
+                let the_type = match constant.value.integer(true, self.hint.as_ref()) {
+                    Some((_, the_type)) => the_type,
+                    None => return self.mismatch(h, expr.upcast(), "Invalid integer constant"),
+                };
+                h[operand].the_type = Some(the_type.clone());
+                self.result(operand.upcast(), InferredType::Exact(the_type.clone()))?;
+                return self.result(expr.upcast(), InferredType::Exact(the_type));
+            }
+        }
+        let operand = self.check_as(h, h[expr].expression, self.hint.clone())?;
+        let legal = match h[expr].operation {
+            UnaryOperation::LogicalNot => operand.is_boolean(),
+            _ => operand.is_integer(),
+        };
+        if !legal {
+            return self.mismatch(
+                h,
+                expr.upcast(),
+                format!("Type mismatch: incompatible operand {}", operand),
+            );
+        }
+        self.result(expr.upcast(), operand)
+    }
+    fn visit_cast_expression(&mut self, h: &mut Heap, expr: CastExpressionId) -> VisitorResult {
+        self.expect_integer(h, h[expr].expression)?;
+        self.result(expr.upcast(), InferredType::Exact(h[expr].the_type.clone()))
+    }
+    fn visit_indexing_expression(
+        &mut self,
+        h: &mut Heap,
+        expr: IndexingExpressionId,
+    ) -> VisitorResult {
+        let subject = self.check(h, h[expr].subject)?;
+        self.expect_integer(h, h[expr].index)?;
+        match subject.element() {
+            Some(the_type) => self.result(expr.upcast(), the_type),
+            None => self.mismatch(
+                h,
+                h[expr].subject,
+                format!("Type mismatch: cannot index {}", subject),
+            ),
+        }
+    }
+    fn visit_slicing_expression(
+        &mut self,
+        h: &mut Heap,
+        expr: SlicingExpressionId,
+    ) -> VisitorResult {
+        let subject = self.check(h, h[expr].subject)?;
+        self.expect_integer(h, h[expr].from_index)?;
+        self.expect_integer(h, h[expr].to_index)?;
+        if subject.is_array() || subject.is_message() {
+            self.result(expr.upcast(), subject)
+        } else {
+            self.mismatch(h, h[expr].subject, format!("Type mismatch: cannot slice {}", subject))
+        }
+    }
+    fn visit_select_expression(&mut self, h: &mut Heap, expr: SelectExpressionId) -> VisitorResult {
+        let subject = self.check(h, h[expr].subject)?;
+        match h[expr].field {
+            Field::Length if subject.is_array() || subject.is_message() => {
+                self.result(expr.upcast(), InferredType::Exact(Type::INT))
+            }
+            Field::Length => self.mismatch(
+                h,
+                h[expr].subject,
+                format!("Type mismatch: {} has no length", subject),
+            ),
+            Field::Symbolic(id) => {
+                let def = subject.exact().and_then(|t| self.get_struct(h, t));
+                match def.and_then(|def| h[def].get_field(h, id).map(|index| (def, index))) {
+                    Some((def, index)) => {
+                        let annotation = h[def].fields[index].type_annotation;
+                        h[expr].field_index = Some(index);
+                        self.result(
+                            expr.upcast(),
+                            InferredType::Exact(h[annotation].the_type.clone()),
+                        )
+                    }
+                    None => self.error(h[id].position, "Unknown field"),
+                }
+            }
+        }
+    }
+    fn visit_array_expression(&mut self, h: &mut Heap, expr: ArrayExpressionId) -> VisitorResult {
+        let hint = self.hint.clone().filter(|t| t.array).map(|t| Type { array: false, ..t });
+        let mut the_type = InferredType::EmptyArray;
+        for &elem in h[expr].elements.clone().iter() {
+            let elem_type = self.check_as(h, elem, hint.clone())?;
+            if elem_type.is_array() {
+                return self.mismatch(h, elem, "Type mismatch: nested arrays are not supported");
+            }
+            let unified = match &the_type {
+                InferredType::EmptyArray => Some(elem_type.clone()),
+                InferredType::Exact(t) => elem_type.unify(&InferredType::Exact(Type {
+                    primitive: t.primitive.clone(),
+                    array: false,
+                })),
+            };
+            match unified {
+                Some(InferredType::Exact(Type {
+                    primitive: PrimitiveType::Symbolic(name),
+                    ..
+                })) => {
+                    let kind = self.type_kind(h, &name).unwrap();
+                    return self.mismatch(
+                        h,
+                        elem,
+                        format!("Type mismatch: arrays of {}s are not supported", kind),
+                    );
+                }
+                Some(InferredType::Exact(t)) => {
+                    the_type = InferredType::Exact(Type { primitive: t.primitive, array: true })
+                }
+                _ => {
+                    return self.mismatch(
+                        h,
+                        elem,
+                        format!("Type mismatch: incompatible array element {}", elem_type),
+                    )
+                }
+            }
+        }
+        self.result(expr.upcast(), the_type)
+    }
+    fn visit_struct_expression(&mut self, h: &mut Heap, expr: StructExpressionId) -> VisitorResult {
+        let identifier = h[expr].identifier;
+        let def = match h[self.pd.unwrap()].get_struct_ident(h, h[identifier].ident()) {
+            Some(def) => def,
+            None => {
+                return self.error(h[expr].position, format!("Unknown type: {}", h[identifier]))
+            }
+        };
+        let mut initialized = vec![false; h[def].fields.len()];
+        for &(field, value) in h[expr].fields.clone().iter() {
+            let index = match h[def].get_field(h, field) {
+                Some(index) => index,
+                None => return self.error(h[field].position, "Unknown field"),
+            };
+            if initialized[index] {
+                return self.error(h[field].position, format!("Duplicate field: {}", h[field]));
+            }
+            initialized[index] = true;
+            let the_type = h[h[def].fields[index].type_annotation].the_type.clone();
+            self.expect(h, value, &the_type)?;
+        }
+        if let Some(index) = initialized.iter().position(|&done| !done) {
+            let field = h[def].fields[index].identifier;
+            return self.error(h[expr].position, format!("Missing field: {}", h[field]));
+        }
+        h[expr].definition = Some(def);
+        let primitive = PrimitiveType::Symbolic(h[identifier].ident().to_vec());
+        self.result(expr.upcast(), InferredType::Exact(Type { primitive, array: false }))
+    }
+    fn visit_enum_expression(&mut self, h: &mut Heap, expr: EnumExpressionId) -> VisitorResult {
+        let identifier = h[expr].identifier;
+        let def = match h[self.pd.unwrap()].get_enum_ident(h, h[identifier].ident()) {
+            Some(def) => def,
+            None => {
+                return self.error(h[expr].position, format!("Unknown type: {}", h[identifier]))
+            }
+        };
+        let variant = h[expr].variant;
+        if h[def].get_variant(h, variant).is_none() {
+            return self.error(h[variant].position, "Unknown variant");
+        }
+        let primitive = PrimitiveType::Symbolic(h[identifier].ident().to_vec());
+        self.result(expr.upcast(), InferredType::Exact(Type { primitive, array: false }))
+    }
+    fn visit_call_expression(&mut self, h: &mut Heap, expr: CallExpressionId) -> VisitorResult {
+        let position = h[expr].position;
+        let arguments = h[expr].arguments.clone();
+        let the_type = match h[expr].method {
+            Method::Get => {
+                self.check_arguments(h, position, &arguments, &[Type::INPUT])?;
+                Type::MESSAGE
+            }
+            Method::Fires => {
+                self.check_arity(position, &arguments, 1)?;
+                let port = self.check(h, arguments[0])?;
+                if !port.is_port() {
+                    return self.mismatch(
+                        h,
+                        arguments[0],
+                        format!("Type mismatch: expected port, found {}", port),
+                    );
+                }
+                Type::BOOLEAN
+            }
+            Method::Create => {
+                self.check_arity(position, &arguments, 1)?;
+                self.expect_integer(h, arguments[0])?;
+                Type::MESSAGE
+            }
+            Method::Random => {
+                self.check_arity(position, &arguments, 0)?;
+                Type::LONG
+            }
+            Method::Choose => {
+                self.check_arity(position, &arguments, 0)?;
+                Type::BOOLEAN
+            }
+            Method::Symbolic(_) => {
+                let decl = h[expr].declaration.unwrap();
+                match h[decl].signature().clone() {
+                    Signature::Component(sig) => {
+                        // Component instantiations only occur in new statements
+                        return self.check_arguments(h, position, &arguments, &sig.arity);
+                    }
+                    Signature::Function(sig) => {
+                        self.check_arguments(h, position, &arguments, &sig.arity)?;
+                        sig.return_type
+                    }
+                }
+            }
+        };
+        self.result(expr.upcast(), InferredType::Exact(the_type))
+    }
+    fn visit_constant_expression(
+        &mut self,
+        h: &mut Heap,
+        expr: ConstantExpressionId,
+    ) -> VisitorResult {
+        let the_type = match &h[expr].value {
+            Constant::Null => Type::MESSAGE,
+            Constant::True | Constant::False => Type::BOOLEAN,
+            constant => match constant.integer(false, self.hint.as_ref()) {
+                Some((_, the_type)) => the_type,
+                None if matches!(constant, Constant::Character(_)) => {
+                    return self.mismatch(h, expr.upcast(), "Invalid character constant")
+                }
+                None => return self.mismatch(h, expr.upcast(), "Invalid integer constant"),
+            },
+        };
+        h[expr].the_type = Some(the_type.clone());
+        self.result(expr.upcast(), InferredType::Exact(the_type))
+    }
+    fn visit_variable_expression(
+        &mut self,
+        h: &mut Heap,
+        expr: VariableExpressionId,
+    ) -> VisitorResult {
+        let var = h[expr].declaration.unwrap();
+        let the_type = h[var].the_type(h).clone();
+        self.result(expr.upcast(), InferredType::Exact(the_type))
+    }
+}
+
 pub struct Parser<'a> {
     source: &'a mut InputSource,
 }
@@ -1797,14 +2930,27 @@ impl<'a> Parser<'a> {
         Parser { source }
     }
     pub fn parse(&mut self, h: &mut Heap) -> Result<RootId, ParseError> {
+        let mut modules = library::Modules::new(&PdlModules::default());
+        *self.source = modules.add(self.source.clone());
+        self.parse_modules(h, &mut modules)
+    }
+    // The source must have been added to the modules, which receive the sources it imports
+    pub fn parse_modules(
+        &mut self,
+        h: &mut Heap,
+        modules: &mut library::Modules,
+    ) -> Result<RootId, ParseError> {
         let mut lex = Lexer::new(self.source);
         let pd = lex.consume_protocol_description(h)?;
+        if let Some(err) = lex.errors().first() {
+            return Err(err.clone());
+        }
         NestedSynchronousStatements::new().visit_protocol_description(h, pd)?;
         ChannelStatementOccurrences::new().visit_protocol_description(h, pd)?;
         FunctionStatementReturns::new().visit_protocol_description(h, pd)?;
         ComponentStatementReturnNew::new().visit_protocol_description(h, pd)?;
         CheckBuiltinOccurrences::new().visit_protocol_description(h, pd)?;
-        BuildSymbolDeclarations::new().visit_protocol_description(h, pd)?;
+        BuildSymbolDeclarations::new(modules).visit_protocol_description(h, pd)?;
         LinkCallExpressions::new().visit_protocol_description(h, pd)?;
         BuildScope::new().visit_protocol_description(h, pd)?;
         ResolveVariables::new().visit_protocol_description(h, pd)?;
@@ -1814,8 +2960,112 @@ impl<'a> Parser<'a> {
         AssignableExpressions::new().visit_protocol_description(h, pd)?;
         IndexableExpressions::new().visit_protocol_description(h, pd)?;
         SelectableExpressions::new().visit_protocol_description(h, pd)?;
+        CheckTypes::new().visit_protocol_description(h, pd)?;
         Ok(pd)
     }
+    // Parses as above, but continues after an error in a definition to find the errors in
+    // the other definitions. All errors are returned, in the order of their positions.
+    pub fn parse_all(
+        &mut self,
+        h: &mut Heap,
+        modules: &mut library::Modules,
+    ) -> Result<RootId, Vec<ParseError>> {
+        match self.parse_partial(h, modules) {
+            (Some(pd), errors) if errors.is_empty() => Ok(pd),
+            (_, errors) => Err(errors),
+        }
+    }
+    // Parses as above, but also returns the description when it has errors, so that the
+    // definitions that passed the checks can still be inspected. There is no description if
+    // the lexer could not recover from an error.
+    pub fn parse_partial(
+        &mut self,
+        h: &mut Heap,
+        modules: &mut library::Modules,
+    ) -> (Option<RootId>, Vec<ParseError>) {
+        let mut lex = Lexer::new(self.source);
+        let result = lex.consume_protocol_description(h);
+        let mut errors = lex.errors().to_vec();
+        let pd = match result {
+            Ok(pd) => pd,
+            Err(_) => return (None, errors),
+        };
+        let all = h[pd].definitions.clone();
+        let mut checked = all.clone();
+        checked.retain(|def| !lex.broken().contains(def));
+        let mut check = Checked { pd, all: all.clone(), definitions: checked, errors: &mut errors };
+        let result = (|| {
+            check.run(h, NestedSynchronousStatements::new)?;
+            check.run(h, ChannelStatementOccurrences::new)?;
+            check.run(h, FunctionStatementReturns::new)?;
+            check.run(h, ComponentStatementReturnNew::new)?;
+            check.run(h, CheckBuiltinOccurrences::new)?;
+            // Definitions with errors are still declared, so that their uses resolve
+            h[pd].definitions = all.clone();
+            BuildSymbolDeclarations::new(modules).visit_protocol_description(h, pd)?;
+            check.run(h, LinkCallExpressions::new)?;
+            check.run(h, BuildScope::new)?;
+            check.run(h, ResolveVariables::new)?;
+            check.run(h, LinkStatements::new)?;
+            check.run(h, BuildLabels::new)?;
+            check.run(h, ResolveLabels::new)?;
+            check.run(h, AssignableExpressions::new)?;
+            check.run(h, IndexableExpressions::new)?;
+            check.run(h, SelectableExpressions::new)?;
+            check.run(h, CheckTypes::new)
+        })();
+        h[pd].definitions = all;
+        if let Err(err) = result {
+            errors.push(err);
+        }
+        errors.sort_by_key(|err| (err.position().file(), err.position().offset()));
+        (Some(pd), errors)
+    }
+}
+
+// The definitions that passed all checks so far, and the errors of the others
+struct Checked<'a> {
+    pd: RootId,
+    all: Vec<DefinitionId>,
+    definitions: Vec<DefinitionId>,
+    errors: &'a mut Vec<ParseError>,
+}
+
+impl Checked<'_> {
+    // Runs a pass over each definition by itself, after running it over the rest of the
+    // description. An error inside a definition excludes it from further passes, any other
+    // error ends the parse.
+    fn run<T: Visitor>(&mut self, h: &mut Heap, new: impl Fn() -> T) -> VisitorResult {
+        let pd = self.pd;
+        h[pd].definitions = Vec::new();
+        new().visit_protocol_description(h, pd)?;
+        for def in self.definitions.clone() {
+            h[pd].definitions = vec![def];
+            if let Err(err) = new().visit_protocol_description(h, pd) {
+                if !self.encloses(h, def, err.position()) {
+                    return Err(err);
+                }
+                self.errors.push(err);
+                self.definitions.retain(|&other| other != def);
+            }
+        }
+        Ok(())
+    }
+    // A definition extends up to the next definition, struct or enum
+    fn encloses(&self, h: &Heap, def: DefinitionId, pos: InputPosition) -> bool {
+        let pd = self.pd;
+        let start = h[def].position();
+        if pos.file() != start.file() || pos.offset() < start.offset() {
+            return false;
+        }
+        let mut starts = Vec::new();
+        starts.extend(h[pd].structs.iter().map(|&def| h[def].position));
+        starts.extend(h[pd].enums.iter().map(|&def| h[def].position));
+        starts.extend(self.all.iter().map(|&def| h[def].position()));
+        !starts
+            .iter()
+            .any(|other| other.offset() > start.offset() && other.offset() <= pos.offset())
+    }
 }
 
 #[cfg(test)]
@@ -1851,9 +3101,10 @@ mod tests {
         let path = Path::new(resource);
         let expect = path.with_extension("txt");
         let mut heap = Heap::new();
-        let mut source = InputSource::from_file(&path).unwrap();
+        let mut modules = library::Modules::new(&PdlModules::default());
+        let mut source = modules.add(InputSource::from_file(&path).unwrap());
         let mut parser = Parser::new(&mut source);
-        match parser.parse(&mut heap) {
+        match parser.parse_modules(&mut heap, &mut modules) {
             Ok(pd) => {
                 println!("{:?}", heap[pd]);
                 println!("Expected parse error:");
@@ -1867,8 +3118,39 @@ mod tests {
             Err(err) => {
                 println!("{:?}", err);
 
+                // Notes may be shown in the sources that are imported
+                let mut vec: Vec<u8> = Vec::new();
+                err.write_in(&modules.sources, &mut vec).unwrap();
+                println!("{}", String::from_utf8_lossy(&vec));
+
+                let mut cev: Vec<u8> = Vec::new();
+                let mut f = File::open(expect).unwrap();
+                f.read_to_end(&mut cev).unwrap();
+                println!("{}", String::from_utf8_lossy(&cev));
+
+                assert_eq!(vec, cev);
+            }
+        }
+    }
+
+    #[test_resources("testdata/parser/recovery/*.pdl")]
+    fn batch3(resource: &str) {
+        let path = Path::new(resource);
+        let expect = path.with_extension("txt");
+        let mut heap = Heap::new();
+        let mut modules = library::Modules::new(&PdlModules::default());
+        let mut source = modules.add(InputSource::from_file(path).unwrap());
+        let mut parser = Parser::new(&mut source);
+        match parser.parse_all(&mut heap, &mut modules) {
+            Ok(pd) => {
+                println!("{:?}", heap[pd]);
+                panic!("Expected parse errors");
+            }
+            Err(errors) => {
                 let mut vec: Vec<u8> = Vec::new();
-                err.write(&source, &mut vec).unwrap();
+                for err in errors.iter() {
+                    err.write(&source, &mut vec).unwrap();
+                }
                 println!("{}", String::from_utf8_lossy(&vec));
 
                 let mut cev: Vec<u8> = Vec::new();
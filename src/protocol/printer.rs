@@ -0,0 +1,1056 @@
+use crate::protocol::ast::*;
+use crate::protocol::inputsource::*;
+use crate::protocol::lexer::*;
+use crate::protocol::parser::{Visitor, VisitorResult};
+
+// Precedence levels of expressions, from loosely to tightly binding
+pub(crate) const ASSIGNMENT: u8 = 0;
+pub(crate) const CONDITIONAL: u8 = 1;
+pub(crate) const CONCATENATE: u8 = 2;
+pub(crate) const LOGICAL_OR: u8 = 3;
+pub(crate) const LOGICAL_AND: u8 = 4;
+pub(crate) const BITWISE_OR: u8 = 5;
+pub(crate) const BITWISE_XOR: u8 = 6;
+pub(crate) const BITWISE_AND: u8 = 7;
+pub(crate) const EQUALITY: u8 = 8;
+pub(crate) const RELATIONAL: u8 = 9;
+pub(crate) const SHIFT: u8 = 10;
+pub(crate) const ADDITIVE: u8 = 11;
+pub(crate) const MULTIPLICATIVE: u8 = 12;
+pub(crate) const PREFIX: u8 = 13;
+pub(crate) const POSTFIX: u8 = 14;
+
+// Formats a protocol description in its canonical form. Only the syntax is checked, so that
+// descriptions with semantic errors can be formatted too. Comments are not preserved.
+pub fn format(source: &mut InputSource) -> Result<String, Vec<ParseError>> {
+    let mut h = Heap::new();
+    let mut lex = Lexer::new(source);
+    let result = lex.consume_protocol_description(&mut h);
+    if !lex.errors().is_empty() {
+        return Err(lex.errors().to_vec());
+    }
+    let pd = result.map_err(|err| vec![err])?;
+    let mut printer = Printer::new();
+    printer.visit_protocol_description(&mut h, pd).unwrap();
+    Ok(printer.out)
+}
+
+/* Prints the tree of a protocol description as source text that parses to the same tree.
+Every statement is printed on its own line, indented by tabs, and every expression is
+parenthesized where the precedence of its operators requires so. Top-level definitions are
+printed in a fixed order: pragmas, imports, structs, enums, and then the other definitions. */
+struct Printer {
+    out: String,
+    indent: usize,
+    // The least precedence that the expression being printed must have without parentheses
+    precedence: u8,
+}
+
+impl Printer {
+    fn new() -> Self {
+        Printer { out: String::new(), indent: 0, precedence: ASSIGNMENT }
+    }
+    fn write(&mut self, text: &str) {
+        self.out.push_str(text);
+    }
+    fn write_ident(&mut self, h: &Heap, id: SourceIdentifierId) {
+        self.out.push_str(&h[id].to_string());
+    }
+    fn newline(&mut self) {
+        self.out.push('\n');
+        for _ in 0..self.indent {
+            self.out.push('\t');
+        }
+    }
+    fn type_annotation(&mut self, h: &Heap, id: TypeAnnotationId) {
+        self.out.push_str(&h[id].the_type.to_string());
+    }
+    fn parameters(&mut self, h: &Heap, params: &[ParameterId]) {
+        self.write("(");
+        for (i, &param) in params.iter().enumerate() {
+            if i > 0 {
+                self.write(", ");
+            }
+            self.type_annotation(h, h[param].type_annotation);
+            self.write(" ");
+            self.write_ident(h, h[param].identifier);
+        }
+        self.write(")");
+    }
+    fn definition_body(&mut self, h: &mut Heap, body: StatementId) -> VisitorResult {
+        // An empty block is parsed as a skip statement
+        if let Statement::Skip(_) = &h[body] {
+            self.write(" {}");
+            return Ok(());
+        }
+        self.body(h, body)
+    }
+    // Prints the body of a compound statement: a block on the same line, any other
+    // statement on the next line and indented
+    fn body(&mut self, h: &mut Heap, body: StatementId) -> VisitorResult {
+        if Printer::is_block(h, body) {
+            self.write(" ");
+            self.visit_statement(h, body)?;
+        } else {
+            self.indent += 1;
+            self.newline();
+            self.visit_statement(h, body)?;
+            self.indent -= 1;
+        }
+        Ok(())
+    }
+    fn is_block(h: &Heap, stmt: StatementId) -> bool {
+        matches!(&h[stmt], Statement::Block(_))
+    }
+    fn expression(&mut self, h: &mut Heap, expr: ExpressionId, precedence: u8) -> VisitorResult {
+        let old = std::mem::replace(&mut self.precedence, precedence);
+        let result = self.visit_expression(h, expr);
+        self.precedence = old;
+        result
+    }
+    // Prints an expression, in parentheses if its printed form would start with the given
+    // character, as it would then be parsed as another construct. A leading cast is not
+    // mistaken for a parenthesized expression.
+    fn expression_avoiding(
+        &mut self,
+        h: &mut Heap,
+        expr: ExpressionId,
+        start: char,
+    ) -> VisitorResult {
+        let offset = self.out.len();
+        self.expression(h, expr, ASSIGNMENT)?;
+        let printed = &self.out[offset..];
+        let cast = ["(byte)", "(short)", "(int)", "(long)"].iter().any(|c| printed.starts_with(c));
+        if printed.starts_with(start) && !cast {
+            self.out.insert(offset, '(');
+            self.write(")");
+        }
+        Ok(())
+    }
+    fn expressions(&mut self, h: &mut Heap, exprs: &[ExpressionId]) -> VisitorResult {
+        for (i, &expr) in exprs.iter().enumerate() {
+            if i > 0 {
+                self.write(", ");
+            }
+            self.expression(h, expr, ASSIGNMENT)?;
+        }
+        Ok(())
+    }
+    // Opens a parenthesis if an expression of the given precedence needs one, the
+    // returned flag tells to close it afterwards.
+    fn open(&mut self, precedence: u8) -> bool {
+        let paren = precedence < self.precedence;
+        if paren {
+            self.write("(");
+        }
+        paren
+    }
+    fn close(&mut self, paren: bool) {
+        if paren {
+            self.write(")");
+        }
+    }
+}
+
+// The operators of expressions, as they are written, and the precedence of binary ones
+pub(crate) fn binary_operator(operation: &BinaryOperator) -> (&'static str, u8) {
+    match operation {
+        BinaryOperator::Concatenate => ("@", CONCATENATE),
+        BinaryOperator::LogicalOr => ("||", LOGICAL_OR),
+        BinaryOperator::LogicalAnd => ("&&", LOGICAL_AND),
+        BinaryOperator::BitwiseOr => ("|", BITWISE_OR),
+        BinaryOperator::BitwiseXor => ("^", BITWISE_XOR),
+        BinaryOperator::BitwiseAnd => ("&", BITWISE_AND),
+        BinaryOperator::Equality => ("==", EQUALITY),
+        BinaryOperator::Inequality => ("!=", EQUALITY),
+        BinaryOperator::LessThan => ("<", RELATIONAL),
+        BinaryOperator::GreaterThan => (">", RELATIONAL),
+        BinaryOperator::LessThanEqual => ("<=", RELATIONAL),
+        BinaryOperator::GreaterThanEqual => (">=", RELATIONAL),
+        BinaryOperator::ShiftLeft => ("<<", SHIFT),
+        BinaryOperator::ShiftRight => (">>", SHIFT),
+        BinaryOperator::Add => ("+", ADDITIVE),
+        BinaryOperator::Subtract => ("-", ADDITIVE),
+        BinaryOperator::Multiply => ("*", MULTIPLICATIVE),
+        BinaryOperator::Divide => ("/", MULTIPLICATIVE),
+        BinaryOperator::Remainder => ("%", MULTIPLICATIVE),
+    }
+}
+
+pub(crate) fn assignment_operator(operation: &AssignmentOperator) -> &'static str {
+    match operation {
+        AssignmentOperator::Set => "=",
+        AssignmentOperator::Multiplied => "*=",
+        AssignmentOperator::Divided => "/=",
+        AssignmentOperator::Remained => "%=",
+        AssignmentOperator::Added => "+=",
+        AssignmentOperator::Subtracted => "-=",
+        AssignmentOperator::ShiftedLeft => "<<=",
+        AssignmentOperator::ShiftedRight => ">>=",
+        AssignmentOperator::BitwiseAnded => "&=",
+        AssignmentOperator::BitwiseXored => "^=",
+        AssignmentOperator::BitwiseOred => "|=",
+    }
+}
+
+impl Visitor for Printer {
+    fn visit_protocol_description(&mut self, h: &mut Heap, pd: RootId) -> VisitorResult {
+        let mut sections = 0;
+        for &pragma in h[pd].pragmas.iter() {
+            match &h[pragma].value {
+                PragmaValue::Version(version) => {
+                    self.out.push_str(&format!("#version {}\n", version));
+                }
+                PragmaValue::Module(name) => {
+                    self.out.push_str(&format!("#module {}\n", String::from_utf8_lossy(name)));
+                }
+                PragmaValue::Feature(name) => {
+                    self.out.push_str(&format!("#feature {}\n", String::from_utf8_lossy(name)));
+                }
+            }
+            sections = 1;
+        }
+        if !h[pd].imports.is_empty() && sections > 0 {
+            self.write("\n");
+        }
+        for &import in h[pd].imports.iter() {
+            self.out.push_str(&format!("import {};\n", String::from_utf8_lossy(&h[import].value)));
+            sections = 1;
+        }
+        for &def in h[pd].structs.iter() {
+            if sections > 0 {
+                self.write("\n");
+            }
+            self.write("struct ");
+            self.write_ident(h, h[def].identifier);
+            self.write(" {");
+            self.indent += 1;
+            for field in h[def].fields.iter() {
+                self.newline();
+                self.type_annotation(h, field.type_annotation);
+                self.write(" ");
+                self.write_ident(h, field.identifier);
+                self.write(";");
+            }
+            self.indent -= 1;
+            self.write("\n}\n");
+            sections += 1;
+        }
+        for &def in h[pd].enums.iter() {
+            if sections > 0 {
+                self.write("\n");
+            }
+            self.write("enum ");
+            self.write_ident(h, h[def].identifier);
+            self.write(" { ");
+            for (i, &variant) in h[def].variants.iter().enumerate() {
+                if i > 0 {
+                    self.write(", ");
+                }
+                self.write_ident(h, variant);
+            }
+            self.write(" }\n");
+            sections += 1;
+        }
+        for &def in h[pd].definitions.clone().iter() {
+            if sections > 0 {
+                self.write("\n");
+            }
+            self.visit_symbol_definition(h, def)?;
+            self.write("\n");
+            sections += 1;
+        }
+        Ok(())
+    }
+    fn visit_composite_definition(&mut self, h: &mut Heap, def: CompositeId) -> VisitorResult {
+        self.write("composite ");
+        self.write_ident(h, h[def].identifier);
+        self.parameters(h, &h[def].parameters);
+        self.definition_body(h, h[def].body)
+    }
+    fn visit_primitive_definition(&mut self, h: &mut Heap, def: PrimitiveId) -> VisitorResult {
+        self.write("primitive ");
+        self.write_ident(h, h[def].identifier);
+        self.parameters(h, &h[def].parameters);
+        self.definition_body(h, h[def].body)
+    }
+    fn visit_function_definition(&mut self, h: &mut Heap, def: FunctionId) -> VisitorResult {
+        self.type_annotation(h, h[def].return_type);
+        self.write(" ");
+        self.write_ident(h, h[def].identifier);
+        self.parameters(h, &h[def].parameters);
+        self.definition_body(h, h[def].body)
+    }
+
+    // Statements are printed from the current position up to their last character, the
+    // enclosing statement continues the line or starts a new one.
+    fn visit_block_statement(&mut self, h: &mut Heap, stmt: BlockStatementId) -> VisitorResult {
+        self.write("{");
+        self.indent += 1;
+        for &stmt in h[stmt].statements.clone().iter() {
+            self.newline();
+            self.visit_statement(h, stmt)?;
+        }
+        self.indent -= 1;
+        self.newline();
+        self.write("}");
+        Ok(())
+    }
+    fn visit_memory_statement(&mut self, h: &mut Heap, stmt: MemoryStatementId) -> VisitorResult {
+        let local = h[stmt].variable;
+        self.type_annotation(h, h[local].type_annotation);
+        self.write(" ");
+        self.write_ident(h, h[local].identifier);
+        self.write(" = ");
+        self.expression(h, h[stmt].initial, ASSIGNMENT)?;
+        self.write(";");
+        Ok(())
+    }
+    fn visit_channel_statement(&mut self, h: &mut Heap, stmt: ChannelStatementId) -> VisitorResult {
+        self.write("channel ");
+        self.write_ident(h, h[h[stmt].from].identifier);
+        self.write(" -> ");
+        self.write_ident(h, h[h[stmt].to].identifier);
+        self.write(";");
+        Ok(())
+    }
+    fn visit_labeled_statement(&mut self, h: &mut Heap, stmt: LabeledStatementId) -> VisitorResult {
+        self.write_ident(h, h[stmt].label);
+        self.write(": ");
+        self.visit_statement(h, h[stmt].body)
+    }
+    fn visit_skip_statement(&mut self, _h: &mut Heap, _stmt: SkipStatementId) -> VisitorResult {
+        self.write("skip;");
+        Ok(())
+    }
+    fn visit_if_statement(&mut self, h: &mut Heap, stmt: IfStatementId) -> VisitorResult {
+        let IfStatement { position, test, true_body, false_body, .. } = h[stmt].clone();
+        // Without else, the parser puts a skip statement at the position of the if. Such an
+        // if statement never ends the true body of another one with else, as the parser gives
+        // the else to the innermost if statement.
+        let implicit = match &h[false_body] {
+            Statement::Skip(skip) => skip.position == position,
+            _ => false,
+        };
+        self.write("if (");
+        self.expression(h, test, ASSIGNMENT)?;
+        self.write(")");
+        self.body(h, true_body)?;
+        if implicit {
+            return Ok(());
+        }
+        if Printer::is_block(h, true_body) {
+            self.write(" else");
+        } else {
+            self.newline();
+            self.write("else");
+        }
+        if matches!(&h[false_body], Statement::If(_)) {
+            self.write(" ");
+            self.visit_statement(h, false_body)
+        } else {
+            self.body(h, false_body)
+        }
+    }
+    fn visit_switch_statement(&mut self, h: &mut Heap, stmt: SwitchStatementId) -> VisitorResult {
+        self.write("switch (");
+        self.expression(h, h[stmt].subject, ASSIGNMENT)?;
+        self.write(") {");
+        self.indent += 1;
+        for case in h[stmt].cases.clone().iter() {
+            self.newline();
+            self.write("case ");
+            for (i, &variant) in case.variants.iter().enumerate() {
+                if i > 0 {
+                    self.write(", ");
+                }
+                self.write_ident(h, variant);
+            }
+            self.write(":");
+            self.body(h, case.body)?;
+        }
+        if let Some(default) = h[stmt].default {
+            self.newline();
+            self.write("default:");
+            self.body(h, default)?;
+        }
+        self.indent -= 1;
+        self.newline();
+        self.write("}");
+        Ok(())
+    }
+    fn visit_while_statement(&mut self, h: &mut Heap, stmt: WhileStatementId) -> VisitorResult {
+        self.write("while (");
+        self.expression(h, h[stmt].test, ASSIGNMENT)?;
+        self.write(")");
+        self.body(h, h[stmt].body)
+    }
+    fn visit_for_statement(&mut self, h: &mut Heap, stmt: ForStatementId) -> VisitorResult {
+        let local = h[stmt].variable;
+        self.write(if h[stmt].each { "foreach (" } else { "for (" });
+        self.type_annotation(h, h[local].type_annotation);
+        self.write(" ");
+        self.write_ident(h, h[local].identifier);
+        self.write(" : ");
+        self.expression(h, h[stmt].subject, ASSIGNMENT)?;
+        self.write(")");
+        self.body(h, h[stmt].body)
+    }
+    fn visit_break_statement(&mut self, h: &mut Heap, stmt: BreakStatementId) -> VisitorResult {
+        self.write("break");
+        if let Some(label) = h[stmt].label {
+            self.write(" ");
+            self.write_ident(h, label);
+        }
+        self.write(";");
+        Ok(())
+    }
+    fn visit_continue_statement(
+        &mut self,
+        h: &mut Heap,
+        stmt: ContinueStatementId,
+    ) -> VisitorResult {
+        self.write("continue");
+        if let Some(label) = h[stmt].label {
+            self.write(" ");
+            self.write_ident(h, label);
+        }
+        self.write(";");
+        Ok(())
+    }
+    fn visit_synchronous_statement(
+        &mut self,
+        h: &mut Heap,
+        stmt: SynchronousStatementId,
+    ) -> VisitorResult {
+        let body = h[stmt].body;
+        self.write("synchronous");
+        // Without parameters, the body must be a block or a skip statement
+        let bare = matches!(&h[body], Statement::Block(_) | Statement::Skip(_));
+        if !bare || !h[stmt].parameters.is_empty() {
+            self.write(" ");
+            self.parameters(h, &h[stmt].parameters);
+        }
+        self.body(h, body)
+    }
+    fn visit_return_statement(&mut self, h: &mut Heap, stmt: ReturnStatementId) -> VisitorResult {
+        // An opening parenthesis after return must enclose the whole expression
+        self.write("return ");
+        self.expression_avoiding(h, h[stmt].expression, '(')?;
+        self.write(";");
+        Ok(())
+    }
+    fn visit_assert_statement(&mut self, h: &mut Heap, stmt: AssertStatementId) -> VisitorResult {
+        self.write("assert ");
+        self.expression_avoiding(h, h[stmt].expression, '(')?;
+        self.write(";");
+        Ok(())
+    }
+    fn visit_goto_statement(&mut self, h: &mut Heap, stmt: GotoStatementId) -> VisitorResult {
+        self.write("goto ");
+        self.write_ident(h, h[stmt].label);
+        self.write(";");
+        Ok(())
+    }
+    fn visit_new_statement(&mut self, h: &mut Heap, stmt: NewStatementId) -> VisitorResult {
+        self.write("new ");
+        self.visit_call_expression(h, h[stmt].expression)?;
+        self.write(";");
+        Ok(())
+    }
+    fn visit_put_statement(&mut self, h: &mut Heap, stmt: PutStatementId) -> VisitorResult {
+        self.write("put(");
+        self.expression(h, h[stmt].port, ASSIGNMENT)?;
+        self.write(", ");
+        self.expression(h, h[stmt].message, ASSIGNMENT)?;
+        self.write(");");
+        Ok(())
+    }
+    fn visit_expression_statement(
+        &mut self,
+        h: &mut Heap,
+        stmt: ExpressionStatementId,
+    ) -> VisitorResult {
+        // An opening brace would start a block statement instead of an array
+        self.expression_avoiding(h, h[stmt].expression, '{')?;
+        self.write(";");
+        Ok(())
+    }
+
+    fn visit_assignment_expression(
+        &mut self,
+        h: &mut Heap,
+        expr: AssignmentExpressionId,
+    ) -> VisitorResult {
+        let paren = self.open(ASSIGNMENT);
+        // The left operand is parsed as a conditional expression, of which the false
+        // expression would take the assignment
+        self.expression(h, h[expr].left, CONCATENATE)?;
+        self.write(" ");
+        self.write(assignment_operator(&h[expr].operation));
+        self.write(" ");
+        self.expression(h, h[expr].right, ASSIGNMENT)?;
+        self.close(paren);
+        Ok(())
+    }
+    fn visit_conditional_expression(
+        &mut self,
+        h: &mut Heap,
+        expr: ConditionalExpressionId,
+    ) -> VisitorResult {
+        let paren = self.open(CONDITIONAL);
+        self.expression(h, h[expr].test, CONCATENATE)?;
+        self.write(" ? ");
+        self.expression(h, h[expr].true_expression, ASSIGNMENT)?;
+        self.write(" : ");
+        self.expression(h, h[expr].false_expression, ASSIGNMENT)?;
+        self.close(paren);
+        Ok(())
+    }
+    fn visit_binary_expression(&mut self, h: &mut Heap, expr: BinaryExpressionId) -> VisitorResult {
+        // Binary operators associate to the left
+        let (operator, precedence) = binary_operator(&h[expr].operation);
+        let paren = self.open(precedence);
+        self.expression(h, h[expr].left, precedence)?;
+        self.write(" ");
+        self.write(operator);
+        self.write(" ");
+        self.expression(h, h[expr].right, precedence + 1)?;
+        self.close(paren);
+        Ok(())
+    }
+    fn visit_unary_expression(&mut self, h: &mut Heap, expr: UnaryExpressionId) -> VisitorResult {
+        let UnaryExpression { operation, expression: operand, .. } = h[expr].clone();
+        let prefix = match operation {
+            UnaryOperation::Positive => "+",
+            UnaryOperation::Negative => "-",
+            UnaryOperation::BitwiseNot => "~",
+            UnaryOperation::LogicalNot => "!",
+            UnaryOperation::PreIncrement => "++",
+            UnaryOperation::PreDecrement => "--",
+            UnaryOperation::PostIncrement | UnaryOperation::PostDecrement => {
+                let paren = self.open(POSTFIX);
+                self.expression(h, operand, POSTFIX)?;
+                if operation == UnaryOperation::PostIncrement {
+                    self.write("++");
+                } else {
+                    self.write("--");
+                }
+                self.close(paren);
+                return Ok(());
+            }
+        };
+        let paren = self.open(PREFIX);
+        self.write(prefix);
+        // Consecutive prefix operators could be read as another one, as in - -x
+        let nested = match &h[operand] {
+            Expression::Unary(unary) => !matches!(
+                unary.operation,
+                UnaryOperation::PostIncrement | UnaryOperation::PostDecrement
+            ),
+            _ => false,
+        };
+        self.expression(h, operand, if nested { POSTFIX } else { PREFIX })?;
+        self.close(paren);
+        Ok(())
+    }
+    fn visit_cast_expression(&mut self, h: &mut Heap, expr: CastExpressionId) -> VisitorResult {
+        let paren = self.open(PREFIX);
+        self.write("(");
+        self.write(&h[expr].the_type.to_string());
+        self.write(") ");
+        self.expression(h, h[expr].expression, PREFIX)?;
+        self.close(paren);
+        Ok(())
+    }
+    fn visit_indexing_expression(
+        &mut self,
+        h: &mut Heap,
+        expr: IndexingExpressionId,
+    ) -> VisitorResult {
+        let paren = self.open(POSTFIX);
+        self.expression(h, h[expr].subject, POSTFIX)?;
+        self.write("[");
+        self.expression(h, h[expr].index, ASSIGNMENT)?;
+        self.write("]");
+        self.close(paren);
+        Ok(())
+    }
+    fn visit_slicing_expression(
+        &mut self,
+        h: &mut Heap,
+        expr: SlicingExpressionId,
+    ) -> VisitorResult {
+        let paren = self.open(POSTFIX);
+        self.expression(h, h[expr].subject, POSTFIX)?;
+        self.write("[");
+        self.expression(h, h[expr].from_index, ASSIGNMENT)?;
+        self.write(" : ");
+        self.expression(h, h[expr].to_index, ASSIGNMENT)?;
+        self.write("]");
+        self.close(paren);
+        Ok(())
+    }
+    fn visit_select_expression(&mut self, h: &mut Heap, expr: SelectExpressionId) -> VisitorResult {
+        let paren = self.open(POSTFIX);
+        self.expression(h, h[expr].subject, POSTFIX)?;
+        match h[expr].field {
+            Field::Length => self.write(".length"),
+            Field::Symbolic(field) => {
+                self.write(".");
+                self.write_ident(h, field);
+            }
+        }
+        self.close(paren);
+        Ok(())
+    }
+    fn visit_array_expression(&mut self, h: &mut Heap, expr: ArrayExpressionId) -> VisitorResult {
+        self.write("{");
+        self.expressions(h, &h[expr].elements.clone())?;
+        self.write("}");
+        Ok(())
+    }
+    fn visit_struct_expression(&mut self, h: &mut Heap, expr: StructExpressionId) -> VisitorResult {
+        self.write_ident(h, h[expr].identifier);
+        if h[expr].fields.is_empty() {
+            self.write(" {}");
+            return Ok(());
+        }
+        self.write(" { ");
+        for (i, &(field, value)) in h[expr].fields.clone().iter().enumerate() {
+            if i > 0 {
+                self.write(", ");
+            }
+            self.write_ident(h, field);
+            self.write(": ");
+            self.expression(h, value, ASSIGNMENT)?;
+        }
+        self.write(" }");
+        Ok(())
+    }
+    fn visit_call_expression(&mut self, h: &mut Heap, expr: CallExpressionId) -> VisitorResult {
+        match h[expr].method {
+            Method::Get => self.write("get"),
+            Method::Fires => self.write("fires"),
+            Method::Create => self.write("create"),
+            Method::Random => self.write("random"),
+            Method::Choose => self.write("choose"),
+            Method::Symbolic(id) => self.write_ident(h, id),
+        }
+        self.write("(");
+        self.expressions(h, &h[expr].arguments.clone())?;
+        self.write(")");
+        Ok(())
+    }
+    fn visit_enum_expression(&mut self, h: &mut Heap, expr: EnumExpressionId) -> VisitorResult {
+        self.write_ident(h, h[expr].identifier);
+        self.write("::");
+        self.write_ident(h, h[expr].variant);
+        Ok(())
+    }
+    fn visit_constant_expression(
+        &mut self,
+        h: &mut Heap,
+        expr: ConstantExpressionId,
+    ) -> VisitorResult {
+        match &h[expr].value {
+            Constant::Null => self.write("null"),
+            Constant::True => self.write("true"),
+            Constant::False => self.write("false"),
+            Constant::Character(data) => {
+                self.out.push_str(&format!("'{}'", String::from_utf8_lossy(data)));
+            }
+            Constant::Integer(data) => self.out.push_str(&String::from_utf8_lossy(data)),
+        }
+        Ok(())
+    }
+    fn visit_variable_expression(
+        &mut self,
+        h: &mut Heap,
+        expr: VariableExpressionId,
+    ) -> VisitorResult {
+        self.write_ident(h, h[expr].identifier);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate test_generator;
+
+    use std::path::Path;
+
+    use test_generator::test_resources;
+
+    use super::*;
+    use crate::protocol::parser::Parser;
+
+    // Lexes a protocol description into its tree, and returns the shape of that tree
+    fn shape(source: &mut InputSource) -> Vec<String> {
+        let mut h = Heap::new();
+        let mut lex = Lexer::new(source);
+        let pd = lex.consume_protocol_description(&mut h).unwrap();
+        assert!(lex.errors().is_empty());
+        let mut shape = Shape { out: Vec::new() };
+        shape.visit_protocol_description(&mut h, pd).unwrap();
+        shape.out
+    }
+
+    /* Records the shape of a tree: every node in the order of a walk, by its kind and the
+    parts of it that are not other nodes, such as identifiers, types, operators and constants.
+    Positions and ids are left out, so that a tree and its re-parsed printed form have the
+    same shape. Top-level definitions are recorded per kind, as the printer reorders them. */
+    struct Shape {
+        out: Vec<String>,
+    }
+
+    impl Shape {
+        fn push(&mut self, token: impl ToString) {
+            self.out.push(token.to_string());
+        }
+        fn ident(&mut self, h: &Heap, id: SourceIdentifierId) {
+            self.push(h[id].to_string());
+        }
+        fn type_annotation(&mut self, h: &Heap, id: TypeAnnotationId) {
+            self.push(h[id].the_type.to_string());
+        }
+        // The kind of a statement or expression is the name of its variant
+        fn kind(node: String) -> String {
+            let end = node.find('(').unwrap_or(node.len());
+            format!("({}", &node[..end])
+        }
+        fn definition(
+            &mut self,
+            h: &mut Heap,
+            params: Vec<ParameterId>,
+            body: StatementId,
+        ) -> VisitorResult {
+            for param in params {
+                self.visit_variable_declaration(h, param.upcast())?;
+            }
+            self.visit_statement(h, body)?;
+            self.push(")");
+            Ok(())
+        }
+    }
+
+    impl Visitor for Shape {
+        fn visit_protocol_description(&mut self, h: &mut Heap, pd: RootId) -> VisitorResult {
+            for &pragma in h[pd].pragmas.clone().iter() {
+                self.visit_pragma(h, pragma)?;
+            }
+            for &import in h[pd].imports.clone().iter() {
+                self.visit_import(h, import)?;
+            }
+            for &def in h[pd].structs.iter() {
+                self.push("(struct");
+                self.ident(h, h[def].identifier);
+                for field in h[def].fields.iter() {
+                    self.type_annotation(h, field.type_annotation);
+                    self.ident(h, field.identifier);
+                }
+                self.push(")");
+            }
+            for &def in h[pd].enums.iter() {
+                self.push("(enum");
+                self.ident(h, h[def].identifier);
+                for &variant in h[def].variants.iter() {
+                    self.ident(h, variant);
+                }
+                self.push(")");
+            }
+            for &def in h[pd].definitions.clone().iter() {
+                self.visit_symbol_definition(h, def)?;
+            }
+            Ok(())
+        }
+        fn visit_pragma(&mut self, h: &mut Heap, pragma: PragmaId) -> VisitorResult {
+            self.push(format!("(pragma {:?})", h[pragma].value));
+            Ok(())
+        }
+        fn visit_import(&mut self, h: &mut Heap, import: ImportId) -> VisitorResult {
+            self.push(format!("(import {})", String::from_utf8_lossy(&h[import].value)));
+            Ok(())
+        }
+        fn visit_composite_definition(&mut self, h: &mut Heap, def: CompositeId) -> VisitorResult {
+            self.push("(composite");
+            self.ident(h, h[def].identifier);
+            self.definition(h, h[def].parameters.clone(), h[def].body)
+        }
+        fn visit_primitive_definition(&mut self, h: &mut Heap, def: PrimitiveId) -> VisitorResult {
+            self.push("(primitive");
+            self.ident(h, h[def].identifier);
+            self.definition(h, h[def].parameters.clone(), h[def].body)
+        }
+        fn visit_function_definition(&mut self, h: &mut Heap, def: FunctionId) -> VisitorResult {
+            self.push("(function");
+            self.type_annotation(h, h[def].return_type);
+            self.ident(h, h[def].identifier);
+            self.definition(h, h[def].parameters.clone(), h[def].body)
+        }
+        fn visit_parameter_declaration(
+            &mut self,
+            h: &mut Heap,
+            decl: ParameterId,
+        ) -> VisitorResult {
+            self.push("(parameter");
+            self.type_annotation(h, h[decl].type_annotation);
+            self.ident(h, h[decl].identifier);
+            self.push(")");
+            Ok(())
+        }
+        fn visit_local_declaration(&mut self, h: &mut Heap, decl: LocalId) -> VisitorResult {
+            self.push("(local");
+            self.type_annotation(h, h[decl].type_annotation);
+            self.ident(h, h[decl].identifier);
+            self.push(")");
+            Ok(())
+        }
+
+        fn visit_statement(&mut self, h: &mut Heap, stmt: StatementId) -> VisitorResult {
+            self.push(Shape::kind(format!("{:?}", h[stmt])));
+            match h[stmt].clone() {
+                Statement::Block(stmt) => self.visit_block_statement(h, stmt.this),
+                Statement::Local(stmt) => self.visit_local_statement(h, stmt.this()),
+                Statement::Skip(stmt) => self.visit_skip_statement(h, stmt.this),
+                Statement::Labeled(stmt) => self.visit_labeled_statement(h, stmt.this),
+                Statement::If(stmt) => self.visit_if_statement(h, stmt.this),
+                Statement::Switch(stmt) => self.visit_switch_statement(h, stmt.this),
+                Statement::While(stmt) => self.visit_while_statement(h, stmt.this),
+                Statement::For(stmt) => self.visit_for_statement(h, stmt.this),
+                Statement::Break(stmt) => self.visit_break_statement(h, stmt.this),
+                Statement::Continue(stmt) => self.visit_continue_statement(h, stmt.this),
+                Statement::Synchronous(stmt) => self.visit_synchronous_statement(h, stmt.this),
+                Statement::Return(stmt) => self.visit_return_statement(h, stmt.this),
+                Statement::Assert(stmt) => self.visit_assert_statement(h, stmt.this),
+                Statement::Goto(stmt) => self.visit_goto_statement(h, stmt.this),
+                Statement::New(stmt) => self.visit_new_statement(h, stmt.this),
+                Statement::Put(stmt) => self.visit_put_statement(h, stmt.this),
+                Statement::Expression(stmt) => self.visit_expression_statement(h, stmt.this),
+                Statement::EndIf(_)
+                | Statement::EndWhile(_)
+                | Statement::EndFor(_)
+                | Statement::EndSynchronous(_) => unreachable!(), // pseudo-statements
+            }?;
+            self.push(")");
+            Ok(())
+        }
+        fn visit_memory_statement(
+            &mut self,
+            h: &mut Heap,
+            stmt: MemoryStatementId,
+        ) -> VisitorResult {
+            self.visit_local_declaration(h, h[stmt].variable)?;
+            self.visit_expression(h, h[stmt].initial)
+        }
+        fn visit_channel_statement(
+            &mut self,
+            h: &mut Heap,
+            stmt: ChannelStatementId,
+        ) -> VisitorResult {
+            self.visit_local_declaration(h, h[stmt].from)?;
+            self.visit_local_declaration(h, h[stmt].to)
+        }
+        fn visit_labeled_statement(
+            &mut self,
+            h: &mut Heap,
+            stmt: LabeledStatementId,
+        ) -> VisitorResult {
+            self.ident(h, h[stmt].label);
+            self.visit_statement(h, h[stmt].body)
+        }
+        fn visit_switch_statement(
+            &mut self,
+            h: &mut Heap,
+            stmt: SwitchStatementId,
+        ) -> VisitorResult {
+            self.visit_expression(h, h[stmt].subject)?;
+            for case in h[stmt].cases.clone() {
+                self.push("(case");
+                for variant in case.variants {
+                    self.ident(h, variant);
+                }
+                self.visit_statement(h, case.body)?;
+                self.push(")");
+            }
+            if let Some(default) = h[stmt].default {
+                self.push("default");
+                self.visit_statement(h, default)?;
+            }
+            Ok(())
+        }
+        fn visit_for_statement(&mut self, h: &mut Heap, stmt: ForStatementId) -> VisitorResult {
+            self.push(h[stmt].each);
+            self.visit_local_declaration(h, h[stmt].variable)?;
+            self.visit_expression(h, h[stmt].subject)?;
+            self.visit_statement(h, h[stmt].body)
+        }
+        fn visit_break_statement(&mut self, h: &mut Heap, stmt: BreakStatementId) -> VisitorResult {
+            if let Some(label) = h[stmt].label {
+                self.ident(h, label);
+            }
+            Ok(())
+        }
+        fn visit_continue_statement(
+            &mut self,
+            h: &mut Heap,
+            stmt: ContinueStatementId,
+        ) -> VisitorResult {
+            if let Some(label) = h[stmt].label {
+                self.ident(h, label);
+            }
+            Ok(())
+        }
+        fn visit_goto_statement(&mut self, h: &mut Heap, stmt: GotoStatementId) -> VisitorResult {
+            self.ident(h, h[stmt].label);
+            Ok(())
+        }
+
+        fn visit_expression(&mut self, h: &mut Heap, expr: ExpressionId) -> VisitorResult {
+            self.push(Shape::kind(format!("{:?}", h[expr])));
+            match h[expr].clone() {
+                Expression::Assignment(expr) => self.visit_assignment_expression(h, expr.this),
+                Expression::Conditional(expr) => self.visit_conditional_expression(h, expr.this),
+                Expression::Binary(expr) => self.visit_binary_expression(h, expr.this),
+                Expression::Unary(expr) => self.visit_unary_expression(h, expr.this),
+                Expression::Cast(expr) => self.visit_cast_expression(h, expr.this),
+                Expression::Indexing(expr) => self.visit_indexing_expression(h, expr.this),
+                Expression::Slicing(expr) => self.visit_slicing_expression(h, expr.this),
+                Expression::Select(expr) => self.visit_select_expression(h, expr.this),
+                Expression::Array(expr) => self.visit_array_expression(h, expr.this),
+                Expression::Struct(expr) => self.visit_struct_expression(h, expr.this),
+                Expression::Enum(expr) => self.visit_enum_expression(h, expr.this),
+                Expression::Constant(expr) => self.visit_constant_expression(h, expr.this),
+                Expression::Call(expr) => self.visit_call_expression(h, expr.this),
+                Expression::Variable(expr) => self.visit_variable_expression(h, expr.this),
+            }?;
+            self.push(")");
+            Ok(())
+        }
+        fn visit_assignment_expression(
+            &mut self,
+            h: &mut Heap,
+            expr: AssignmentExpressionId,
+        ) -> VisitorResult {
+            self.push(assignment_operator(&h[expr].operation));
+            self.visit_expression(h, h[expr].left)?;
+            self.visit_expression(h, h[expr].right)
+        }
+        fn visit_binary_expression(
+            &mut self,
+            h: &mut Heap,
+            expr: BinaryExpressionId,
+        ) -> VisitorResult {
+            self.push(binary_operator(&h[expr].operation).0);
+            self.visit_expression(h, h[expr].left)?;
+            self.visit_expression(h, h[expr].right)
+        }
+        fn visit_unary_expression(
+            &mut self,
+            h: &mut Heap,
+            expr: UnaryExpressionId,
+        ) -> VisitorResult {
+            self.push(format!("{:?}", h[expr].operation));
+            self.visit_expression(h, h[expr].expression)
+        }
+        fn visit_cast_expression(&mut self, h: &mut Heap, expr: CastExpressionId) -> VisitorResult {
+            self.push(h[expr].the_type.to_string());
+            self.visit_expression(h, h[expr].expression)
+        }
+        fn visit_select_expression(
+            &mut self,
+            h: &mut Heap,
+            expr: SelectExpressionId,
+        ) -> VisitorResult {
+            match h[expr].field {
+                Field::Length => self.push("length"),
+                Field::Symbolic(field) => self.ident(h, field),
+            }
+            self.visit_expression(h, h[expr].subject)
+        }
+        fn visit_struct_expression(
+            &mut self,
+            h: &mut Heap,
+            expr: StructExpressionId,
+        ) -> VisitorResult {
+            self.ident(h, h[expr].identifier);
+            for (field, value) in h[expr].fields.clone() {
+                self.ident(h, field);
+                self.visit_expression(h, value)?;
+            }
+            Ok(())
+        }
+        fn visit_call_expression(&mut self, h: &mut Heap, expr: CallExpressionId) -> VisitorResult {
+            match h[expr].method {
+                Method::Symbolic(name) => self.ident(h, name),
+                ref method => self.push(format!("{:?}", method)),
+            }
+            for argument in h[expr].arguments.clone() {
+                self.visit_expression(h, argument)?;
+            }
+            Ok(())
+        }
+        fn visit_enum_expression(&mut self, h: &mut Heap, expr: EnumExpressionId) -> VisitorResult {
+            self.ident(h, h[expr].identifier);
+            self.ident(h, h[expr].variant);
+            Ok(())
+        }
+        fn visit_constant_expression(
+            &mut self,
+            h: &mut Heap,
+            expr: ConstantExpressionId,
+        ) -> VisitorResult {
+            self.push(format!("{:?}", h[expr].value));
+            Ok(())
+        }
+        fn visit_variable_expression(
+            &mut self,
+            h: &mut Heap,
+            expr: VariableExpressionId,
+        ) -> VisitorResult {
+            self.ident(h, h[expr].identifier);
+            Ok(())
+        }
+    }
+
+    // The printed form of a description parses to a tree of the same shape, and prints the
+    // same again
+    #[test_resources("testdata/parser/positive/*.pdl")]
+    fn round_trip(resource: &str) {
+        let mut source = InputSource::from_file(Path::new(resource)).unwrap();
+        let printed = format(&mut source).unwrap();
+        println!("{}", printed);
+        let mut source = InputSource::from_file(Path::new(resource)).unwrap();
+        let mut reparsed = InputSource::from_string(&printed).unwrap();
+        assert_eq!(shape(&mut source), shape(&mut reparsed));
+        let mut source = InputSource::from_string(&printed).unwrap();
+        assert_eq!(printed, format(&mut source).unwrap());
+        // The printed description passes all checks, like the original
+        let mut source = InputSource::from_string(&printed).unwrap();
+        if let Err(err) = Parser::new(&mut source).parse(&mut Heap::new()) {
+            panic!("{}", err.display(&source));
+        }
+    }
+
+    #[test]
+    fn precedence() {
+        let pdl = b"int f(int a, int b) { a=(a+b)*2; return -(-a)-(b-a)+(a=b)?a:b; }
+            int g(int a) { return ((a+1)*2); }
+            byte h(long a) { return (byte)(a+1)*(byte)-a; }";
+        let mut source = InputSource::from_buffer(pdl).unwrap();
+        let expected = "int f(int a, int b) {
+\ta = (a + b) * 2;
+\treturn -(-a) - (b - a) + (a = b) ? a : b;
+}
+
+int g(int a) {
+\treturn ((a + 1) * 2);
+}
+
+byte h(long a) {
+\treturn (byte) (a + 1) * (byte) -a;
+}
+";
+        assert_eq!(expected, format(&mut source).unwrap());
+    }
+}
@@ -0,0 +1,88 @@
+use crate::protocol::analysis::{port_variables, reachable_statements};
+use crate::protocol::ast::*;
+use crate::protocol::automaton::dot_string;
+
+use std::collections::HashMap;
+
+/* Draws a composite component as a Graphviz DOT graph. Its port parameters are the boundary of
+the graph, the components it creates are boxes labeled by their definitions, and the channels it
+creates are edges from the component that holds the output of the channel to the one that holds
+its input. A port is held by every component it is passed to, so that creations in different
+branches each show up. A channel end that is passed to no component is drawn as a point. */
+pub fn topology(h: &Heap, def: DefinitionId) -> String {
+    let mut out = format!("digraph {} {{\n", dot_string(&h[h[def].identifier()].to_string()));
+    out.push_str("\trankdir=LR;\n");
+    // The nodes that hold every port
+    let mut holders: HashMap<VariableId, Vec<String>> = HashMap::new();
+    let mut channels = Vec::new();
+    let mut created = 0;
+    for stmt in reachable_statements(h, h[def].body()) {
+        match &h[stmt] {
+            Statement::Local(LocalStatement::Channel(channel)) => channels.push(channel),
+            Statement::New(new) => {
+                let call = &h[new.expression];
+                let callee = match call.method {
+                    Method::Symbolic(id) => h[id].to_string(),
+                    _ => unreachable!(),
+                };
+                let node = format!("c{}", created);
+                created += 1;
+                let pos = new.position;
+                let label = dot_string(&format!("{}\n{}:{}", callee, pos.line(), pos.column()));
+                out.push_str(&format!("\t{} [shape=box, label={}];\n", node, label));
+                for &arg in call.arguments.iter() {
+                    for var in port_variables(h, arg) {
+                        let nodes = holders.entry(var).or_default();
+                        if !nodes.contains(&node) {
+                            nodes.push(node.clone());
+                        }
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+    let name = |var: VariableId| h[h[var].identifier()].to_string();
+    let mut edges = Vec::new();
+    for (index, &param) in h[def].parameters().iter().enumerate() {
+        let var = param.upcast();
+        let node = format!("p{}", index);
+        let label = dot_string(&name(var));
+        out.push_str(&format!("\t{} [shape=plaintext, label={}];\n", node, label));
+        for holder in holders.get(&var).into_iter().flatten() {
+            match h[var].the_type(h).primitive {
+                PrimitiveType::Input => edges.push((node.clone(), holder.clone(), label.clone())),
+                _ => edges.push((holder.clone(), node.clone(), label.clone())),
+            }
+        }
+    }
+    let mut ends = 0;
+    for channel in channels {
+        let label = dot_string(&format!(
+            "{} -> {}",
+            name(channel.from.upcast()),
+            name(channel.to.upcast())
+        ));
+        let mut end = |var: VariableId, out: &mut String| match holders.get(&var) {
+            Some(nodes) => nodes.clone(),
+            None => {
+                let node = format!("e{}", ends);
+                ends += 1;
+                out.push_str(&format!("\t{} [shape=point];\n", node));
+                vec![node]
+            }
+        };
+        let from = end(channel.from.upcast(), &mut out);
+        let to = end(channel.to.upcast(), &mut out);
+        for from in from.iter() {
+            for to in to.iter() {
+                edges.push((from.clone(), to.clone(), label.clone()));
+            }
+        }
+    }
+    for (from, to, label) in edges {
+        out.push_str(&format!("\t{} -> {} [label={}];\n", from, to, label));
+    }
+    out.push_str("}\n");
+    out
+}
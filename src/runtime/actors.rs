@@ -34,6 +34,8 @@ pub(crate) struct BranchP {
     pub blocking_on: Option<Port>,
     pub outbox: HashMap<Port, Payload>,
     pub inbox: HashMap<Port, Payload>,
+    // oracle variables of the predicate, deciding the nondeterministic choices in order
+    pub choices: Vec<ChannelId>,
     pub state: ProtocolS,
 }
 
@@ -44,7 +46,7 @@ impl PolyP {
         &mut self,
         m_ctx: PolyPContext,
         protocol_description: &ProtocolD,
-    ) -> Result<SyncRunResult, EndpointErr> {
+    ) -> Result<SyncRunResult, SyncErr> {
         let to_run: Vec<_> = self.incomplete.drain().collect();
         self.poly_run_these_branches(m_ctx, protocol_description, to_run)
     }
@@ -54,7 +56,7 @@ impl PolyP {
         mut m_ctx: PolyPContext,
         protocol_description: &ProtocolD,
         mut to_run: Vec<(Predicate, BranchP)>,
-    ) -> Result<SyncRunResult, EndpointErr> {
+    ) -> Result<SyncRunResult, SyncErr> {
         use SyncRunResult as Srr;
         log!(&mut m_ctx.inner.logger, "~ Running branches for PolyP {:?}!", m_ctx.my_subtree_id,);
         'to_run_loop: while let Some((mut predicate, mut branch)) = to_run.pop() {
@@ -63,6 +65,7 @@ impl PolyP {
                 ports: &self.ports,
                 predicate: &predicate,
                 inbox: &branch.inbox,
+                choices: &branch.choices,
             };
             use PolyBlocker as Sb;
             let blocker = branch.state.sync_run(&mut r_ctx, protocol_description);
@@ -75,6 +78,10 @@ impl PolyP {
             );
             match blocker {
                 Sb::Inconsistent => {} // DROP
+                Sb::StepBudgetExhausted => {
+                    return Err(SyncErr::EvalErr(EvalErr::StepBudgetExhausted))
+                }
+                Sb::Fault(message) => return Err(SyncErr::EvalErr(EvalErr::Fault(message))),
                 Sb::CouldntReadMsg(port) => {
                     assert!(self.ports.contains(&port));
                     let channel_id =
@@ -111,6 +118,23 @@ impl PolyP {
                     to_run.push((predicate, branch));
                     to_run.push((predicate_f, branch_f));
                 }
+                Sb::CouldntChoose => {
+                    // split the branch on a fresh oracle variable, which no channel shares
+                    let oracle = r_ctx.m_ctx.inner.channel_id_stream.next();
+                    log!(
+                        &mut r_ctx.m_ctx.inner.logger,
+                        "~ ... {:?} choosing with oracle {:?}",
+                        r_ctx.m_ctx.my_subtree_id,
+                        oracle,
+                    );
+                    branch.choices.push(oracle);
+                    let branch_f = branch.clone();
+                    let mut predicate_f = predicate.clone();
+                    assert!(predicate_f.replace_assignment(oracle, false).is_none());
+                    assert!(predicate.replace_assignment(oracle, true).is_none());
+                    to_run.push((predicate, branch));
+                    to_run.push((predicate_f, branch_f));
+                }
                 Sb::SyncBlockEnd => {
                     let ControllerInner { logger, endpoint_exts, .. } = m_ctx.inner;
                     log!(
@@ -214,7 +238,7 @@ impl PolyP {
         port: Port,
         payload_predicate: Predicate,
         payload: Payload,
-    ) -> Result<SyncRunResult, EndpointErr> {
+    ) -> Result<SyncRunResult, SyncErr> {
         // try exact match
 
         let to_run = if self.complete.contains_key(&payload_predicate) {
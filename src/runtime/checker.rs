@@ -0,0 +1,428 @@
+use crate::common::*;
+use crate::runtime::{errors::*, *};
+
+use std::collections::BTreeSet;
+
+/// Bounds and inputs of an offline check of a protocol description.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CheckOptions {
+    /// Arguments for the parameters of the main component, as for `Connector::configure_with_args`.
+    pub args: Option<Vec<ComponentArg>>,
+    /// The number of synchronous rounds that are explored from the start, at most.
+    pub max_rounds: usize,
+    /// The messages that the environment may put on the ports that the main component gets
+    /// from. Payloads are abstracted to these: no others are tried.
+    pub payloads: Vec<Vec<u8>>,
+    /// The steps each component may take in a round, as for `Connector::set_step_budget`.
+    pub step_budget: Option<usize>,
+}
+/// A synchronous round, in which the ports of the main component with the given indices
+/// fired with the given messages. Ports that are not listed were silent.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Default)]
+pub struct Round {
+    pub fired: Vec<(usize, Vec<u8>)>,
+}
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ViolationKind {
+    /// No round can complete, not even one in which all ports are silent.
+    Deadlock,
+    /// An assertion failed outside of a synchronous block. Inside one, a failing
+    /// assertion only rules out the round that the component was attempting.
+    Inconsistent,
+    Fault(String),
+    StepBudgetExhausted,
+}
+/// A problem found by a check, with a counterexample trace of the rounds leading to it.
+/// For a deadlock, these are the completed rounds after which no round can complete.
+/// Otherwise, the last round is the one that the problem occurred in, with the ports
+/// that had fired before it did.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Violation {
+    pub kind: ViolationKind,
+    pub trace: Vec<Round>,
+}
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CheckReport {
+    /// The number of distinct states between rounds that were reached.
+    pub states: usize,
+    /// Whether all reachable states were explored, rather than some cut off by `max_rounds`.
+    pub exhaustive: bool,
+    /// The violation with the shortest trace, if any was found.
+    pub violation: Option<Violation>,
+}
+
+// The state of the system between rounds: the components with the ports they own, and the
+// channels of all ports. The ports of the main component are channels with the environment.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+struct State {
+    components: Vec<(ProtocolS, BTreeSet<Port>)>,
+    // The putter of the channel of every port, which identifies the channel
+    putters: BTreeMap<Port, Port>,
+    next_port: usize,
+    randoms: u64,
+}
+
+// A component during a round, in one of the branches of the system
+#[derive(Debug, Clone)]
+struct Part {
+    state: ProtocolS,
+    ports: BTreeSet<Port>,
+    inbox: HashMap<Port, Payload>,
+    outbox: HashSet<Port>,
+    // Oracle variables of the predicate, deciding the nondeterministic choices in order
+    choices: Vec<ChannelId>,
+    status: Status,
+}
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Status {
+    Running,
+    Blocked(Port),
+    Done,
+}
+
+// A branch of the whole system during a round. Unlike in the runtime, the components do not
+// branch separately: their messages are only delivered within the branch that put them.
+#[derive(Debug, Clone)]
+struct Branch {
+    predicate: Predicate,
+    messages: HashMap<ChannelId, Payload>,
+    parts: Vec<Part>,
+    oracles: ChannelIndex,
+}
+
+// A reached state, with the index of the state and the round it was reached from, and the
+// number of rounds it takes to reach it
+type Reached = (State, Option<(usize, Round)>, usize);
+
+struct Checker<'a> {
+    pd: &'a ProtocolD,
+    options: &'a CheckOptions,
+    // The number of ports of the main component, which are the first ports
+    boundary: usize,
+}
+
+struct MonoCheckContext<'a> {
+    state: &'a mut State,
+    ports: &'a mut BTreeSet<Port>,
+    created: &'a mut Vec<(ProtocolS, BTreeSet<Port>)>,
+    step_budget: Option<usize>,
+}
+struct PolyCheckContext<'a> {
+    predicate: &'a Predicate,
+    putters: &'a BTreeMap<Port, Port>,
+    inbox: &'a HashMap<Port, Payload>,
+    choices: &'a [ChannelId],
+    step_budget: Option<usize>,
+}
+
+/// Explores all synchronous rounds that the main component of the protocol description can
+/// take, breadth-first up to `max_rounds` of them, to find the problems that the runtime would
+/// run into. The environment may put any of the given payloads on the ports that the main
+/// component gets from, and may get from the others, in any round.
+pub(crate) fn check(
+    pd: &ProtocolD,
+    main_component: &[u8],
+    options: &CheckOptions,
+) -> Result<CheckReport, ConfigErr> {
+    let boundary = pd.component_polarities(main_component, options.args.as_deref())?.len();
+    let ports: Vec<Port> = (0..boundary).map(Port::from_raw).collect();
+    let main = pd.new_main_component(main_component, options.args.as_deref(), &ports);
+    let initial = State {
+        components: vec![(main, ports.iter().copied().collect())],
+        putters: ports.iter().map(|&port| (port, port)).collect(),
+        next_port: ports.len(),
+        randoms: 0,
+    };
+    let checker = Checker { pd, options, boundary };
+    let mut states: Vec<Reached> = vec![(initial.clone(), None, 0)];
+    let mut indices: HashMap<State, usize> = hashmap! { initial => 0 };
+    let mut exhaustive = true;
+    let mut index = 0;
+    while index < states.len() {
+        let (state, _, depth) = &states[index];
+        if *depth == options.max_rounds {
+            exhaustive = false;
+            index += 1;
+            continue;
+        }
+        let depth = *depth;
+        let violation = match checker.round(state) {
+            Err((kind, round)) => {
+                let mut trace = trace(&states, index);
+                trace.push(round);
+                Some(Violation { kind, trace })
+            }
+            Ok(rounds) if rounds.is_empty() => {
+                Some(Violation { kind: ViolationKind::Deadlock, trace: trace(&states, index) })
+            }
+            Ok(rounds) => {
+                for (round, next) in rounds {
+                    if !indices.contains_key(&next) {
+                        indices.insert(next.clone(), states.len());
+                        states.push((next, Some((index, round)), depth + 1));
+                    }
+                }
+                None
+            }
+        };
+        if violation.is_some() {
+            return Ok(CheckReport { states: states.len(), exhaustive: false, violation });
+        }
+        index += 1;
+    }
+    Ok(CheckReport { states: states.len(), exhaustive, violation: None })
+}
+
+// The rounds that lead from the initial state to the state with the given index
+fn trace(states: &[Reached], mut index: usize) -> Vec<Round> {
+    let mut trace = Vec::new();
+    while let Some((parent, round)) = &states[index].1 {
+        trace.push(round.clone());
+        index = *parent;
+    }
+    trace.reverse();
+    trace
+}
+
+fn channel_id(putters: &BTreeMap<Port, Port>, port: Port) -> ChannelId {
+    ChannelId { controller_id: 0, channel_index: putters[&port].to_raw() as ChannelIndex }
+}
+
+impl Checker<'_> {
+    // All rounds that can complete in the given state, with the states they result in,
+    // or the first problem that occurs in one of them
+    fn round(&self, state: &State) -> Result<Vec<(Round, State)>, (ViolationKind, Round)> {
+        let mut next = State { components: Vec::new(), ..state.clone() };
+        let mut todo = state.components.clone();
+        let mut parts = Vec::new();
+        while let Some((mut component, mut ports)) = todo.pop() {
+            let mut created = Vec::new();
+            let mut context = MonoCheckContext {
+                state: &mut next,
+                ports: &mut ports,
+                created: &mut created,
+                step_budget: self.options.step_budget,
+            };
+            let blocker = component.pre_sync_run(&mut context, self.pd);
+            todo.extend(created);
+            let kind = match blocker {
+                MonoBlocker::SyncBlockStart => {
+                    parts.push(Part {
+                        state: component,
+                        ports,
+                        inbox: HashMap::new(),
+                        outbox: HashSet::new(),
+                        choices: Vec::new(),
+                        status: Status::Running,
+                    });
+                    continue;
+                }
+                MonoBlocker::ComponentExit => continue,
+                MonoBlocker::Inconsistent => ViolationKind::Inconsistent,
+                MonoBlocker::StepBudgetExhausted => ViolationKind::StepBudgetExhausted,
+                MonoBlocker::Fault(message) => ViolationKind::Fault(message),
+            };
+            return Err((kind, Round::default()));
+        }
+        let branch = Branch {
+            predicate: Predicate::new_trivial(),
+            messages: HashMap::new(),
+            parts,
+            oracles: 0,
+        };
+        let mut rounds = Vec::new();
+        let mut seen = HashSet::new();
+        let mut to_run = vec![branch];
+        while let Some(branch) = to_run.pop() {
+            if let Some(branch) = self.run(branch, &next, &mut to_run)? {
+                let round = self.fired(&branch, &next);
+                let components =
+                    branch.parts.into_iter().map(|part| (part.state, part.ports)).collect();
+                let state = State { components, ..next.clone() };
+                if seen.insert((round.clone(), state.clone())) {
+                    rounds.push((round, state));
+                }
+            }
+        }
+        Ok(rounds)
+    }
+    // Runs the components of the branch until they all complete the round, resulting in the
+    // completed branch. Forks of the branch are added to the ones to run.
+    fn run(
+        &self,
+        mut branch: Branch,
+        state: &State,
+        to_run: &mut Vec<Branch>,
+    ) -> Result<Option<Branch>, (ViolationKind, Round)> {
+        let putters = &state.putters;
+        loop {
+            // A blocked component continues once the message it waits for is put
+            let runnable = branch.parts.iter().position(|part| match part.status {
+                Status::Running => true,
+                Status::Blocked(port) => branch.messages.contains_key(&channel_id(putters, port)),
+                Status::Done => false,
+            });
+            let index = match runnable {
+                Some(index) => index,
+                None if branch.parts.iter().all(|part| part.status == Status::Done) => {
+                    return Ok(Some(branch))
+                }
+                // The rest wait for messages that are never put
+                None => return Ok(None),
+            };
+            let part = &mut branch.parts[index];
+            if let Status::Blocked(port) = part.status {
+                let payload = branch.messages[&channel_id(putters, port)].clone();
+                part.inbox.insert(port, payload);
+                part.status = Status::Running;
+            }
+            let Part { state: component, inbox, choices, .. } = part;
+            let mut context = PolyCheckContext {
+                predicate: &branch.predicate,
+                putters,
+                inbox,
+                choices,
+                step_budget: self.options.step_budget,
+            };
+            let kind = match component.sync_run(&mut context, self.pd) {
+                PolyBlocker::Inconsistent => return Ok(None),
+                PolyBlocker::StepBudgetExhausted => ViolationKind::StepBudgetExhausted,
+                PolyBlocker::Fault(message) => ViolationKind::Fault(message),
+                PolyBlocker::CouldntReadMsg(port) => {
+                    let channel_id = channel_id(putters, port);
+                    if branch.predicate.replace_assignment(channel_id, true) == Some(false) {
+                        return Ok(None);
+                    }
+                    branch.parts[index].status = Status::Blocked(port);
+                    // The environment puts any of the payloads, if no component does
+                    if port.to_raw() < self.boundary {
+                        for payload in self.options.payloads.iter() {
+                            let mut fork = branch.clone();
+                            fork.messages.insert(channel_id, payload.clone().into());
+                            to_run.push(fork);
+                        }
+                        return Ok(None);
+                    }
+                    continue;
+                }
+                PolyBlocker::CouldntCheckFiring(port) => {
+                    let channel_id = channel_id(putters, port);
+                    let mut fork = branch.clone();
+                    fork.predicate.replace_assignment(channel_id, false);
+                    to_run.push(fork);
+                    branch.predicate.replace_assignment(channel_id, true);
+                    continue;
+                }
+                PolyBlocker::CouldntChoose => {
+                    // split the branch on a fresh oracle variable, which no channel shares
+                    let oracle = ChannelId { controller_id: 1, channel_index: branch.oracles };
+                    branch.oracles += 1;
+                    branch.parts[index].choices.push(oracle);
+                    let mut fork = branch.clone();
+                    fork.predicate.replace_assignment(oracle, false);
+                    to_run.push(fork);
+                    branch.predicate.replace_assignment(oracle, true);
+                    continue;
+                }
+                PolyBlocker::PutMsg(port, payload) => {
+                    let channel_id = channel_id(putters, port);
+                    if branch.predicate.replace_assignment(channel_id, true) == Some(false) {
+                        return Ok(None);
+                    }
+                    if let Some(previous) = branch.messages.insert(channel_id, payload.clone()) {
+                        if previous != payload {
+                            return Ok(None);
+                        }
+                    }
+                    branch.parts[index].outbox.insert(port);
+                    continue;
+                }
+                PolyBlocker::SyncBlockEnd => {
+                    // Ports of the component fire if and only if it got or put on them
+                    let part = &mut branch.parts[index];
+                    for &port in part.ports.iter() {
+                        let fired = part.inbox.contains_key(&port) || part.outbox.contains(&port);
+                        let channel_id = channel_id(putters, port);
+                        match branch.predicate.query(channel_id) {
+                            Some(firing) if firing != fired => return Ok(None),
+                            Some(_) => {}
+                            None => {
+                                branch.predicate.replace_assignment(channel_id, false);
+                            }
+                        }
+                    }
+                    part.status = Status::Done;
+                    continue;
+                }
+            };
+            return Err((kind, self.fired(&branch, state)));
+        }
+    }
+    // The ports of the main component that fired in the branch, as far as it has run
+    fn fired(&self, branch: &Branch, state: &State) -> Round {
+        let mut fired = Vec::new();
+        for index in 0..self.boundary {
+            let channel_id = channel_id(&state.putters, Port::from_raw(index));
+            if let Some(payload) = branch.messages.get(&channel_id) {
+                fired.push((index, payload.as_slice().to_vec()));
+            }
+        }
+        Round { fired }
+    }
+}
+
+impl Default for CheckOptions {
+    fn default() -> Self {
+        Self {
+            args: None,
+            max_rounds: 8,
+            payloads: vec![vec![], vec![0]],
+            step_budget: Some(10_000),
+        }
+    }
+}
+impl MonoContext for MonoCheckContext<'_> {
+    type D = ProtocolD;
+    type S = ProtocolS;
+    fn new_component(&mut self, moved_ports: HashSet<Port>, init_state: Self::S) {
+        assert!(moved_ports.iter().all(|port| self.ports.contains(port)));
+        self.ports.retain(|port| !moved_ports.contains(port));
+        self.created.push((init_state, moved_ports.into_iter().collect()));
+    }
+    fn new_channel(&mut self) -> [Port; 2] {
+        let putter = Port::from_raw(self.state.next_port);
+        let getter = Port::from_raw(self.state.next_port + 1);
+        self.state.next_port += 2;
+        self.state.putters.insert(putter, putter);
+        self.state.putters.insert(getter, putter);
+        self.ports.insert(putter);
+        self.ports.insert(getter);
+        [putter, getter]
+    }
+    fn new_random(&mut self) -> u64 {
+        // A fixed sequence, so that checks are reproducible
+        self.state.randoms += 1;
+        let mut x = self.state.randoms.wrapping_mul(0x9E37_79B9_7F4A_7C15);
+        x = (x ^ (x >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        x ^ (x >> 31)
+    }
+    fn step_budget(&self) -> Option<usize> {
+        self.step_budget
+    }
+}
+impl PolyContext for PolyCheckContext<'_> {
+    type D = ProtocolD;
+
+    fn is_firing(&mut self, port: Port) -> Option<bool> {
+        self.predicate.query(channel_id(self.putters, port))
+    }
+    fn read_msg(&mut self, port: Port) -> Option<&Payload> {
+        self.inbox.get(&port)
+    }
+    fn choose(&mut self, index: usize) -> Option<bool> {
+        self.choices.get(index).and_then(|&oracle| self.predicate.query(oracle))
+    }
+    fn step_budget(&self) -> Option<usize> {
+        self.step_budget
+    }
+}
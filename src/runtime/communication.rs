@@ -141,14 +141,14 @@ impl Controller {
         deadline: Option<Instant>,
         sync_batches: Option<impl Iterator<Item = SyncBatch>>,
     ) -> Result<(), SyncErr> {
-        if let Some(e) = self.unrecoverable_error {
+        if let Some(e) = &self.unrecoverable_error {
             return Err(e.clone());
         }
         self.sync_round_inner(deadline, sync_batches).map_err(move |e| match e {
             SyncErr::Timeout => e, // this isn't unrecoverable
             _ => {
                 // Must set unrecoverable error! and tear down our net channels
-                self.unrecoverable_error = Some(e);
+                self.unrecoverable_error = Some(e.clone());
                 self.ephemeral.clear();
                 self.inner.endpoint_exts = Default::default();
                 e
@@ -187,6 +187,12 @@ impl Controller {
             log!(&mut self.inner.logger, "... MonoP's pre_sync_run got blocker {:?}", &blocker);
             match blocker {
                 MonoBlocker::Inconsistent => return Err(SyncErr::Inconsistent),
+                MonoBlocker::StepBudgetExhausted => {
+                    return Err(SyncErr::EvalErr(EvalErr::StepBudgetExhausted))
+                }
+                MonoBlocker::Fault(message) => {
+                    return Err(SyncErr::EvalErr(EvalErr::Fault(message)))
+                }
                 MonoBlocker::ComponentExit => drop(mono_p),
                 MonoBlocker::SyncBlockStart => self.ephemeral.poly_ps.push(mono_p.into()),
             }
@@ -549,6 +555,7 @@ impl Into<PolyP> for MonoP {
                     state: self.state,
                     inbox: Default::default(),
                     outbox: Default::default(),
+                    choices: Default::default(),
                     blocking_on: None,
                 }
             },
@@ -605,6 +612,9 @@ impl MonoContext for MonoPContext<'_> {
         );
         [kp, kg]
     }
+    fn step_budget(&self) -> Option<usize> {
+        self.inner.step_budget
+    }
     fn new_random(&mut self) -> u64 {
         type Bytes8 = [u8; std::mem::size_of::<u64>()];
         let mut bytes = Bytes8::default();
@@ -725,6 +735,19 @@ impl PolyContext for BranchPContext<'_, '_> {
         );
         val
     }
+    fn step_budget(&self) -> Option<usize> {
+        self.m_ctx.inner.step_budget
+    }
+    fn choose(&mut self, index: usize) -> Option<bool> {
+        let val = self.choices.get(index).and_then(|&oracle| self.predicate.query(oracle));
+        log!(
+            &mut self.m_ctx.inner.logger,
+            "!! PolyContext callback to choose by {:?}! returning {:?}",
+            self.m_ctx.my_subtree_id,
+            val,
+        );
+        val
+    }
     fn read_msg(&mut self, port: Port) -> Option<&Payload> {
         assert!(self.ports.contains(&port));
         let val = self.inbox.get(&port);
@@ -22,21 +22,111 @@ impl Default for Connector {
 impl Connector {
     /// Configure the Connector with the given Pdl description.
     pub fn configure(&mut self, pdl: &[u8], main_component: &[u8]) -> Result<(), ConfigErr> {
+        self.configure_main(pdl, &PdlModules::default(), main_component, None)
+    }
+    /// Configure the Connector with the given Pdl description, passing the given
+    /// arguments to the parameters of the main component. Port indices are assigned
+    /// to the ports of `Port` and `Ports` arguments, in the order of the parameters.
+    pub fn configure_with_args(
+        &mut self,
+        pdl: &[u8],
+        main_component: &[u8],
+        args: &[ComponentArg],
+    ) -> Result<(), ConfigErr> {
+        self.configure_main(pdl, &PdlModules::default(), main_component, Some(args.to_vec()))
+    }
+    /// Configure the Connector with the given Pdl description, which may import
+    /// the given user modules. Their definitions are named by the last segment
+    /// of the module name, e.g. `util.forward` after `import lib.util;`.
+    pub fn configure_with_modules(
+        &mut self,
+        pdl: &[u8],
+        modules: &PdlModules,
+        main_component: &[u8],
+    ) -> Result<(), ConfigErr> {
+        self.configure_main(pdl, modules, main_component, None)
+    }
+    /// Check the given Pdl description, which may import the given user modules,
+    /// and return all problems found in it. The description is fine to configure
+    /// with if there are no errors among them.
+    pub fn diagnose(pdl: &[u8], modules: &PdlModules) -> Vec<PdlDiagnostic> {
+        ProtocolD::diagnose(pdl, modules)
+    }
+    /// Format the given Pdl description in its canonical form, with consistent
+    /// indentation and spacing. Only its syntax is checked: the parse errors are
+    /// returned if there are any. Comments are not preserved.
+    pub fn format(pdl: &[u8]) -> Result<String, String> {
+        ProtocolD::format(pdl)
+    }
+    /// Read the pragmas of the given Pdl description: the language version and
+    /// module name it declares, and the language features it enables.
+    pub fn pragmas(pdl: &[u8], modules: &PdlModules) -> Result<PdlPragmas, ConfigErr> {
+        let protocol_description =
+            ProtocolD::parse_with_modules(pdl, modules).map_err(ConfigErr::ParseErr)?;
+        Ok(protocol_description.pragmas())
+    }
+    /// Check the given Pdl description offline, by exploring the synchronous rounds that
+    /// its main component can take with an environment that may put and get any messages
+    /// of the given payloads. The report has a trace of rounds leading to the first
+    /// problem found, such as a deadlock, a fault or a failing assertion.
+    pub fn check(
+        pdl: &[u8],
+        main_component: &[u8],
+        options: &checker::CheckOptions,
+    ) -> Result<checker::CheckReport, ConfigErr> {
+        let protocol_description = ProtocolD::parse(pdl).map_err(ConfigErr::ParseErr)?;
+        checker::check(&protocol_description, main_component, options)
+    }
+    /// Export the constraint automaton of a primitive component of the given Pdl
+    /// description. Its states are the synchronous blocks of the component, and its
+    /// transitions the paths through them, with the ports they fire, the conditions
+    /// they take, the messages they put and the memory they update.
+    pub fn automaton(
+        pdl: &[u8],
+        primitive: &[u8],
+        format: AutomatonFormat,
+    ) -> Result<String, ConfigErr> {
+        let protocol_description = ProtocolD::parse(pdl).map_err(ConfigErr::ParseErr)?;
+        protocol_description.automaton(primitive, format).ok_or(ConfigErr::NoSuchComponent)
+    }
+    /// Draw a composite component of the given Pdl description as a Graphviz DOT graph
+    /// of the components and channels it creates, between its own ports.
+    pub fn topology(pdl: &[u8], composite: &[u8]) -> Result<String, ConfigErr> {
+        let protocol_description = ProtocolD::parse(pdl).map_err(ConfigErr::ParseErr)?;
+        protocol_description.topology(composite).ok_or(ConfigErr::NoSuchComponent)
+    }
+    fn configure_main(
+        &mut self,
+        pdl: &[u8],
+        modules: &PdlModules,
+        main_component: &[u8],
+        main_args: Option<Vec<ComponentArg>>,
+    ) -> Result<(), ConfigErr> {
         use ConfigErr::*;
         let controller_id = match self {
             Connector::Configured(_) => return Err(AlreadyConfigured),
             Connector::Connected(_) => return Err(AlreadyConnected),
             Connector::Unconfigured(Unconfigured { controller_id }) => *controller_id,
         };
-        let protocol_description = Arc::new(ProtocolD::parse(pdl).map_err(ParseErr)?);
-        let polarities = protocol_description.component_polarities(main_component)?;
+        let protocol_description =
+            Arc::new(ProtocolD::parse_with_modules(pdl, modules).map_err(ParseErr)?);
+        let polarities =
+            protocol_description.component_polarities(main_component, main_args.as_deref())?;
+        let warnings = protocol_description.analyze(main_component);
+        let mut logger: String = "Logger created!\n".into();
+        for w in warnings.iter() {
+            log!(&mut logger, "Warning at {}:{}:{}: {}", w.filename, w.line, w.column, w.message);
+        }
         let configured = Configured {
             controller_id,
             protocol_description,
             bindings: Default::default(),
             polarities,
             main_component: main_component.to_vec(),
-            logger: "Logger created!\n".into(),
+            main_args,
+            step_budget: None,
+            warnings,
+            logger,
         };
         *self = Connector::Configured(configured);
         Ok(())
@@ -87,8 +177,10 @@ impl Connector {
         let (controller, native_interface) = Controller::connect(
             configured.controller_id,
             &configured.main_component,
+            configured.main_args.as_deref(),
             configured.protocol_description.clone(),
             &bound_proto_interface[..],
+            configured.step_budget,
             &mut configured.logger,
             deadline,
         )?;
@@ -99,6 +191,35 @@ impl Connector {
         });
         Ok(())
     }
+    /// Limit the number of steps each protocol component may take in a synchronous round.
+    /// A round in which a component exceeds the budget fails; `None` removes the limit.
+    pub fn set_step_budget(&mut self, step_budget: Option<usize>) -> Result<(), ()> {
+        match self {
+            Connector::Configured(configured) => configured.step_budget = step_budget,
+            Connector::Connected(connected) => connected.controller.inner.step_budget = step_budget,
+            _ => return Err(()),
+        }
+        Ok(())
+    }
+    /// The warnings about the main component that were found when the connector was
+    /// configured, such as causal loops that keep its synchronous rounds from ever
+    /// completing. They are written to the log as well. None are kept once connected.
+    pub fn warnings(&self) -> &[PdlDiagnostic] {
+        match self {
+            Connector::Configured(configured) => &configured.warnings,
+            _ => &[],
+        }
+    }
+    /// Draw the components of a connected connector as a Graphviz DOT graph: the
+    /// native component and every protocol component, with the ports they currently
+    /// hold, the channels between them, and the network endpoints with the addresses
+    /// of their peers. None if the connector is not connected.
+    pub fn dump_topology(&self) -> Option<String> {
+        match self {
+            Connector::Connected(connected) => Some(connected.topology()),
+            _ => None,
+        }
+    }
     pub fn get_mut_logger(&mut self) -> Option<&mut String> {
         match self {
             Connector::Configured(configured) => Some(&mut configured.logger),
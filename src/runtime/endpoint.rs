@@ -95,6 +95,14 @@ impl Endpoint {
         Self::Network(NetworkEndpoint { stream, inbox, outbox: vec![] })
     }
 
+    // the address of the peer of a network endpoint
+    pub(crate) fn peer_addr(&self) -> Option<SocketAddr> {
+        match self {
+            Self::Memory { .. } => None,
+            Self::Network(NetworkEndpoint { stream, .. }) => stream.peer_addr().ok(),
+        }
+    }
+
     // symmetric
     pub fn new_memory_pair() -> [Self; 2] {
         let (s1, r1) = mio_extras::channel::channel::<Msg>();
@@ -30,6 +30,12 @@ pub enum ConfigErr {
     AlreadyConfigured,
     NoSuchComponent,
     NonPortTypeParameters,
+    WrongNumberOfArguments { expected: usize },
+    WrongArgumentType { param_index: usize },
+    CannotMovePort(Port),
+    UnknownPort(Port),
+    WrongPortPolarity { param_index: usize, port: Port },
+    DuplicateMovedPort(Port),
 }
 #[derive(Debug, Copy, Clone, PartialEq, Eq)]
 pub enum ConnectErr {
@@ -59,7 +65,7 @@ pub enum EndpointErr {
     MetaProtocolDeviation,
 }
 
-#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub enum SyncErr {
     NotConnected,
     MessengerRecvErr(MessengerRecvErr),
@@ -72,9 +78,13 @@ pub enum SyncErr {
     EndpointErr(EndpointErr),
     EvalErr(EvalErr),
 }
-#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub enum EvalErr {
     ComponentExitWhileBranching,
+    // A protocol component took more steps in a round than the connector's step budget
+    StepBudgetExhausted,
+    // A protocol component faulted; carries the rendered evaluation error
+    Fault(String),
 }
 #[derive(Debug, Copy, Clone, PartialEq, Eq)]
 pub enum MessengerRecvErr {
@@ -88,7 +98,14 @@ impl From<MainComponentErr> for ConfigErr {
         match e {
             M::NoSuchComponent => C::NoSuchComponent,
             M::NonPortTypeParameters => C::NonPortTypeParameters,
-            _ => todo!(),
+            M::WrongNumberOfParamaters { expected } => C::WrongNumberOfArguments { expected },
+            M::WrongArgumentType { param_index } => C::WrongArgumentType { param_index },
+            M::CannotMovePort(port) => C::CannotMovePort(port),
+            M::UnknownPort(port) => C::UnknownPort(port),
+            M::WrongPortPolarity { param_index, port } => {
+                C::WrongPortPolarity { param_index, port }
+            }
+            M::DuplicateMovedPort(port) => C::DuplicateMovedPort(port),
         }
     }
 }
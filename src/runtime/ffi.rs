@@ -29,6 +29,14 @@ fn overwrite_last_error(error_msg: &[u8]) {
     })
 }
 
+// Parse errors are stored as is, as they span several lines
+fn overwrite_config_error(e: ConfigErr) {
+    match e {
+        ConfigErr::ParseErr(message) => overwrite_last_error(message.as_bytes()),
+        e => overwrite_last_error(format!("{:?}", e).as_bytes()),
+    }
+}
+
 unsafe fn as_rust_str<R, F: FnOnce(&str) -> R>(s: *const c_char, f: F) -> Option<R> {
     as_rust_bytes(s, |bytes| {
         let s = std::str::from_utf8(bytes).ok()?;
@@ -55,6 +63,45 @@ unsafe fn try_parse_addr(s: *const c_char) -> Option<SocketAddr> {
     as_rust_str(s, |s| s.parse().ok()).and_then(|x| x)
 }
 
+// Parses a comma-separated list of main component arguments, e.g. "port[2], port, 3"
+fn parse_component_args(s: &str) -> Option<Vec<ComponentArg>> {
+    let mut args = Vec::new();
+    if s.trim().is_empty() {
+        return Some(args);
+    }
+    let mut depth = 0;
+    let mut start = 0;
+    for (i, c) in s.char_indices() {
+        match c {
+            '{' => depth += 1,
+            '}' if depth > 0 => depth -= 1,
+            ',' if depth == 0 => {
+                args.push(parse_component_arg(&s[start..i])?);
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    args.push(parse_component_arg(&s[start..])?);
+    Some(args)
+}
+
+fn parse_component_arg(s: &str) -> Option<ComponentArg> {
+    let s = s.trim();
+    Some(match s {
+        "port" => ComponentArg::Port,
+        "true" => ComponentArg::Boolean(true),
+        "false" => ComponentArg::Boolean(false),
+        _ if s.starts_with("port[") && s.ends_with(']') => {
+            ComponentArg::Ports(s[5..s.len() - 1].trim().parse().ok()?)
+        }
+        _ if s.starts_with('{') && s.ends_with('}') => {
+            ComponentArg::Array(parse_component_args(&s[1..s.len() - 1])?)
+        }
+        _ => ComponentArg::Integer(s.parse().ok()?),
+    })
+}
+
 ///////////////////////////////////////
 
 /// Returns a pointer into the error buffer for reading as a null-terminated string
@@ -107,6 +154,9 @@ pub extern "C" fn connector_with_controller_id(controller_id: ControllerId) -> *
 
 /// Configures the given Reowolf connector with a protocol description in PDL.
 /// Returns:
+/// - 0 SUCCESS
+/// - -1 the connector could not be configured. If the description has errors,
+///   the last error lists all of them.
 /// # Safety
 /// TODO
 #[no_mangle]
@@ -120,7 +170,7 @@ pub unsafe extern "C" fn connector_configure(
         as_rust_bytes(main, |main_bytes| match b.configure(pdl_bytes, main_bytes) {
             Ok(()) => 0,
             Err(e) => {
-                overwrite_last_error(format!("{:?}", e).as_bytes());
+                overwrite_config_error(e);
                 -1
             }
         })
@@ -129,6 +179,50 @@ pub unsafe extern "C" fn connector_configure(
     ret
 }
 
+/// Configures the given Reowolf connector with a protocol description in PDL,
+/// passing arguments to the parameters of the main component. The arguments are
+/// a comma-separated list, with one argument per parameter:
+/// - `port` for an `in` or `out` parameter, bound to the next port index
+/// - `port[N]` for an `in[]` or `out[]` parameter, bound to the next N port indices
+/// - `true`, `false`, or an integer for a parameter of that type
+/// - `{...}` with a list of such arguments for an array parameter
+///
+/// Returns:
+/// - 0 SUCCESS
+/// - 1 the arguments could not be parsed
+/// - -1 the connector could not be configured
+/// # Safety
+/// TODO
+#[no_mangle]
+pub unsafe extern "C" fn connector_configure_with_args(
+    connector: *mut Connector,
+    pdl: *mut c_char,
+    main: *mut c_char,
+    args: *const c_char,
+) -> c_int {
+    let args = match as_rust_str(args, parse_component_args).and_then(|x| x) {
+        Some(args) => args,
+        None => {
+            overwrite_last_error(b"Failed to parse main component arguments!");
+            return 1;
+        }
+    };
+    let mut b = Box::from_raw(connector); // unsafe!
+    let ret = as_rust_bytes(pdl, |pdl_bytes| {
+        as_rust_bytes(main, |main_bytes| {
+            match b.configure_with_args(pdl_bytes, main_bytes, &args) {
+                Ok(()) => 0,
+                Err(e) => {
+                    overwrite_config_error(e);
+                    -1
+                }
+            }
+        })
+    });
+    Box::into_raw(b); // don't drop!
+    ret
+}
+
 /// Provides a binding annotation for the port with the given index with "native":
 /// (The port is exposed for reading and writing from the application)
 /// Returns:
@@ -356,6 +450,28 @@ pub unsafe extern "C" fn connector_next_batch(connector: *mut Connector) -> c_in
     result
 }
 
+/// Limits the number of steps each protocol component may take in a synchronous round.
+/// A step budget of 0 removes the limit.
+/// Returns:
+/// - 0 SUCCESS
+/// - 1 the connector is not yet configured
+/// # Safety
+/// TODO
+#[no_mangle]
+pub unsafe extern "C" fn connector_set_step_budget(
+    connector: *mut Connector,
+    step_budget: usize,
+) -> c_int {
+    let mut b = Box::from_raw(connector); // unsafe!
+    let step_budget = if step_budget == 0 { None } else { Some(step_budget) };
+    let result = match b.set_step_budget(step_budget) {
+        Ok(()) => 0,
+        Err(()) => 1,
+    };
+    Box::into_raw(b); // don't drop!
+    result
+}
+
 /// # Safety
 /// TODO
 #[no_mangle]
@@ -364,6 +480,11 @@ pub unsafe extern "C" fn connector_sync(connector: *mut Connector, timeout_milli
     let result = match b.sync(Duration::from_millis(timeout_millis)) {
         Ok(batch_index) => batch_index.try_into().unwrap(),
         Err(SyncErr::Timeout) => -1, // timeout!
+        Err(SyncErr::EvalErr(EvalErr::Fault(message))) => {
+            // the message shows the offending line of the protocol description
+            overwrite_last_error(message.as_bytes());
+            -2
+        }
         Err(e) => {
             overwrite_last_error(format!("{:?}", e).as_bytes());
             -2
@@ -2,6 +2,7 @@
 pub mod ffi;
 
 mod actors;
+pub mod checker;
 pub(crate) mod communication;
 pub(crate) mod connector;
 pub(crate) mod endpoint;
@@ -224,6 +225,9 @@ pub struct Configured {
     bindings: HashMap<usize, PortBinding>,
     protocol_description: Arc<ProtocolD>,
     main_component: Vec<u8>,
+    main_args: Option<Vec<ComponentArg>>,
+    step_budget: Option<usize>,
+    warnings: Vec<PdlDiagnostic>,
     logger: String,
 }
 #[derive(Debug)]
@@ -281,6 +285,7 @@ struct ControllerInner {
     mono_n: MonoN,       // state at next round start
     mono_ps: Vec<MonoP>, // state at next round start
     family: ControllerFamily,
+    step_budget: Option<usize>, // max steps per protocol component per round
     logger: String,
 }
 
@@ -343,6 +348,7 @@ struct BranchPContext<'m, 'r> {
     ports: &'r HashSet<Port>,
     predicate: &'r Predicate,
     inbox: &'r HashMap<Port, Payload>,
+    choices: &'r [ChannelId],
 }
 
 #[derive(Default)]
@@ -492,6 +498,74 @@ impl<T> Arena<T> {
     }
 }
 
+impl Connected {
+    // Components are boxes labeled by their ports. A channel between two of them is an edge
+    // from the putter to the getter, and a network endpoint is an edge to or from its peer.
+    fn topology(&self) -> String {
+        let inner = &self.controller.inner;
+        let controller_id = inner.channel_id_stream.controller_id;
+        let mut out = format!("digraph \"controller {}\" {{\n", controller_id);
+        out.push_str("\trankdir=LR;\n");
+        let mut owners: HashMap<Port, String> = HashMap::new();
+        let mut component = |node: String, name: String, ports: Vec<Port>| {
+            let labels: Vec<String> = ports.iter().map(|port| port.to_raw().to_string()).collect();
+            let label = format!("{}\\nports {}", name, labels.join(", "));
+            out.push_str(&format!("\t{} [shape=box, label=\"{}\"];\n", node, label));
+            for port in ports {
+                owners.insert(port, node.clone());
+            }
+        };
+        // Native ports are listed in the order of their indices
+        let native = self.native_interface.iter().map(|&(port, _)| port).collect();
+        component("native".to_string(), "native".to_string(), native);
+        for (index, mono_p) in inner.mono_ps.iter().enumerate() {
+            let mut ports: Vec<Port> = mono_p.ports.iter().copied().collect();
+            ports.sort();
+            component(format!("p{}", index), format!("component {}", index), ports);
+        }
+        let owner =
+            |port: Port| owners.get(&port).cloned().unwrap_or_else(|| "unowned".to_string());
+        for (port, ext) in inner.endpoint_exts.iter() {
+            match &ext.endpoint {
+                Endpoint::Memory { .. } => {
+                    if ext.info.polarity != Putter {
+                        continue;
+                    }
+                    let peer = inner.endpoint_exts.iter().find(|(peer, peer_ext)| {
+                        *peer != port && peer_ext.info.channel_id == ext.info.channel_id
+                    });
+                    if let Some((peer, _)) = peer {
+                        let (from, to) = (port.to_raw(), peer.to_raw());
+                        let edge = format!("{} -> {}", owner(port), owner(peer));
+                        out.push_str(&format!("\t{} [label=\"{} -> {}\"];\n", edge, from, to));
+                    }
+                }
+                Endpoint::Network(_) => {
+                    let addr = ext.endpoint.peer_addr();
+                    let addr = addr.map_or("disconnected".to_string(), |addr| addr.to_string());
+                    let node = format!("n{}", port.to_raw());
+                    out.push_str(&format!("\t{} [label=\"{}\"];\n", node, addr));
+                    let role = if inner.family.parent_port == Some(port) {
+                        " (parent)"
+                    } else if inner.family.children_ports.contains(&port) {
+                        " (child)"
+                    } else {
+                        ""
+                    };
+                    let edge = match ext.info.polarity {
+                        Putter => format!("{} -> {}", owner(port), node),
+                        Getter => format!("{} -> {}", node, owner(port)),
+                    };
+                    let label = format!("{}{}", port.to_raw(), role);
+                    out.push_str(&format!("\t{} [label=\"{}\"];\n", edge, label));
+                }
+            }
+        }
+        out.push_str("}\n");
+        out
+    }
+}
+
 impl ChannelIdStream {
     fn new(controller_id: ControllerId) -> Self {
         Self { controller_id, next_channel_index: 0 }
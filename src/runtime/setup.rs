@@ -21,8 +21,10 @@ impl Controller {
     pub fn connect(
         major: ControllerId,
         main_component: &[u8],
+        main_args: Option<&[ComponentArg]>,
         protocol_description: Arc<ProtocolD>,
         bound_proto_interface: &[(PortBinding, Polarity)],
+        step_budget: Option<usize>,
         logger: &mut String,
         deadline: Instant,
     ) -> Result<(Self, Vec<(Port, Polarity)>), ConnectErr> {
@@ -102,7 +104,7 @@ impl Controller {
 
         let n_mono = MonoN { ports: ports_native.into_iter().collect(), result: None };
         let p_monos = vec![MonoP {
-            state: protocol_description.new_main_component(main_component, &ports_proto),
+            state: protocol_description.new_main_component(main_component, main_args, &ports_proto),
             ports: ports_proto.into_iter().collect(),
         }];
 
@@ -125,6 +127,7 @@ impl Controller {
             mono_ps: p_monos,
             mono_n: n_mono,
             round_index: 0,
+            step_budget,
             logger: {
                 let mut l = String::default();
                 std::mem::swap(&mut l, logger);
@@ -0,0 +1,137 @@
+use crate::runtime::checker::*;
+use crate::runtime::Connector;
+
+static PDL: &[u8] = b"
+primitive forward(in i, out o) {
+    while (true) synchronous {
+        if (fires(i)) put(o, get(i));
+    }
+}
+primitive samelen(in a, in b, out c) {
+    synchronous {
+        msg m = get(a);
+        msg n = get(b);
+        assert(m.length == n.length);
+        put(c, m);
+    }
+}
+primitive replicator_2(in i, out l, out r) {
+    while (true) synchronous {
+        if (fires(i)) {
+            msg m = get(i);
+            put(l, m);
+            put(r, m);
+        }
+    }
+}
+composite samelen_repl(in a, out b) {
+    channel c -> d;
+    channel e -> f;
+    new samelen(a, f, c);
+    new replicator_2(d, b, e);
+}
+primitive bounded(out o) {
+    int n = 0;
+    while (true) {
+        synchronous {
+            if (fires(o)) {
+                put(o, create(0));
+                n++;
+            }
+        }
+        assert(n < 2);
+    }
+}
+primitive divide(in i) {
+    while (true) synchronous {
+        if (fires(i)) {
+            msg m = get(i);
+            int x = 10 / m.length;
+        }
+    }
+}
+primitive chooser(out l, out r) {
+    while (true) synchronous {
+        if (choose()) put(l, create(1));
+        else put(r, create(2));
+    }
+}
+primitive counter() {
+    int n = 0;
+    while (true) synchronous { n++; }
+}
+primitive spin() {
+    synchronous {
+        while (true) skip;
+    }
+}
+";
+
+fn check(main_component: &[u8], options: &CheckOptions) -> CheckReport {
+    Connector::check(PDL, main_component, options).unwrap()
+}
+
+#[test]
+fn checker_ok() {
+    let options = CheckOptions::default();
+    for &name in [&b"forward"[..], b"chooser", b"samelen"].iter() {
+        let report = check(name, &options);
+        assert_eq!(None, report.violation);
+        assert!(report.exhaustive);
+    }
+    // Besides the initial state, every round of the chooser ends in the same one
+    assert_eq!(2, check(b"chooser", &options).states);
+}
+
+#[test]
+fn checker_bounded() {
+    let options = CheckOptions { max_rounds: 3, ..CheckOptions::default() };
+    let report = check(b"counter", &options);
+    assert_eq!(None, report.violation);
+    assert!(!report.exhaustive);
+    assert_eq!(4, report.states);
+}
+
+#[test]
+fn checker_deadlock() {
+    let report = check(b"samelen_repl", &CheckOptions::default());
+    let violation = Violation { kind: ViolationKind::Deadlock, trace: vec![] };
+    assert_eq!(Some(violation), report.violation);
+    assert!(!report.exhaustive);
+}
+
+#[test]
+fn checker_assertion() {
+    // Two rounds fire the port, and the third round fails before its synchronous block
+    let report = check(b"bounded", &CheckOptions::default());
+    let fired = Round { fired: vec![(0, vec![])] };
+    let trace = vec![fired.clone(), fired, Round::default()];
+    assert_eq!(Some(Violation { kind: ViolationKind::Inconsistent, trace }), report.violation);
+}
+
+#[test]
+fn checker_fault() {
+    let report = check(b"divide", &CheckOptions::default());
+    let violation = report.violation.unwrap();
+    match &violation.kind {
+        ViolationKind::Fault(message) => assert!(message.contains("Division by zero")),
+        kind => panic!("Expected fault, found {:?}", kind),
+    }
+    assert_eq!(vec![Round { fired: vec![(0, vec![])] }], violation.trace);
+    // Without empty messages the division is fine
+    let options = CheckOptions { payloads: vec![vec![1, 2]], ..CheckOptions::default() };
+    assert_eq!(None, check(b"divide", &options).violation);
+}
+
+#[test]
+fn checker_step_budget() {
+    let report = check(b"spin", &CheckOptions::default());
+    let kind = report.violation.unwrap().kind;
+    assert_eq!(ViolationKind::StepBudgetExhausted, kind);
+}
+
+#[test]
+fn checker_errors() {
+    let err = Connector::check(PDL, b"missing", &CheckOptions::default()).unwrap_err();
+    assert_eq!(crate::runtime::errors::ConfigErr::NoSuchComponent, err);
+}
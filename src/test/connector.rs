@@ -6,6 +6,24 @@ use crate::common::*;
 use crate::runtime::{errors::*, PortBinding::*};
 
 static PDL: &[u8] = b"
+import std.reo;
+
+primitive forward(in i, out o) {
+    while(true) synchronous() put(o, get(i));
+}
+primitive alternator_2(in i, out l, out r) {
+    while(true) {
+        synchronous() put(l, get(i));
+        synchronous() put(r, get(i));
+    }
+}
+primitive replicator_2(in i, out l, out r) {
+    while(true) synchronous() if(fires(i)) {
+        msg m = get(i);
+        put(l, m);
+        put(r, m);
+    }
+}
 primitive forward_once(in i, out o) {
     synchronous() put(o, get(i));
 }
@@ -35,6 +53,33 @@ primitive token_spout(out o) {
         put(o, create(0));
     }
 }
+primitive chooser(out l, out r) {
+    while(true) synchronous {
+        if (choose()) put(l, create(0));
+        else put(r, create(0));
+    }
+}
+primitive dice(out o) {
+    long n = random();
+    synchronous {
+        put(o, create((int) (n & 1) + 1));
+    }
+}
+primitive null_spout(out o) {
+    while(true) synchronous {
+        put(o, null);
+    }
+}
+composite roundrobin_0(in i) {
+    out[] none = {};
+    new roundrobin(i, none);
+}
+primitive spin(out o) {
+    int i = 0;
+    while(true) synchronous {
+        while(true) i++;
+    }
+}
 primitive wait_n(int to_wait, out o) {
     while(to_wait > 0) synchronous() to_wait -= 1;
     synchronous { put(o, create(0)); }
@@ -63,12 +108,31 @@ primitive samelen(in a, in b, out c) {
         put(c, m);
     }
 }
+composite nfifo_2(in i, out o) {
+    new nfifo(i, o, 2);
+}
 composite samelen_repl(in a, out b) {
     channel c -> d;   
     channel e -> f;
     new samelen(a, f, c);
     new replicator_2(d, b, e);
 }
+enum side { left, right }
+primitive alternator_switch(in i, out l, out r) {
+    side s = side::left;
+    while(true) synchronous {
+        if (fires(i)) switch (s) {
+        case left: {
+            put(l, get(i));
+            s = side::right;
+        }
+        case right: {
+            put(r, get(i));
+            s = side::left;
+        }
+        }
+    }
+}
 ";
 
 #[test]
@@ -180,6 +244,236 @@ fn connector_token_spout_ok() {
     ]));
 }
 
+#[test]
+fn connector_chooser_ok() {
+    // Test a protocol whose nondeterministic choice is decided by the native
+    /*
+    Alice<--chooser-->Alice
+    */
+    let timeout = Duration::from_millis(1_500);
+    const N: usize = 5;
+    assert!(run_connector_set(&[
+        //
+        &|x| {
+            // Alice
+            x.configure(PDL, b"chooser").unwrap();
+            x.bind_port(0, Native).unwrap();
+            x.bind_port(1, Native).unwrap();
+            x.connect(timeout).unwrap();
+            for i in 0..N {
+                x.get(i % 2).unwrap();
+                assert_eq!(Ok(0), x.sync(timeout));
+                assert_eq!(Ok(&[] as &[u8]), x.read_gotten(i % 2));
+            }
+        },
+    ]));
+}
+
+#[test]
+fn connector_dice_ok() {
+    // Test a protocol that creates a message of random length
+    /*
+    Alice<--dice
+    */
+    let timeout = Duration::from_millis(1_500);
+    assert!(run_connector_set(&[
+        //
+        &|x| {
+            // Alice
+            x.configure(PDL, b"dice").unwrap();
+            x.bind_port(0, Native).unwrap();
+            x.connect(timeout).unwrap();
+            x.get(0).unwrap();
+            assert_eq!(Ok(0), x.sync(timeout));
+            let length = x.read_gotten(0).unwrap().len();
+            assert!(length == 1 || length == 2);
+        },
+    ]));
+}
+
+#[test]
+fn connector_null_spout_fault() {
+    // Test that a faulty protocol fails the round with its evaluation error
+    /*
+    Alice<--null_spout
+    */
+    let timeout = Duration::from_millis(1_500);
+    assert!(run_connector_set(&[
+        //
+        &|x| {
+            // Alice
+            x.configure(PDL, b"null_spout").unwrap();
+            x.bind_port(0, Native).unwrap();
+            x.connect(timeout).unwrap();
+            x.get(0).unwrap();
+            match x.sync(timeout) {
+                Err(SyncErr::EvalErr(EvalErr::Fault(message))) => {
+                    assert!(message.contains("Null message"));
+                    assert!(message.contains("put(o, null);"));
+                }
+                result => panic!("Expected evaluation error, found {:?}", result),
+            }
+        },
+    ]));
+}
+
+#[test]
+fn connector_std_fault() {
+    // Test that faults in std.reo components are shown in the library source
+    /*
+    Alice-->roundrobin_0
+    */
+    let timeout = Duration::from_millis(1_500);
+    assert!(run_connector_set(&[
+        //
+        &|x| {
+            // Alice
+            x.configure(PDL, b"roundrobin_0").unwrap();
+            x.bind_port(0, Native).unwrap();
+            x.connect(timeout).unwrap();
+            x.put(0, b"lost".to_vec().into()).unwrap();
+            match x.sync(timeout) {
+                Err(SyncErr::EvalErr(EvalErr::Fault(message))) => {
+                    assert!(message.starts_with("Evaluation error at std.reo:"));
+                    assert!(message.contains("Index out of bounds"));
+                }
+                result => panic!("Expected evaluation error, found {:?}", result),
+            }
+        },
+    ]));
+}
+
+#[test]
+fn connector_modules() {
+    // Test that components of user modules run, and that their faults are shown in the module source
+    /*
+    Alice-->main-->Alice
+    Bob-->util.past_end-->Bob
+    */
+    let timeout = Duration::from_millis(1_500);
+    let modules = PdlModules { sources: vec![], search_path: vec!["testdata/modules".into()] };
+    let pdl = b"import lib.util; composite main(in i, out o) { new util.forward(i, o); }";
+    assert!(run_connector_set(&[
+        //
+        &|x| {
+            // Alice
+            x.configure_with_modules(pdl, &modules, b"main").unwrap();
+            x.bind_port(0, Native).unwrap();
+            x.bind_port(1, Native).unwrap();
+            x.connect(timeout).unwrap();
+            assert_eq!(Ok(()), x.put(0, b"hello".to_vec().into()));
+            assert_eq!(Ok(()), x.get(1));
+            assert_eq!(Ok(0), x.sync(timeout));
+            assert_eq!(Ok(b"hello" as &[u8]), x.read_gotten(1));
+        },
+        &|x| {
+            // Bob
+            x.configure_with_modules(pdl, &modules, b"util.past_end").unwrap();
+            x.bind_port(0, Native).unwrap();
+            x.bind_port(1, Native).unwrap();
+            x.connect(timeout).unwrap();
+            x.put(0, b"lost".to_vec().into()).unwrap();
+            match x.sync(timeout) {
+                Err(SyncErr::EvalErr(EvalErr::Fault(message))) => {
+                    assert!(message.starts_with("Evaluation error at util.pdl:"));
+                    assert!(message.contains("Index out of bounds"));
+                }
+                result => panic!("Expected evaluation error, found {:?}", result),
+            }
+        },
+    ]));
+}
+
+#[test]
+fn connector_read_past_end() {
+    // Test that reading past the end of a message drops only the branch that reads it,
+    // and that the round continues with the other branches
+    /*
+    Alice-->whole_int-->Alice
+                     -->Alice
+    */
+    let timeout = Duration::from_millis(1_500);
+    let pdl = b"
+    import std.buf;
+    primitive whole_int(in i, out o, out p) {
+        while(true) synchronous() if(fires(i)) {
+            msg m = get(i);
+            if(fires(o)) {
+                int x = readInt(m, 0);
+                put(o, m);
+            } else put(p, m);
+        }
+    }";
+    assert!(run_connector_set(&[
+        //
+        &|x| {
+            // Alice
+            x.configure(pdl, b"whole_int").unwrap();
+            x.bind_port(0, Native).unwrap();
+            x.bind_port(1, Native).unwrap();
+            x.bind_port(2, Native).unwrap();
+            x.connect(timeout).unwrap();
+            // batches [{0=>*, 1=>*}, {0=>*, 2=>*}]; a message shorter than an int goes to p
+            x.put(0, b"ab".to_vec().into()).unwrap();
+            x.get(1).unwrap();
+            x.next_batch().unwrap();
+            x.put(0, b"ab".to_vec().into()).unwrap();
+            x.get(2).unwrap();
+            assert_eq!(Ok(1), x.sync(timeout));
+            assert_eq!(Ok(b"ab" as &[u8]), x.read_gotten(2));
+            x.put(0, b"abcd".to_vec().into()).unwrap();
+            x.get(1).unwrap();
+            assert_eq!(Ok(0), x.sync(timeout));
+            assert_eq!(Ok(b"abcd" as &[u8]), x.read_gotten(1));
+        },
+    ]));
+}
+
+#[test]
+fn connector_step_budget_ok() {
+    // Test that the step budget is per round
+    /*
+    Alice<--token_spout
+    */
+    let timeout = Duration::from_millis(1_500);
+    const N: usize = 5;
+    assert!(run_connector_set(&[
+        //
+        &|x| {
+            // Alice
+            x.configure(PDL, b"token_spout").unwrap();
+            x.set_step_budget(Some(20)).unwrap();
+            x.bind_port(0, Native).unwrap();
+            x.connect(timeout).unwrap();
+            for _ in 0..N {
+                x.get(0).unwrap();
+                assert_eq!(Ok(0), x.sync(timeout));
+            }
+        },
+    ]));
+}
+
+#[test]
+fn connector_step_budget_exhausted() {
+    // Test that a component looping forever fails the round
+    /*
+    Alice<--spin
+    */
+    let timeout = Duration::from_millis(1_500);
+    assert!(run_connector_set(&[
+        //
+        &|x| {
+            // Alice
+            x.configure(PDL, b"spin").unwrap();
+            x.set_step_budget(Some(1_000)).unwrap();
+            x.bind_port(0, Native).unwrap();
+            x.connect(timeout).unwrap();
+            x.get(0).unwrap();
+            assert_eq!(Err(SyncErr::EvalErr(EvalErr::StepBudgetExhausted)), x.sync(timeout));
+        },
+    ]));
+}
+
 #[test]
 fn connector_waiter_ok() {
     // Test a stateful proto that blocks port 0 for 10 rounds
@@ -456,6 +750,73 @@ fn connector_alternator_2() {
     ]));
 }
 
+#[test]
+fn connector_alternator_switch() {
+    // Like connector_alternator_2, but alternating on the
+    // state of the primitive, kept in an enum
+    /*                         /--|-->A
+    Sender -->alternator_switch
+                               \--|-->B
+    */
+    let timeout = Duration::from_millis(1_500);
+    let addrs = [next_addr(), next_addr()];
+    const N: usize = 5;
+    static MSG: &[u8] = b"message";
+    assert!(run_connector_set(&[
+        //
+        &|x| {
+            // Sender
+            x.configure(PDL, b"alternator_switch").unwrap();
+            x.bind_port(0, Native).unwrap();
+            x.bind_port(1, Passive(addrs[0])).unwrap();
+            x.bind_port(2, Passive(addrs[1])).unwrap();
+            x.connect(timeout).unwrap();
+
+            for _ in 0..N {
+                for _ in 0..2 {
+                    x.put(0, MSG.to_vec().into()).unwrap();
+                    assert_eq!(0, x.sync(timeout).unwrap());
+                }
+            }
+        },
+        &|x| {
+            // A
+            x.configure(PDL, b"sync").unwrap();
+            x.bind_port(0, Active(addrs[0])).unwrap();
+            x.bind_port(1, Native).unwrap();
+            x.connect(timeout).unwrap();
+            for _ in 0..N {
+                // get msg round
+                x.get(0).unwrap();
+                assert_eq!(Ok(0), x.sync(timeout)); // GET ONE
+                assert_eq!(Ok(MSG), x.read_gotten(0));
+
+                // silent round
+                assert_eq!(Ok(0), x.sync(timeout)); // MISS ONE
+                assert_eq!(Err(ReadGottenErr::DidNotGet), x.read_gotten(0));
+            }
+        },
+        &|x| {
+            // B
+            x.configure(PDL, b"sync").unwrap();
+            x.bind_port(0, Active(addrs[1])).unwrap();
+            x.bind_port(1, Native).unwrap();
+            x.connect(timeout).unwrap();
+
+            for _ in 0..N {
+                // silent round
+                assert_eq!(Ok(0), x.sync(timeout)); // MISS ONE
+                assert_eq!(Err(ReadGottenErr::DidNotGet), x.read_gotten(0));
+
+                // get msg round
+                x.get(0).unwrap();
+                assert_eq!(Ok(0), x.sync(timeout)); // GET ONE
+                assert_eq!(Ok(MSG), x.read_gotten(0));
+            }
+        },
+    ]));
+}
+
 #[test]
 fn connector_composite_chain_a() {
     // Check if composition works. Forward messages through long chains
@@ -716,6 +1077,165 @@ fn connector_fifo_1_e() {
     ]));
 }
 
+#[test]
+fn connector_std_fifo() {
+    /*
+        /-->\
+    Alice   fifo
+        \<--/
+    */
+    let timeout = Duration::from_millis(1_500);
+    const N: usize = 10;
+    assert!(run_connector_set(&[
+        //
+        &|x| {
+            // Alice
+            x.configure(PDL, b"fifo").unwrap();
+            x.bind_port(0, Native).unwrap();
+            x.bind_port(1, Native).unwrap();
+            x.connect(timeout).unwrap();
+
+            for _ in 0..N {
+                // put
+                assert_eq!(Ok(()), x.put(0, b"message~".to_vec().into()));
+                assert_eq!(Ok(0), x.sync(timeout));
+
+                // get
+                assert_eq!(Ok(()), x.get(1));
+                assert_eq!(Ok(0), x.sync(timeout));
+                assert_eq!(Ok(b"message~" as &[u8]), x.read_gotten(1));
+            }
+        },
+    ]));
+}
+
+#[test]
+fn connector_std_nfifo() {
+    /*
+        /-->\
+    Alice   nfifo_2
+        \<--/
+    */
+    let timeout = Duration::from_millis(1_500);
+    assert!(run_connector_set(&[
+        //
+        &|x| {
+            // Alice
+            x.configure(PDL, b"nfifo_2").unwrap();
+            x.bind_port(0, Native).unwrap();
+            x.bind_port(1, Native).unwrap();
+            x.connect(timeout).unwrap();
+
+            // fill the buffer
+            assert_eq!(Ok(()), x.put(0, b"first".to_vec().into()));
+            assert_eq!(Ok(0), x.sync(timeout));
+            assert_eq!(Ok(()), x.put(0, b"second".to_vec().into()));
+            assert_eq!(Ok(0), x.sync(timeout));
+
+            // empty the buffer in order
+            assert_eq!(Ok(()), x.get(1));
+            assert_eq!(Ok(0), x.sync(timeout));
+            assert_eq!(Ok(b"first" as &[u8]), x.read_gotten(1));
+            assert_eq!(Ok(()), x.get(1));
+            assert_eq!(Ok(0), x.sync(timeout));
+            assert_eq!(Ok(b"second" as &[u8]), x.read_gotten(1));
+        },
+    ]));
+}
+
+#[test]
+fn connector_std_merger_args() {
+    /*
+        /-->\
+    Alice-->merger-->\
+        \<-----------/
+    */
+    let timeout = Duration::from_millis(1_500);
+    assert!(run_connector_set(&[
+        //
+        &|x| {
+            // Alice
+            let args = [ComponentArg::Ports(2), ComponentArg::Port];
+            x.configure_with_args(PDL, b"merger", &args).unwrap();
+            x.bind_port(0, Native).unwrap();
+            x.bind_port(1, Native).unwrap();
+            x.bind_port(2, Native).unwrap();
+            x.connect(timeout).unwrap();
+
+            // messages from either input are merged into the output
+            assert_eq!(Ok(()), x.put(1, b"second".to_vec().into()));
+            assert_eq!(Ok(()), x.get(2));
+            assert_eq!(Ok(0), x.sync(timeout));
+            assert_eq!(Ok(b"second" as &[u8]), x.read_gotten(2));
+            assert_eq!(Ok(()), x.put(0, b"first".to_vec().into()));
+            assert_eq!(Ok(()), x.get(2));
+            assert_eq!(Ok(0), x.sync(timeout));
+            assert_eq!(Ok(b"first" as &[u8]), x.read_gotten(2));
+        },
+    ]));
+}
+
+#[test]
+fn connector_std_replicator_args() {
+    /*
+    Alice-->replicator-->Alice
+                     \-->Alice
+    */
+    let timeout = Duration::from_millis(1_500);
+    assert!(run_connector_set(&[
+        //
+        &|x| {
+            // Alice
+            let args = [ComponentArg::Port, ComponentArg::Ports(2)];
+            x.configure_with_args(PDL, b"replicator", &args).unwrap();
+            x.bind_port(0, Native).unwrap();
+            x.bind_port(1, Native).unwrap();
+            x.bind_port(2, Native).unwrap();
+            x.connect(timeout).unwrap();
+
+            // every message is copied to all outputs
+            for _ in 0..2 {
+                assert_eq!(Ok(()), x.put(0, b"hello".to_vec().into()));
+                assert_eq!(Ok(()), x.get(1));
+                assert_eq!(Ok(()), x.get(2));
+                assert_eq!(Ok(0), x.sync(timeout));
+                assert_eq!(Ok(b"hello" as &[u8]), x.read_gotten(1));
+                assert_eq!(Ok(b"hello" as &[u8]), x.read_gotten(2));
+            }
+            // and without a message, nothing fires
+            assert_eq!(Ok(0), x.sync(timeout));
+            assert_eq!(Err(ReadGottenErr::DidNotGet), x.read_gotten(1));
+        },
+    ]));
+}
+
+#[test]
+fn connector_std_nfifo_args() {
+    /*
+        /-->\
+    Alice   nfifo
+        \<--/
+    */
+    let timeout = Duration::from_millis(1_500);
+    assert!(run_connector_set(&[
+        //
+        &|x| {
+            // Alice
+            let args = [ComponentArg::Port, ComponentArg::Port, ComponentArg::Integer(1)];
+            x.configure_with_args(PDL, b"nfifo", &args).unwrap();
+            x.bind_port(0, Native).unwrap();
+            x.bind_port(1, Native).unwrap();
+            x.connect(timeout).unwrap();
+
+            assert_eq!(Ok(()), x.put(0, b"first".to_vec().into()));
+            assert_eq!(Ok(0), x.sync(timeout));
+            assert_eq!(Ok(()), x.get(1));
+            assert_eq!(Ok(0), x.sync(timeout));
+            assert_eq!(Ok(b"first" as &[u8]), x.read_gotten(1));
+        },
+    ]));
+}
+
 #[test]
 #[should_panic]
 fn connector_causal_loop() {
@@ -844,3 +1364,31 @@ fn connector_recover() {
         },
     ]));
 }
+
+#[test]
+fn connector_dump_topology() {
+    let timeout = Duration::from_millis(1_500);
+    let addrs = [next_addr()];
+    assert!(run_connector_set(&[
+        &|x| {
+            x.configure(PDL, b"fifo_1_e").unwrap();
+            assert_eq!(None, x.dump_topology());
+            x.bind_port(0, Native).unwrap();
+            x.bind_port(1, Passive(addrs[0])).unwrap();
+            x.connect(timeout).unwrap();
+            // The native puts into the fifo, which puts to the peer over the network
+            let dot = x.dump_topology().unwrap();
+            assert!(dot.contains("native [shape=box, label=\"native\\nports 0\"];"));
+            assert!(dot.contains("p0 [shape=box, label=\"component 0\\nports 1, 2\"];"));
+            assert!(dot.contains("native -> p0 [label=\"0 -> 1\"];"));
+            assert!(dot.contains("n2 [label=\"127.0.0.1:"));
+            assert!(dot.contains("p0 -> n2 [label=\"2"));
+        },
+        &|x| {
+            x.configure(PDL, b"forward").unwrap();
+            x.bind_port(0, Active(addrs[0])).unwrap();
+            x.bind_port(1, Native).unwrap();
+            x.connect(timeout).unwrap();
+        },
+    ]));
+}
@@ -4,6 +4,7 @@ use crate::runtime::Unconfigured;
 use core::fmt::Debug;
 use std::net::SocketAddr;
 
+mod checker;
 mod connector;
 mod setup;
 
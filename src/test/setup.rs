@@ -7,7 +7,7 @@ use PortBinding::*;
 fn config_ok_0() {
     let pdl = b"primitive main() {}";
     let d = ProtocolD::parse(pdl).unwrap();
-    let pol = d.component_polarities(b"main").unwrap();
+    let pol = d.component_polarities(b"main", None).unwrap();
     assert_eq!(&pol[..], &[]);
 }
 
@@ -15,7 +15,7 @@ fn config_ok_0() {
 fn config_ok_2() {
     let pdl = b"primitive main(in x, out y) {}";
     let d = ProtocolD::parse(pdl).unwrap();
-    let pol = d.component_polarities(b"main").unwrap();
+    let pol = d.component_polarities(b"main", None).unwrap();
     assert_eq!(&pol[..], &[Getter, Putter]);
 }
 
@@ -26,6 +26,292 @@ fn config_non_port() {
     ProtocolD::parse(pdl).unwrap();
 }
 
+#[test]
+fn config_args() {
+    let pdl = b"primitive main(in[] x, out y, int n, byte[] b) {}";
+    let d = ProtocolD::parse(pdl).unwrap();
+    assert_eq!(Err(MainComponentErr::NonPortTypeParameters), d.component_polarities(b"main", None));
+    let args = [
+        ComponentArg::Ports(2),
+        ComponentArg::Port,
+        ComponentArg::Integer(3),
+        ComponentArg::Array(vec![ComponentArg::Integer(1), ComponentArg::Integer(2)]),
+    ];
+    let pol = d.component_polarities(b"main", Some(&args)).unwrap();
+    assert_eq!(&pol[..], &[Getter, Getter, Putter]);
+}
+
+#[test]
+fn config_wrong_args() {
+    let pdl = b"primitive main(in x, byte n) {}";
+    let d = ProtocolD::parse(pdl).unwrap();
+    let args = [ComponentArg::Port];
+    let err = MainComponentErr::WrongNumberOfParamaters { expected: 2 };
+    assert_eq!(Err(err), d.component_polarities(b"main", Some(&args)));
+    let args = [ComponentArg::Port, ComponentArg::Integer(128)];
+    let err = MainComponentErr::WrongArgumentType { param_index: 1 };
+    assert_eq!(Err(err), d.component_polarities(b"main", Some(&args)));
+    let args = [ComponentArg::Integer(0), ComponentArg::Integer(0)];
+    let err = MainComponentErr::WrongArgumentType { param_index: 0 };
+    assert_eq!(Err(err), d.component_polarities(b"main", Some(&args)));
+}
+
+#[test]
+fn config_modules() {
+    let modules = PdlModules {
+        sources: vec![(b"lib.pass".to_vec(), b"primitive main(in a, out b) {}".to_vec())],
+        search_path: vec!["testdata/modules".into()],
+    };
+    let pdl = b"import lib.pass; import lib.util; composite main(in a, out b) { new util.forward(a, b); }";
+    let d = ProtocolD::parse_with_modules(pdl, &modules).unwrap();
+    let pol = d.component_polarities(b"main", None).unwrap();
+    assert_eq!(&pol[..], &[Getter, Putter]);
+    let pol = d.component_polarities(b"pass.main", None).unwrap();
+    assert_eq!(&pol[..], &[Getter, Putter]);
+    // Imported symbols are only declared under their module name
+    let pdl = b"import lib.util; composite main(in a, out b) { new forward(a, b); }";
+    assert!(ProtocolD::parse_with_modules(pdl, &modules).is_err());
+}
+
+#[test]
+fn config_modules_errors() {
+    let modules = PdlModules {
+        sources: vec![
+            (b"lib.broken".to_vec(), b"primitive main(in a) { skip }".to_vec()),
+            (b"lib.misnamed".to_vec(), b"#module lib.other\nprimitive main() {}".to_vec()),
+        ],
+        search_path: vec!["testdata/modules".into()],
+    };
+    let err = ProtocolD::parse(b"import lib.util; primitive main() {}").unwrap_err();
+    assert!(err.starts_with("Parse error at 1:1: Unknown import"));
+    let err = ProtocolD::parse_with_modules(b"import cycle.a; primitive main() {}", &modules)
+        .unwrap_err();
+    assert!(err.starts_with("Parse error at b.pdl:1:1: Cyclic import: cycle.a"));
+    let err = ProtocolD::parse_with_modules(b"import lib.broken; primitive main() {}", &modules)
+        .unwrap_err();
+    assert!(err.starts_with("Parse error at lib.broken:1:"));
+    let err = ProtocolD::parse_with_modules(b"import lib.misnamed; primitive main() {}", &modules)
+        .unwrap_err();
+    assert!(err.starts_with("Parse error at 1:1: Module name mismatch: lib.other"));
+}
+
+#[test]
+fn config_pragmas() {
+    let d = ProtocolD::parse(b"#version 100\n#module net.main\nprimitive main() {}").unwrap();
+    let pragmas = d.pragmas();
+    assert_eq!(Some(100), pragmas.version);
+    assert_eq!(Some(b"net.main".to_vec()), pragmas.module_name);
+    assert!(pragmas.features.is_empty());
+    let pdl = b"#feature checked_overflow\nprimitive main() {}";
+    let pragmas = Connector::pragmas(pdl, &PdlModules::default()).unwrap();
+    assert_eq!(None, pragmas.version);
+    assert_eq!(None, pragmas.module_name);
+    assert_eq!(vec![b"checked_overflow".to_vec()], pragmas.features);
+    let mut x = Connector::Unconfigured(Unconfigured { controller_id: 0 });
+    match x.configure(b"#version 101\nprimitive main() {}", b"main") {
+        Err(errors::ConfigErr::ParseErr(message)) => {
+            assert!(message.starts_with("Parse error at 1:10: Unsupported version: 101"))
+        }
+        result => panic!("Expected parse error, found {:?}", result),
+    }
+}
+
+#[test]
+fn config_diagnose() {
+    let pdl = b"primitive main(in a) { skip }\nint f() { return true; }";
+    let diagnostics = Connector::diagnose(pdl, &PdlModules::default());
+    // Without a span, a diagnostic ends at the character after its position
+    let expected = [
+        (1, 29, 1, 30, "Expected ;"),
+        (2, 18, 2, 22, "Type mismatch: expected int, found boolean"),
+    ];
+    assert_eq!(expected.len(), diagnostics.len());
+    for (diagnostic, &(line, column, end_line, end_column, message)) in
+        diagnostics.iter().zip(expected.iter())
+    {
+        assert_eq!(Severity::Error, diagnostic.severity);
+        assert_eq!("", diagnostic.filename);
+        assert_eq!(
+            (line, column, message),
+            (diagnostic.line, diagnostic.column, &diagnostic.message[..])
+        );
+        assert_eq!((end_line, end_column), (diagnostic.end_line, diagnostic.end_column));
+    }
+    assert!(Connector::diagnose(b"primitive main() {}", &PdlModules::default()).is_empty());
+    // The configuration error lists all of them
+    let mut x = Connector::Unconfigured(Unconfigured { controller_id: 0 });
+    match x.configure(pdl, b"main") {
+        Err(errors::ConfigErr::ParseErr(message)) => {
+            assert_eq!(2, message.matches("Parse error at ").count())
+        }
+        result => panic!("Expected parse error, found {:?}", result),
+    }
+}
+
+#[test]
+fn config_format() {
+    // Type errors do not prevent formatting, syntax errors do
+    let pdl = b"primitive main(in a){while(true){synchronous{if(fires(a))get(a);}}}\nint f() { return true; }";
+    let expected = "primitive main(in a) {
+\twhile (true) {
+\t\tsynchronous {
+\t\t\tif (fires(a))
+\t\t\t\tget(a);
+\t\t}
+\t}
+}
+
+int f() {
+\treturn true;
+}
+";
+    assert_eq!(Ok(expected.to_string()), Connector::format(pdl));
+    match Connector::format(b"primitive main(in a) { skip }") {
+        Err(message) => assert!(message.starts_with("Parse error at 1:29: Expected ;")),
+        result => panic!("Expected parse error, found {:?}", result),
+    }
+}
+
+#[test]
+fn config_analysis() {
+    let pdl = b"
+primitive samelen(in a, in b, out c) {
+    synchronous {
+        msg m = get(a);
+        assert(m.length == get(b).length);
+        put(c, m);
+    }
+}
+primitive fifo(msg m, in i, out o) {
+    while (true) synchronous {
+        if (m == null && fires(i)) m = get(i);
+        else if (m != null && fires(o)) { put(o, m); m = null; }
+    }
+}
+primitive copy(in i, out l, out r) {
+    while (true) synchronous { if (fires(i)) { msg m = get(i); put(l, m); put(r, m); } }
+}
+composite looped(in a, out b) {
+    channel c -> d;
+    channel e -> f;
+    new samelen(a, f, c);
+    new copy(d, b, e);
+}
+composite buffered(in a, out b) {
+    channel c -> d;
+    channel e -> f;
+    channel g -> h;
+    new samelen(a, h, c);
+    new copy(d, b, e);
+    new fifo(null, f, g);
+}
+primitive stuck(in a) {
+    synchronous { if (fires(a)) assert(false); else while (true) skip; }
+}";
+    let d = ProtocolD::parse(pdl).unwrap();
+    let warnings = d.analyze(b"looped");
+    assert_eq!(1, warnings.len());
+    assert_eq!(Severity::Warning, warnings[0].severity);
+    assert_eq!((19, 5), (warnings[0].line, warnings[0].column));
+    assert_eq!("Causal loop: c -> d -> e -> f -> c", warnings[0].message);
+    // The fifo breaks the loop, as it puts a message in a later round than it gets it
+    assert!(d.analyze(b"buffered").is_empty());
+    assert!(d.analyze(b"copy").is_empty());
+    let warnings = d.analyze(b"stuck");
+    assert_eq!(1, warnings.len());
+    assert_eq!((33, 5), (warnings[0].line, warnings[0].column));
+    assert_eq!("Synchronous block never completes", warnings[0].message);
+    // Configuring reports the warnings, and logs them
+    let mut x = Connector::Unconfigured(Unconfigured { controller_id: 0 });
+    x.configure(pdl, b"looped").unwrap();
+    assert_eq!(&d.analyze(b"looped")[..], x.warnings());
+    let logger = x.get_mut_logger().unwrap();
+    assert!(logger.contains("Warning at :19:5: Causal loop: c -> d -> e -> f -> c"));
+}
+
+#[test]
+fn config_automaton() {
+    let pdl = b"primitive fifo(msg m, in i, out o) {
+    while (true) synchronous {
+        if (m == null && fires(i)) m = get(i);
+        else if (m != null && fires(o)) { put(o, m); m = null; }
+    }
+}
+primitive count(in i, out o) {
+    int n = 0;
+    while (n < 10) {
+        synchronous {
+            msg m = get(i);
+            if (m.length > 0) { put(o, m @ m); n++; }
+        }
+    }
+}
+composite twice(in i, out o) { new count(i, o); }";
+    let expected = r#"{
+  "name": "fifo",
+  "ports": [{"name": "i", "type": "in"}, {"name": "o", "type": "out"}],
+  "memory": ["m"],
+  "states": [{"id": 0, "line": 2, "column": 18}],
+  "initial": [0],
+  "transitions": [
+    {"from": 0, "to": 0, "fire": ["i"], "guards": ["m == null"], "data": [], "updates": ["m := d(i)"]},
+    {"from": 0, "to": 0, "fire": [], "guards": ["m == null"], "data": [], "updates": []},
+    {"from": 0, "to": 0, "fire": ["o"], "guards": ["m != null"], "data": ["d(o) = m"], "updates": ["m := null"]},
+    {"from": 0, "to": 0, "fire": [], "guards": ["m != null"], "data": [], "updates": []}
+  ],
+  "complete": true
+}
+"#;
+    assert_eq!(Ok(expected.to_string()), Connector::automaton(pdl, b"fifo", AutomatonFormat::Json));
+    // Locals of the block are substituted by their values, and the end of the component is a state
+    let expected = r#"digraph "count" {
+	node [shape=circle];
+	start [shape=point];
+	s0 [label="10:9"];
+	end [shape=doublecircle, label=""];
+	start -> s0;
+	start -> end;
+	s0 -> s0 [label="{i, o}\ld(i).length > 0\ln + 1 < 10\ld(o) = d(i) @ d(i)\ln := n + 1\l"];
+	s0 -> end [label="{i, o}\ld(i).length > 0\ln + 1 >= 10\ld(o) = d(i) @ d(i)\ln := n + 1\l"];
+	s0 -> s0 [label="{i}\ld(i).length <= 0\ln < 10\l"];
+	s0 -> end [label="{i}\ld(i).length <= 0\ln >= 10\l"];
+}
+"#;
+    assert_eq!(Ok(expected.to_string()), Connector::automaton(pdl, b"count", AutomatonFormat::Dot));
+    let result = Connector::automaton(pdl, b"twice", AutomatonFormat::Json);
+    assert_eq!(Err(errors::ConfigErr::NoSuchComponent), result);
+    let result =
+        Connector::automaton(b"primitive main(in a) { skip }", b"main", AutomatonFormat::Dot);
+    assert!(matches!(result, Err(errors::ConfigErr::ParseErr(_))));
+}
+
+#[test]
+fn config_topology() {
+    let pdl = b"import std.reo;
+composite main(in a, out b) {
+    channel c -> d;
+    channel e -> f;
+    new sync(a, c);
+    new replicator(d, {b, e});
+}";
+    // The unused end of the channel from e to f is a point
+    let expected = r#"digraph "main" {
+	rankdir=LR;
+	c0 [shape=box, label="sync\n5:5"];
+	c1 [shape=box, label="replicator\n6:5"];
+	p0 [shape=plaintext, label="a"];
+	p1 [shape=plaintext, label="b"];
+	e0 [shape=point];
+	p0 -> c0 [label="a"];
+	c1 -> p1 [label="b"];
+	c0 -> c1 [label="c -> d"];
+	c1 -> e0 [label="e -> f"];
+}
+"#;
+    assert_eq!(Ok(expected.to_string()), Connector::topology(pdl, b"main"));
+    assert_eq!(Err(errors::ConfigErr::NoSuchComponent), Connector::topology(pdl, b"sync"));
+}
+
 #[test]
 fn bind_too_much() {
     let mut x = Connector::Unconfigured(Unconfigured { controller_id: 0 });
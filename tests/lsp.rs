@@ -0,0 +1,234 @@
+use std::io::Write;
+use std::process::{Command, Stdio};
+
+const URI: &str = "file:///nowhere/main.pdl";
+
+// Runs the language server on the given messages, and returns its exit code and the messages
+// it sent back
+fn session(messages: &[String]) -> (i32, Vec<String>) {
+    let mut server = Command::new(env!("CARGO_BIN_EXE_reowolf-lsp"))
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+    let mut stdin = server.stdin.take().unwrap();
+    for message in messages {
+        write!(stdin, "Content-Length: {}\r\n\r\n{}", message.len(), message).unwrap();
+    }
+    drop(stdin);
+    let output = server.wait_with_output().unwrap();
+    let mut stdout = std::str::from_utf8(&output.stdout).unwrap();
+    let mut received = Vec::new();
+    while !stdout.is_empty() {
+        let (header, rest) = stdout.split_at(stdout.find("\r\n\r\n").unwrap());
+        let length: usize = header.trim_start_matches("Content-Length: ").parse().unwrap();
+        let (body, rest) = rest[4..].split_at(length);
+        received.push(body.to_string());
+        stdout = rest;
+    }
+    (output.status.code().unwrap(), received)
+}
+
+fn request(id: usize, method: &str, params: &str) -> String {
+    format!(r#"{{"jsonrpc":"2.0","id":{},"method":"{}","params":{}}}"#, id, method, params)
+}
+
+fn notification(method: &str, params: &str) -> String {
+    format!(r#"{{"jsonrpc":"2.0","method":"{}","params":{}}}"#, method, params)
+}
+
+fn open(text: &str) -> String {
+    let text = text.replace('\n', "\\n");
+    let params = format!(
+        r#"{{"textDocument":{{"uri":"{}","languageId":"pdl","version":1,"text":"{}"}}}}"#,
+        URI, text
+    );
+    notification("textDocument/didOpen", &params)
+}
+
+fn change(text: &str) -> String {
+    let text = text.replace('\n', "\\n");
+    let params = format!(
+        r#"{{"textDocument":{{"uri":"{}","version":2}},"contentChanges":[{{"text":"{}"}}]}}"#,
+        URI, text
+    );
+    notification("textDocument/didChange", &params)
+}
+
+fn at(id: usize, method: &str, line: usize, character: usize) -> String {
+    let params = format!(
+        r#"{{"textDocument":{{"uri":"{}"}},"position":{{"line":{},"character":{}}}}}"#,
+        URI, line, character
+    );
+    request(id, method, &params)
+}
+
+fn result(id: usize, result: &str) -> String {
+    format!(r#"{{"jsonrpc":"2.0","id":{},"result":{}}}"#, id, result)
+}
+
+fn diagnostics(diagnostics: &str) -> String {
+    format!(
+        r#"{{"jsonrpc":"2.0","method":"textDocument/publishDiagnostics","params":{{"uri":"{}","diagnostics":{}}}}}"#,
+        URI, diagnostics
+    )
+}
+
+fn range(line: usize, start: usize, end: usize) -> String {
+    format!(
+        r#"{{"start":{{"line":{},"character":{}}},"end":{{"line":{},"character":{}}}}}"#,
+        line, start, line, end
+    )
+}
+
+const PDL: &str = "import std.reo;
+import std.buf;
+composite main(in a, out b) {
+    channel x -> y;
+    new sync(a, x);
+    new relay(y, b);
+}
+primitive relay(in a, out b) {
+    while (true) synchronous {
+        msg m = get(a);
+        if (readByte(m, 0) != 0) put(b, m);
+    }
+}";
+
+#[test]
+fn lsp_navigation() {
+    let (code, received) = session(&[
+        request(1, "initialize", r#"{"capabilities":{}}"#),
+        notification("initialized", "{}"),
+        open(PDL),
+        // The channel end y, passed to relay
+        at(2, "textDocument/definition", 5, 14),
+        at(3, "textDocument/hover", 5, 14),
+        // The component relay, and the variable m in its body
+        at(4, "textDocument/definition", 5, 9),
+        at(5, "textDocument/definition", 10, 40),
+        at(6, "textDocument/hover", 10, 40),
+        // The connector of std.reo and the function of std.buf
+        at(7, "textDocument/definition", 4, 9),
+        at(8, "textDocument/hover", 4, 9),
+        at(9, "textDocument/hover", 10, 12),
+        // Nothing is declared by a keyword
+        at(10, "textDocument/hover", 8, 4),
+        request(
+            11,
+            "textDocument/documentSymbol",
+            &format!(r#"{{"textDocument":{{"uri":"{}"}}}}"#, URI),
+        ),
+        request(12, "shutdown", "null"),
+        notification("exit", "null"),
+    ]);
+    let location =
+        |line, start, end| format!(r#"{{"uri":"{}","range":{}}}"#, URI, range(line, start, end));
+    let hover =
+        |value: &str| format!(r#"{{"contents":{{"kind":"markdown","value":"{}"}}}}"#, value);
+    let symbol = |name: &str, detail: &str, kind: usize, line: usize, start: usize| {
+        let range = range(line, start, start + name.len());
+        format!(
+            r#"{{"name":"{}","detail":"{}","kind":{},"range":{},"selectionRange":{}}}"#,
+            name, detail, kind, range, range
+        )
+    };
+    assert_eq!(code, 0);
+    assert!(received[0].starts_with(r#"{"jsonrpc":"2.0","id":1,"result":{"capabilities":{"#));
+    assert_eq!(
+        received[1..],
+        [
+            diagnostics("[]"),
+            result(2, &location(3, 17, 18)),
+            result(3, &hover(r"```pdl\nin y\n```")),
+            result(4, &location(7, 10, 15)),
+            result(5, &location(9, 12, 13)),
+            result(6, &hover(r"```pdl\nmsg m\n```")),
+            result(7, "null"),
+            result(8, &hover(r"```pdl\nprimitive sync(in a, out b)\n```\n\nfrom `std.reo`")),
+            result(9, &hover(r"```pdl\nbyte readByte(msg, int)\n```\n\nfrom `std.buf`")),
+            result(10, "null"),
+            result(
+                11,
+                &format!(
+                    "[{},{}]",
+                    symbol("main", "composite main(in a, out b)", 5, 2, 10),
+                    symbol("relay", "primitive relay(in a, out b)", 5, 7, 10)
+                )
+            ),
+            result(12, "null"),
+        ]
+    );
+}
+
+#[test]
+fn lsp_diagnostics() {
+    let broken = PDL.replace("new relay(y, b);", "new relay(y, c);");
+    let (code, received) = session(&[
+        request(1, "initialize", r#"{"capabilities":{}}"#),
+        open(PDL),
+        change(&broken),
+        // The definitions without errors are still navigable
+        at(2, "textDocument/definition", 10, 40),
+        request(3, "textDocument/formatting", "{}"),
+        notification(
+            "textDocument/didClose",
+            &format!(r#"{{"textDocument":{{"uri":"{}"}}}}"#, URI),
+        ),
+        notification("exit", "null"),
+    ]);
+    let error = format!(
+        r#"{{"range":{},"severity":1,"source":"reowolf","message":"Unresolved variable"}}"#,
+        range(5, 17, 18)
+    );
+    // The client exits without asking the server to shut down
+    assert_eq!(code, 1);
+    assert_eq!(
+        received[1..],
+        [
+            diagnostics("[]"),
+            diagnostics(&format!("[{}]", error)),
+            result(
+                2,
+                r#"{"uri":"file:///nowhere/main.pdl","range":{"start":{"line":9,"character":12},"end":{"line":9,"character":13}}}"#
+            ),
+            r#"{"jsonrpc":"2.0","id":3,"error":{"code":-32601,"message":"Unknown method"}}"#
+                .to_string(),
+            diagnostics("[]"),
+        ]
+    );
+}
+
+#[test]
+fn lsp_multibyte() {
+    // Characters count UTF-16 code units: é is one, and 𝄞 is two
+    let pdl = "primitive main(in a, out b) {
+    while (true) synchronous {
+        /* é𝄞 */ msg m = get(a); /* ü */ put(b, m);
+    }
+}";
+    let broken = pdl.replace("put(b, m)", "put(c, m)");
+    let (code, received) = session(&[
+        request(1, "initialize", r#"{"capabilities":{}}"#),
+        open(pdl),
+        // The message m, put after the comments
+        at(2, "textDocument/definition", 2, 49),
+        at(3, "textDocument/hover", 2, 22),
+        change(&broken),
+        notification("exit", "null"),
+    ]);
+    let error = format!(
+        r#"{{"range":{},"severity":1,"source":"reowolf","message":"Unresolved variable"}}"#,
+        range(2, 46, 47)
+    );
+    assert_eq!(code, 1);
+    assert_eq!(
+        received[1..],
+        [
+            diagnostics("[]"),
+            result(2, &format!(r#"{{"uri":"{}","range":{}}}"#, URI, range(2, 22, 23))),
+            result(3, r#"{"contents":{"kind":"markdown","value":"```pdl\nmsg m\n```"}}"#),
+            diagnostics(&format!("[{}]", error)),
+        ]
+    );
+}